@@ -1,8 +1,15 @@
-use std;
+use serde_yaml;
+use std::{self, fmt, fs, io};
+use std::collections::{BTreeMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use super::password;
+use super::service::channel::ChannelMode;
+use super::service::mask::{self, Cidr};
 
 static DEFAULT_VERSION: &'static str = "1.0";
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
 pub struct Configuration {
     pub version: String,
     pub network_name: String,
@@ -11,7 +18,244 @@ pub struct Configuration {
     pub secure_listen_address: Option<std::net::SocketAddr>,
     pub debug_http_listen_address: Option<std::net::SocketAddr>,
 
-    pub connection_message_queue_length: usize,
+    // Hash of the password every client has to send with PASS to register, see password::hash.
+    pub server_password: Option<String>,
+
+    // Where client hostnames are looked up. None uses IP addresses as hostnames.
+    pub dns_resolver: Option<DnsResolver>,
+    // How long registration waits for a hostname lookup before settling for the IP address.
+    pub dns_timeout_seconds: u64,
+    // How long hostname lookups are reused for.
+    pub dns_cache_seconds: u64,
+    // How long registration waits for an ident reply, for classes that query ident.
+    pub ident_timeout_seconds: u64,
+    // Port ident is queried on. Only changed for tests.
+    pub ident_port: u16,
+
+    // Secret keying the cloaks shown instead of the hostnames of clients with user mode +x. None
+    // disables +x.
+    pub cloak_key: Option<String>,
+    // Leads the cloak of named hosts, as in <prefix>-1A2B3C4D.example.com.
+    pub cloak_prefix: String,
+    // Whether clients are given +x when they register.
+    pub cloak_on_connect: bool,
+
+    // Replaced by max_sendq of connection classes. Only read to warn that it is ignored.
+    #[serde(skip_serializing)]
+    pub connection_message_queue_length: Option<usize>,
+    // What to do with client lines that are not valid UTF-8.
+    pub invalid_utf8: InvalidUtf8,
+
+    // O-lines.
+    pub operators: Vec<Operator>,
+
+    // I-lines and Y-lines. Connections are placed in the first class they match.
+    pub classes: Vec<ConnectionClass>,
+
+    // Connections accepted from a single IP within throttle_window_seconds, past which further
+    // ones are refused. 0 disables throttling.
+    pub throttle_connections: usize,
+    pub throttle_window_seconds: u64,
+    // Connections that have yet to register, past which new ones are refused. 0 is unlimited.
+    pub max_registering: usize,
+    // Open connections from a single IPv4 address or IPv6 /64, past which new ones are refused.
+    // 0 is unlimited.
+    pub max_clones: usize,
+    // IP addresses or CIDR ranges exempt from throttling and clone limits.
+    pub throttle_exempt: Vec<String>,
+
+    // Flood protection cost of each command, in tokens. Unlisted commands cost 1.
+    pub command_costs: BTreeMap<String, u64>,
+    // nick!user@host masks of clients, such as bots, exempt from flood protection. Operators
+    // always are.
+    pub flood_exempt: Vec<String>,
+
+    // Channels a user may be in at once, advertised as CHANLIMIT. 0 is unlimited.
+    pub max_channels: usize,
+    // Channels created at startup, and brought back in line with these settings on REHASH.
+    pub channels: Vec<ChannelConfiguration>,
+
+    // Where K/G/D-lines are persisted. None keeps bans in memory only.
+    pub bans_file: Option<String>,
+    // Where NickServ accounts are persisted. None keeps accounts in memory only.
+    pub accounts_file: Option<String>,
+    // Where ChanServ channel registrations are persisted. None keeps them in memory only.
+    pub registered_channels_file: Option<String>,
+    // How long a user may keep a nick owned by another account before being renamed. 0 only
+    // warns.
+    pub nick_enforce_seconds: u64,
+
+    // Message of the day, sent after registration and on MOTD.
+    pub motd_file: Option<String>,
+
+    // Overrides for the built-in reply templates, keyed by template name.
+    pub templates: BTreeMap<String, String>,
+
+    // Sent to clients on DIE/RESTART/SIGTERM when no reason is given.
+    pub shutdown_message: String,
+    // How long to wait for clients to receive pending messages on shutdown.
+    pub shutdown_timeout_seconds: u64,
+    // Whether RESTART hands client connections over to the new process. SIGUSR2 always does.
+    pub hot_restart: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Operator {
+    pub name: String,
+    // See password::hash.
+    pub password: String,
+    // user@host masks this operator may authenticate from. Empty allows any host.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[serde(default)]
+    pub privileges: Vec<OperatorPrivilege>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(default)]
+pub struct ChannelConfiguration {
+    pub name: String,
+    // Unset settings are left to channel operators.
+    pub topic: Option<String>,
+    // Modes kept set or unset, e.g. "+t".
+    pub modes: String,
+    pub key: Option<String>,
+    // Members past which joins are refused.
+    pub limit: Option<usize>,
+    // Whether the channel is kept once its last member leaves.
+    pub permanent: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum DnsResolver {
+    // The system resolver, as configured by nsswitch.conf.
+    System,
+    // A file in /etc/hosts format.
+    HostsFile(String),
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum InvalidUtf8 {
+    // Close the link.
+    Disconnect,
+    // Drop the line, and tell the client with a NOTICE.
+    Notice,
+    // Decode as ISO-8859-1.
+    Latin1,
+    // Decode as Windows-1252, a superset of the printable ISO-8859-1 characters.
+    Cp1252,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(default)]
+pub struct ConnectionClass {
+    pub name: String,
+    // Who may connect in this class. Empty criteria match anyone.
+    // IP addresses or CIDR ranges.
+    pub ips: Vec<String>,
+    // user@host masks. Only checked on registration, once the username is known.
+    pub hosts: Vec<String>,
+    // Local addresses of the listeners the class applies to.
+    pub listeners: Vec<SocketAddr>,
+    // Whether only TLS (true) or only plaintext (false) connections match. There are no TLS
+    // listeners yet, so true is refused.
+    pub tls: Option<bool>,
+    // Hash of the password clients in this class have to send with PASS to register, in place of
+    // any server_password.
+    pub password: Option<String>,
+
+    // Whether to query the client's identd (RFC 1413) for its username. If it does not answer,
+    // the username given with USER is prefixed with ~.
+    pub ident: bool,
+
+    // Registered clients in this class, and per IP in this class, past which further clients are
+    // refused. 0 is unlimited.
+    pub max_clients: usize,
+    pub max_clients_per_ip: usize,
+    // Idle time after which the server PINGs the client.
+    pub ping_frequency_seconds: u64,
+    // How long the client has to answer a PING before it is disconnected.
+    pub ping_timeout_seconds: u64,
+    // How long a connection may take to register.
+    pub registration_timeout_seconds: u64,
+    // Commands worth of tokens a client may burst before being throttled.
+    pub flood_burst: u64,
+    // Tokens regained per second.
+    pub flood_rate: u64,
+    // Cost of throttled commands held back, past which the client is disconnected.
+    pub flood_limit: u64,
+    // Bytes of messages waiting to be written, past which the client is disconnected.
+    pub max_sendq: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Hash)]
+pub enum OperatorPrivilege {
+    Kill,
+    // Add and remove K/G/D-lines.
+    Ban,
+    // DIE and RESTART.
+    Die,
+    // Set the displayed host of any client with CHGHOST.
+    Vhost,
+    // See the real hosts behind cloaks and vhosts.
+    RealHost,
+}
+
+impl ConnectionClass {
+    // Whether a connection from ip, accepted by listener, falls into this class. The user@host
+    // masks are only checked if userhost is known.
+    pub fn admits(
+        &self,
+        ip: &IpAddr,
+        listener: &SocketAddr,
+        tls: bool,
+        userhost: Option<&str>,
+    ) -> bool {
+        (self.ips.is_empty() ||
+             self.ips.iter().any(|c| {
+                c.parse::<Cidr>().map_or(false, |c| c.contains(ip))
+            })) &&
+            (self.listeners.is_empty() || self.listeners.contains(listener)) &&
+            self.tls.map_or(true, |t| t == tls) &&
+            (self.hosts.is_empty() ||
+                 userhost.map_or(true, |uh| self.hosts.iter().any(|m| mask::matches(m, uh))))
+    }
+}
+
+impl std::default::Default for ChannelConfiguration {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            topic: None,
+            modes: String::new(),
+            key: None,
+            limit: None,
+            permanent: true,
+        }
+    }
+}
+
+impl std::default::Default for ConnectionClass {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            ips: Vec::new(),
+            hosts: Vec::new(),
+            listeners: Vec::new(),
+            tls: None,
+            password: None,
+            ident: false,
+            max_clients: 0,
+            max_clients_per_ip: 0,
+            ping_frequency_seconds: 120,
+            ping_timeout_seconds: 60,
+            registration_timeout_seconds: 30,
+            flood_burst: 10,
+            flood_rate: 1,
+            flood_limit: 50,
+            max_sendq: 100 * 1024,
+        }
+    }
 }
 
 impl std::default::Default for Configuration {
@@ -24,7 +268,410 @@ impl std::default::Default for Configuration {
             secure_listen_address: Some("0.0.0.0:6697".parse().unwrap()),
             debug_http_listen_address: Some("0.0.0.0:8080".parse().unwrap()),
 
-            connection_message_queue_length: 10,
+            server_password: None,
+
+            dns_resolver: Some(DnsResolver::System),
+            dns_timeout_seconds: 5,
+            dns_cache_seconds: 300,
+            ident_timeout_seconds: 5,
+            ident_port: 113,
+
+            cloak_key: None,
+            cloak_prefix: "cloaked".to_string(),
+            cloak_on_connect: false,
+
+            connection_message_queue_length: None,
+            invalid_utf8: InvalidUtf8::Cp1252,
+
+            operators: Vec::new(),
+
+            classes: vec![ConnectionClass::default()],
+
+            throttle_connections: 10,
+            throttle_window_seconds: 60,
+            max_registering: 100,
+            max_clones: 20,
+            throttle_exempt: Vec::new(),
+
+            command_costs: [
+                ("JOIN", 2),
+                ("LIST", 5),
+                ("NAMES", 2),
+                ("PONG", 0),
+                ("QUIT", 0),
+                ("WHO", 3),
+                ("WHOIS", 2),
+            ].iter()
+                .map(|&(c, n)| (c.to_string(), n))
+                .collect(),
+            flood_exempt: Vec::new(),
+
+            max_channels: 20,
+            channels: Vec::new(),
+
+            bans_file: Some("bans.yaml".to_string()),
+            accounts_file: Some("accounts.yaml".to_string()),
+            registered_channels_file: Some("registered_channels.yaml".to_string()),
+            nick_enforce_seconds: 30,
+
+            motd_file: None,
+
+            templates: BTreeMap::new(),
+
+            shutdown_message: "Server shutting down".to_string(),
+            shutdown_timeout_seconds: 5,
+            hot_restart: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigurationError {
+    Io(io::Error),
+    Parse(serde_yaml::Error),
+    Invalid(String),
+    // A file the server persists state in is unreadable.
+    Store(String),
+}
+
+impl fmt::Display for ConfigurationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            &ConfigurationError::Io(ref e) => write!(f, "{}", e),
+            &ConfigurationError::Parse(ref e) => write!(f, "{}", e),
+            &ConfigurationError::Invalid(ref e) => write!(f, "{}", e),
+            &ConfigurationError::Store(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Configuration {
+    // Reads and validates the configuration at path.
+    pub fn load(path: &str) -> Result<Self, ConfigurationError> {
+        let file = fs::File::open(path).map_err(ConfigurationError::Io)?;
+        let configuration: Configuration = serde_yaml::from_reader(file).map_err(
+            ConfigurationError::Parse,
+        )?;
+        configuration.validate()?;
+        for d in configuration.deprecations() {
+            warn!("{}: {}.", path, d);
+        }
+        Ok(configuration)
+    }
+
+    // Settings that are still accepted but no longer have any effect.
+    pub fn deprecations(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        if self.connection_message_queue_length.is_some() {
+            result.push(
+                "connection_message_queue_length is ignored, set max_sendq (in bytes) of \
+                 connection classes instead"
+                    .to_string(),
+            );
+        }
+        result
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigurationError> {
+        if self.server_password.as_ref().map_or(false, |p| !password::is_valid(p)) {
+            return Err(ConfigurationError::Invalid(
+                "server_password is not a password hash".to_string(),
+            ));
         }
+        if self.classes.is_empty() {
+            return Err(ConfigurationError::Invalid(
+                "at least one connection class is required".to_string(),
+            ));
+        }
+        let mut names = HashSet::new();
+        for c in self.classes.iter() {
+            if !names.insert(&c.name) {
+                return Err(ConfigurationError::Invalid(
+                    format!("duplicate connection class {}", c.name),
+                ));
+            }
+            if c.ping_frequency_seconds == 0 || c.ping_timeout_seconds == 0 ||
+                c.registration_timeout_seconds == 0
+            {
+                return Err(ConfigurationError::Invalid(
+                    format!("connection class {} timeouts must be positive", c.name),
+                ));
+            }
+            if c.ips.iter().any(|ip| ip.parse::<Cidr>().is_err()) {
+                return Err(ConfigurationError::Invalid(
+                    format!("invalid ip range for connection class {}", c.name),
+                ));
+            }
+            if c.hosts.iter().any(|h| h.len() == 0 || h.contains(' ')) {
+                return Err(ConfigurationError::Invalid(
+                    format!("invalid host mask for connection class {}", c.name),
+                ));
+            }
+            if c.tls == Some(true) {
+                return Err(ConfigurationError::Invalid(format!(
+                    "connection class {} requires TLS, which is not supported",
+                    c.name
+                )));
+            }
+            if c.password.as_ref().map_or(false, |p| !password::is_valid(p)) {
+                return Err(ConfigurationError::Invalid(format!(
+                    "password of connection class {} is not a password hash",
+                    c.name
+                )));
+            }
+            if c.flood_rate == 0 {
+                return Err(ConfigurationError::Invalid(
+                    format!("connection class {} flood_rate must be positive", c.name),
+                ));
+            }
+            // Otherwise the command could never be processed.
+            let too_costly = self.command_costs.iter().find(|&(_, &n)| n > c.flood_burst);
+            if let Some((command, _)) = too_costly {
+                return Err(ConfigurationError::Invalid(format!(
+                    "cost of {} exceeds flood_burst of connection class {}",
+                    command,
+                    c.name
+                )));
+            }
+        }
+        if self.ident_timeout_seconds == 0 {
+            return Err(ConfigurationError::Invalid(
+                "ident_timeout_seconds must be positive".to_string(),
+            ));
+        }
+        if self.cloak_key.as_ref().map_or(false, |k| k.is_empty()) {
+            return Err(ConfigurationError::Invalid(
+                "cloak_key must not be empty".to_string(),
+            ));
+        }
+        if self.cloak_prefix.is_empty() ||
+            !self.cloak_prefix.chars().all(
+                |c| c.is_ascii_alphanumeric() || c == '-',
+            )
+        {
+            return Err(ConfigurationError::Invalid(
+                "cloak_prefix must be letters, digits and dashes".to_string(),
+            ));
+        }
+        if self.throttle_connections > 0 && self.throttle_window_seconds == 0 {
+            return Err(ConfigurationError::Invalid(
+                "throttle_window_seconds must be positive".to_string(),
+            ));
+        }
+        if self.throttle_exempt.iter().any(|ip| ip.parse::<Cidr>().is_err()) {
+            return Err(ConfigurationError::Invalid("invalid throttle_exempt range".to_string()));
+        }
+        if self.flood_exempt.iter().any(|m| m.len() == 0 || m.contains(' ')) {
+            return Err(ConfigurationError::Invalid("invalid flood_exempt mask".to_string()));
+        }
+
+        let mut names = HashSet::new();
+        for c in self.channels.iter() {
+            if !c.name.starts_with('#') || c.name.contains(|c| c == ' ' || c == ',') {
+                return Err(ConfigurationError::Invalid(format!("invalid channel name {}", c.name)));
+            }
+            // Channel names are case-insensitive.
+            if !names.insert(c.name.to_lowercase()) {
+                return Err(ConfigurationError::Invalid(format!("duplicate channel {}", c.name)));
+            }
+            if !c.modes.chars().all(|m| {
+                m == '+' || m == '-' || ChannelMode::from_char(m).is_some()
+            })
+            {
+                return Err(ConfigurationError::Invalid(
+                    format!("invalid modes for channel {}", c.name),
+                ));
+            }
+            if c.key.as_ref().map_or(false, |k| k.is_empty() || k.contains(' ')) {
+                return Err(ConfigurationError::Invalid(
+                    format!("invalid key for channel {}", c.name),
+                ));
+            }
+        }
+
+        let mut names = HashSet::new();
+        for o in self.operators.iter() {
+            if o.name.len() == 0 || o.password.len() == 0 {
+                return Err(ConfigurationError::Invalid(
+                    "operators need a name and a password".to_string(),
+                ));
+            }
+            if !names.insert(&o.name) {
+                return Err(ConfigurationError::Invalid(
+                    format!("duplicate operator {}", o.name),
+                ));
+            }
+            if !password::is_valid(&o.password) {
+                return Err(ConfigurationError::Invalid(
+                    format!("password of operator {} is not a password hash", o.name),
+                ));
+            }
+            if o.hosts.iter().any(|h| h.len() == 0 || h.contains(' ')) {
+                return Err(ConfigurationError::Invalid(
+                    format!("invalid host mask for operator {}", o.name),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn command_cost(&self, command: &str) -> u64 {
+        self.command_costs.get(command).cloned().unwrap_or(1)
+    }
+
+    // The class a connection is placed in, if it is allowed to connect at all. See
+    // ConnectionClass::admits.
+    pub fn connection_class(
+        &self,
+        ip: &IpAddr,
+        listener: &SocketAddr,
+        tls: bool,
+        userhost: Option<&str>,
+    ) -> Option<&ConnectionClass> {
+        self.classes.iter().find(
+            |c| c.admits(ip, listener, tls, userhost),
+        )
+    }
+
+    pub fn throttle_exempt(&self, ip: &IpAddr) -> bool {
+        self.throttle_exempt.iter().any(|c| {
+            c.parse::<Cidr>().map_or(false, |c| c.contains(ip))
+        })
+    }
+
+    // The operator named name, if password is its password.
+    pub fn authenticate_operator(&self, name: &str, password: &str) -> Option<&Operator> {
+        self.operators.iter().find(|o| {
+            o.name == name && password::verify(password, &o.password)
+        })
+    }
+
+    pub fn find_class(&self, name: &str) -> Option<&ConnectionClass> {
+        self.classes.iter().find(|c| c.name == name)
+    }
+
+    // Settings that only take effect on restart, and differ between self and other.
+    pub fn restart_required(&self, other: &Configuration) -> Vec<&'static str> {
+        let mut result = Vec::new();
+        if self.insecure_listen_address != other.insecure_listen_address {
+            result.push("insecure_listen_address");
+        }
+        if self.secure_listen_address != other.secure_listen_address {
+            result.push("secure_listen_address");
+        }
+        if self.debug_http_listen_address != other.debug_http_listen_address {
+            result.push("debug_http_listen_address");
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml;
+    use super::super::password;
+    use super::{ChannelConfiguration, Configuration, ConnectionClass, Operator};
+
+    #[test]
+    fn test_authenticate_operator() {
+        let configuration = Configuration {
+            operators: vec![
+                Operator {
+                    name: "admin".to_string(),
+                    password: password::hash("secret"),
+                    hosts: Vec::new(),
+                    privileges: Vec::new(),
+                },
+            ],
+            ..Default::default()
+        };
+        assert!(configuration.validate().is_ok());
+        assert_eq!(
+            configuration.authenticate_operator("admin", "secret").unwrap().name,
+            "admin"
+        );
+        assert!(configuration.authenticate_operator("admin", "wrong").is_none());
+        assert!(configuration.authenticate_operator("root", "secret").is_none());
+
+        // Passwords in the clear are refused.
+        let configuration = Configuration {
+            operators: vec![
+                Operator {
+                    name: "admin".to_string(),
+                    password: "secret".to_string(),
+                    hosts: Vec::new(),
+                    privileges: Vec::new(),
+                },
+            ],
+            ..Default::default()
+        };
+        assert!(configuration.validate().is_err());
+    }
+
+    #[test]
+    fn test_deprecations() {
+        let configuration: Configuration =
+            serde_yaml::from_str("connection_message_queue_length: 10").unwrap();
+        assert!(configuration.validate().is_ok());
+        assert_eq!(configuration.deprecations().len(), 1);
+        assert_eq!(configuration.classes, Configuration::default().classes);
+        // Nor is it written back out.
+        assert!(!serde_yaml::to_string(&configuration).unwrap().contains(
+            "connection_message_queue_length",
+        ));
+
+        assert!(Configuration::default().deprecations().is_empty());
+    }
+
+    #[test]
+    fn test_admits() {
+        let ip = "10.0.0.1".parse().unwrap();
+        let listener = "127.0.0.1:6667".parse().unwrap();
+        assert!(ConnectionClass::default().admits(&ip, &listener, false, None));
+
+        let class = ConnectionClass {
+            ips: vec!["10.0.0.0/8".to_string()],
+            listeners: vec![listener],
+            tls: Some(false),
+            hosts: vec!["bot@*".to_string()],
+            ..Default::default()
+        };
+        // The user@host masks wait until the username is known.
+        assert!(class.admits(&ip, &listener, false, None));
+        assert!(class.admits(&ip, &listener, false, Some("bot@10.0.0.1")));
+        assert!(!class.admits(&ip, &listener, false, Some("alice@10.0.0.1")));
+        assert!(!class.admits(&"192.168.0.1".parse().unwrap(), &listener, false, None));
+        assert!(!class.admits(&ip, &"127.0.0.1:6697".parse().unwrap(), false, None));
+        assert!(!class.admits(&ip, &listener, true, None));
+
+        let tls = Configuration {
+            classes: vec![
+                ConnectionClass {
+                    tls: Some(true),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        assert!(tls.validate().is_err());
+    }
+
+    #[test]
+    fn test_channels() {
+        let channel = |name: &str| {
+            ChannelConfiguration {
+                name: name.to_string(),
+                ..Default::default()
+            }
+        };
+        let configuration = |channels| {
+            Configuration {
+                channels: channels,
+                ..Default::default()
+            }
+        };
+        assert!(configuration(vec![channel("#general"), channel("#help")]).validate().is_ok());
+        assert!(configuration(vec![channel("general")]).validate().is_err());
+        assert!(configuration(vec![channel("#general"), channel("#General")]).validate().is_err());
     }
 }
@@ -3,7 +3,6 @@ use hyper;
 use hyper::server::{Request, Response, Service};
 use serde_yaml;
 use std::collections::HashMap;
-use std::ops::Deref;
 use std::sync::{Arc, Mutex};
 use super::service::server::Server;
 use super::service::connection::{Connection, SocketPair};
@@ -22,15 +21,21 @@ pub struct DebugService {
 struct DebugOutputData {
     configuration: (bool, String),
 
-    // SocketPair -> (Registered, (Nick, Nick HTML Element ID)).
+    // SocketPair -> (Registered, (Nick, Nick HTML Element ID), SendQ bytes).
     // There may be some connections without a Nick.
-    connections: HashMap<String, (bool, (String, String))>,
+    connections: HashMap<String, (bool, (String, String), usize)>,
 
     // Channel Name -> Vec<(Nick, Nick HTML Element ID)>.
     channels_to_nicks: HashMap<String, Vec<(String, String)>>,
 
+    // Reason -> connections refused before registering.
+    refusals: Vec<(String, usize)>,
+
     // Nick -> (User Serialized, Nick HTML Element ID, Channel, Channel HTML Element ID).
     user_to_channels: HashMap<String, (String, String, Vec<(String, String)>)>,
+
+    // Disagreements between the server, channels and users. See Server::check_consistency.
+    inconsistencies: Vec<String>,
 }
 
 impl DebugService {
@@ -53,7 +58,7 @@ impl DebugService {
         }
         let serialized = maybe_serialized.unwrap();
         trace!("About to render: {:?}.", serialized);
-        match self.shared_state.template_engine.0.render(
+        match self.shared_state.settings().template_engine.0.render(
             templates::DEBUG_TEMPLATE_NAME,
             &serialized,
         ) {
@@ -66,9 +71,10 @@ impl DebugService {
     }
 
     fn serialize(&self) -> Result<DebugOutputData, String> {
+        let settings = self.shared_state.settings();
         let configuration = (
-            self.shared_state.configuration.deref() == &Configuration::default(),
-            serde_yaml::to_string(self.shared_state.configuration.deref())
+            settings.configuration == Configuration::default(),
+            serde_yaml::to_string(&settings.configuration)
                 .map_err(|e| e.to_string())?,
         );
 
@@ -79,8 +85,13 @@ impl DebugService {
         let mut channels_to_nicks = HashMap::new();
         let mut connections_output = HashMap::new();
         let mut user_to_channels = HashMap::new();
+        let mut refusals = Vec::new();
         {
             let server = self.server.lock().unwrap();
+            for (kind, count) in server.refusals() {
+                refusals.push((kind.to_string(), *count));
+            }
+            refusals.sort();
             // Assign HTML element IDs to every nick.
             for user in server.users() {
                 nick_to_id.insert(
@@ -124,7 +135,7 @@ impl DebugService {
                     connections_output.insert(socket.to_string(), (true, (
                         nick.clone(),
                         nick_to_id.get(nick).unwrap().clone(),
-                    )));
+                    ), conn.sendq()));
                     let mut channels = Vec::new();
                     for chan in u.channels() {
                         channels.push((
@@ -148,17 +159,29 @@ impl DebugService {
                 } else {
                     connections_output.insert(
                         socket.to_string(),
-                        (false, ("".to_string(), "".to_string())),
+                        (false, ("".to_string(), "".to_string()), conn.sendq()),
                     );
                 }
             }
         }
 
+        let inconsistencies = {
+            // Lock order is connections, connection, server.
+            let conns = self.connections.lock().unwrap();
+            let guards: Vec<_> = conns.values().map(|c| c.lock().unwrap()).collect();
+            let server = self.server.lock().unwrap();
+            server.check_consistency(guards.iter().filter(|c| c.registered()).map(
+                |c| c.get_user(),
+            ))
+        };
+
         Ok(DebugOutputData {
             configuration: configuration,
             connections: connections_output,
             channels_to_nicks: channels_to_nicks,
+            refusals: refusals,
             user_to_channels: user_to_channels,
+            inconsistencies: inconsistencies,
         })
     }
 }
@@ -1,18 +1,24 @@
+extern crate base64;
 extern crate bytes;
 extern crate chrono;
 extern crate futures;
 extern crate futures_cpupool;
 extern crate handlebars;
+extern crate hmac;
 extern crate hostname;
 extern crate hyper;
+extern crate libc;
 #[macro_use]
 extern crate log;
 extern crate num_cpus;
+extern crate pbkdf2;
 extern crate rand;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_yaml;
+extern crate sha2;
+extern crate signal_hook;
 extern crate tokio_core;
 extern crate tokio_io;
 extern crate tokio_proto;
@@ -20,5 +26,6 @@ extern crate tokio_service;
 
 pub mod configuration;
 mod debug;
+pub mod password;
 pub mod service;
 pub mod templates;
@@ -3,7 +3,9 @@ extern crate getopts;
 extern crate serde_yaml;
 extern crate irc_server;
 
-use irc_server::{configuration, service};
+use irc_server::{configuration, password, service};
+use irc_server::service::server::ShutdownKind;
+use std::os::unix::process::CommandExt;
 
 fn print_usage(prog: &str, opts: getopts::Options) {
     let brief = format!("Usage: {} port", prog);
@@ -24,6 +26,11 @@ fn main() {
         "config.yaml",
     );
     opts.optflagopt("c", "config_file", "Configuration filename.", "config.yaml");
+    opts.optflag(
+        "p",
+        "hash_password",
+        "Read a password from stdin, and print its hash for the configuration file.",
+    );
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
         Err(f) => {
@@ -43,13 +50,39 @@ fn main() {
         return;
     }
 
-    let config = if matches.opt_present("c") {
-        serde_yaml::from_reader(&std::fs::File::open(
-            matches.opt_str("c").unwrap_or("config.yaml".to_string()),
-        ).unwrap()).unwrap()
+    if matches.opt_present("p") {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).unwrap();
+        println!("{}", password::hash(line.trim_end_matches(|c| c == '\r' || c == '\n')));
+        return;
+    }
+
+    let path = if matches.opt_present("c") {
+        Some(matches.opt_str("c").unwrap_or("config.yaml".to_string()))
     } else {
-        configuration::Configuration::default()
+        None
+    };
+    let config = match path {
+        Some(ref p) => {
+            match configuration::Configuration::load(p) {
+                Ok(c) => c,
+                Err(e) => panic!("Invalid configuration {}: {}", p, e),
+            }
+        }
+        None => configuration::Configuration::default(),
     };
 
-    service::start(std::sync::Arc::new(config));
+    let shutdown = match service::start(config, path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to start: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if shutdown.kind == ShutdownKind::Restart {
+        let e = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(&args[1..])
+            .exec();
+        panic!("Failed to restart: {}", e);
+    }
 }
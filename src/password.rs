@@ -0,0 +1,103 @@
+// Salted password hashes, as stored in the configuration file. Generate them with
+// irc_server --hash_password.
+//
+// Hashes are PBKDF2-HMAC-SHA256, formatted as $pbkdf2-sha256$<rounds>$<hex salt>$<hex hash>.
+use pbkdf2;
+use rand;
+use sha2::Sha256;
+
+static PREFIX: &'static str = "$pbkdf2-sha256$";
+const ROUNDS: u32 = 10000;
+const SALT_LENGTH: usize = 16;
+const HASH_LENGTH: usize = 32;
+
+pub fn hash(password: &str) -> String {
+    let salt: Vec<u8> = (0..SALT_LENGTH).map(|_| rand::random::<u8>()).collect();
+    format(ROUNDS, &salt, &derive(password, &salt, ROUNDS))
+}
+
+// Whether password matches hash. Malformed hashes match nothing.
+pub fn verify(password: &str, hash: &str) -> bool {
+    match parse(hash) {
+        Some((rounds, salt, expected)) => {
+            let actual = derive(password, &salt, rounds);
+            // Constant time, so the comparison doesn't leak how much of the hash matched.
+            actual.iter().zip(expected.iter()).fold(0, |d, (a, b)| d | (a ^ b)) == 0 &&
+                actual.len() == expected.len()
+        }
+        None => false,
+    }
+}
+
+pub fn is_valid(hash: &str) -> bool {
+    parse(hash).is_some()
+}
+
+fn derive(password: &str, salt: &[u8], rounds: u32) -> Vec<u8> {
+    let mut result = vec![0; HASH_LENGTH];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, rounds, &mut result);
+    result
+}
+
+fn format(rounds: u32, salt: &[u8], hash: &[u8]) -> String {
+    format!("{}{}${}${}", PREFIX, rounds, to_hex(salt), to_hex(hash))
+}
+
+fn parse(hash: &str) -> Option<(u32, Vec<u8>, Vec<u8>)> {
+    if !hash.starts_with(PREFIX) {
+        return None;
+    }
+    let fields: Vec<&str> = hash[PREFIX.len()..].split('$').collect();
+    if fields.len() != 3 {
+        return None;
+    }
+    let rounds = match fields[0].parse::<u32>() {
+        Ok(r) if r > 0 => r,
+        _ => return None,
+    };
+    match (from_hex(fields[1]), from_hex(fields[2])) {
+        (Some(salt), Some(hash)) => {
+            if hash.len() == HASH_LENGTH {
+                Some((rounds, salt, hash))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hash, is_valid, verify};
+
+    #[test]
+    fn test_hash() {
+        let h = hash("hunter2");
+        assert!(is_valid(&h));
+        assert!(verify("hunter2", &h));
+        assert!(!verify("hunter3", &h));
+        assert!(!verify("", &h));
+        // Salted.
+        assert!(h != hash("hunter2"));
+
+        assert!(!is_valid("hunter2"));
+        assert!(!is_valid("$pbkdf2-sha256$0$00$00"));
+        assert!(!is_valid("$pbkdf2-sha256$1000$zz$00"));
+        assert!(!verify("hunter2", "hunter2"));
+    }
+}
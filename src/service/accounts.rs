@@ -0,0 +1,253 @@
+use chrono;
+use std::collections::BTreeMap;
+use super::super::password;
+use super::persist;
+use super::sasl::{Backend, ScramCredentials, ScramKeys};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Account {
+    // The nick the account was registered with.
+    pub name: String,
+    // See password::hash.
+    pub password: String,
+    // For SASL SCRAM-SHA-256. Missing from accounts registered before they were kept, until the
+    // password is set again.
+    #[serde(default)]
+    pub scram: Option<ScramKeys>,
+    pub email: Option<String>,
+    // Nicks owned by the account, lowercased, starting with its name.
+    pub nicks: Vec<String>,
+    // Shown instead of the host of users identified to the account.
+    #[serde(default)]
+    pub vhost: Option<String>,
+    // Unix timestamp.
+    pub registered_at: i64,
+}
+
+// A password, in the forms accounts keep it. Made before taking the server lock, as hashing is
+// slow.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    password: String,
+    scram: ScramKeys,
+}
+
+impl Credentials {
+    pub fn new(password: &str) -> Self {
+        Credentials {
+            password: password::hash(password),
+            scram: ScramKeys::new(password),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AccountError {
+    // The nick already belongs to an account.
+    NickRegistered,
+    NoSuchAccount,
+}
+
+// Registered accounts, keyed by lowercased name, persisted to path (if any) on every change.
+#[derive(Debug)]
+pub struct Accounts {
+    accounts: BTreeMap<String, Account>,
+    path: Option<String>,
+}
+
+impl Accounts {
+    pub fn new(path: Option<String>) -> Self {
+        Accounts {
+            accounts: BTreeMap::new(),
+            path: path,
+        }
+    }
+
+    // Loads accounts from path. A missing file has no accounts.
+    pub fn load(path: Option<String>) -> Result<Self, String> {
+        let mut accounts = Accounts::new(path);
+        if let Some(ref p) = accounts.path.clone() {
+            accounts.restore(persist::load(p)?.unwrap_or_default());
+        }
+        Ok(accounts)
+    }
+
+    fn save(&self) {
+        if let Some(ref path) = self.path {
+            if let Err(e) = persist::save(path, &self.snapshot()) {
+                error!("Failed to save accounts: {}.", e);
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<Account> {
+        self.accounts.values().cloned().collect()
+    }
+
+    // Replaces the accounts with ones read from the file, or handed over by a hot restart.
+    pub fn restore(&mut self, accounts: Vec<Account>) {
+        self.accounts = accounts
+            .into_iter()
+            .map(|a| (a.name.to_lowercase(), a))
+            .collect();
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Account> {
+        self.accounts.get(&name.to_lowercase())
+    }
+
+    // The account owning nick, if any.
+    pub fn owner(&self, nick: &str) -> Option<&Account> {
+        let nick = nick.to_lowercase();
+        self.accounts.values().find(|a| a.nicks.contains(&nick))
+    }
+
+    // Registers nick as an account.
+    pub fn register(
+        &mut self,
+        nick: &str,
+        credentials: Credentials,
+        email: Option<String>,
+    ) -> Result<&Account, AccountError> {
+        if self.owner(nick).is_some() {
+            return Err(AccountError::NickRegistered);
+        }
+        let key = nick.to_lowercase();
+        self.accounts.insert(
+            key.clone(),
+            Account {
+                name: nick.to_string(),
+                password: credentials.password,
+                scram: Some(credentials.scram),
+                email: email,
+                nicks: vec![key.clone()],
+                vhost: None,
+                registered_at: chrono::offset::Utc::now().timestamp(),
+            },
+        );
+        self.save();
+        Ok(&self.accounts[&key])
+    }
+
+    // Adds nick to the nicks owned by the account named name.
+    pub fn group(&mut self, name: &str, nick: &str) -> Result<(), AccountError> {
+        if self.owner(nick).is_some() {
+            return Err(AccountError::NickRegistered);
+        }
+        self.modify(name, |a| a.nicks.push(nick.to_lowercase()))
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<Account, AccountError> {
+        let dropped = self.accounts.remove(&name.to_lowercase()).ok_or(
+            AccountError::NoSuchAccount,
+        )?;
+        self.save();
+        Ok(dropped)
+    }
+
+    pub fn set_password(
+        &mut self,
+        name: &str,
+        credentials: Credentials,
+    ) -> Result<(), AccountError> {
+        self.modify(name, |a| {
+            a.password = credentials.password;
+            a.scram = Some(credentials.scram);
+        })
+    }
+
+    pub fn set_email(&mut self, name: &str, email: Option<String>) -> Result<(), AccountError> {
+        self.modify(name, |a| a.email = email)
+    }
+
+    pub fn set_vhost(&mut self, name: &str, vhost: Option<String>) -> Result<(), AccountError> {
+        self.modify(name, |a| a.vhost = vhost)
+    }
+
+    fn modify<F>(&mut self, name: &str, f: F) -> Result<(), AccountError>
+    where
+        F: FnOnce(&mut Account),
+    {
+        match self.accounts.get_mut(&name.to_lowercase()) {
+            Some(a) => f(a),
+            None => return Err(AccountError::NoSuchAccount),
+        }
+        self.save();
+        Ok(())
+    }
+}
+
+impl Backend for Accounts {
+    fn password(&self, account: &str) -> Option<(String, String)> {
+        self.get(account).map(|a| (a.name.clone(), a.password.clone()))
+    }
+
+    fn scram_credentials(&self, account: &str) -> Option<ScramCredentials> {
+        let account = self.get(account)?;
+        account.scram.as_ref()?.credentials(&account.name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{self, fs};
+    use super::super::super::password;
+    use super::super::sasl::Backend;
+    use super::{AccountError, Accounts, Credentials};
+
+    #[test]
+    fn test_accounts() {
+        let mut accounts = Accounts::new(None);
+        assert_eq!(accounts.register("Alice", Credentials::new("pw"), None).unwrap().name, "Alice");
+        assert_eq!(
+            accounts.register("alice", Credentials::new("pw"), None).unwrap_err(),
+            AccountError::NickRegistered
+        );
+        let verify = |accounts: &Accounts, name, pw| {
+            accounts.get(name).map_or(false, |a| password::verify(pw, &a.password))
+        };
+        assert_eq!(accounts.get("ALICE").unwrap().name, "Alice");
+        assert!(verify(&accounts, "alice", "pw"));
+        assert!(!verify(&accounts, "alice", "wrong"));
+        assert_eq!(
+            accounts.set_email("bob", None).unwrap_err(),
+            AccountError::NoSuchAccount
+        );
+
+        accounts.group("alice", "Alice_").unwrap();
+        assert_eq!(accounts.owner("alice_").unwrap().name, "Alice");
+        assert_eq!(
+            accounts.register("alice_", Credentials::new("pw"), None).unwrap_err(),
+            AccountError::NickRegistered
+        );
+
+        accounts.set_password("alice", Credentials::new("new")).unwrap();
+        assert!(!verify(&accounts, "alice", "pw"));
+        assert!(verify(&accounts, "alice", "new"));
+        // SCRAM keys are replaced along with the password.
+        let scram = accounts.scram_credentials("alice").unwrap();
+        accounts.set_password("alice", Credentials::new("new")).unwrap();
+        assert!(accounts.scram_credentials("alice").unwrap().stored_key != scram.stored_key);
+
+        let snapshot = accounts.snapshot();
+        accounts.remove("alice").unwrap();
+        assert!(accounts.owner("alice_").is_none());
+        accounts.restore(snapshot);
+        assert!(accounts.get("Alice").is_some());
+    }
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join(format!("irc_server.accounts.{}", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let mut accounts = Accounts::load(Some(path.clone())).unwrap();
+        accounts.register("alice", Credentials::new("pw"), None).unwrap();
+        let loaded = Accounts::load(Some(path.clone())).unwrap();
+        assert_eq!(loaded.snapshot(), accounts.snapshot());
+
+        // Rather than starting out empty, and overwriting the file on the next change.
+        fs::write(&path, "- name: [").unwrap();
+        assert!(Accounts::load(Some(path.clone())).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
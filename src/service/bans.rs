@@ -0,0 +1,269 @@
+use chrono;
+use std::{self, fmt};
+use std::net::IpAddr;
+use super::mask::{self, Cidr};
+use super::persist;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum BanKind {
+    // user@host ban on this server.
+    K,
+    // user@host ban meant to be network wide.
+    G,
+    // IP/CIDR ban, enforced before registration.
+    D,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Ban {
+    pub kind: BanKind,
+    // user@host for K/G-lines, IP or CIDR for D-lines.
+    pub mask: String,
+    pub reason: String,
+    pub set_by: String,
+    // Unix timestamps.
+    pub set_at: i64,
+    pub expires: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum BanError {
+    InvalidMask,
+}
+
+// Server bans, persisted to path (if any) on every change.
+#[derive(Debug)]
+pub struct Bans {
+    bans: Vec<Ban>,
+    path: Option<String>,
+}
+
+impl fmt::Display for BanKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{}",
+            match self {
+                &BanKind::K => "K",
+                &BanKind::G => "G",
+                &BanKind::D => "D",
+            }
+        )
+    }
+}
+
+impl Ban {
+    pub fn new(
+        kind: BanKind,
+        mask: &String,
+        reason: String,
+        set_by: String,
+        duration: Option<chrono::Duration>,
+    ) -> Result<Self, BanError> {
+        let mask = match kind {
+            BanKind::D => {
+                mask.parse::<Cidr>().map_err(|_| BanError::InvalidMask)?;
+                mask.clone()
+            }
+            // A bare host bans every user on it.
+            _ if !mask.contains('@') => format!("*@{}", mask),
+            _ => mask.clone(),
+        };
+        if mask.len() == 0 || mask.contains(' ') {
+            return Err(BanError::InvalidMask);
+        }
+        let now = chrono::offset::Utc::now();
+        Ok(Ban {
+            kind,
+            mask,
+            reason,
+            set_by,
+            set_at: now.timestamp(),
+            expires: duration.map(|d| (now + d).timestamp()),
+        })
+    }
+
+    pub fn expired(&self, now: i64) -> bool {
+        self.expires.map_or(false, |e| e <= now)
+    }
+
+    fn matches_user(&self, username: &String, hostname: &String, ip: &IpAddr) -> bool {
+        match self.kind {
+            BanKind::K | BanKind::G => {
+                mask::matches(&self.mask, &format!("{}@{}", username, hostname)) ||
+                    mask::matches(&self.mask, &format!("{}@{}", username, ip))
+            }
+            BanKind::D => self.matches_ip(ip),
+        }
+    }
+
+    fn matches_ip(&self, ip: &IpAddr) -> bool {
+        match self.kind {
+            BanKind::D => self.mask.parse::<Cidr>().map_or(false, |c| c.contains(ip)),
+            _ => false,
+        }
+    }
+
+    // The reason shown to the banned user.
+    pub fn describe(&self) -> String {
+        match self.expires {
+            Some(e) => {
+                format!(
+                    "{}-lined: {} (expires {})",
+                    self.kind,
+                    self.reason,
+                    chrono::NaiveDateTime::from_timestamp(e, 0)
+                )
+            }
+            None => format!("{}-lined: {}", self.kind, self.reason),
+        }
+    }
+}
+
+impl Bans {
+    pub fn new(path: Option<String>) -> Self {
+        Bans {
+            bans: Vec::new(),
+            path: path,
+        }
+    }
+
+    // Loads bans from path. A missing file is an empty ban list.
+    pub fn load(path: Option<String>) -> Result<Self, String> {
+        let mut bans = Bans::new(path);
+        if let Some(ref p) = bans.path {
+            bans.bans = persist::load(p)?.unwrap_or_default();
+        }
+        bans.expire();
+        Ok(bans)
+    }
+
+    fn save(&self) {
+        if let Some(ref path) = self.path {
+            if let Err(e) = persist::save(path, &self.bans) {
+                error!("Failed to save bans: {}.", e);
+            }
+        }
+    }
+
+    // Removes expired bans. They are left in the file until the next change saves it, and dropped
+    // again when it is loaded.
+    fn expire(&mut self) {
+        let now = chrono::offset::Utc::now().timestamp();
+        let before = self.bans.len();
+        self.bans.retain(|b| !b.expired(now));
+        if self.bans.len() != before {
+            debug!("Expired {} bans.", before - self.bans.len());
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<Ban> {
+        self.bans.clone()
+    }
+
+    // Replaces the loaded bans with ones handed over by a hot restart.
+    pub fn restore(&mut self, bans: Vec<Ban>) {
+        self.bans = bans;
+        self.expire();
+    }
+
+    // Adds ban, replacing any existing ban of the same kind and mask.
+    pub fn add(&mut self, ban: Ban) {
+        self.bans.retain(|b| !(b.kind == ban.kind && b.mask == ban.mask));
+        self.bans.push(ban);
+        self.save();
+    }
+
+    // Returns whether a ban was removed.
+    pub fn remove(&mut self, kind: &BanKind, mask: &String) -> bool {
+        let before = self.bans.len();
+        self.bans.retain(|b| {
+            !(&b.kind == kind && (&b.mask == mask || b.mask == format!("*@{}", mask)))
+        });
+        let removed = self.bans.len() != before;
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    // Finds a ban applying to a registering (or registered) user.
+    pub fn find_user(
+        &mut self,
+        username: &String,
+        hostname: &String,
+        ip: &IpAddr,
+    ) -> Option<&Ban> {
+        self.expire();
+        self.bans.iter().find(
+            |b| b.matches_user(username, hostname, ip),
+        )
+    }
+
+    // Finds a D-line applying to ip.
+    pub fn find_ip(&mut self, ip: &IpAddr) -> Option<&Ban> {
+        self.expire();
+        self.bans.iter().find(|b| b.matches_ip(ip))
+    }
+
+    pub fn iter<'a>(&'a mut self) -> std::slice::Iter<'a, Ban> {
+        self.expire();
+        self.bans.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono;
+    use super::{Ban, BanKind, Bans};
+
+    #[test]
+    fn test_bans() {
+        let mut bans = Bans::new(None);
+        let ip = "10.0.0.5".parse().unwrap();
+        let add = |bans: &mut Bans, kind, mask: &str, duration| {
+            bans.add(
+                Ban::new(
+                    kind,
+                    &mask.to_string(),
+                    "reason".to_string(),
+                    "oper".to_string(),
+                    duration,
+                ).unwrap(),
+            )
+        };
+
+        add(&mut bans, BanKind::K, "evil@*", None);
+        assert!(
+            bans.find_user(&"evil".to_string(), &"host".to_string(), &ip)
+                .is_some()
+        );
+        assert!(
+            bans.find_user(&"good".to_string(), &"host".to_string(), &ip)
+                .is_none()
+        );
+        assert!(bans.find_ip(&ip).is_none());
+
+        add(&mut bans, BanKind::D, "10.0.0.0/8", None);
+        assert!(bans.find_ip(&ip).is_some());
+        assert!(bans.remove(&BanKind::D, &"10.0.0.0/8".to_string()));
+        assert!(bans.find_ip(&ip).is_none());
+
+        add(&mut bans, BanKind::K, "host.example.com", Some(chrono::Duration::seconds(-1)));
+        assert!(
+            bans.find_user(&"any".to_string(), &"host.example.com".to_string(), &ip)
+                .is_none()
+        );
+        assert_eq!(bans.iter().count(), 1);
+
+        assert!(
+            Ban::new(
+                BanKind::D,
+                &"not.an.ip".to_string(),
+                "".to_string(),
+                "".to_string(),
+                None,
+            ).is_err()
+        );
+    }
+}
@@ -1,28 +1,335 @@
-use futures::*;
 use serde::ser::{self, SerializeSeq};
-use std;
-use std::collections::{HashMap, HashSet};
+use std::{self, fmt, str};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::cell::RefCell;
-use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use super::connection::{ConnectionTX, Event};
 use super::messages::Message as IRCMessage;
 use super::messages::commands::{Command, requests as Requests};
-use super::shared_state::SharedState;
-use super::user::Identifier as UserIdentifier;
+use super::user::{Identifier as UserIdentifier, SetMode};
 
-#[derive(Clone, Debug, Serialize, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Identifier {
     name: String,
 }
 
+// Channel modes without a parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ChannelMode {
+    // i: Only invited users may join.
+    InviteOnly,
+    // m: Only voiced users and channel operators may speak.
+    Moderated,
+    // n: Only members may send messages to the channel.
+    NoExternalMessages,
+    // r: Only users identified to an account may speak.
+    RegisteredOnly,
+    // t: Only channel operators may change the topic.
+    ProtectedTopic,
+}
+
+// Most masks each list mode holds, advertised as MAXLIST.
+pub const MAX_LIST_LENGTH: usize = 100;
+
+// Channel modes holding a list of nick!user@host masks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ListMode {
+    // b: May neither join nor speak.
+    Ban,
+    // q: May join, but not speak.
+    Quiet,
+}
+
+// j: At most joins per seconds, as in +j 5:10.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JoinThrottle {
+    pub joins: usize,
+    pub seconds: u64,
+}
+
+// Statuses of channel members, shown as a prefix to their nick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MemberStatus {
+    // o: May change the channel's modes and topic.
+    Operator,
+    // v: Voiced.
+    Voice,
+}
+
+// A change to a channel's modes, as in MODE #channel +tk key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModeChange {
+    Flag(SetMode, ChannelMode),
+    // The key is ignored when removing it.
+    Key(SetMode, String),
+    // l: Members past which joins are refused. The limit is ignored when removing it.
+    Limit(SetMode, usize),
+    Throttle(SetMode, JoinThrottle),
+    // f: Where users refused by +i or +l are sent instead. Ignored when removing it.
+    Forward(SetMode, String),
+    // Of the member with the nick.
+    Status(SetMode, MemberStatus, String),
+    List(SetMode, ListMode, String),
+}
+
 #[derive(Debug)]
 pub struct Channel {
     ident: Identifier,
     topic: Option<String>,
     users: HashMap<UserIdentifier, ConnectionTX>,
-    banned: HashSet<UserIdentifier>,
+    bans: Vec<String>,
+    quiets: Vec<String>,
     key: Option<String>,
-    shared_state: Arc<SharedState>,
+    // Members past which joins are refused.
+    limit: Option<usize>,
+    join_throttle: Option<JoinThrottle>,
+    // When recent joins happened, oldest first, for join_throttle.
+    joins: VecDeque<Instant>,
+    forward: Option<String>,
+    // Users invited since they last joined, who may join past +i and +l.
+    invited: HashSet<UserIdentifier>,
+    modes: HashSet<ChannelMode>,
+    operators: HashSet<UserIdentifier>,
+    voiced: HashSet<UserIdentifier>,
+    // Kept once its last member leaves.
+    permanent: bool,
+}
+
+// Channel, preserved across hot restarts.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    ident: Identifier,
+    topic: Option<String>,
+    users: Vec<UserIdentifier>,
+    // Banned users, from snapshots taken before bans were masks. They matched by nick alone.
+    #[serde(default, skip_serializing)]
+    banned: Vec<UserIdentifier>,
+    #[serde(default)]
+    bans: Vec<String>,
+    #[serde(default)]
+    quiets: Vec<String>,
+    key: Option<String>,
+    #[serde(default)]
+    modes: Vec<ChannelMode>,
+    #[serde(default)]
+    operators: Vec<UserIdentifier>,
+    #[serde(default)]
+    voiced: Vec<UserIdentifier>,
+    #[serde(default)]
+    limit: Option<usize>,
+    #[serde(default)]
+    permanent: bool,
+    #[serde(default)]
+    join_throttle: Option<JoinThrottle>,
+    #[serde(default)]
+    forward: Option<String>,
+    #[serde(default)]
+    invited: Vec<UserIdentifier>,
+}
+
+impl ChannelMode {
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'i' => Some(ChannelMode::InviteOnly),
+            'm' => Some(ChannelMode::Moderated),
+            'n' => Some(ChannelMode::NoExternalMessages),
+            'r' => Some(ChannelMode::RegisteredOnly),
+            't' => Some(ChannelMode::ProtectedTopic),
+            _ => None,
+        }
+    }
+
+    pub fn to_char(&self) -> char {
+        match *self {
+            ChannelMode::InviteOnly => 'i',
+            ChannelMode::Moderated => 'm',
+            ChannelMode::NoExternalMessages => 'n',
+            ChannelMode::RegisteredOnly => 'r',
+            ChannelMode::ProtectedTopic => 't',
+        }
+    }
+}
+
+impl ListMode {
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'b' => Some(ListMode::Ban),
+            'q' => Some(ListMode::Quiet),
+            _ => None,
+        }
+    }
+
+    pub fn to_char(&self) -> char {
+        match *self {
+            ListMode::Ban => 'b',
+            ListMode::Quiet => 'q',
+        }
+    }
+}
+
+// Completes a ban mask, so "nick" becomes "nick!*@*" and "user@host" becomes "*!user@host".
+pub fn normalize_mask(mask: &str) -> String {
+    match (mask.contains('!'), mask.contains('@')) {
+        (false, false) => format!("{}!*@*", mask),
+        (false, true) => format!("*!{}", mask),
+        (true, false) => format!("{}@*", mask),
+        (true, true) => mask.to_string(),
+    }
+}
+
+impl str::FromStr for JoinThrottle {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let joins = parts.next().and_then(|j| j.parse().ok()).ok_or(())?;
+        let seconds = parts.next().and_then(|t| t.parse().ok()).ok_or(())?;
+        if joins == 0 || seconds == 0 {
+            return Err(());
+        }
+        Ok(JoinThrottle {
+            joins: joins,
+            seconds: seconds,
+        })
+    }
+}
+
+impl fmt::Display for JoinThrottle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}:{}", self.joins, self.seconds)
+    }
+}
+
+impl MemberStatus {
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'o' => Some(MemberStatus::Operator),
+            'v' => Some(MemberStatus::Voice),
+            _ => None,
+        }
+    }
+
+    pub fn to_char(&self) -> char {
+        match *self {
+            MemberStatus::Operator => 'o',
+            MemberStatus::Voice => 'v',
+        }
+    }
+}
+
+// Parses the modes and arguments of MODE #channel. Changes missing their argument, or with an
+// invalid one, are skipped. Returns the first unknown mode letter on error.
+pub fn parse_modes(modes: &str, args: &[String]) -> Result<Vec<ModeChange>, char> {
+    let mut args = args.iter();
+    let mut set = SetMode::Add;
+    let mut changes = Vec::new();
+    for c in modes.chars() {
+        match c {
+            '+' => set = SetMode::Add,
+            '-' => set = SetMode::Remove,
+            'k' => {
+                match (args.next(), &set) {
+                    (Some(k), _) => changes.push(ModeChange::Key(set.clone(), k.clone())),
+                    (None, &SetMode::Remove) => {
+                        changes.push(ModeChange::Key(SetMode::Remove, "*".to_string()))
+                    }
+                    (None, &SetMode::Add) => {}
+                }
+            }
+            'l' | 'j' | 'f' if set == SetMode::Remove => {
+                let throttle = JoinThrottle {
+                    joins: 0,
+                    seconds: 0,
+                };
+                changes.push(match c {
+                    'l' => ModeChange::Limit(SetMode::Remove, 0),
+                    'j' => ModeChange::Throttle(SetMode::Remove, throttle),
+                    _ => ModeChange::Forward(SetMode::Remove, String::new()),
+                })
+            }
+            'l' => {
+                if let Some(limit) = args.next().and_then(|a| a.parse().ok()) {
+                    changes.push(ModeChange::Limit(SetMode::Add, limit));
+                }
+            }
+            'j' => {
+                if let Some(throttle) = args.next().and_then(|a| a.parse().ok()) {
+                    changes.push(ModeChange::Throttle(SetMode::Add, throttle));
+                }
+            }
+            'f' => {
+                match args.next() {
+                    Some(f) if f.starts_with('#') => {
+                        changes.push(ModeChange::Forward(SetMode::Add, f.clone()))
+                    }
+                    _ => {}
+                }
+            }
+            c => {
+                if let Some(m) = ChannelMode::from_char(c) {
+                    changes.push(ModeChange::Flag(set.clone(), m));
+                } else if let Some(list) = ListMode::from_char(c) {
+                    if let Some(mask) = args.next() {
+                        changes.push(ModeChange::List(set.clone(), list, normalize_mask(mask)));
+                    }
+                } else if let Some(status) = MemberStatus::from_char(c) {
+                    if let Some(nick) = args.next() {
+                        changes.push(ModeChange::Status(set.clone(), status, nick.clone()));
+                    }
+                } else {
+                    return Err(c);
+                }
+            }
+        }
+    }
+    Ok(changes)
+}
+
+// Formats changes as a mode string, e.g. "+t-o", and its arguments.
+pub fn format_modes(changes: &[ModeChange]) -> (String, Vec<String>) {
+    let mut modes = String::new();
+    let mut args = Vec::new();
+    let mut current = None;
+    for change in changes {
+        let (set, c) = match *change {
+            ModeChange::Flag(ref set, m) => (set, m.to_char()),
+            ModeChange::Key(ref set, ref key) => {
+                args.push(key.clone());
+                (set, 'k')
+            }
+            ModeChange::Limit(ref set, limit) => {
+                if *set == SetMode::Add {
+                    args.push(limit.to_string());
+                }
+                (set, 'l')
+            }
+            ModeChange::Throttle(ref set, throttle) => {
+                if *set == SetMode::Add {
+                    args.push(throttle.to_string());
+                }
+                (set, 'j')
+            }
+            ModeChange::Forward(ref set, ref forward) => {
+                if *set == SetMode::Add {
+                    args.push(forward.clone());
+                }
+                (set, 'f')
+            }
+            ModeChange::Status(ref set, status, ref nick) => {
+                args.push(nick.clone());
+                (set, status.to_char())
+            }
+            ModeChange::List(ref set, list, ref mask) => {
+                args.push(mask.clone());
+                (set, list.to_char())
+            }
+        };
+        if current != Some(set.clone()) {
+            modes.push(if *set == SetMode::Add { '+' } else { '-' });
+            current = Some(set.clone());
+        }
+        modes.push(c);
+    }
+    (modes, args)
 }
 
 impl Identifier {
@@ -51,31 +358,98 @@ impl std::hash::Hash for Channel {
     }
 }
 
-macro_rules! send_log_err {
-    ($tx:expr, $message:expr) => {
-        match $tx.try_send($message) {
-            Err(e) => debug!("Send error: {:?}.", e),
-            _ =>{},
-        };
-    }
-}
-
 #[derive(Debug)]
 pub enum ChannelError {
     BadKey,
     Banned,
+    // The limit is reached.
+    Full,
+    // +i, and not invited.
+    InviteOnly,
+    // Too many joins lately, see JoinThrottle.
+    Throttled,
+    // The user is in as many channels as allowed.
+    TooManyChannels,
     AlreadyMember,
 }
 
 impl Channel {
-    pub fn new(ident: Identifier, shared_state: Arc<SharedState>) -> Self {
+    pub fn new(ident: Identifier) -> Self {
         Self {
             ident: ident,
             topic: None,
             users: HashMap::new(),
-            banned: HashSet::new(),
+            bans: Vec::new(),
+            quiets: Vec::new(),
             key: None,
-            shared_state: shared_state,
+            limit: None,
+            join_throttle: None,
+            joins: VecDeque::new(),
+            forward: None,
+            invited: HashSet::new(),
+            modes: HashSet::new(),
+            operators: HashSet::new(),
+            voiced: HashSet::new(),
+            permanent: false,
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            ident: self.ident.clone(),
+            topic: self.topic.clone(),
+            users: self.users.keys().cloned().collect(),
+            banned: Vec::new(),
+            bans: self.bans.clone(),
+            quiets: self.quiets.clone(),
+            key: self.key.clone(),
+            modes: self.modes.iter().cloned().collect(),
+            operators: self.operators.iter().cloned().collect(),
+            voiced: self.voiced.iter().cloned().collect(),
+            limit: self.limit,
+            permanent: self.permanent,
+            join_throttle: self.join_throttle,
+            forward: self.forward.clone(),
+            invited: self.invited.iter().cloned().collect(),
+        }
+    }
+
+    // Restores a channel, with members that lookup finds a connection for.
+    pub fn restore<F>(snapshot: Snapshot, lookup: F) -> Self
+    where
+        F: Fn(&UserIdentifier) -> Option<ConnectionTX>,
+    {
+        let users: HashMap<UserIdentifier, ConnectionTX> = snapshot
+            .users
+            .into_iter()
+            .filter_map(|u| lookup(&u).map(|tx| (u, tx)))
+            .collect();
+        let mut bans = snapshot.bans;
+        bans.extend(snapshot.banned.iter().map(|u| format!("{}!*@*", u.nick())));
+        Self {
+            ident: snapshot.ident,
+            topic: snapshot.topic,
+            bans: bans,
+            quiets: snapshot.quiets,
+            key: snapshot.key,
+            limit: snapshot.limit,
+            join_throttle: snapshot.join_throttle,
+            joins: VecDeque::new(),
+            forward: snapshot.forward,
+            invited: snapshot.invited.into_iter().collect(),
+            modes: snapshot.modes.into_iter().collect(),
+            operators: snapshot
+                .operators
+                .into_iter()
+                .filter(|u| users.contains_key(u))
+                .collect(),
+            voiced: snapshot
+                .voiced
+                .into_iter()
+                .filter(|u| users.contains_key(u))
+                .collect(),
+            users: users,
+            permanent: snapshot.permanent,
         }
     }
 
@@ -91,8 +465,97 @@ impl Channel {
         &self.topic
     }
 
+    // Silently replaces the topic, e.g. with the one ChanServ kept.
+    pub fn restore_topic(&mut self, topic: Option<String>) {
+        self.topic = topic;
+    }
+
     pub fn verify_key(&self, key: &Option<String>) -> bool {
-        &self.key == key
+        self.key.is_none() || &self.key == key
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn forward(&self) -> Option<&String> {
+        self.forward.as_ref()
+    }
+
+    // Lets user join past +i and +l, once.
+    pub fn invite(&mut self, user: &UserIdentifier) {
+        self.invited.insert(user.clone());
+    }
+
+    pub fn is_permanent(&self) -> bool {
+        self.permanent
+    }
+
+    pub fn set_permanent(&mut self, permanent: bool) {
+        self.permanent = permanent;
+    }
+
+    pub fn has_mode(&self, mode: &ChannelMode) -> bool {
+        self.modes.contains(mode)
+    }
+
+    // Sets or unsets mode silently, e.g. when a mode lock is restored.
+    pub fn set_mode(&mut self, set: &SetMode, mode: ChannelMode) {
+        match *set {
+            SetMode::Add => self.modes.insert(mode),
+            SetMode::Remove => self.modes.remove(&mode),
+        };
+    }
+
+    // The channel's modes, e.g. "+tljfk", and their arguments. The key is only shown to members.
+    pub fn mode_string(&self, show_key: bool) -> (String, Vec<String>) {
+        let mut modes: Vec<char> = self.modes.iter().map(|m| m.to_char()).collect();
+        modes.sort();
+        let mut modes: String = modes.into_iter().collect();
+        let mut args = Vec::new();
+        if let Some(limit) = self.limit {
+            modes.push('l');
+            args.push(limit.to_string());
+        }
+        if let Some(throttle) = self.join_throttle {
+            modes.push('j');
+            args.push(throttle.to_string());
+        }
+        if let Some(ref forward) = self.forward {
+            modes.push('f');
+            args.push(forward.clone());
+        }
+        if let Some(ref key) = self.key {
+            modes.push('k');
+            args.push(if show_key { key.clone() } else { "*".to_string() });
+        }
+        (format!("+{}", modes), args)
+    }
+
+    pub fn has_status(&self, user: &UserIdentifier, status: MemberStatus) -> bool {
+        match status {
+            MemberStatus::Operator => self.operators.contains(user),
+            MemberStatus::Voice => self.voiced.contains(user),
+        }
+    }
+
+    // Shown before user's nick in NAMES.
+    pub fn prefix(&self, user: &UserIdentifier) -> &'static str {
+        if self.operators.contains(user) {
+            "@"
+        } else if self.voiced.contains(user) {
+            "+"
+        } else {
+            ""
+        }
+    }
+
+    // Members, with their prefix.
+    pub fn names(&self) -> Vec<(String, UserIdentifier)> {
+        self.users
+            .keys()
+            .map(|u| (self.prefix(u).to_string(), u.clone()))
+            .collect()
     }
 
     pub fn users<'a>(
@@ -101,21 +564,65 @@ impl Channel {
         self.users.keys()
     }
 
+    pub fn members<'a>(
+        &'a self,
+    ) -> std::collections::hash_map::Iter<'a, UserIdentifier, ConnectionTX> {
+        self.users.iter()
+    }
+
+    // Operators and voiced users that aren't members, which there should never be.
+    pub fn stray_members(&self) -> Vec<&UserIdentifier> {
+        self.operators
+            .iter()
+            .chain(self.voiced.iter())
+            .filter(|u| !self.users.contains_key(u))
+            .collect()
+    }
+
     pub fn has_user(&self, user: &UserIdentifier) -> bool {
         self.users.contains_key(user)
     }
 
+    // Silently removes user. Returns whether user was a member.
+    pub fn remove_user(&mut self, user: &UserIdentifier) -> bool {
+        self.invited.remove(user);
+        self.operators.remove(user);
+        self.voiced.remove(user);
+        self.users.remove(user).is_some()
+    }
+
+    // Silently renames user after a nick change. Returns whether user was a member.
+    pub fn rename_user(&mut self, old: &UserIdentifier, new: &UserIdentifier) -> bool {
+        if self.operators.remove(old) {
+            self.operators.insert(new.clone());
+        }
+        if self.voiced.remove(old) {
+            self.voiced.insert(new.clone());
+        }
+        if self.invited.remove(old) {
+            self.invited.insert(new.clone());
+        }
+        match self.users.remove(old) {
+            Some(tx) => {
+                self.users.insert(new.clone(), tx);
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn join(
         &mut self,
         user: &UserIdentifier,
         tx: &ConnectionTX,
         key: &Option<String>,
+        status: Option<MemberStatus>,
     ) -> Result<(), ChannelError> {
         if !self.verify_key(key) {
             return Err(ChannelError::BadKey);
         }
 
-        if self.banned.contains(user) {
+        if self.bans.iter().any(|b| user.matches(b)) {
             return Err(ChannelError::Banned);
         }
 
@@ -123,6 +630,28 @@ impl Channel {
             return Err(ChannelError::AlreadyMember);
         }
 
+        let invited = self.invited.contains(user);
+        if !invited && self.has_mode(&ChannelMode::InviteOnly) {
+            return Err(ChannelError::InviteOnly);
+        }
+
+        if !invited && self.limit.map_or(false, |l| self.users.len() >= l) {
+            return Err(ChannelError::Full);
+        }
+
+        if let Some(throttle) = self.join_throttle {
+            let now = Instant::now();
+            let window = Duration::from_secs(throttle.seconds);
+            while self.joins.front().map_or(false, |&j| now.duration_since(j) >= window) {
+                self.joins.pop_front();
+            }
+            if self.joins.len() >= throttle.joins {
+                return Err(ChannelError::Throttled);
+            }
+            self.joins.push_back(now);
+        }
+        self.invited.remove(user);
+
         self.broadcast(
             None,
             Event::Message(vec![
@@ -135,11 +664,16 @@ impl Channel {
             ]),
         );
         self.users.insert(user.clone(), tx.clone());
+        match status {
+            Some(MemberStatus::Operator) => self.operators.insert(user.clone()),
+            Some(MemberStatus::Voice) => self.voiced.insert(user.clone()),
+            None => false,
+        };
         Ok(())
     }
 
     pub fn part(&mut self, user: &UserIdentifier, message: &Option<String>) {
-        assert!(self.users.remove(user).is_some());
+        assert!(self.remove_user(user));
         self.broadcast(
             None,
             Event::Message(vec![
@@ -154,21 +688,161 @@ impl Channel {
         );
     }
 
-    pub fn privmsg(&mut self, source: &UserIdentifier, message: &String) {
+    pub fn list(&self, list: ListMode) -> &Vec<String> {
+        match list {
+            ListMode::Ban => &self.bans,
+            ListMode::Quiet => &self.quiets,
+        }
+    }
+
+    // Whether user, identified to account if any, may send messages to the channel. Errors are
+    // the reason why not. Bans and quiets apply to everyone. Voiced users and channel operators
+    // are exempt from +m and +r.
+    pub fn can_send(
+        &self,
+        user: &UserIdentifier,
+        account: Option<&String>,
+    ) -> Result<(), &'static str> {
+        if !self.has_user(user) && self.has_mode(&ChannelMode::NoExternalMessages) {
+            return Err("Cannot send to channel (+n) - no external messages");
+        }
+        if self.bans.iter().any(|b| user.matches(b)) {
+            return Err("Cannot send to channel (+b) - you are banned");
+        }
+        if self.quiets.iter().any(|q| user.matches(q)) {
+            return Err("Cannot send to channel (+q) - you are quieted");
+        }
+        if self.operators.contains(user) || self.voiced.contains(user) {
+            return Ok(());
+        }
+        if self.has_mode(&ChannelMode::Moderated) {
+            return Err("Cannot send to channel (+m) - you need voice");
+        }
+        if account.is_none() && self.has_mode(&ChannelMode::RegisteredOnly) {
+            return Err("Cannot send to channel (+r) - you need to identify to an account");
+        }
+        Ok(())
+    }
+
+    // Sends message, a PRIVMSG or NOTICE from source, to every other member.
+    pub fn send(&self, source: &UserIdentifier, message: IRCMessage) {
+        self.broadcast(Some(source.clone()), Event::Message(vec![message]));
+    }
+
+    // Sets the topic on behalf of source, a prefix, telling every member. An empty topic unsets it.
+    pub fn set_topic(&mut self, source: String, topic: String) {
+        self.topic = if topic.is_empty() {
+            None
+        } else {
+            Some(topic.clone())
+        };
         self.broadcast(
-            Some(source.clone()),
+            None,
             Event::Message(vec![
                 IRCMessage {
-                    prefix: Some(source.as_prefix()),
-                    command: Command::PRIVMSG(Requests::Privmsg {
-                        targets: vec![self.name().clone()],
-                        message: message.clone(),
+                    prefix: Some(source),
+                    command: Command::TOPIC(Requests::Topic {
+                        channel: self.name().clone(),
+                        topic: Some(topic),
                     }),
                 },
             ]),
         );
     }
 
+    // Applies changes on behalf of source, a prefix, telling every member about those that changed
+    // anything. Status changes of non-members and masks added to full lists are ignored. Returns
+    // the changes applied.
+    pub fn apply_modes(&mut self, source: String, changes: Vec<ModeChange>) -> Vec<ModeChange> {
+        let mut applied = Vec::new();
+        for change in changes {
+            let changed = match change {
+                ModeChange::Flag(ref set, mode) => {
+                    match *set {
+                        SetMode::Add => self.modes.insert(mode),
+                        SetMode::Remove => self.modes.remove(&mode),
+                    }
+                }
+                ModeChange::Key(SetMode::Add, ref key) => {
+                    let changed = self.key.as_ref() != Some(key);
+                    self.key = Some(key.clone());
+                    changed
+                }
+                ModeChange::Key(SetMode::Remove, _) => self.key.take().is_some(),
+                ModeChange::Limit(SetMode::Add, limit) => {
+                    self.limit.replace(limit) != Some(limit)
+                }
+                ModeChange::Limit(SetMode::Remove, _) => self.limit.take().is_some(),
+                ModeChange::Throttle(SetMode::Add, throttle) => {
+                    self.joins.clear();
+                    self.join_throttle.replace(throttle) != Some(throttle)
+                }
+                ModeChange::Throttle(SetMode::Remove, _) => self.join_throttle.take().is_some(),
+                ModeChange::Forward(SetMode::Add, ref forward) => {
+                    self.forward.replace(forward.clone()).as_ref() != Some(forward)
+                }
+                ModeChange::Forward(SetMode::Remove, _) => self.forward.take().is_some(),
+                ModeChange::List(ref set, list, ref mask) => {
+                    let masks = match list {
+                        ListMode::Ban => &mut self.bans,
+                        ListMode::Quiet => &mut self.quiets,
+                    };
+                    let listed = masks.iter().position(|m| m.eq_ignore_ascii_case(mask));
+                    match (set, listed) {
+                        (&SetMode::Add, None) if masks.len() < MAX_LIST_LENGTH => {
+                            masks.push(mask.clone());
+                            true
+                        }
+                        (&SetMode::Remove, Some(i)) => {
+                            masks.remove(i);
+                            true
+                        }
+                        _ => false,
+                    }
+                }
+                ModeChange::Status(ref set, status, ref nick) => {
+                    let user = match self.users.get_key_value(&UserIdentifier::from_nick(nick)) {
+                        Some((u, _)) => u.clone(),
+                        None => continue,
+                    };
+                    let members = match status {
+                        MemberStatus::Operator => &mut self.operators,
+                        MemberStatus::Voice => &mut self.voiced,
+                    };
+                    match *set {
+                        SetMode::Add => members.insert(user),
+                        SetMode::Remove => members.remove(&user),
+                    }
+                }
+            };
+            if changed {
+                applied.push(change);
+            }
+        }
+        if applied.is_empty() {
+            return applied;
+        }
+        let (modes, args) = format_modes(&applied);
+        self.broadcast(
+            None,
+            Event::Message(vec![
+                IRCMessage {
+                    prefix: Some(source),
+                    command: Command::MODE(Requests::Mode {
+                        target: self.name().clone(),
+                        mode_string: Some(modes),
+                        mode_args: if args.is_empty() {
+                            None
+                        } else {
+                            Some(args.join(" "))
+                        },
+                    }),
+                },
+            ]),
+        );
+        applied
+    }
+
     fn broadcast(&self, skip_user: Option<UserIdentifier>, message: Event) {
         self.users
             .iter()
@@ -177,15 +851,71 @@ impl Channel {
             } else {
                 true
             })
-            .for_each(|(_, tx)| {
-                let tx = tx.clone();
-                let message = message.clone();
-                self.shared_state
-                    .thread_pool
-                    .spawn_fn(move || tx.send(message))
-                    .forget()
-            });
+            .for_each(|(_, tx)| tx.send(message.clone()));
     }
 
     //pub fn broadcast(&self,
 }
+
+#[cfg(test)]
+mod test {
+    use super::super::user::SetMode;
+    use super::{format_modes, parse_modes, ChannelMode, JoinThrottle, ListMode, MemberStatus,
+                ModeChange};
+
+    #[test]
+    fn test_parse_modes() {
+        let args = vec!["key".to_string(), "alice".to_string()];
+        let changes = parse_modes("+tk-o", &args).unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                ModeChange::Flag(SetMode::Add, ChannelMode::ProtectedTopic),
+                ModeChange::Key(SetMode::Add, "key".to_string()),
+                ModeChange::Status(SetMode::Remove, MemberStatus::Operator, "alice".to_string()),
+            ]
+        );
+        assert_eq!(
+            format_modes(&changes),
+            ("+tk-o".to_string(), args.clone())
+        );
+        // Missing arguments skip the change.
+        assert_eq!(parse_modes("+v", &[]).unwrap(), vec![]);
+        assert_eq!(
+            parse_modes("-k", &[]).unwrap(),
+            vec![ModeChange::Key(SetMode::Remove, "*".to_string())]
+        );
+        assert_eq!(parse_modes("+tZ", &[]), Err('Z'));
+
+        let args = vec!["10".to_string(), "3:5".to_string(), "#overflow".to_string()];
+        let changes = parse_modes("+ljf-jf", &args).unwrap();
+        assert_eq!(changes[0], ModeChange::Limit(SetMode::Add, 10));
+        assert_eq!(
+            changes[1],
+            ModeChange::Throttle(
+                SetMode::Add,
+                JoinThrottle {
+                    joins: 3,
+                    seconds: 5,
+                },
+            )
+        );
+        assert_eq!(
+            format_modes(&changes),
+            ("+ljf-jf".to_string(), args.clone())
+        );
+        // Invalid arguments skip the change.
+        let args = vec!["ten".to_string(), "3".to_string(), "overflow".to_string()];
+        assert_eq!(parse_modes("+ljf", &args).unwrap(), vec![]);
+
+        // Masks are completed, and list modes without one are queries.
+        let args = vec!["bob".to_string(), "*@spam.example.com".to_string()];
+        assert_eq!(
+            parse_modes("+bq-b", &args).unwrap(),
+            vec![
+                ModeChange::List(SetMode::Add, ListMode::Ban, "bob!*@*".to_string()),
+                ModeChange::List(SetMode::Add, ListMode::Quiet, "*!*@spam.example.com".to_string()),
+            ]
+        );
+    }
+}
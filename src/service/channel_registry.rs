@@ -0,0 +1,369 @@
+use chrono;
+use std::{self, str};
+use std::collections::BTreeMap;
+use super::channel::ChannelMode;
+use super::persist;
+use super::user::{Identifier as UserIdentifier, SetMode};
+
+// Levels of the ChanServ access list, lowest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum AccessLevel {
+    // Kept out of the channel.
+    Akick,
+    // Voiced on join.
+    Voice,
+    // Opped on join, and may change a locked topic.
+    Op,
+    Founder,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AccessEntry {
+    // An account name, or a nick!user@host mask.
+    pub target: String,
+    pub level: AccessLevel,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RegisteredChannel {
+    pub name: String,
+    // Account of the founder.
+    pub founder: String,
+    #[serde(default)]
+    pub access: Vec<AccessEntry>,
+    // Restored whenever the channel is created.
+    pub topic: Option<String>,
+    // Only the founder and ops on the access list may change the topic.
+    #[serde(default)]
+    pub topic_lock: bool,
+    // Modes kept set or unset, e.g. "+t".
+    #[serde(default)]
+    pub mode_lock: Option<String>,
+    // Noticed to users joining.
+    #[serde(default)]
+    pub entry_message: Option<String>,
+    // Unix timestamp.
+    pub registered_at: i64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RegistryError {
+    AlreadyRegistered,
+    NotRegistered,
+}
+
+impl str::FromStr for AccessLevel {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_ref() {
+            "AKICK" => Ok(AccessLevel::Akick),
+            "VOICE" => Ok(AccessLevel::Voice),
+            "OP" => Ok(AccessLevel::Op),
+            _ => Err(()),
+        }
+    }
+}
+
+// Targets with any of these are masks, others account names.
+pub fn is_mask(target: &str) -> bool {
+    target.contains(|c| c == '!' || c == '@' || c == '*' || c == '?')
+}
+
+// Parses a mode lock, e.g. "+t-s", into the modes it keeps set and keeps unset.
+pub fn parse_mode_lock(lock: &str) -> Option<(Vec<ChannelMode>, Vec<ChannelMode>)> {
+    let mut set = SetMode::Add;
+    let (mut on, mut off) = (Vec::new(), Vec::new());
+    for c in lock.chars() {
+        match c {
+            '+' => set = SetMode::Add,
+            '-' => set = SetMode::Remove,
+            c => {
+                let mode = ChannelMode::from_char(c)?;
+                on.retain(|m| m != &mode);
+                off.retain(|m| m != &mode);
+                match set {
+                    SetMode::Add => on.push(mode),
+                    SetMode::Remove => off.push(mode),
+                }
+            }
+        }
+    }
+    Some((on, off))
+}
+
+impl RegisteredChannel {
+    // The access of user, logged in to account if any. The founder outranks the access list, and
+    // any other level outranks an akick.
+    pub fn access(&self, user: &UserIdentifier, account: Option<&String>) -> Option<AccessLevel> {
+        if account.map_or(false, |a| a.eq_ignore_ascii_case(&self.founder)) {
+            return Some(AccessLevel::Founder);
+        }
+        // Masks match the real host as well as the displayed one.
+        self.access
+            .iter()
+            .filter(|e| if is_mask(&e.target) {
+                user.matches(&e.target)
+            } else {
+                account.map_or(false, |a| a.eq_ignore_ascii_case(&e.target))
+            })
+            .map(|e| e.level)
+            .max_by_key(|&l| if l == AccessLevel::Akick { None } else { Some(l) })
+    }
+
+    // The modes the mode lock keeps set, and keeps unset.
+    pub fn locked_modes(&self) -> (Vec<ChannelMode>, Vec<ChannelMode>) {
+        self.mode_lock
+            .as_ref()
+            .and_then(|l| parse_mode_lock(l))
+            .unwrap_or_default()
+    }
+}
+
+// Channels registered with ChanServ, keyed by lowercased name, persisted to path (if any) on
+// every change.
+#[derive(Debug)]
+pub struct ChannelRegistry {
+    channels: BTreeMap<String, RegisteredChannel>,
+    path: Option<String>,
+}
+
+impl ChannelRegistry {
+    pub fn new(path: Option<String>) -> Self {
+        ChannelRegistry {
+            channels: BTreeMap::new(),
+            path: path,
+        }
+    }
+
+    // Loads registrations from path. A missing file has none.
+    pub fn load(path: Option<String>) -> Result<Self, String> {
+        let mut registry = ChannelRegistry::new(path);
+        if let Some(ref p) = registry.path.clone() {
+            registry.restore(persist::load(p)?.unwrap_or_default());
+        }
+        Ok(registry)
+    }
+
+    fn save(&self) {
+        if let Some(ref path) = self.path {
+            if let Err(e) = persist::save(path, &self.snapshot()) {
+                error!("Failed to save registered channels: {}.", e);
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<RegisteredChannel> {
+        self.channels.values().cloned().collect()
+    }
+
+    // Replaces the registrations with ones read from the file, or handed over by a hot restart.
+    pub fn restore(&mut self, channels: Vec<RegisteredChannel>) {
+        self.channels = channels
+            .into_iter()
+            .map(|c| (c.name.to_lowercase(), c))
+            .collect();
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RegisteredChannel> {
+        self.channels.get(&name.to_lowercase())
+    }
+
+    pub fn iter<'a>(
+        &'a self,
+    ) -> std::collections::btree_map::Values<'a, String, RegisteredChannel> {
+        self.channels.values()
+    }
+
+    // Names of the channels account founded.
+    pub fn founded_by(&self, account: &str) -> Vec<String> {
+        self.channels
+            .values()
+            .filter(|c| c.founder.eq_ignore_ascii_case(account))
+            .map(|c| c.name.clone())
+            .collect()
+    }
+
+    // Takes account off every access list, so that whoever registers its name next gains none of
+    // its access.
+    pub fn remove_account(&mut self, account: &str) {
+        let mut changed = false;
+        for c in self.channels.values_mut() {
+            let before = c.access.len();
+            c.access.retain(
+                |e| is_mask(&e.target) || !e.target.eq_ignore_ascii_case(account),
+            );
+            changed |= c.access.len() != before;
+        }
+        if changed {
+            self.save();
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        name: &str,
+        founder: &str,
+        topic: Option<String>,
+    ) -> Result<(), RegistryError> {
+        let key = name.to_lowercase();
+        if self.channels.contains_key(&key) {
+            return Err(RegistryError::AlreadyRegistered);
+        }
+        self.channels.insert(
+            key,
+            RegisteredChannel {
+                name: name.to_string(),
+                founder: founder.to_string(),
+                access: Vec::new(),
+                topic: topic,
+                topic_lock: false,
+                mode_lock: None,
+                entry_message: None,
+                registered_at: chrono::offset::Utc::now().timestamp(),
+            },
+        );
+        self.save();
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<RegisteredChannel, RegistryError> {
+        let dropped = self.channels.remove(&name.to_lowercase()).ok_or(
+            RegistryError::NotRegistered,
+        )?;
+        self.save();
+        Ok(dropped)
+    }
+
+    // Sets the access level of target, replacing any it had, or removes it from the access list.
+    pub fn set_access(
+        &mut self,
+        name: &str,
+        target: &str,
+        level: Option<AccessLevel>,
+    ) -> Result<(), RegistryError> {
+        self.modify(name, |c| {
+            c.access.retain(|e| !e.target.eq_ignore_ascii_case(target));
+            if let Some(level) = level {
+                c.access.push(AccessEntry {
+                    target: target.to_string(),
+                    level: level,
+                });
+            }
+        })
+    }
+
+    pub fn set_topic(&mut self, name: &str, topic: Option<String>) -> Result<(), RegistryError> {
+        self.modify(name, |c| c.topic = topic)
+    }
+
+    pub fn set_topic_lock(&mut self, name: &str, lock: bool) -> Result<(), RegistryError> {
+        self.modify(name, |c| c.topic_lock = lock)
+    }
+
+    pub fn set_mode_lock(&mut self, name: &str, lock: Option<String>) -> Result<(), RegistryError> {
+        self.modify(name, |c| c.mode_lock = lock)
+    }
+
+    pub fn set_entry_message(
+        &mut self,
+        name: &str,
+        message: Option<String>,
+    ) -> Result<(), RegistryError> {
+        self.modify(name, |c| c.entry_message = message)
+    }
+
+    fn modify<F>(&mut self, name: &str, f: F) -> Result<(), RegistryError>
+    where
+        F: FnOnce(&mut RegisteredChannel),
+    {
+        match self.channels.get_mut(&name.to_lowercase()) {
+            Some(c) => f(c),
+            None => return Err(RegistryError::NotRegistered),
+        }
+        self.save();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{self, fs};
+    use super::super::channel::ChannelMode;
+    use super::super::user::Identifier as UserIdentifier;
+    use super::{parse_mode_lock, AccessLevel, ChannelRegistry, RegistryError};
+
+    #[test]
+    fn test_registry() {
+        let mut registry = ChannelRegistry::new(None);
+        registry.register("#Rust", "alice", None).unwrap();
+        assert_eq!(
+            registry.register("#rust", "bob", None),
+            Err(RegistryError::AlreadyRegistered)
+        );
+        registry.set_access("#rust", "bob", Some(AccessLevel::Op)).unwrap();
+        registry.set_access("#rust", "*!*@spam.example.com", Some(AccessLevel::Akick)).unwrap();
+        registry.set_access("#rust", "*!carol@*", Some(AccessLevel::Voice)).unwrap();
+
+        let channel = registry.get("#RUST").unwrap();
+        let user = |nick: &str, host: &str| {
+            UserIdentifier::new(
+                nick.to_string(),
+                nick.to_lowercase(),
+                "real".to_string(),
+                host.to_string(),
+            )
+        };
+        let alice = "Alice".to_string();
+        let bob = "bob".to_string();
+        assert_eq!(
+            channel.access(&user("x", "h"), Some(&alice)),
+            Some(AccessLevel::Founder)
+        );
+        assert_eq!(channel.access(&user("x", "h"), Some(&bob)), Some(AccessLevel::Op));
+        assert_eq!(channel.access(&user("bob", "h"), None), None);
+        assert_eq!(
+            channel.access(&user("x", "spam.example.com"), None),
+            Some(AccessLevel::Akick)
+        );
+        // Any other level outranks an akick.
+        assert_eq!(
+            channel.access(&user("carol", "spam.example.com"), None),
+            Some(AccessLevel::Voice)
+        );
+
+        registry.set_access("#rust", "BOB", None).unwrap();
+        assert_eq!(registry.get("#rust").unwrap().access.len(), 2);
+        assert_eq!(registry.remove("#rust").unwrap().name, "#Rust");
+        assert_eq!(
+            registry.set_topic("#rust", None),
+            Err(RegistryError::NotRegistered)
+        );
+    }
+
+    #[test]
+    fn test_parse_mode_lock() {
+        assert_eq!(
+            parse_mode_lock("+t"),
+            Some((vec![ChannelMode::ProtectedTopic], vec![]))
+        );
+        assert_eq!(
+            parse_mode_lock("+t-t"),
+            Some((vec![], vec![ChannelMode::ProtectedTopic]))
+        );
+        assert_eq!(parse_mode_lock("+Z"), None);
+    }
+
+    #[test]
+    fn test_load() {
+        let path = std::env::temp_dir().join(format!("irc_server.channels.{}", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let mut registry = ChannelRegistry::load(Some(path.clone())).unwrap();
+        registry.register("#rust", "alice", None).unwrap();
+        let loaded = ChannelRegistry::load(Some(path.clone())).unwrap();
+        assert_eq!(loaded.snapshot(), registry.snapshot());
+
+        fs::write(&path, "- name: [").unwrap();
+        assert!(ChannelRegistry::load(Some(path.clone())).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+// The ChanServ pseudo-client, which users PRIVMSG to register and manage channels.
+use super::channel_registry::AccessLevel;
+
+pub static NICK: &'static str = "ChanServ";
+
+// ChanServ's prefix on the server named hostname.
+pub fn prefix(hostname: &str) -> String {
+    format!("{}!{}@{}", NICK, NICK, hostname)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Request {
+    // Registers channel to the account logged in to. Channel operators only.
+    Register { channel: String },
+    // Unregisters channel. Founder only.
+    Drop { channel: String },
+    Info { channel: String },
+    AccessList { channel: String },
+    // Gives target, an account or a mask, level in channel, or takes it away.
+    AccessAdd {
+        channel: String,
+        target: String,
+        level: AccessLevel,
+    },
+    AccessDel { channel: String, target: String },
+    SetTopicLock { channel: String, lock: bool },
+    SetModeLock {
+        channel: String,
+        lock: Option<String>,
+    },
+    SetEntryMessage {
+        channel: String,
+        message: Option<String>,
+    },
+    Help,
+}
+
+static HELP: &'static [&'static str] = &[
+    "REGISTER <#channel>",
+    "DROP <#channel>",
+    "INFO <#channel>",
+    "ACCESS <#channel> LIST",
+    "ACCESS <#channel> ADD <account|mask> <OP|VOICE|AKICK>",
+    "ACCESS <#channel> DEL <account|mask>",
+    "SET <#channel> TOPICLOCK <ON|OFF>",
+    "SET <#channel> MLOCK [modes]",
+    "SET <#channel> ENTRYMSG [message]",
+];
+
+pub fn help() -> Vec<String> {
+    let mut lines = vec![format!("{} commands:", NICK)];
+    lines.extend(HELP.iter().map(|h| format!("  {}", h)));
+    lines
+}
+
+// Parses text sent to ChanServ. Errors are the usage to show.
+pub fn parse(text: &str) -> Result<Request, String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let command = match words.first() {
+        Some(c) => c.to_uppercase(),
+        None => return Ok(Request::Help),
+    };
+    let usage = |command: &str| {
+        let help = HELP.iter().find(|h| h.starts_with(command)).unwrap();
+        Err(format!("Syntax: {}", help))
+    };
+    if command == "HELP" {
+        return Ok(Request::Help);
+    }
+    let channel = match words.get(1) {
+        Some(c) if c.starts_with('#') => c.to_string(),
+        _ if HELP.iter().any(|h| h.starts_with(&format!("{} ", command))) => {
+            return usage(&command)
+        }
+        _ => return Err(format!("Unknown command {}. Try HELP.", command)),
+    };
+    let args = &words[2..];
+    let subcommand = args.first().map(|a| a.to_uppercase()).unwrap_or_default();
+    match (command.as_ref(), subcommand.as_ref(), args.len()) {
+        ("REGISTER", _, 0) => Ok(Request::Register { channel: channel }),
+        ("REGISTER", _, _) => usage("REGISTER"),
+        ("DROP", _, 0) => Ok(Request::Drop { channel: channel }),
+        ("DROP", _, _) => usage("DROP"),
+        ("INFO", _, 0) => Ok(Request::Info { channel: channel }),
+        ("INFO", _, _) => usage("INFO"),
+        ("ACCESS", "LIST", 1) => Ok(Request::AccessList { channel: channel }),
+        ("ACCESS", "ADD", 3) => {
+            match args[2].parse::<AccessLevel>() {
+                Ok(level) => Ok(Request::AccessAdd {
+                    channel: channel,
+                    target: args[1].to_string(),
+                    level: level,
+                }),
+                Err(_) => usage("ACCESS <#channel> ADD"),
+            }
+        }
+        ("ACCESS", "DEL", 2) => Ok(Request::AccessDel {
+            channel: channel,
+            target: args[1].to_string(),
+        }),
+        ("ACCESS", "ADD", _) => usage("ACCESS <#channel> ADD"),
+        ("ACCESS", "DEL", _) => usage("ACCESS <#channel> DEL"),
+        ("ACCESS", _, _) => usage("ACCESS <#channel> LIST"),
+        ("SET", "TOPICLOCK", 2) => {
+            match args[1].to_uppercase().as_ref() {
+                "ON" => Ok(Request::SetTopicLock {
+                    channel: channel,
+                    lock: true,
+                }),
+                "OFF" => Ok(Request::SetTopicLock {
+                    channel: channel,
+                    lock: false,
+                }),
+                _ => usage("SET <#channel> TOPICLOCK"),
+            }
+        }
+        ("SET", "TOPICLOCK", _) => usage("SET <#channel> TOPICLOCK"),
+        ("SET", "MLOCK", 1) | ("SET", "MLOCK", 2) => Ok(Request::SetModeLock {
+            channel: channel,
+            lock: args.get(1).map(|l| l.to_string()),
+        }),
+        ("SET", "MLOCK", _) => usage("SET <#channel> MLOCK"),
+        ("SET", "ENTRYMSG", 1) => Ok(Request::SetEntryMessage {
+            channel: channel,
+            message: None,
+        }),
+        ("SET", "ENTRYMSG", _) => Ok(Request::SetEntryMessage {
+            channel: channel,
+            message: Some(args[1..].join(" ")),
+        }),
+        ("SET", _, _) => usage("SET <#channel> TOPICLOCK"),
+        _ => Err(format!("Unknown command {}. Try HELP.", command)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::channel_registry::AccessLevel;
+    use super::{parse, Request};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("register #rust"),
+            Ok(Request::Register { channel: "#rust".to_string() })
+        );
+        assert_eq!(
+            parse("ACCESS #rust add bob op"),
+            Ok(Request::AccessAdd {
+                channel: "#rust".to_string(),
+                target: "bob".to_string(),
+                level: AccessLevel::Op,
+            })
+        );
+        assert_eq!(
+            parse("ACCESS #rust ADD bob FOUNDER"),
+            Err(
+                "Syntax: ACCESS <#channel> ADD <account|mask> <OP|VOICE|AKICK>".to_string(),
+            )
+        );
+        assert_eq!(
+            parse("set #rust entrymsg Welcome  to #rust"),
+            Ok(Request::SetEntryMessage {
+                channel: "#rust".to_string(),
+                message: Some("Welcome to #rust".to_string()),
+            })
+        );
+        assert_eq!(
+            parse("SET #rust MLOCK"),
+            Ok(Request::SetModeLock {
+                channel: "#rust".to_string(),
+                lock: None,
+            })
+        );
+        assert_eq!(parse("REGISTER"), Err("Syntax: REGISTER <#channel>".to_string()));
+        assert_eq!(parse(""), Ok(Request::Help));
+        assert!(parse("FROB #rust").is_err());
+    }
+}
@@ -0,0 +1,86 @@
+// Hostname cloaking, shown instead of the real hostname of clients with user mode +x.
+//
+// Cloaks are keyed hashes, so they can't be reversed or precomputed without the key, yet the
+// same host always gets the same cloak. IP cloaks hash successively shorter prefixes of the
+// address, so a ban on the trailing segments of a cloak still covers a whole range.
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::net::IpAddr;
+use super::super::password::to_hex;
+
+// Bytes of each hash shown.
+const SEGMENT_LENGTH: usize = 4;
+
+// Cloaks hostname, which may be an IP address, with key. Named hosts keep their domain, behind
+// prefix.
+pub fn cloak(key: &str, prefix: &str, hostname: &str) -> String {
+    match hostname.parse::<IpAddr>() {
+        Ok(IpAddr::V4(v4)) => {
+            let o = v4.octets();
+            format!(
+                "{}.{}.{}.IP",
+                segment(key, &format!("{}.{}.{}.{}", o[0], o[1], o[2], o[3])),
+                segment(key, &format!("{}.{}.{}", o[0], o[1], o[2])),
+                segment(key, &format!("{}.{}", o[0], o[1]))
+            )
+        }
+        Ok(IpAddr::V6(v6)) => {
+            let s = v6.segments();
+            let join = |n: usize| {
+                s[..n]
+                    .iter()
+                    .map(|s| format!("{:x}", s))
+                    .collect::<Vec<_>>()
+                    .join(":")
+            };
+            format!(
+                "{}.{}.{}.IP",
+                segment(key, &join(8)),
+                segment(key, &join(4)),
+                segment(key, &join(3))
+            )
+        }
+        Err(_) => {
+            let hash = segment(key, &hostname.to_lowercase());
+            match hostname.find('.') {
+                Some(idx) => format!("{}-{}{}", prefix, hash, &hostname[idx..]),
+                None => format!("{}-{}", prefix, hash),
+            }
+        }
+    }
+}
+
+fn segment(key: &str, data: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("any key length");
+    mac.update(data.as_bytes());
+    to_hex(&mac.finalize().into_bytes()[..SEGMENT_LENGTH]).to_uppercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::cloak;
+
+    #[test]
+    fn test_cloak() {
+        let a = cloak("key", "net", "10.0.0.1");
+        assert!(a.ends_with(".IP"));
+        assert_eq!(a.len(), "01234567.01234567.01234567.IP".len());
+        assert_eq!(a, cloak("key", "net", "10.0.0.1"));
+        assert!(a != cloak("other key", "net", "10.0.0.1"));
+        // The same /24 shares the trailing segments.
+        let b = cloak("key", "net", "10.0.0.2");
+        assert!(a != b);
+        assert_eq!(a[9..], b[9..]);
+        assert!(a[18..] == cloak("key", "net", "10.0.1.1")[18..]);
+
+        let v6 = cloak("key", "net", "2001:db8:1:2::1");
+        assert!(!v6.contains(':'));
+        assert_eq!(v6[9..], cloak("key", "net", "2001:db8:1:2::ffff")[9..]);
+
+        let host = cloak("key", "net", "client.Example.com");
+        assert!(host.starts_with("net-"));
+        assert!(host.ends_with(".Example.com"));
+        assert_eq!(host, cloak("key", "net", "CLIENT.Example.com"));
+        assert!(cloak("key", "net", "localhost").starts_with("net-"));
+    }
+}
@@ -5,10 +5,86 @@ use bytes::BytesMut;
 
 use tokio_io::codec::{Encoder, Decoder};
 
+use super::messages::MAX_LINE_LENGTH;
+use super::super::configuration::InvalidUtf8;
+
+// Longest IRCv3 tags section accepted from clients, including the leading '@' and trailing space.
+// Allowed on top of MAX_LINE_LENGTH.
+const MAX_TAGS_LENGTH: usize = 4096;
+
+// Frames LF or CRLF terminated lines. Lines longer than the limit are truncated, and the remainder
+// up to the next line ending discarded.
 #[derive(Debug)]
-pub struct Utf8CrlfCodec;
+pub struct LineCodec {
+    invalid_utf8: InvalidUtf8,
+    // Set while skipping the remainder of a truncated line.
+    discarding: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line {
+    Text(String),
+    // A line of this many bytes that was not valid UTF-8, and dropped per InvalidUtf8::Notice.
+    Invalid(usize),
+}
+
+// Windows-1252 characters for 0x80-0x9F. Unassigned bytes map to the C1 control of the same value.
+static CP1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+impl LineCodec {
+    pub fn new(invalid_utf8: InvalidUtf8) -> Self {
+        Self {
+            invalid_utf8: invalid_utf8,
+            discarding: false,
+        }
+    }
 
-impl Encoder for Utf8CrlfCodec {
+    fn decode_line(&self, line: &[u8], truncated: bool) -> Result<Line, io::Error> {
+        let e = match str::from_utf8(line) {
+            Ok(s) => return Ok(Line::Text(s.to_string())),
+            Err(e) => e,
+        };
+        // The line may have been truncated in the middle of a character.
+        if truncated && e.error_len().is_none() {
+            return Ok(Line::Text(
+                str::from_utf8(&line[..e.valid_up_to()]).unwrap().to_string(),
+            ));
+        }
+        debug!("Line is not valid UTF-8: {:?}.", e);
+        match self.invalid_utf8 {
+            InvalidUtf8::Disconnect => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not valid utf-8 string",
+            )),
+            InvalidUtf8::Notice => Ok(Line::Invalid(line.len())),
+            InvalidUtf8::Latin1 => Ok(Line::Text(line.iter().map(|&b| b as char).collect())),
+            InvalidUtf8::Cp1252 => Ok(Line::Text(
+                line.iter()
+                    .map(|&b| match b {
+                        0x80..=0x9F => CP1252_HIGH[(b - 0x80) as usize],
+                        _ => b as char,
+                    })
+                    .collect(),
+            )),
+        }
+    }
+}
+
+// Longest line that may start with buf, excluding the line ending.
+fn max_length(buf: &[u8]) -> usize {
+    if buf.first() == Some(&b'@') {
+        MAX_TAGS_LENGTH + MAX_LINE_LENGTH
+    } else {
+        MAX_LINE_LENGTH
+    }
+}
+
+impl Encoder for LineCodec {
     type Item = Vec<String>;
     type Error = io::Error;
     fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
@@ -21,43 +97,120 @@ impl Encoder for Utf8CrlfCodec {
     }
 }
 
-impl Decoder for Utf8CrlfCodec {
-    type Item = String;
+impl Decoder for LineCodec {
+    type Item = Line;
     type Error = io::Error;
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<String>, io::Error> {
-        let mut crlf_pos: Option<usize> = None;
-        for (pos, &c) in src.iter().enumerate() {
-            if pos > 1 && c == b'\n' && src[pos - 1] == b'\r' {
-                crlf_pos = Some(pos);
-                break;
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Line>, io::Error> {
+        loop {
+            let lf_pos = src.iter().position(|&c| c == b'\n');
+            if self.discarding {
+                match lf_pos {
+                    Some(pos) => {
+                        src.split_to(pos + 1);
+                        self.discarding = false;
+                    }
+                    None => {
+                        src.clear();
+                        return Ok(None);
+                    }
+                }
+                continue;
             }
-        }
 
-        match crlf_pos {
-            Some(pos) => {
-                let line = &src.split_to(pos + 1)[0..(pos - 1)];
-                match str::from_utf8(&line) {
-                    Ok(s) => Ok(Some(s.to_string())),
-                    // TODO(lazau): Maybe optionally support ISO-8859-1?
-                    Err(ref e) => {
-                        debug!("Error: {:?}.", e.to_string());
-                        Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            "not valid utf-8 string",
-                        ))
+            let max = max_length(&src);
+            let (mut line, mut truncated) = match lf_pos {
+                Some(pos) => {
+                    let mut line = src.split_to(pos + 1);
+                    line.truncate(pos);
+                    if line.last() == Some(&b'\r') {
+                        line.truncate(pos - 1);
                     }
+                    (line, false)
                 }
+                // No line ending in sight, and there's already more than a line's worth buffered.
+                None if src.len() > max => {
+                    self.discarding = true;
+                    (src.split_to(max), true)
+                }
+                None => return Ok(None),
+            };
+            if line.len() > max {
+                line.truncate(max);
+                truncated = true;
+            }
+            if truncated {
+                debug!("Truncated line longer than {} bytes.", max);
             }
-            None => Ok(None),
+            // Empty lines are silently ignored.
+            if line.is_empty() {
+                continue;
+            }
+            return self.decode_line(&line, truncated).map(Some);
         }
     }
 
     // TODO(lazau): Maybe don't need to propagate EOF inband?
-    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<String>, io::Error> {
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Line>, io::Error> {
         match try!(self.decode(src)) {
             Some(frame) => Ok(Some(frame)),
             None => Err(io::Error::new(io::ErrorKind::Other, "EOF")),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use bytes::BytesMut;
+    use super::{Line, LineCodec};
+    use super::super::super::configuration::InvalidUtf8;
+    use tokio_io::codec::Decoder;
+
+    fn text(s: &str) -> Option<Line> {
+        Some(Line::Text(s.to_string()))
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut codec = LineCodec::new(InvalidUtf8::Disconnect);
+        let mut buf = BytesMut::from(&b"\r\nNICK lazau\r\nUSER"[..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), text("NICK lazau"));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend(b" a 0 * :b\nPING a\n\nPING b\r");
+        assert_eq!(codec.decode(&mut buf).unwrap(), text("USER a 0 * :b"));
+        assert_eq!(codec.decode(&mut buf).unwrap(), text("PING a"));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend(b"\n");
+        assert_eq!(codec.decode(&mut buf).unwrap(), text("PING b"));
+    }
+
+    #[test]
+    fn test_decode_long_lines() {
+        let mut codec = LineCodec::new(InvalidUtf8::Disconnect);
+        // Never terminated, so can't be buffered forever.
+        let mut buf = BytesMut::from(format!("PRIVMSG #a :{}", "é".repeat(1000)).as_bytes());
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            text(&format!("PRIVMSG #a :{}", "é".repeat(249)))
+        );
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert!(buf.is_empty());
+        buf.extend(b"rest\r\nPING a\r\n");
+        assert_eq!(codec.decode(&mut buf).unwrap(), text("PING a"));
+
+        // Tags get a separate allowance.
+        let tagged = format!("@{} PING a", "a".repeat(2000));
+        let mut buf = BytesMut::from(format!("{}\r\n", tagged).as_bytes());
+        assert_eq!(codec.decode(&mut buf).unwrap(), text(&tagged));
+    }
+
+    #[test]
+    fn test_decode_invalid_utf8() {
+        let line = &b"PRIVMSG #a :caf\xe9 \x80\r\n"[..];
+        let decode = |invalid_utf8| LineCodec::new(invalid_utf8).decode(&mut BytesMut::from(line));
+        assert!(decode(InvalidUtf8::Disconnect).is_err());
+        assert_eq!(decode(InvalidUtf8::Notice).unwrap(), Some(Line::Invalid(18)));
+        assert_eq!(decode(InvalidUtf8::Latin1).unwrap(), text("PRIVMSG #a :café \u{80}"));
+        assert_eq!(decode(InvalidUtf8::Cp1252).unwrap(), text("PRIVMSG #a :café €"));
+    }
+}
@@ -1,28 +1,50 @@
+use chrono;
 use futures::prelude::*;
 use futures::*;
 use futures::stream::*;
 use futures::sink::*;
-use futures::sync::mpsc;
+use futures::future::Either;
+use futures::sync::oneshot;
 use futures_cpupool::CpuPool;
+use rand;
 use std::{self, fmt, io};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::clone::Clone;
 use std::ops::Deref;
 use std::sync::{Arc, Mutex};
-use super::{codec, user};
-use super::messages::Message as IRCMessage;
+use std::time::{Duration, Instant};
+use super::user;
+use super::codec::{Line, LineCodec};
+use super::bans::{Ban, BanKind};
+use super::messages::{Message as IRCMessage, MAX_LINE_LENGTH};
 use super::messages::commands::{Command, requests as Requests, responses as Responses};
 use super::shared_state::SharedState;
-use super::channel::{Identifier as ChannelIdentifier, ChannelError, Channel};
-use super::server::{Server, ServerError};
-use super::user::{User, Message as UserMessage, Identifier as UserIdentifier, UserMode, SetMode};
+use super::channel::{self, Identifier as ChannelIdentifier, ChannelError, Channel, ChannelMode,
+                     ListMode, MemberStatus, ModeChange, MAX_LIST_LENGTH};
+use super::channel_registry::{self, AccessLevel};
+use super::chanserv::{self, Request as ChanServRequest};
+use super::cloak;
+use super::dns;
+use super::flood::TokenBucket;
+use super::sendq::{self, SendQueue};
+use super::mask;
+use super::nickserv::{self, Request as NickServRequest};
+use super::accounts::Credentials;
+use super::sasl::{self, Mechanism, Reply as SaslReply, Session as SaslSession};
+use super::server::{Server, ServerError, Shutdown, ShutdownKind, SnoMask, DEFAULT_SNOMASK};
+use super::statistics::{LinkStatistics, Snapshot as LinkSnapshot};
+use super::user::{User, Message as UserMessage, Identifier as UserIdentifier, UserMode, SetMode,
+                  Snapshot as UserSnapshot};
+use super::super::configuration::{ConnectionClass, OperatorPrivilege};
+use super::super::password;
 use super::super::templates;
 use tokio_core;
 use tokio_io::AsyncRead;
 
 // Used to identify connections.
 // Server is represented by (local, local) pair.
-#[derive(Debug, Serialize, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
 pub struct SocketPair {
     pub local: std::net::SocketAddr,
     pub remote: std::net::SocketAddr,
@@ -40,23 +62,98 @@ enum ConnectionType {
 pub enum Event {
     Event,
     Message(Vec<IRCMessage>),
+    // Close the link, with reason.
+    Disconnect(String),
+    // Server bans changed.
+    CheckBans,
+    // Delivered only if the user is +w.
+    Wallops(IRCMessage),
+    // The server is going away, with reason.
+    Shutdown(String),
+    // The server is restarting in place, with reason. The link stays open.
+    Restarting(String),
+    // Process commands held back by flood protection, if now allowed.
+    ReleaseHeld,
+    // The client's hostname lookup finished, with its hostname if it has one.
+    Hostname(Option<String>),
+    // The client's ident query finished, with the username if identd answered.
+    Ident(Option<String>),
+    // An operator set the client's displayed host.
+    Vhost(String),
+    // The client's account was dropped, logging it out.
+    LoggedOut,
+    // Disconnected by NickServ GHOST with reason. The connection, if any, is told to take over
+    // the nick once it is free.
+    Ghost(String, Option<ConnectionTX>),
+    // The nick taken over with NickServ REGAIN is free.
+    Regain(String),
 }
 
-pub type ConnectionTX = mpsc::Sender<Event>;
+pub type ConnectionTX = SendQueue;
+
+impl Event {
+    // Bytes this event takes up in the send queue.
+    pub fn size(&self) -> usize {
+        match *self {
+            Event::Message(ref messages) => messages.iter().map(|m| m.to_string().len() + 2).sum(),
+            Event::Wallops(ref m) => m.to_string().len() + 2,
+            _ => 0,
+        }
+    }
+}
 
 // A union of socket and connection events.
 #[derive(Debug)]
 enum ConnectionEvent {
-    Socket(String),
+    Socket(Line),
     Event(Event),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Registration {
     nickname: Option<String>,
     username: Option<String>,
     realname: Option<String>,
     hostname: String,
+    // Sent with PASS. Kept out of the hot restart state, since it is in the clear, so clients
+    // registering across a restart have to send it again.
+    #[serde(skip)]
+    password: Option<String>,
+    #[serde(default)]
+    ident: Ident,
+    // Set from CAP LS or REQ until CAP END, holding registration.
+    #[serde(default)]
+    negotiating: bool,
+    // Logged in to with SASL.
+    #[serde(default)]
+    account: Option<String>,
+}
+
+// Outcome of the ident query for a registering client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Ident {
+    // Not queried. The username given with USER is used as is.
+    Unchecked,
+    // No answer. The username given with USER is prefixed with ~.
+    Failed,
+    // The username identd answered with.
+    Username(String),
+}
+
+impl std::default::Default for Ident {
+    fn default() -> Self {
+        Ident::Unchecked
+    }
+}
+
+impl Ident {
+    fn username(&self, given: &str) -> String {
+        match *self {
+            Ident::Unchecked => given.to_string(),
+            Ident::Failed => format!("~{}", given),
+            Ident::Username(ref u) => u.clone(),
+        }
+    }
 }
 
 impl Registration {
@@ -66,18 +163,75 @@ impl Registration {
             username: None,
             realname: None,
             hostname,
+            password: None,
+            ident: Ident::Unchecked,
+            negotiating: false,
+            account: None,
         }
     }
 }
 
+// Connection, preserved across hot restarts along with its socket.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    fd: RawFd,
+    socket: SocketPair,
+    registration: Option<Registration>,
+    user: Option<UserSnapshot>,
+    link: LinkSnapshot,
+    #[serde(default)]
+    class: String,
+    #[serde(default)]
+    capabilities: Vec<String>,
+}
+
+impl Snapshot {
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    pub fn socket(&self) -> &SocketPair {
+        &self.socket
+    }
+}
+
 #[derive(Debug)]
 pub struct Connection {
     // Unique per Connection.
     socket: SocketPair,
+    fd: RawFd,
     conn_type: ConnectionType,
     server: Arc<Mutex<Server>>,
     shared_state: Arc<SharedState>,
     tx: ConnectionTX,
+    link: Arc<LinkStatistics>,
+    class: ConnectionClass,
+    connected: Instant,
+    // Last time anything was read from the socket.
+    last_activity: Instant,
+    // Set while waiting for the answer to a keepalive PING.
+    ping_sent: Option<Instant>,
+    // Set once keepalive asked for the link to be closed, so it only asks once.
+    timed_out: bool,
+    flood: TokenBucket,
+    // Commands the client sent faster than allowed, with their cost, and the total cost.
+    held: VecDeque<(u64, IRCMessage)>,
+    held_cost: u64,
+    // When the hostname lookup and ident query started, while they are pending.
+    hostname_lookup: Option<Instant>,
+    ident_lookup: Option<Instant>,
+    // When the user took a nick owned by an account it isn't logged in to.
+    nick_enforcement: Option<Instant>,
+    // Told once this user is gone, to take over its nick with NickServ REGAIN.
+    regainer: Option<ConnectionTX>,
+    // Wrong NickServ passwords sent, see nickserv::MAX_PASSWORD_FAILURES.
+    password_failures: usize,
+    // Enabled with CAP REQ.
+    capabilities: HashSet<String>,
+    // SASL authentication in progress, which holds registration.
+    sasl: Option<SaslSession>,
+    // Set once the link is closing. Broadcast as the QUIT message.
+    quit_message: Option<String>,
 }
 
 impl fmt::Display for SocketPair {
@@ -104,6 +258,15 @@ macro_rules! error_resp {
     ($err:expr) => { vec![ IRCMessage { prefix: None, command: $err } ] };
 }
 
+macro_rules! notice_resp {
+    ($nick:expr, $message:expr) => {
+        vec![ IRCMessage {
+            prefix: None,
+            command: Command::NOTICE(Requests::Notice { targets: vec![$nick], message: $message }),
+        } ]
+    };
+}
+
 impl Connection {
     pub fn handle_new_connection(
         stream: tokio_core::net::TcpStream,
@@ -117,13 +280,118 @@ impl Connection {
         };
 
         debug!("Accepting new connection {:?}.", socket);
-        let (tx, rx) = mpsc::channel(shared_state.configuration.connection_message_queue_length);
-        let connection = Arc::new(Mutex::new(Connection::new(
-            socket.clone(),
+        // Provisional until registration, when the username is known and the class enforced.
+        let class = {
+            let settings = shared_state.settings();
+            let configuration = &settings.configuration;
+            configuration
+                .connection_class(&socket.remote.ip(), &socket.local, false, None)
+                .unwrap_or(&configuration.classes[0])
+                .clone()
+        };
+        let link = Arc::new(LinkStatistics::new(format!("*[{}]", socket.remote.ip())));
+        let (tx, rx, sendq_exceeded) = sendq::channel(Arc::clone(&link), class.max_sendq);
+        let mut connection = Connection::new(
+            socket,
+            stream.as_raw_fd(),
+            class,
             shared_state.clone(),
             server.clone(),
             tx,
-        )));
+            link,
+        );
+        connection.lookup_hostname();
+        connection.lookup_ident();
+        Self::run(stream, connection, rx, sendq_exceeded, connections)
+    }
+
+    // Adopts a connection handed over by a hot restart. Its user must not be known to server yet.
+    // Channels are restored separately, once every connection has been resumed.
+    pub fn resume(
+        snapshot: Snapshot,
+        handle: &tokio_core::reactor::Handle,
+        shared_state: Arc<SharedState>,
+        server: Arc<Mutex<Server>>,
+        connections: Arc<Mutex<HashMap<SocketPair, Arc<Mutex<Connection>>>>>,
+    ) -> io::Result<Box<Future<Item = (), Error = ()> + std::marker::Send>> {
+        // The fd was inherited across exec, and nothing else in this process owns it.
+        let stream = tokio_core::net::TcpStream::from_stream(
+            unsafe { std::net::TcpStream::from_raw_fd(snapshot.fd) },
+            handle,
+        )?;
+        debug!("Resuming connection {:?}.", snapshot.socket);
+
+        let class = {
+            let settings = shared_state.settings();
+            let configuration = &settings.configuration;
+            configuration
+                .find_class(&snapshot.class)
+                .unwrap_or(&configuration.classes[0])
+                .clone()
+        };
+        let link = Arc::new(LinkStatistics::restore(snapshot.link));
+        let (tx, rx, sendq_exceeded) = sendq::channel(Arc::clone(&link), class.max_sendq);
+        let conn_type = match (snapshot.user, snapshot.registration) {
+            (Some(user), _) => {
+                let user = User::restore(user, Arc::clone(&server), tx.clone());
+                if let Err(e) = server.lock().unwrap().add_user(user.identifier(), tx.clone()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{:?} for {:?}", e, user.identifier()),
+                    ));
+                }
+                server.lock().unwrap().set_class(&snapshot.socket, &class);
+                if let Some(account) = user.account() {
+                    server.lock().unwrap().set_account(user.identifier(), Some(account.clone()));
+                }
+                ConnectionType::Client(user)
+            }
+            (None, Some(registration)) => ConnectionType::Registering(registration),
+            (None, None) => ConnectionType::Server,
+        };
+        server.lock().unwrap().add_link(&snapshot.socket, Arc::clone(&link));
+        let now = Instant::now();
+        let flood = TokenBucket::new(class.flood_burst, class.flood_rate, now);
+        let connection = Connection {
+            socket: snapshot.socket,
+            fd: snapshot.fd,
+            conn_type: conn_type,
+            server: server,
+            shared_state: shared_state,
+            tx: tx,
+            link: link,
+            class: class,
+            connected: now,
+            last_activity: now,
+            ping_sent: None,
+            timed_out: false,
+            flood: flood,
+            held: VecDeque::new(),
+            held_cost: 0,
+            hostname_lookup: None,
+            ident_lookup: None,
+            nick_enforcement: None,
+            regainer: None,
+            password_failures: 0,
+            capabilities: snapshot.capabilities.into_iter().collect(),
+            sasl: None,
+            quit_message: None,
+        };
+        Ok(Self::run(stream, connection, rx, sendq_exceeded, connections))
+    }
+
+    // Drives connection until either end closes the link.
+    fn run(
+        stream: tokio_core::net::TcpStream,
+        connection: Connection,
+        rx: sendq::Receiver,
+        sendq_exceeded: oneshot::Receiver<()>,
+        connections: Arc<Mutex<HashMap<SocketPair, Arc<Mutex<Connection>>>>>,
+    ) -> Box<Future<Item = (), Error = ()> + std::marker::Send> {
+        let socket = connection.socket.clone();
+        let shared_state = Arc::clone(&connection.shared_state);
+        let link = Arc::clone(&connection.link);
+        let connection = Arc::new(Mutex::new(connection));
         connections.lock().unwrap().insert(
             socket.clone(),
             Arc::clone(&connection),
@@ -132,8 +400,10 @@ impl Connection {
         let connection_cleanup = Arc::clone(&connection);
         let connections_cleanup = Arc::clone(&connections);
         let shared_state_serialization = Arc::clone(&shared_state);
+        let link_serialization = Arc::clone(&link);
 
-        let (sink, stream) = stream.framed(codec::Utf8CrlfCodec).split();
+        let codec = LineCodec::new(shared_state.settings().configuration.invalid_utf8.clone());
+        let (sink, stream) = stream.framed(codec).split();
         let fut = stream
             .map(|m| ConnectionEvent::Socket(m))
             .select(rx.then(move |rx| {
@@ -150,41 +420,70 @@ impl Connection {
                     return future::err(err);
                 }
 
+                let mut connection = connection.lock().unwrap();
                 let res = match event.unwrap() {
-                    ConnectionEvent::Socket(s) => {
+                    ConnectionEvent::Socket(Line::Invalid(len)) => {
+                        link.received(len + 2);
+                        connection.last_activity = Instant::now();
+                        connection.ping_sent = None;
+                        notice_resp!(
+                            connection.nick_or_star(),
+                            "*** Dropped a line that is not valid UTF-8".to_string()
+                        )
+                    }
+                    ConnectionEvent::Socket(Line::Text(s)) => {
+                        link.received(s.len() + 2);
+                        connection.last_activity = Instant::now();
+                        connection.ping_sent = None;
                         let message = match s.parse::<IRCMessage>() {
                             Ok(m) => m,
                             // TODO(lazau): Maybe do some additional error processing here?
                             Err(e) => {
                                 warn!("Failed to parse {}: {:?}.", s, e);
-                                return future::ok(Vec::new());
+                                return future::ok((Vec::new(), false));
                             }
                         };
-                        connection.lock().unwrap().process_irc_message(message)
+                        connection.receive(message)
                     }
-                    ConnectionEvent::Event(e) => connection.lock().unwrap().process_system_event(e),
+                    ConnectionEvent::Event(e) => connection.process_system_event(e),
                 };
-                future::ok(res)
+                future::ok((res, connection.closing()))
             })
-            .then(move |messages: Result<Vec<IRCMessage>, _>| {
+            .then(move |messages: Result<(Vec<IRCMessage>, bool), _>| {
                 // ** Serialization future.
                 if messages.is_err() {
                     return future::err(messages.err().unwrap());
                 }
+                let (messages, closing) = messages.unwrap();
                 let mut result = Vec::new();
-                // TODO(lazau): Perform 512 max line size here.
-                for mut m in messages.unwrap() {
+                for mut m in messages {
                     if m.prefix.is_none() {
                         m.prefix = Some(shared_state_serialization.hostname.clone());
                     }
                     // TODO(lazau): Convert serialization error to future::err.
-                    result.push(format!("{}", m));
+                    for line in m.to_lines(MAX_LINE_LENGTH) {
+                        link_serialization.sent(line.len() + 2);
+                        result.push(line);
+                    }
                 }
                 debug!("Response: {:?}.", result);
-                future::ok(result)
+                future::ok((result, closing))
             })
+            .map(|(result, closing)| {
+                // A None frame terminates the stream once the final responses are written.
+                let mut frames = vec![Some(result)];
+                if closing {
+                    frames.push(None);
+                }
+                stream::iter_ok::<_, io::Error>(frames)
+            })
+            .flatten()
+            .take_while(|frame| future::ok(frame.is_some()))
+            .map(|frame| frame.unwrap())
             .forward(sink)
-            .then(move |e: Result<(_, _), io::Error>| {
+            // Abandon the link, pending writes and all, if the client can't keep up.
+            .select2(sendq_exceeded.or_else(|_| future::empty::<(), ()>()))
+            .then(move |r| {
                 // ** Cleanup future.
                 assert!(
                     connections_cleanup
@@ -194,8 +493,10 @@ impl Connection {
                         .is_some()
                 );
                 connection_cleanup.lock().unwrap().disconnect();
-                if let Err(e) = e {
-                    warn!("Connection error: {:?}.", e);
+                match r {
+                    Ok(Either::B(_)) => debug!("SendQ exceeded for {:?}.", socket),
+                    Err(Either::A((e, _))) => warn!("Connection error: {:?}.", e),
+                    _ => {}
                 }
                 debug!("Dropping connection {:?}.", socket);
                 Ok(())
@@ -205,58 +506,134 @@ impl Connection {
 
     fn new(
         addr: SocketPair,
+        fd: RawFd,
+        class: ConnectionClass,
         shared_state: Arc<SharedState>,
         server: Arc<Mutex<Server>>,
         tx: ConnectionTX,
+        link: Arc<LinkStatistics>,
     ) -> Self {
-        // TODO(lazau): Resolve IP to fullname here.
+        // Until the hostname lookup, if any, finishes.
         let hostname = addr.remote.ip().to_string();
+        server.lock().unwrap().add_link(&addr, Arc::clone(&link));
+        let now = Instant::now();
+        let flood = TokenBucket::new(class.flood_burst, class.flood_rate, now);
         Connection {
             socket: addr,
+            fd: fd,
             conn_type: ConnectionType::Registering(Registration::new(hostname)),
             server: server,
             shared_state: shared_state,
             tx: tx,
+            link: link,
+            class: class,
+            connected: now,
+            last_activity: now,
+            ping_sent: None,
+            timed_out: false,
+            flood: flood,
+            held: VecDeque::new(),
+            held_cost: 0,
+            hostname_lookup: None,
+            ident_lookup: None,
+            nick_enforcement: None,
+            regainer: None,
+            password_failures: 0,
+            capabilities: HashSet::new(),
+            sasl: None,
+            quit_message: None,
         }
     }
 
     fn try_register(&mut self) -> Vec<IRCMessage> {
         assert!(!self.registered());
-        let ident = if let ConnectionType::Registering(ref r) = self.conn_type {
-            if r.nickname.is_none() || r.username.is_none() || r.realname.is_none() {
+        if self.hostname_lookup.is_some() || self.ident_lookup.is_some() || self.sasl.is_some() {
+            return Vec::new();
+        }
+        let (ident, account) = if let ConnectionType::Registering(ref r) = self.conn_type {
+            if r.nickname.is_none() || r.username.is_none() || r.realname.is_none() ||
+                r.negotiating
+            {
                 return Vec::new();
             }
-            UserIdentifier::new(
-                r.nickname.as_ref().unwrap().clone(),
-                r.username.as_ref().unwrap().clone(),
-                r.realname.as_ref().unwrap().clone(),
-                r.hostname.clone(),
+            (
+                UserIdentifier::new(
+                    r.nickname.as_ref().unwrap().clone(),
+                    r.ident.username(r.username.as_ref().unwrap()),
+                    r.realname.as_ref().unwrap().clone(),
+                    r.hostname.clone(),
+                ),
+                r.account.clone(),
             )
         } else {
             unreachable!()
         };
 
+        let banned = self.check_bans();
+        if banned.len() > 0 {
+            return banned;
+        }
+        let refused = self.assign_class(&ident);
+        if refused.len() > 0 {
+            return refused;
+        }
+
         let nickname = ident.nick().clone();
-        match self.server.lock().unwrap().add_user(
+        let added = self.server.lock().unwrap().add_user(
             &ident,
             self.tx.clone(),
-        ) {
+        );
+        match added {
             Ok(_) => {
-                self.conn_type = ConnectionType::Client(
-                    User::new(&ident, Arc::clone(&self.server), self.tx.clone()),
+                self.link.set_name(
+                    format!("{}[{}]", nickname, self.socket.remote.ip()),
                 );
-                vec![
+                self.server.lock().unwrap().server_notice(
+                    &SnoMask::Connect,
+                    &format!(
+                        "Client connecting: {} ({}@{}) [{}]",
+                        nickname,
+                        ident.username(),
+                        ident.hostname(),
+                        self.socket.remote.ip()
+                    ),
+                );
+                let settings = self.shared_state.settings();
+                let cloak = settings.configuration.cloak_key.as_ref().map(|key| {
+                    cloak::cloak(key, &settings.configuration.cloak_prefix, ident.hostname())
+                });
+                let mut user = User::new(&ident, cloak, Arc::clone(&self.server), self.tx.clone());
+                let mut cloaked = Vec::new();
+                if settings.configuration.cloak_on_connect {
+                    cloaked = user.set_mode(&SetMode::Add, &vec![UserMode::Cloaked]);
+                    if cloaked.len() > 0 {
+                        self.server.lock().unwrap().update_user(user.identifier());
+                        cloaked.extend(Connection::host_hidden(user.identifier()));
+                    }
+                }
+                if let Some(account) = account {
+                    let mut server = self.server.lock().unwrap();
+                    let vhost = server.accounts().get(&account).and_then(|a| a.vhost.clone());
+                    user.set_account(Some(account.clone()));
+                    server.set_account(user.identifier(), Some(account));
+                    if vhost.map_or(false, |v| user.set_vhost(v)) {
+                        server.update_user(user.identifier());
+                        cloaked.extend(Connection::host_hidden(user.identifier()));
+                    }
+                }
+                self.conn_type = ConnectionType::Client(user);
+                let mut result = vec![
                     IRCMessage {
                         prefix: None,
                         command: Command::RPL_WELCOME(Responses::Welcome {
                             nick: nickname.clone(),
-                            message: self.shared_state
+                            message: settings
                                 .template_engine
                                 .0
                                 .render(
                                     templates::RPL_WELCOME_TEMPLATE_NAME,
                                     &templates::Welcome {
-                                        network_name: &self.shared_state.configuration.network_name,
+                                        network_name: &settings.configuration.network_name,
                                         nick: &nickname,
                                     },
                                 )
@@ -267,14 +644,14 @@ impl Connection {
                         prefix: None,
                         command: Command::RPL_YOURHOST(Responses::YourHost {
                             nick: nickname.clone(),
-                            message: self.shared_state
+                            message: settings
                                 .template_engine
                                 .0
                                 .render(
                                     templates::RPL_YOURHOST_TEMPLATE_NAME,
                                     &templates::YourHost {
                                         hostname: &self.shared_state.hostname,
-                                        version: &self.shared_state.configuration.version,
+                                        version: &settings.configuration.version,
                                     },
                                 )
                                 .unwrap(),
@@ -284,7 +661,7 @@ impl Connection {
                         prefix: None,
                         command: Command::RPL_CREATED(Responses::Created {
                             nick: nickname.clone(),
-                            message: self.shared_state
+                            message: settings
                                 .template_engine
                                 .0
                                 .render(
@@ -300,9 +677,16 @@ impl Connection {
                         prefix: None,
                         command: Command::RPL_MYINFO(Responses::MyInfo::default()),
                     },
-                ]
+                    self.isupport(),
+                ];
+                result.extend(self.motd());
+                result.extend(cloaked);
+                result.extend(self.check_nick_owner());
+                result
             }
             Err(e) => {
+                // The class is entered again once the nick is sorted out.
+                self.server.lock().unwrap().remove_class(&self.socket);
                 error_resp!(Command::ERR_NICKNAMEINUSE(
                     Responses::NICKNAMEINUSE { nick: nickname },
                 ))
@@ -310,6 +694,178 @@ impl Connection {
         }
     }
 
+    // Starts looking up the client's hostname. Registration waits until it is found, or the lookup
+    // times out.
+    fn lookup_hostname(&mut self) {
+        let settings = self.shared_state.settings();
+        let resolver = match settings.resolver {
+            Some(ref r) => Arc::clone(r),
+            None => return,
+        };
+        self.notify(Event::Message(notice_resp!(
+            "*".to_string(),
+            "*** Looking up your hostname...".to_string()
+        )));
+        self.hostname_lookup = Some(Instant::now());
+        let tx = self.tx.clone();
+        self.shared_state.dns.lookup(
+            resolver,
+            self.socket.remote.ip(),
+            Duration::from_secs(settings.configuration.dns_cache_seconds),
+            move |hostname| tx.send(Event::Hostname(hostname)),
+        );
+    }
+
+    // Finishes the hostname lookup, unless it already timed out.
+    fn hostname_found(&mut self, hostname: Option<String>) -> Vec<IRCMessage> {
+        if self.hostname_lookup.take().is_none() {
+            return Vec::new();
+        }
+        let mut result = match hostname {
+            Some(hostname) => {
+                if let ConnectionType::Registering(ref mut r) = self.conn_type {
+                    r.hostname = hostname;
+                }
+                notice_resp!("*".to_string(), "*** Found your hostname".to_string())
+            }
+            None => {
+                notice_resp!(
+                    "*".to_string(),
+                    "*** Couldn't look up your hostname".to_string()
+                )
+            }
+        };
+        if !self.registered() && !self.closing() {
+            result.extend(self.try_register());
+        }
+        result
+    }
+
+    // Starts querying the client's identd, if its class asks for it. Registration waits until it
+    // answers, or the query times out.
+    fn lookup_ident(&mut self) {
+        if !self.class.ident {
+            return;
+        }
+        let settings = self.shared_state.settings();
+        self.notify(Event::Message(
+            notice_resp!("*".to_string(), "*** Checking Ident".to_string()),
+        ));
+        self.ident_lookup = Some(Instant::now());
+        let tx = self.tx.clone();
+        self.shared_state.ident.lookup(
+            self.socket.remote,
+            self.socket.local,
+            settings.configuration.ident_port,
+            Duration::from_secs(settings.configuration.ident_timeout_seconds),
+            move |username| tx.send(Event::Ident(username)),
+        );
+    }
+
+    // Finishes the ident query, unless it already timed out.
+    fn ident_found(&mut self, username: Option<String>) -> Vec<IRCMessage> {
+        if self.ident_lookup.take().is_none() {
+            return Vec::new();
+        }
+        let (ident, notice) = match username {
+            Some(username) => (Ident::Username(username), "*** Got Ident response"),
+            None => (Ident::Failed, "*** No Ident response"),
+        };
+        if let ConnectionType::Registering(ref mut r) = self.conn_type {
+            r.ident = ident;
+        }
+        let mut result = notice_resp!("*".to_string(), notice.to_string());
+        if !self.registered() && !self.closing() {
+            result.extend(self.try_register());
+        }
+        result
+    }
+
+    // Places the client being registered as ident in its connection class. Closes the link if it
+    // is not allowed in any, gave the wrong password, or its class is full.
+    fn assign_class(&mut self, ident: &UserIdentifier) -> Vec<IRCMessage> {
+        let ip = self.socket.remote.ip();
+        let userhost = format!("{}@{}", ident.username(), ident.hostname());
+        let (class, server_password) = {
+            let settings = self.shared_state.settings();
+            let configuration = &settings.configuration;
+            (
+                configuration
+                    .connection_class(&ip, &self.socket.local, false, Some(&userhost))
+                    .cloned(),
+                configuration.server_password.clone(),
+            )
+        };
+        let class = match class {
+            Some(c) => c,
+            None => {
+                return self.refuse(ident, "You are not authorized to use this server".to_string())
+            }
+        };
+
+        let password = match self.conn_type {
+            ConnectionType::Registering(ref r) => r.password.clone().unwrap_or_default(),
+            _ => unreachable!(),
+        };
+        // The class password stands in for the server password.
+        if let Some(hash) = class.password.as_ref().or(server_password.as_ref()) {
+            if !password::verify(&password, hash) {
+                info!("{} sent a wrong or no password.", self.socket);
+                let mut result = error_resp!(Command::ERR_PASSWDMISMATCH(
+                    Responses::PasswdMismatch { nick: ident.nick().clone() },
+                ));
+                result.extend(self.close_link("Bad password".to_string()));
+                return result;
+            }
+        }
+
+        let checked = {
+            let mut server = self.server.lock().unwrap();
+            let checked = server.check_class(&self.socket, &class);
+            if checked.is_ok() {
+                server.set_class(&self.socket, &class);
+            }
+            checked
+        };
+        match checked {
+            Ok(_) => {}
+            Err(ServerError::TooManyFromHost) => {
+                return self.refuse(ident, "Too many connections from your host".to_string())
+            }
+            Err(_) => return self.refuse(ident, "Server is full, try again later".to_string()),
+        }
+
+        if class != self.class {
+            debug!("{} moves to connection class {}.", self.socket, class.name);
+            self.tx.set_limit(class.max_sendq);
+            self.flood = TokenBucket::new(class.flood_burst, class.flood_rate, Instant::now());
+            self.class = class;
+        }
+        Vec::new()
+    }
+
+    // Refuses to register ident, and closes the link.
+    fn refuse(&mut self, ident: &UserIdentifier, reason: String) -> Vec<IRCMessage> {
+        info!("Refusing {}: {}.", self.socket, reason);
+        self.server.lock().unwrap().server_notice(
+            &SnoMask::Reject,
+            &format!(
+                "Rejecting {} ({}@{}) [{}]: {}",
+                ident.nick(),
+                ident.username(),
+                ident.hostname(),
+                self.socket.remote.ip(),
+                reason
+            ),
+        );
+        let mut result = error_resp!(Command::ERR_YOUREBANNEDCREEP(Responses::YoureBannedCreep {
+            nick: ident.nick().clone(),
+            reason: reason.clone(),
+        }));
+        result.extend(self.close_link(reason));
+        result
+    }
+
     fn add_registration_info(
         &mut self,
         nickname: Option<String>,
@@ -320,17 +876,29 @@ impl Connection {
         let mut u;
         let mut r;
         let h;
+        let p;
+        let i;
+        let ng;
+        let a;
         match self.conn_type {
             ConnectionType::Registering(Registration {
                                             ref nickname,
                                             ref username,
                                             ref realname,
                                             ref hostname,
+                                            ref password,
+                                            ref ident,
+                                            ref negotiating,
+                                            ref account,
                                         }) => {
                 n = nickname.clone();
                 u = username.clone();
                 r = realname.clone();
                 h = hostname.clone();
+                p = password.clone();
+                i = ident.clone();
+                ng = negotiating.clone();
+                a = account.clone();
             }
             _ => unreachable!(),
         };
@@ -350,9 +918,204 @@ impl Connection {
             username: u,
             realname: r,
             hostname: h,
+            password: p,
+            ident: i,
+            negotiating: ng,
+            account: a,
         });
     }
 
+    // Queues event for this connection's pipeline.
+    pub fn notify(&self, event: Event) {
+        self.tx.send(event);
+    }
+
+    // Bytes of messages waiting to be written.
+    pub fn sendq(&self) -> usize {
+        self.link.sendq()
+    }
+
+    fn nick_or_star(&self) -> String {
+        match self.conn_type {
+            ConnectionType::Registering(ref r) => r.nickname.clone().unwrap_or("*".to_string()),
+            ConnectionType::Client(ref u) => u.nick().clone(),
+            ConnectionType::Server => "*".to_string(),
+        }
+    }
+
+    pub fn closing(&self) -> bool {
+        self.quit_message.is_some()
+    }
+
+    // Called periodically, on the reactor. Anything slow, like registering a client whose lookups
+    // timed out, is queued for the connection to handle on the pool.
+    pub fn tick(&mut self, now: Instant) {
+        let (dns_timeout, ident_timeout) = {
+            let settings = self.shared_state.settings();
+            (
+                Duration::from_secs(settings.configuration.dns_timeout_seconds),
+                Duration::from_secs(settings.configuration.ident_timeout_seconds),
+            )
+        };
+        let expired = |started: Option<Instant>, timeout| {
+            started.map_or(false, |s| now.duration_since(s) >= timeout)
+        };
+        if expired(self.hostname_lookup, dns_timeout) {
+            self.notify(Event::Hostname(None));
+        }
+        if expired(self.ident_lookup, ident_timeout) {
+            self.notify(Event::Ident(None));
+        }
+        let nick_enforce_seconds = self.shared_state.settings().configuration.nick_enforce_seconds;
+        if nick_enforce_seconds > 0 &&
+            expired(self.nick_enforcement, Duration::from_secs(nick_enforce_seconds))
+        {
+            let result = self.enforce_nick();
+            self.notify(Event::Message(result));
+        }
+        self.keepalive(now);
+        if !self.held.is_empty() {
+            self.notify(Event::ReleaseHeld);
+        }
+    }
+
+    // Processes message from the client, unless it is flooding, in which case message is held
+    // back until it may be processed.
+    pub fn receive(&mut self, message: IRCMessage) -> Vec<IRCMessage> {
+        let cost = self.flood_cost(&message);
+        if self.held.is_empty() && self.flood.take(cost, Instant::now()) {
+            return self.process_irc_message(message);
+        }
+
+        if self.held.is_empty() {
+            self.flood_notice("is being throttled");
+        }
+        self.held.push_back((cost, message));
+        self.held_cost += cost;
+        if self.held_cost > self.class.flood_limit {
+            self.flood_notice("disconnected for Excess Flood");
+            self.held.clear();
+            self.held_cost = 0;
+            return self.close_link("Excess Flood".to_string());
+        }
+        Vec::new()
+    }
+
+    // Processes held back commands, as far as the client's flood allowance goes.
+    fn release_held(&mut self) -> Vec<IRCMessage> {
+        let now = Instant::now();
+        let mut result = Vec::new();
+        while !self.closing() {
+            let cost = match self.held.front() {
+                Some(&(cost, _)) => cost,
+                None => break,
+            };
+            if !self.flood.take(cost, now) {
+                break;
+            }
+            let (_, message) = self.held.pop_front().unwrap();
+            self.held_cost -= cost;
+            result.extend(self.process_irc_message(message));
+        }
+        result
+    }
+
+    // Flood protection cost of message. Free for exempt clients.
+    fn flood_cost(&self, message: &IRCMessage) -> u64 {
+        let settings = self.shared_state.settings();
+        if let ConnectionType::Client(ref user) = self.conn_type {
+            let prefix = user.identifier().as_prefix();
+            if user.is_operator() ||
+                settings.configuration.flood_exempt.iter().any(
+                    |m| mask::matches(m, &prefix),
+                )
+            {
+                return 0;
+            }
+        }
+        settings.configuration.command_cost(message.command.name())
+    }
+
+    fn flood_notice(&self, what: &str) {
+        let who = match self.conn_type {
+            ConnectionType::Client(ref u) => u.identifier().as_prefix(),
+            _ => format!("{}[{}]", self.nick_or_star(), self.socket.remote.ip()),
+        };
+        self.server.lock().unwrap().server_notice(
+            &SnoMask::Flood,
+            &format!("Flood: {} {}", who, what),
+        );
+    }
+
+    // PINGs idle clients, and disconnects ones that didn't answer or didn't register in time.
+    fn keepalive(&mut self, now: Instant) {
+        if self.closing() || self.timed_out {
+            return;
+        }
+        if !self.registered() {
+            let timeout = Duration::from_secs(self.class.registration_timeout_seconds);
+            if now.duration_since(self.connected) >= timeout {
+                self.timed_out = true;
+                self.notify(Event::Disconnect("Registration timed out".to_string()));
+            }
+            return;
+        }
+        match self.ping_sent {
+            Some(sent) => {
+                if now.duration_since(sent) >= Duration::from_secs(self.class.ping_timeout_seconds) {
+                    let idle = now.duration_since(self.last_activity).as_secs();
+                    self.timed_out = true;
+                    self.notify(Event::Disconnect(format!("Ping timeout: {} seconds", idle)));
+                }
+            }
+            None => {
+                let frequency = Duration::from_secs(self.class.ping_frequency_seconds);
+                if now.duration_since(self.last_activity) >= frequency {
+                    self.ping_sent = Some(now);
+                    self.notify(Event::Message(vec![
+                        IRCMessage {
+                            prefix: None,
+                            command: Command::PING(Requests::Ping {
+                                originator: self.shared_state.hostname.clone(),
+                                target: None,
+                            }),
+                        },
+                    ]));
+                }
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let (registration, user) = match self.conn_type {
+            ConnectionType::Registering(ref r) => (Some(r.clone()), None),
+            ConnectionType::Client(ref u) => (None, Some(u.snapshot())),
+            ConnectionType::Server => (None, None),
+        };
+        Snapshot {
+            fd: self.fd,
+            socket: self.socket.clone(),
+            registration: registration,
+            user: user,
+            link: self.link.snapshot(),
+            class: self.class.name.clone(),
+            capabilities: self.capabilities.iter().cloned().collect(),
+        }
+    }
+
+    // Marks the link as closing. Returns the ERROR message to send before closing.
+    fn close_link(&mut self, reason: String) -> Vec<IRCMessage> {
+        debug!("Closing link {}: {}.", self.socket, reason);
+        let message = format!("Closing Link ({})", reason);
+        self.quit_message = Some(reason);
+        vec![
+            IRCMessage {
+                prefix: None,
+                command: Command::ERROR(Requests::Error { message: message }),
+            },
+        ]
+    }
+
     pub fn registered(&self) -> bool {
         match self.conn_type {
             ConnectionType::Registering(_) => false,
@@ -378,6 +1141,9 @@ impl Connection {
 
     pub fn process_irc_message(&mut self, req: IRCMessage) -> Vec<IRCMessage> {
         trace!("Connection state: {:?}.", self);
+        self.server.lock().unwrap().record_command(
+            req.command.name(),
+        );
 
         macro_rules! verify_registered {
             () => {
@@ -420,6 +1186,9 @@ impl Connection {
                               mode_args,
                           }) => {
                 verify_registered!();
+                if target.starts_with('#') {
+                    return self.channel_mode(target, mode_string, mode_args);
+                }
                 // MODE query.
                 if mode_string.is_none() {
                     //unimplemented!();
@@ -462,20 +1231,126 @@ impl Connection {
                     };
                 }
 
-                user.set_mode(&set, &modes)
+                let was_operator = user.is_operator();
+                let hostname = user.identifier().displayed_hostname().clone();
+                let mut result = user.set_mode(&set, &modes);
+                let is_operator = user.is_operator();
+                let deopered = was_operator && !is_operator;
+                let ident = user.identifier().clone();
+                let mut server = self.server.lock().unwrap();
+                if ident.displayed_hostname() != &hostname {
+                    server.update_user(&ident);
+                    result.extend(Connection::host_hidden(&ident));
+                }
+                if deopered {
+                    server.remove_operator(&ident);
+                } else if is_operator && modes.contains(&UserMode::ServerNotices) {
+                    // +s takes the snomask change as its argument, e.g. MODE nick +s +cC-k.
+                    let snomasks = match set {
+                        SetMode::Add => {
+                            let current = server.snomasks(&ident).cloned().unwrap_or_default();
+                            let changes = match mode_args {
+                                Some(ref a) => a.clone(),
+                                None if current.is_empty() => DEFAULT_SNOMASK.to_string(),
+                                None => String::new(),
+                            };
+                            SnoMask::apply(&current, &changes)
+                        }
+                        SetMode::Remove => HashSet::new(),
+                    };
+                    result.extend(notice_resp!(
+                        ident.nick().clone(),
+                        format!("Server notice mask is now {}", SnoMask::format(&snomasks))
+                    ));
+                    server.add_operator(&ident, snomasks);
+                }
+                result
             }
 
             Command::NICK(Requests::Nick { nickname: nick }) => {
-                // TODO(lazau): Validate nick based on
-                // https://tools.ietf.org/html/rfc2812#section-2.3.1.
+                if !user::valid_nick(&nick) {
+                    return error_resp!(Command::ERR_ERRONEUSNICKNAME(
+                        Responses::ErroneousNickname {
+                            nick: self.nick_or_star(),
+                            target: nick,
+                        },
+                    ));
+                }
                 if self.registered() {
-                    unimplemented!()
+                    self.change_nick(nick)
                 } else {
                     self.add_registration_info(Some(nick.clone()), None, None);
                     self.try_register()
                 }
             }
 
+            Command::KLINE(Requests::Kline {
+                               duration,
+                               mask,
+                               reason,
+                           }) => {
+                verify_registered!();
+                self.add_ban(BanKind::K, duration, mask, reason)
+            }
+
+            Command::UNKLINE(Requests::Unkline { mask }) => {
+                verify_registered!();
+                self.remove_ban(BanKind::K, mask)
+            }
+
+            Command::GLINE(Requests::Gline {
+                               duration,
+                               mask,
+                               reason,
+                           }) => {
+                verify_registered!();
+                self.add_ban(BanKind::G, duration, mask, reason)
+            }
+
+            Command::UNGLINE(Requests::Ungline { mask }) => {
+                verify_registered!();
+                self.remove_ban(BanKind::G, mask)
+            }
+
+            Command::DLINE(Requests::Dline {
+                               duration,
+                               mask,
+                               reason,
+                           }) => {
+                verify_registered!();
+                self.add_ban(BanKind::D, duration, mask, reason)
+            }
+
+            Command::UNDLINE(Requests::Undline { mask }) => {
+                verify_registered!();
+                self.remove_ban(BanKind::D, mask)
+            }
+
+            Command::CHGHOST(Requests::Chghost { nickname, host }) => {
+                verify_registered!();
+                self.chghost(nickname, host)
+            }
+
+            Command::KILL(Requests::Kill { nickname, comment }) => {
+                verify_registered!();
+                self.kill(nickname, comment)
+            }
+
+            Command::OPER(Requests::Oper { name, password }) => {
+                verify_registered!();
+                self.oper(name, password)
+            }
+
+            Command::TOPIC(Requests::Topic { channel, topic }) => {
+                verify_registered!();
+                self.topic(channel, topic)
+            }
+
+            Command::INVITE(Requests::Invite { nickname, channel }) => {
+                verify_registered!();
+                self.invite(nickname, channel)
+            }
+
             Command::PART(Requests::Part { channels, message }) => {
                 let user = self.get_user().identifier().clone();
                 self.part(user, channels, message)
@@ -497,52 +1372,2045 @@ impl Connection {
                 ]
             }
 
+            // Any traffic resets the keepalive timer, so there is nothing left to do.
+            Command::PONG(_) => Vec::new(),
+
             Command::PRIVMSG(Requests::Privmsg { targets, message }) => {
-                if targets.len() > 1 {
-                    unimplemented!()
+                verify_registered!();
+                let mut responses = Vec::new();
+                for target in targets {
+                    if target.eq_ignore_ascii_case(nickserv::NICK) {
+                        responses.extend(self.nickserv(message.clone()));
+                    } else if target.eq_ignore_ascii_case(chanserv::NICK) {
+                        responses.extend(self.chanserv(message.clone()));
+                    } else {
+                        responses.extend(self.message(target, &message, false));
+                    }
                 }
-                let user = self.get_user().identifier().clone();
+                responses
+            }
 
-                self.server.lock().unwrap().send(&user, &targets, &message);
+            // Like PRIVMSG, but never answered, so services ignore it too.
+            Command::NOTICE(Requests::Notice { targets, message }) => {
+                verify_registered!();
+                for target in targets {
+                    if !target.eq_ignore_ascii_case(nickserv::NICK) &&
+                        !target.eq_ignore_ascii_case(chanserv::NICK)
+                    {
+                        self.message(target, &message, true);
+                    }
+                }
                 Vec::new()
             }
 
-            Command::USER(Requests::User {
-                              username,
-                              mode: _mode,
-                              unused: _unused,
-                              realname,
-                          }) => {
-                if self.registered() {
-                    let user = self.get_user();
-                    return error_resp!(Command::ERR_ALREADYREGISTRED(
-                        Responses::AlreadyRegistered { nick: user.nick().clone() },
+            Command::WALLOPS(Requests::Wallops { message }) => {
+                verify_registered!();
+                let user = self.get_user();
+                if !user.is_operator() {
+                    return error_resp!(Command::ERR_NOPRIVILEGES(
+                        Responses::NoPrivileges { nick: user.nick().clone() },
                     ));
-                } else {
-                    self.add_registration_info(None, Some(username), Some(realname));
-                    self.try_register()
                 }
+                self.server.lock().unwrap().wallops(
+                    user.identifier(),
+                    &message,
+                );
+                Vec::new()
             }
 
-            u @ _ => {
-                error!("{:?} not yet implemented.", u);
-                Vec::new()
-                //TXDestination::None
+            Command::WHO(Requests::Who { mask, operators }) => {
+                verify_registered!();
+                self.who(mask, operators)
             }
-        }
-    }
 
-    pub fn process_system_event(&mut self, e: Event) -> Vec<IRCMessage> {
-        match e {
-            Event::Event => unimplemented!(),
-            Event::Message(m) => m,
-        }
-    }
+            Command::WHOIS(Requests::Whois { target: _target, masks }) => {
+                verify_registered!();
+                self.whois(masks)
+            }
 
-    fn join(
-        &mut self,
-        _user: UserIdentifier,
-        channels: Vec<(String, Option<String>)>,
+            Command::MOTD(Requests::Motd { target: _target }) => {
+                verify_registered!();
+                self.motd()
+            }
+
+            Command::REHASH(Requests::Rehash {}) => {
+                verify_registered!();
+                self.rehash()
+            }
+
+            Command::DIE(Requests::Die { reason }) => {
+                verify_registered!();
+                self.shutdown(ShutdownKind::Die, reason)
+            }
+
+            Command::RESTART(Requests::Restart { reason }) => {
+                verify_registered!();
+                let kind = if self.shared_state.settings().configuration.hot_restart {
+                    ShutdownKind::HotRestart
+                } else {
+                    ShutdownKind::Restart
+                };
+                self.shutdown(kind, reason)
+            }
+
+            Command::QUIT(Requests::Quit { message }) => {
+                self.close_link(format!("Quit: {}", message.unwrap_or_default()))
+            }
+
+            Command::STATS(Requests::Stats { query, target: _target }) => {
+                verify_registered!();
+                self.stats(query)
+            }
+
+            Command::PASS(Requests::Pass { password }) => {
+                match self.conn_type {
+                    ConnectionType::Registering(ref mut r) => {
+                        r.password = Some(password);
+                        Vec::new()
+                    }
+                    _ => {
+                        error_resp!(Command::ERR_ALREADYREGISTRED(
+                            Responses::AlreadyRegistered { nick: self.nick_or_star() },
+                        ))
+                    }
+                }
+            }
+
+            Command::USER(Requests::User {
+                              username,
+                              mode: _mode,
+                              unused: _unused,
+                              realname,
+                          }) => {
+                if self.registered() {
+                    let user = self.get_user();
+                    return error_resp!(Command::ERR_ALREADYREGISTRED(
+                        Responses::AlreadyRegistered { nick: user.nick().clone() },
+                    ));
+                } else {
+                    self.add_registration_info(None, Some(username), Some(realname));
+                    self.try_register()
+                }
+            }
+
+            Command::CAP(Requests::Cap {
+                             target: _target,
+                             subcommand,
+                             capabilities,
+                         }) => self.cap(subcommand, capabilities),
+
+            Command::AUTHENTICATE(Requests::Authenticate { data }) => self.authenticate(data),
+
+            u @ _ => {
+                error!("{:?} not yet implemented.", u);
+                Vec::new()
+                //TXDestination::None
+            }
+        }
+    }
+
+    pub fn process_system_event(&mut self, e: Event) -> Vec<IRCMessage> {
+        match e {
+            Event::Event => unimplemented!(),
+            Event::Message(m) => m,
+            Event::Disconnect(reason) => self.close_link(reason),
+            Event::CheckBans => self.check_bans(),
+            Event::Hostname(hostname) => self.hostname_found(hostname),
+            Event::Ident(username) => self.ident_found(username),
+            Event::Vhost(host) => {
+                if !self.registered() {
+                    return Vec::new();
+                }
+                let user = self.get_user_mut();
+                if !user.set_vhost(host) {
+                    return Vec::new();
+                }
+                let ident = user.identifier().clone();
+                self.server.lock().unwrap().update_user(&ident);
+                Connection::host_hidden(&ident)
+            }
+            Event::LoggedOut => {
+                if !self.registered() {
+                    return Vec::new();
+                }
+                let ident = self.get_user().identifier().clone();
+                self.get_user_mut().set_account(None);
+                self.server.lock().unwrap().set_account(&ident, None);
+                vec![self.nickserv_notice("Your account has been dropped.".to_string())]
+            }
+            Event::Ghost(_, _) if self.closing() => Vec::new(),
+            Event::Ghost(reason, regainer) => {
+                self.regainer = regainer;
+                self.close_link(reason)
+            }
+            Event::Regain(nick) => {
+                if !self.registered() {
+                    return Vec::new();
+                }
+                self.change_nick(nick)
+            }
+            Event::Shutdown(_) if self.closing() => Vec::new(),
+            Event::Shutdown(reason) => {
+                let mut result = notice_resp!(
+                    self.nick_or_star(),
+                    format!("*** Server terminating: {}", reason)
+                );
+                result.extend(self.close_link(reason));
+                result
+            }
+            Event::Restarting(_) if self.closing() => Vec::new(),
+            Event::Restarting(reason) => {
+                notice_resp!(self.nick_or_star(), format!("*** Server restarting: {}", reason))
+            }
+            Event::ReleaseHeld => self.release_held(),
+            Event::Wallops(m) => {
+                if self.registered() && self.get_user().has_mode(&UserMode::WallOps) {
+                    vec![m]
+                } else {
+                    Vec::new()
+                }
+            }
+        }
+    }
+
+    // Closes the link if this connection is banned.
+    fn check_bans(&mut self) -> Vec<IRCMessage> {
+        if self.closing() {
+            return Vec::new();
+        }
+        let ip = self.socket.remote.ip();
+        let (nick, username, hostname) = match self.conn_type {
+            ConnectionType::Registering(ref r) => {
+                (
+                    r.nickname.clone().unwrap_or("*".to_string()),
+                    r.username.clone().unwrap_or_default(),
+                    r.hostname.clone(),
+                )
+            }
+            ConnectionType::Client(ref u) => {
+                (
+                    u.nick().clone(),
+                    u.identifier().username().clone(),
+                    u.identifier().hostname().clone(),
+                )
+            }
+            ConnectionType::Server => return Vec::new(),
+        };
+        let reason = match self.server.lock().unwrap().bans().find_user(
+            &username,
+            &hostname,
+            &ip,
+        ) {
+            Some(ban) => ban.describe(),
+            None => return Vec::new(),
+        };
+
+        info!("{} is banned: {}.", self.socket, reason);
+        self.server.lock().unwrap().server_notice(
+            &SnoMask::Ban,
+            &format!("Ban active for {} ({}@{}): {}", nick, username, hostname, reason),
+        );
+        let mut result = error_resp!(Command::ERR_YOUREBANNEDCREEP(Responses::YoureBannedCreep {
+            nick: nick,
+            reason: reason.clone(),
+        }));
+        result.extend(self.close_link(reason));
+        result
+    }
+
+    fn add_ban(
+        &mut self,
+        kind: BanKind,
+        duration: Option<u64>,
+        mask: String,
+        reason: Option<String>,
+    ) -> Vec<IRCMessage> {
+        let nick = self.get_user().nick().clone();
+        if !self.get_user().has_privilege(&OperatorPrivilege::Ban) {
+            return error_resp!(Command::ERR_NOPRIVILEGES(Responses::NoPrivileges { nick: nick }));
+        }
+
+        let ban = match Ban::new(
+            kind.clone(),
+            &mask,
+            reason.unwrap_or("No reason".to_string()),
+            nick.clone(),
+            duration.map(|d| chrono::Duration::minutes(d as i64)),
+        ) {
+            Ok(b) => b,
+            Err(e) => {
+                debug!("Invalid ban mask {}: {:?}.", mask, e);
+                return notice_resp!(nick, format!("Invalid {}-line mask: {}", kind, mask));
+            }
+        };
+        let message = match duration {
+            Some(d) => format!("Added temporary {} min. {}-line for [{}]", d, kind, ban.mask),
+            None => format!("Added {}-line for [{}]", kind, ban.mask),
+        };
+        self.server.lock().unwrap().add_ban(ban);
+        notice_resp!(nick, message)
+    }
+
+    fn remove_ban(&mut self, kind: BanKind, mask: String) -> Vec<IRCMessage> {
+        let nick = self.get_user().nick().clone();
+        if !self.get_user().has_privilege(&OperatorPrivilege::Ban) {
+            return error_resp!(Command::ERR_NOPRIVILEGES(Responses::NoPrivileges { nick: nick }));
+        }
+
+        if self.server.lock().unwrap().bans().remove(&kind, &mask) {
+            info!("{} removed {}-line for {}.", nick, kind, mask);
+            notice_resp!(nick, format!("{}-line for [{}] is removed", kind, mask))
+        } else {
+            notice_resp!(nick, format!("No {}-line for [{}] found", kind, mask))
+        }
+    }
+
+    // RPL_ISUPPORT, split into as many lines as it takes when serialized.
+    fn isupport(&self) -> IRCMessage {
+        let settings = self.shared_state.settings();
+        let chanlimit = match settings.configuration.max_channels {
+            0 => String::new(),
+            n => n.to_string(),
+        };
+        IRCMessage {
+            prefix: None,
+            command: Command::RPL_ISUPPORT(Responses::ISupport {
+                nick: self.nick_or_star(),
+                tokens: vec![
+                    // Spaces are escaped, as values are space separated.
+                    format!(
+                        "NETWORK={}",
+                        settings.configuration.network_name.replace(' ', "\\x20")
+                    ),
+                    "CHANTYPES=#".to_string(),
+                    "CHANMODES=bq,k,fjl,imnrt".to_string(),
+                    format!("NICKLEN={}", user::MAX_NICK_LENGTH),
+                    format!("CHANLIMIT=#:{}", chanlimit),
+                    format!("MAXLIST=bq:{}", MAX_LIST_LENGTH),
+                ],
+            }),
+        }
+    }
+
+    fn motd(&self) -> Vec<IRCMessage> {
+        let nick = self.get_user().nick().clone();
+        let settings = self.shared_state.settings();
+        let motd = match settings.motd {
+            Some(ref m) => m,
+            None => return error_resp!(Command::ERR_NOMOTD(Responses::NoMotd { nick: nick })),
+        };
+        let mut result = vec![
+            IRCMessage {
+                prefix: None,
+                command: Command::RPL_MOTDSTART(Responses::MotdStart {
+                    nick: nick.clone(),
+                    server: self.shared_state.hostname.clone(),
+                }),
+            },
+        ];
+        for line in motd.iter() {
+            result.push(IRCMessage {
+                prefix: None,
+                command: Command::RPL_MOTD(Responses::Motd {
+                    nick: nick.clone(),
+                    line: line.clone(),
+                }),
+            });
+        }
+        result.push(IRCMessage {
+            prefix: None,
+            command: Command::RPL_ENDOFMOTD(Responses::EndOfMotd { nick: nick }),
+        });
+        result
+    }
+
+    fn rehash(&mut self) -> Vec<IRCMessage> {
+        let (prefix, nick) = {
+            let user = self.get_user();
+            (user.identifier().as_prefix(), user.nick().clone())
+        };
+        if !self.get_user().is_operator() {
+            return error_resp!(Command::ERR_NOPRIVILEGES(Responses::NoPrivileges { nick: nick }));
+        }
+
+        let mut result = vec![
+            IRCMessage {
+                prefix: None,
+                command: Command::RPL_REHASHING(Responses::Rehashing {
+                    nick: nick.clone(),
+                    config_file: self.shared_state.configuration_path.clone().unwrap_or(
+                        "*".to_string(),
+                    ),
+                }),
+            },
+        ];
+        match self.server.lock().unwrap().rehash(&prefix) {
+            Ok(restart_required) => {
+                for setting in restart_required {
+                    result.extend(notice_resp!(
+                        nick.clone(),
+                        format!("Changed {} requires a restart to take effect", setting)
+                    ));
+                }
+            }
+            Err(e) => result.extend(notice_resp!(nick, format!("Rehash failed: {}", e))),
+        }
+        result
+    }
+
+    fn shutdown(&mut self, kind: ShutdownKind, reason: Option<String>) -> Vec<IRCMessage> {
+        let (prefix, nick) = {
+            let user = self.get_user();
+            (user.identifier().as_prefix(), user.nick().clone())
+        };
+        if !self.get_user().has_privilege(&OperatorPrivilege::Die) {
+            return error_resp!(Command::ERR_NOPRIVILEGES(Responses::NoPrivileges { nick: nick }));
+        }
+        let reason = reason.unwrap_or(
+            self.shared_state.settings().configuration.shutdown_message.clone(),
+        );
+        if !self.server.lock().unwrap().shutdown(
+            &prefix,
+            Shutdown {
+                kind: kind,
+                reason: reason,
+            },
+        )
+        {
+            return notice_resp!(nick, "Server is already shutting down".to_string());
+        }
+        Vec::new()
+    }
+
+    fn stats(&mut self, query: Option<Requests::StatsQuery>) -> Vec<IRCMessage> {
+        let nick = self.get_user().nick().clone();
+        let query = match query {
+            Some(q) => q,
+            None => {
+                return error_resp!(Command::ERR_NEEDMOREPARAMS(Responses::NeedMoreParams {
+                    command: "STATS".to_string(),
+                }))
+            }
+        };
+
+        // Everything but uptime and command usage exposes other users or the configuration.
+        match query {
+            Requests::StatsQuery::U |
+            Requests::StatsQuery::M => {}
+            _ if !self.get_user().is_operator() => {
+                return error_resp!(Command::ERR_NOPRIVILEGES(
+                    Responses::NoPrivileges { nick: nick },
+                ));
+            }
+            _ => {}
+        }
+
+        let mut result = Vec::new();
+        match query {
+            Requests::StatsQuery::U => {
+                let uptime = chrono::offset::Utc::now().signed_duration_since(
+                    self.shared_state.created,
+                );
+                result.push(IRCMessage {
+                    prefix: None,
+                    command: Command::RPL_STATSUPTIME(Responses::StatsUptime {
+                        nick: nick.clone(),
+                        uptime: uptime.num_seconds(),
+                    }),
+                });
+            }
+            Requests::StatsQuery::M => {
+                let server = self.server.lock().unwrap();
+                let mut usage: Vec<_> = server.command_usage().collect();
+                usage.sort();
+                for (command, count) in usage {
+                    result.push(IRCMessage {
+                        prefix: None,
+                        command: Command::RPL_STATSCOMMANDS(Responses::StatsCommands {
+                            nick: nick.clone(),
+                            command: command.to_string(),
+                            count: *count,
+                        }),
+                    });
+                }
+            }
+            Requests::StatsQuery::L => {
+                let now = chrono::offset::Utc::now();
+                let server = self.server.lock().unwrap();
+                for link in server.links().map(|(_, l)| l) {
+                    result.push(IRCMessage {
+                        prefix: None,
+                        command: Command::RPL_STATSLINKINFO(Responses::StatsLinkInfo {
+                            nick: nick.clone(),
+                            link: link.name(),
+                            sendq: link.sendq(),
+                            sent_messages: link.sent_messages(),
+                            sent_kbytes: link.sent_bytes() / 1024,
+                            received_messages: link.received_messages(),
+                            received_kbytes: link.received_bytes() / 1024,
+                            time_open: now.signed_duration_since(link.opened).num_seconds(),
+                        }),
+                    });
+                }
+            }
+            Requests::StatsQuery::O => {
+                for oper in self.shared_state.settings().configuration.operators.iter() {
+                    let any_host = vec!["*@*".to_string()];
+                    let hosts = if oper.hosts.is_empty() {
+                        &any_host
+                    } else {
+                        &oper.hosts
+                    };
+                    for host in hosts {
+                        result.push(IRCMessage {
+                            prefix: None,
+                            command: Command::RPL_STATSOLINE(Responses::StatsOLine {
+                                nick: nick.clone(),
+                                hostmask: host.clone(),
+                                name: oper.name.clone(),
+                            }),
+                        });
+                    }
+                }
+            }
+            // One line per ip range or host mask of each class.
+            Requests::StatsQuery::I => {
+                let settings = self.shared_state.settings();
+                for class in settings.configuration.classes.iter() {
+                    let mut masks: Vec<_> = class.ips.iter().chain(class.hosts.iter()).collect();
+                    let any = "*@*".to_string();
+                    if masks.is_empty() {
+                        masks.push(&any);
+                    }
+                    for mask in masks {
+                        result.push(IRCMessage {
+                            prefix: None,
+                            command: Command::RPL_STATSILINE(Responses::StatsILine {
+                                nick: nick.clone(),
+                                mask: mask.clone(),
+                                class: class.name.clone(),
+                            }),
+                        });
+                    }
+                }
+            }
+            Requests::StatsQuery::Y => {
+                let settings = self.shared_state.settings();
+                for class in settings.configuration.classes.iter() {
+                    result.push(IRCMessage {
+                        prefix: None,
+                        command: Command::RPL_STATSYLINE(Responses::StatsYLine {
+                            nick: nick.clone(),
+                            class: class.name.clone(),
+                            ping_frequency: class.ping_frequency_seconds,
+                            connect_frequency: 0,
+                            max_sendq: class.max_sendq,
+                        }),
+                    });
+                }
+            }
+            Requests::StatsQuery::K => {
+                let mut server = self.server.lock().unwrap();
+                for ban in server.bans().iter() {
+                    let (username, host) = match ban.mask.find('@') {
+                        Some(idx) => (ban.mask[..idx].to_string(), ban.mask[idx + 1..].to_string()),
+                        None => ("*".to_string(), ban.mask.clone()),
+                    };
+                    let kind = ban.kind.to_string().chars().next().unwrap();
+                    result.push(IRCMessage {
+                        prefix: None,
+                        command: Command::RPL_STATSKLINE(Responses::StatsKLine {
+                            nick: nick.clone(),
+                            // Temporary bans are reported in lowercase.
+                            kind: if ban.expires.is_some() {
+                                kind.to_ascii_lowercase()
+                            } else {
+                                kind
+                            },
+                            host: host,
+                            username: username,
+                            reason: ban.reason.clone(),
+                        }),
+                    });
+                }
+            }
+            _ => {}
+        }
+        result.push(IRCMessage {
+            prefix: None,
+            command: Command::RPL_ENDOFSTATS(Responses::EndOfStats {
+                nick: nick,
+                query: query.to_string(),
+            }),
+        });
+        result
+    }
+
+    fn change_nick(&mut self, nickname: String) -> Vec<IRCMessage> {
+        let old = self.get_user().identifier().clone();
+        if &nickname == old.nick() {
+            return Vec::new();
+        }
+        let mut new = old.clone();
+        new.set_nick(nickname.clone());
+
+        if !user::valid_nick(&nickname) {
+            return error_resp!(Command::ERR_ERRONEUSNICKNAME(Responses::ErroneousNickname {
+                nick: old.nick().clone(),
+                target: nickname,
+            }));
+        }
+        let mut server = self.server.lock().unwrap();
+        match server.replace_nick(&old, &new) {
+            Ok(()) => {}
+            Err(ServerError::NickInUse) => {
+                return error_resp!(Command::ERR_NICKNAMEINUSE(
+                    Responses::NICKNAMEINUSE { nick: nickname },
+                ))
+            }
+            Err(e) => {
+                error!("Failed to change nick of {:?} to {}: {:?}.", old, nickname, e);
+                return Vec::new();
+            }
+        }
+        server.server_notice(
+            &SnoMask::Nick,
+            &format!("Nick change: From {} to {} [{}@{}]", old.nick(), nickname, old.username(), old.hostname()),
+        );
+        drop(server);
+
+        self.get_user_mut().set_nick(nickname.clone());
+        self.link.set_name(
+            format!("{}[{}]", nickname, self.socket.remote.ip()),
+        );
+        let mut result = vec![
+            IRCMessage {
+                prefix: Some(old.as_prefix()),
+                command: Command::NICK(Requests::Nick { nickname: nickname }),
+            },
+        ];
+        result.extend(self.check_nick_owner());
+        result
+    }
+
+    fn oper(&mut self, name: String, password: String) -> Vec<IRCMessage> {
+        let (ident, nick) = {
+            let user = self.get_user();
+            (user.identifier().clone(), user.nick().clone())
+        };
+        let operator = self.shared_state
+            .settings()
+            .configuration
+            .authenticate_operator(&name, &password)
+            .cloned();
+        let operator = match operator {
+            Some(o) => o,
+            None => {
+                warn!("{} failed OPER attempt as {}.", ident.as_prefix(), name);
+                self.server.lock().unwrap().server_notice(
+                    &SnoMask::Oper,
+                    &format!("Failed OPER attempt as {} by {}", name, ident.as_prefix()),
+                );
+                return error_resp!(Command::ERR_PASSWDMISMATCH(
+                    Responses::PasswdMismatch { nick: nick },
+                ));
+            }
+        };
+
+        let userhost = format!("{}@{}", ident.username(), ident.hostname());
+        if operator.hosts.len() > 0 && !operator.hosts.iter().any(|h| mask::matches(h, &userhost)) {
+            warn!("{} OPER as {} from disallowed host.", ident.as_prefix(), name);
+            self.server.lock().unwrap().server_notice(
+                &SnoMask::Oper,
+                &format!(
+                    "Failed OPER attempt as {} by {} (host mismatch)",
+                    name,
+                    ident.as_prefix()
+                ),
+            );
+            return error_resp!(Command::ERR_NOOPERHOST(Responses::NoOperHost { nick: nick }));
+        }
+
+        info!("{} is now operator {}.", ident.as_prefix(), name);
+        {
+            let mut server = self.server.lock().unwrap();
+            server.server_notice(
+                &SnoMask::Oper,
+                &format!("{} is now an operator ({})", ident.as_prefix(), name),
+            );
+            server.add_operator(
+                &ident,
+                SnoMask::apply(&HashSet::new(), DEFAULT_SNOMASK),
+            );
+        }
+        let mut result = vec![
+            IRCMessage {
+                prefix: None,
+                command: Command::RPL_YOUREOPER(Responses::YoureOper { nick: nick }),
+            },
+        ];
+        result.extend(self.get_user_mut().oper(&operator.privileges));
+        result
+    }
+
+    fn kill(&mut self, nickname: String, comment: String) -> Vec<IRCMessage> {
+        let nick = self.get_user().nick().clone();
+        if !self.get_user().has_privilege(&OperatorPrivilege::Kill) {
+            return error_resp!(Command::ERR_NOPRIVILEGES(Responses::NoPrivileges { nick: nick }));
+        }
+        if nickname == self.shared_state.hostname {
+            return error_resp!(Command::ERR_CANTKILLSERVER(
+                Responses::CantKillServer { nick: nick },
+            ));
+        }
+
+        let server = self.server.lock().unwrap();
+        let reason = format!("Killed ({} ({}))", nick, comment);
+        match server.disconnect_user(&UserIdentifier::from_nick(&nickname), &reason) {
+            Ok(victim) => {
+                server.server_notice(
+                    &SnoMask::Kill,
+                    &format!(
+                        "Received KILL message for {}. From {} ({})",
+                        victim.as_prefix(),
+                        nick,
+                        comment
+                    ),
+                );
+                Vec::new()
+            }
+            Err(_) => {
+                error_resp!(Command::ERR_NOSUCHNICK(Responses::NoSuchNick {
+                    nick: nick,
+                    target: nickname,
+                }))
+            }
+        }
+    }
+
+    fn chghost(&mut self, nickname: String, host: String) -> Vec<IRCMessage> {
+        let nick = self.get_user().nick().clone();
+        if !self.get_user().has_privilege(&OperatorPrivilege::Vhost) {
+            return error_resp!(Command::ERR_NOPRIVILEGES(Responses::NoPrivileges { nick: nick }));
+        }
+        if !dns::valid_hostname(&host) {
+            return notice_resp!(nick, format!("Invalid host: {}", host));
+        }
+
+        let server = self.server.lock().unwrap();
+        match server.notify_user(&UserIdentifier::from_nick(&nickname), Event::Vhost(host.clone())) {
+            Ok(target) => notice_resp!(nick, format!("Changed host of {} to {}", target.nick(), host)),
+            Err(_) => {
+                error_resp!(Command::ERR_NOSUCHNICK(Responses::NoSuchNick {
+                    nick: nick,
+                    target: nickname,
+                }))
+            }
+        }
+    }
+
+    // Lists the members of the channel named mask, or the users matching mask. Operators with the
+    // RealHost privilege also match users by their real host.
+    fn who(&mut self, mask: Option<String>, operators: bool) -> Vec<IRCMessage> {
+        let user = self.get_user();
+        let nick = user.nick().clone();
+        let real_host = user.has_privilege(&OperatorPrivilege::RealHost);
+        let mask = match mask {
+            Some(ref m) if m != "0" => m.clone(),
+            _ => "*".to_string(),
+        };
+
+        let mut server = self.server.lock().unwrap();
+        let (channel, users): (String, Vec<UserIdentifier>) = if mask.starts_with('#') {
+            match server.lookup_channel(&ChannelIdentifier::from_name(&mask)) {
+                Some(c) => (c.name().clone(), c.users().cloned().collect()),
+                None => (mask.clone(), Vec::new()),
+            }
+        } else {
+            let users = server
+                .users()
+                .filter(|u| {
+                    mask::matches(&mask, u.nick()) || mask::matches(&mask, &u.as_prefix()) ||
+                        mask::matches(&mask, u.displayed_hostname()) ||
+                        (real_host && mask::matches(&mask, u.hostname()))
+                })
+                .cloned()
+                .collect();
+            ("*".to_string(), users)
+        };
+
+        let mut result = Vec::new();
+        for u in users.iter() {
+            let operator = server.is_operator(u);
+            if operators && !operator {
+                continue;
+            }
+            result.push(IRCMessage {
+                prefix: None,
+                command: Command::RPL_WHOREPLY(Responses::WhoReply {
+                    nick: nick.clone(),
+                    channel: channel.clone(),
+                    username: u.username().clone(),
+                    host: u.displayed_hostname().clone(),
+                    server: self.shared_state.hostname.clone(),
+                    target: u.nick().clone(),
+                    flags: if operator { "H*" } else { "H" }.to_string(),
+                    realname: u.realname().clone(),
+                }),
+            });
+        }
+        result.push(IRCMessage {
+            prefix: None,
+            command: Command::RPL_ENDOFWHO(Responses::EndOfWho {
+                nick: nick,
+                mask: mask,
+            }),
+        });
+        result
+    }
+
+    // Describes each of the nicks in masks. The real host is only shown to the user itself and to
+    // operators with the RealHost privilege.
+    fn whois(&mut self, masks: Vec<String>) -> Vec<IRCMessage> {
+        let user = self.get_user();
+        let nick = user.nick().clone();
+        let real_host = user.has_privilege(&OperatorPrivilege::RealHost);
+        let settings = self.shared_state.settings();
+        let server = self.server.lock().unwrap();
+
+        let mut result = Vec::new();
+        for target in masks {
+            let ident = match server.find_user(&UserIdentifier::from_nick(&target)) {
+                Some(ident) => ident.clone(),
+                None => {
+                    result.push(IRCMessage {
+                        prefix: None,
+                        command: Command::ERR_NOSUCHNICK(Responses::NoSuchNick {
+                            nick: nick.clone(),
+                            target: target.clone(),
+                        }),
+                    });
+                    result.push(IRCMessage {
+                        prefix: None,
+                        command: Command::RPL_ENDOFWHOIS(Responses::EndOfWhois {
+                            nick: nick.clone(),
+                            target: target,
+                        }),
+                    });
+                    continue;
+                }
+            };
+            let target = ident.nick().clone();
+            let channels: Vec<String> = server
+                .channels()
+                .filter(|&(_, c)| c.has_user(&ident))
+                .map(|(_, c)| c.name().clone())
+                .collect();
+
+            result.push(IRCMessage {
+                prefix: None,
+                command: Command::RPL_WHOISUSER(Responses::WhoisUser {
+                    nick: nick.clone(),
+                    target: target.clone(),
+                    username: ident.username().clone(),
+                    host: ident.displayed_hostname().clone(),
+                    realname: ident.realname().clone(),
+                }),
+            });
+            if channels.len() > 0 {
+                result.push(IRCMessage {
+                    prefix: None,
+                    command: Command::RPL_WHOISCHANNELS(Responses::WhoisChannels {
+                        nick: nick.clone(),
+                        target: target.clone(),
+                        channels: channels,
+                    }),
+                });
+            }
+            result.push(IRCMessage {
+                prefix: None,
+                command: Command::RPL_WHOISSERVER(Responses::WhoisServer {
+                    nick: nick.clone(),
+                    target: target.clone(),
+                    server: self.shared_state.hostname.clone(),
+                    info: settings.configuration.network_name.clone(),
+                }),
+            });
+            if server.is_operator(&ident) {
+                result.push(IRCMessage {
+                    prefix: None,
+                    command: Command::RPL_WHOISOPERATOR(Responses::WhoisOperator {
+                        nick: nick.clone(),
+                        target: target.clone(),
+                    }),
+                });
+            }
+            if let Some(account) = server.account(&ident) {
+                result.push(IRCMessage {
+                    prefix: None,
+                    command: Command::RPL_WHOISACCOUNT(Responses::WhoisAccount {
+                        nick: nick.clone(),
+                        target: target.clone(),
+                        account: account.clone(),
+                    }),
+                });
+            }
+            if real_host || target == nick {
+                result.push(IRCMessage {
+                    prefix: None,
+                    command: Command::RPL_WHOISHOST(Responses::WhoisHost {
+                        nick: nick.clone(),
+                        target: target.clone(),
+                        host: ident.hostname().clone(),
+                    }),
+                });
+            }
+            result.push(IRCMessage {
+                prefix: None,
+                command: Command::RPL_ENDOFWHOIS(Responses::EndOfWhois {
+                    nick: nick.clone(),
+                    target: target,
+                }),
+            });
+        }
+        result
+    }
+
+    // Answers a wrong NickServ password for account, telling operators. Closes the link once the
+    // connection has sent too many.
+    fn password_failed(&mut self, account: &str) -> Vec<IRCMessage> {
+        let ident = self.get_user().identifier().as_prefix();
+        warn!("{} sent a wrong password for {}.", ident, account);
+        self.server.lock().unwrap().server_notice(
+            &SnoMask::Oper,
+            &format!("Wrong NickServ password for {} by {}", account, ident),
+        );
+        let mut result = vec![self.nickserv_notice(format!("Invalid password for {}.", account))];
+        self.password_failures += 1;
+        if self.password_failures >= nickserv::MAX_PASSWORD_FAILURES {
+            result.extend(self.close_link("Too many wrong passwords".to_string()));
+        }
+        result
+    }
+
+    fn nickserv_notice(&self, message: String) -> IRCMessage {
+        IRCMessage {
+            prefix: Some(format!(
+                "{}!{}@{}",
+                nickserv::NICK,
+                nickserv::NICK,
+                self.shared_state.hostname
+            )),
+            command: Command::NOTICE(Requests::Notice {
+                targets: vec![self.get_user().nick().clone()],
+                message: message,
+            }),
+        }
+    }
+
+    // Handles a PRIVMSG to NickServ.
+    fn nickserv(&mut self, text: String) -> Vec<IRCMessage> {
+        let request = match nickserv::parse(&text) {
+            Ok(r) => r,
+            Err(usage) => return vec![self.nickserv_notice(usage)],
+        };
+        let (nick, account) = {
+            let user = self.get_user();
+            (user.nick().clone(), user.account().cloned())
+        };
+        let reply = |connection: &Self, message: String| vec![connection.nickserv_notice(message)];
+        let not_logged_in = "You are not logged in.".to_string();
+        match request {
+            NickServRequest::Register { password, email } => {
+                if let Some(a) = account {
+                    return reply(self, format!("You are already logged in as {}.", a));
+                }
+                if password.len() < nickserv::MIN_PASSWORD_LENGTH {
+                    return reply(
+                        self,
+                        format!(
+                            "Passwords must be at least {} characters long.",
+                            nickserv::MIN_PASSWORD_LENGTH
+                        ),
+                    );
+                }
+                if email.as_ref().map_or(false, |e| !nickserv::valid_email(e)) {
+                    return reply(self, format!("{} is not a valid email address.", email.unwrap()));
+                }
+                let credentials = Credentials::new(&password);
+                let registered = self.server.lock().unwrap().accounts().register(
+                    &nick,
+                    credentials,
+                    email,
+                ).map(|a| a.name.clone());
+                match registered {
+                    Ok(name) => {
+                        let mut result = reply(self, format!("{} is now registered.", name));
+                        result.extend(self.log_in(&name, None));
+                        result
+                    }
+                    Err(_) => reply(self, format!("{} is already registered.", nick)),
+                }
+            }
+
+            NickServRequest::Identify { account: name, password } => {
+                if let Some(a) = account {
+                    return reply(self, format!("You are already logged in as {}.", a));
+                }
+                let name = match name {
+                    Some(name) => name,
+                    None => {
+                        match self.server.lock().unwrap().accounts().owner(&nick) {
+                            Some(a) => a.name.clone(),
+                            None => return reply(self, format!("{} is not registered.", nick)),
+                        }
+                    }
+                };
+                // Verified outside the server lock, as it is slow.
+                let stored = self.server.lock().unwrap().accounts().get(&name).cloned();
+                match stored {
+                    Some(ref a) if password::verify(&password, &a.password) => {
+                        self.log_in(&a.name, a.vhost.clone())
+                    }
+                    None => reply(self, format!("{} is not registered.", name)),
+                    Some(_) => self.password_failed(&name),
+                }
+            }
+
+            NickServRequest::Group => {
+                let account = match account {
+                    Some(a) => a,
+                    None => return reply(self, not_logged_in),
+                };
+                let grouped = self.server.lock().unwrap().accounts().group(&account, &nick);
+                match grouped {
+                    Ok(_) => {
+                        let mut result =
+                            reply(self, format!("{} is now grouped to {}.", nick, account));
+                        result.extend(self.check_nick_owner());
+                        result
+                    }
+                    Err(_) => reply(self, format!("{} is already registered.", nick)),
+                }
+            }
+
+            NickServRequest::Ghost { nick: target, password } => {
+                self.ghost(target, password, false)
+            }
+
+            NickServRequest::Regain { nick: target, password } => {
+                self.ghost(target, password, true)
+            }
+
+            NickServRequest::Drop { password } => {
+                let account = match account {
+                    Some(a) => a,
+                    None => return reply(self, not_logged_in),
+                };
+                let ident = self.get_user().identifier().clone();
+                let stored = self.server.lock().unwrap().accounts().get(&account).cloned();
+                if !stored.map_or(false, |a| password::verify(&password, &a.password)) {
+                    return self.password_failed(&account);
+                }
+                let mut server = self.server.lock().unwrap();
+                // Otherwise whoever registers the name next would own them.
+                let founded = server.registry().founded_by(&account);
+                if !founded.is_empty() {
+                    drop(server);
+                    return reply(
+                        self,
+                        format!(
+                            "{} still owns {}. Drop them with ChanServ first.",
+                            account,
+                            founded.join(", ")
+                        ),
+                    );
+                }
+                if let Err(e) = server.accounts().remove(&account) {
+                    error!("Failed to drop account {}: {:?}.", account, e);
+                }
+                server.registry().remove_account(&account);
+                for user in server.logged_in(&account) {
+                    if user != ident {
+                        let _ = server.notify_user(&user, Event::LoggedOut);
+                    }
+                }
+                server.set_account(&ident, None);
+                drop(server);
+                self.get_user_mut().set_account(None);
+                self.nick_enforcement = None;
+                reply(self, format!("{} has been dropped.", account))
+            }
+
+            NickServRequest::SetPassword(password) => {
+                let account = match account {
+                    Some(a) => a,
+                    None => return reply(self, not_logged_in),
+                };
+                if password.len() < nickserv::MIN_PASSWORD_LENGTH {
+                    return reply(
+                        self,
+                        format!(
+                            "Passwords must be at least {} characters long.",
+                            nickserv::MIN_PASSWORD_LENGTH
+                        ),
+                    );
+                }
+                let credentials = Credentials::new(&password);
+                let _ = self.server.lock().unwrap().accounts().set_password(&account, credentials);
+                reply(self, "Your password has been changed.".to_string())
+            }
+
+            NickServRequest::SetEmail(email) => {
+                let account = match account {
+                    Some(a) => a,
+                    None => return reply(self, not_logged_in),
+                };
+                if email.as_ref().map_or(false, |e| !nickserv::valid_email(e)) {
+                    return reply(self, format!("{} is not a valid email address.", email.unwrap()));
+                }
+                let message = match email {
+                    Some(ref e) => format!("Your email address is now {}.", e),
+                    None => "Your email address has been removed.".to_string(),
+                };
+                let _ = self.server.lock().unwrap().accounts().set_email(&account, email);
+                reply(self, message)
+            }
+
+            NickServRequest::Vhost { account: name, host } => {
+                if !self.get_user().has_privilege(&OperatorPrivilege::Vhost) {
+                    return reply(self, "Permission denied.".to_string());
+                }
+                if host.as_ref().map_or(false, |h| !dns::valid_hostname(h)) {
+                    return reply(self, format!("Invalid host: {}", host.unwrap()));
+                }
+                let mut server = self.server.lock().unwrap();
+                if server.accounts().set_vhost(&name, host.clone()).is_err() {
+                    return reply(self, format!("{} is not registered.", name));
+                }
+                match host {
+                    Some(host) => {
+                        for user in server.logged_in(&name) {
+                            let _ = server.notify_user(&user, Event::Vhost(host.clone()));
+                        }
+                        reply(self, format!("The vhost of {} is now {}.", name, host))
+                    }
+                    None => reply(self, format!("The vhost of {} has been removed.", name)),
+                }
+            }
+
+            NickServRequest::Help => {
+                nickserv::help()
+                    .into_iter()
+                    .map(|l| self.nickserv_notice(l))
+                    .collect()
+            }
+        }
+    }
+
+    fn chanserv_notice(&self, message: String) -> IRCMessage {
+        IRCMessage {
+            prefix: Some(chanserv::prefix(&self.shared_state.hostname)),
+            command: Command::NOTICE(Requests::Notice {
+                targets: vec![self.get_user().nick().clone()],
+                message: message,
+            }),
+        }
+    }
+
+    // Checks that channel is registered and the user has at least level in it. Errors are the
+    // notice to reply with.
+    fn check_access(
+        &self,
+        server: &mut Server,
+        channel: &String,
+        level: AccessLevel,
+    ) -> Result<(), String> {
+        let user = self.get_user();
+        match server.registry().get(channel) {
+            Some(r) if r.access(user.identifier(), user.account()) >= Some(level) => Ok(()),
+            Some(_) => Err("Permission denied.".to_string()),
+            None => Err(format!("{} is not registered.", channel)),
+        }
+    }
+
+    // Handles a PRIVMSG to ChanServ.
+    fn chanserv(&mut self, text: String) -> Vec<IRCMessage> {
+        let request = match chanserv::parse(&text) {
+            Ok(r) => r,
+            Err(usage) => return vec![self.chanserv_notice(usage)],
+        };
+        let (ident, account) = {
+            let user = self.get_user();
+            (user.identifier().clone(), user.account().cloned())
+        };
+        let reply = |connection: &Self, message: String| vec![connection.chanserv_notice(message)];
+        let server = Arc::clone(&self.server);
+        let mut server = server.lock().unwrap();
+        match request {
+            ChanServRequest::Register { channel } => {
+                let account = match account {
+                    Some(a) => a,
+                    None => {
+                        return reply(
+                            self,
+                            format!("You must identify to {} first.", nickserv::NICK),
+                        )
+                    }
+                };
+                let topic = match server.lookup_channel(&ChannelIdentifier::from_name(&channel)) {
+                    Some(c) if c.has_status(&ident, MemberStatus::Operator) => c.topic().clone(),
+                    _ => {
+                        return reply(
+                            self,
+                            format!("You must be a channel operator in {} to register it.", channel),
+                        )
+                    }
+                };
+                match server.registry().register(&channel, &account, topic) {
+                    Ok(_) => {
+                        info!("{} registered {} to {}.", ident.as_prefix(), channel, account);
+                        reply(self, format!("{} is now registered to {}.", channel, account))
+                    }
+                    Err(_) => reply(self, format!("{} is already registered.", channel)),
+                }
+            }
+
+            ChanServRequest::Drop { channel } => {
+                if let Err(e) = self.check_access(&mut server, &channel, AccessLevel::Founder) {
+                    return reply(self, e);
+                }
+                let _ = server.registry().remove(&channel);
+                server.collect_channel(&ChannelIdentifier::from_name(&channel));
+                info!("{} dropped {}.", ident.as_prefix(), channel);
+                reply(self, format!("{} has been dropped.", channel))
+            }
+
+            ChanServRequest::Info { channel } => {
+                let registered = match server.registry().get(&channel) {
+                    Some(r) => r.clone(),
+                    None => return reply(self, format!("{} is not registered.", channel)),
+                };
+                let on_off = |b| if b { "ON" } else { "OFF" };
+                vec![
+                    format!("Information on {}:", registered.name),
+                    format!("Founder: {}", registered.founder),
+                    format!(
+                        "Registered: {}",
+                        chrono::NaiveDateTime::from_timestamp(registered.registered_at, 0)
+                    ),
+                    format!("Topic lock: {}", on_off(registered.topic_lock)),
+                    format!(
+                        "Mode lock: {}",
+                        registered.mode_lock.unwrap_or("(none)".to_string())
+                    ),
+                    format!(
+                        "Entry message: {}",
+                        registered.entry_message.unwrap_or("(none)".to_string())
+                    ),
+                ].into_iter()
+                    .map(|l| self.chanserv_notice(l))
+                    .collect()
+            }
+
+            ChanServRequest::AccessList { channel } => {
+                if let Err(e) = self.check_access(&mut server, &channel, AccessLevel::Op) {
+                    return reply(self, e);
+                }
+                let registered = server.registry().get(&channel).unwrap().clone();
+                let mut result = reply(self, format!("Access list of {}:", registered.name));
+                result.push(self.chanserv_notice(
+                    format!("  {} FOUNDER", registered.founder),
+                ));
+                for entry in registered.access.iter() {
+                    result.push(self.chanserv_notice(format!(
+                        "  {} {}",
+                        entry.target,
+                        format!("{:?}", entry.level).to_uppercase()
+                    )));
+                }
+                result
+            }
+
+            ChanServRequest::AccessAdd {
+                channel,
+                target,
+                level,
+            } => {
+                // Only the founder hands out op access.
+                let needed = if level >= AccessLevel::Op {
+                    AccessLevel::Founder
+                } else {
+                    AccessLevel::Op
+                };
+                if let Err(e) = self.check_access(&mut server, &channel, needed) {
+                    return reply(self, e);
+                }
+                if !channel_registry::is_mask(&target) &&
+                    server.accounts().get(&target).is_none()
+                {
+                    return reply(self, format!("{} is not registered.", target));
+                }
+                let _ = server.registry().set_access(&channel, &target, Some(level));
+                let level = format!("{:?}", level).to_uppercase();
+                reply(self, format!("{} now has {} access to {}.", target, level, channel))
+            }
+
+            ChanServRequest::AccessDel { channel, target } => {
+                let level = server.registry().get(&channel).and_then(|r| {
+                    r.access
+                        .iter()
+                        .find(|e| e.target.eq_ignore_ascii_case(&target))
+                        .map(|e| e.level)
+                });
+                let needed = if level >= Some(AccessLevel::Op) {
+                    AccessLevel::Founder
+                } else {
+                    AccessLevel::Op
+                };
+                if let Err(e) = self.check_access(&mut server, &channel, needed) {
+                    return reply(self, e);
+                }
+                if level.is_none() {
+                    return reply(self, format!("{} is not on the access list of {}.", target, channel));
+                }
+                let _ = server.registry().set_access(&channel, &target, None);
+                reply(self, format!("{} has been removed from the access list of {}.", target, channel))
+            }
+
+            ChanServRequest::SetTopicLock { channel, lock } => {
+                if let Err(e) = self.check_access(&mut server, &channel, AccessLevel::Founder) {
+                    return reply(self, e);
+                }
+                let _ = server.registry().set_topic_lock(&channel, lock);
+                let on_off = if lock { "ON" } else { "OFF" };
+                reply(self, format!("Topic lock of {} is now {}.", channel, on_off))
+            }
+
+            ChanServRequest::SetModeLock { channel, lock } => {
+                if let Err(e) = self.check_access(&mut server, &channel, AccessLevel::Founder) {
+                    return reply(self, e);
+                }
+                let locked = match lock {
+                    Some(ref l) => {
+                        match channel_registry::parse_mode_lock(l) {
+                            Some(locked) => locked,
+                            None => return reply(self, format!("Invalid mode lock: {}", l)),
+                        }
+                    }
+                    None => (Vec::new(), Vec::new()),
+                };
+                let _ = server.registry().set_mode_lock(&channel, lock.clone());
+                // Brought in line with the lock right away.
+                let chanserv = chanserv::prefix(&self.shared_state.hostname);
+                if let Some(c) = server.lookup_channel_mut(&ChannelIdentifier::from_name(&channel)) {
+                    let changes = locked
+                        .0
+                        .into_iter()
+                        .map(|m| ModeChange::Flag(SetMode::Add, m))
+                        .chain(locked.1.into_iter().map(
+                            |m| ModeChange::Flag(SetMode::Remove, m),
+                        ))
+                        .collect();
+                    c.apply_modes(chanserv, changes);
+                }
+                match lock {
+                    Some(l) => reply(self, format!("Mode lock of {} is now {}.", channel, l)),
+                    None => reply(self, format!("Mode lock of {} has been removed.", channel)),
+                }
+            }
+
+            ChanServRequest::SetEntryMessage { channel, message } => {
+                if let Err(e) = self.check_access(&mut server, &channel, AccessLevel::Founder) {
+                    return reply(self, e);
+                }
+                let _ = server.registry().set_entry_message(&channel, message.clone());
+                match message {
+                    Some(m) => reply(self, format!("Entry message of {} is now: {}", channel, m)),
+                    None => reply(self, format!("Entry message of {} has been removed.", channel)),
+                }
+            }
+
+            ChanServRequest::Help => {
+                chanserv::help()
+                    .into_iter()
+                    .map(|l| self.chanserv_notice(l))
+                    .collect()
+            }
+        }
+    }
+
+    // Sends text to target, a channel or a nick. Only a PRIVMSG is answered with errors.
+    fn message(&mut self, target: String, text: &String, notice: bool) -> Vec<IRCMessage> {
+        let ident = self.get_user().identifier().clone();
+        let res = self.server.lock().unwrap().send(&ident, &target, text, notice);
+        match res {
+            Ok(()) => Vec::new(),
+            Err(_) if notice => Vec::new(),
+            Err(ServerError::CannotSend(reason)) => {
+                error_resp!(Command::ERR_CANNOTSENDTOCHAN(Responses::CannotSendToChan {
+                    nick: ident.nick().clone(),
+                    channel: target,
+                    reason: reason.to_string(),
+                }))
+            }
+            Err(ServerError::NoSuchChannel) => {
+                error_resp!(Command::ERR_NOSUCHCHANNEL(Responses::NoSuchChannel {
+                    nick: ident.nick().clone(),
+                    channel: target,
+                }))
+            }
+            Err(_) => {
+                error_resp!(Command::ERR_NOSUCHNICK(Responses::NoSuchNick {
+                    nick: ident.nick().clone(),
+                    target: target,
+                }))
+            }
+        }
+    }
+
+    // Invites the user with nick target to the channel named name.
+    fn invite(&mut self, target: String, name: String) -> Vec<IRCMessage> {
+        let ident = self.get_user().identifier().clone();
+        let nick = ident.nick().clone();
+        let channel_ident = ChannelIdentifier::from_name(&name);
+        let mut server = self.server.lock().unwrap();
+        {
+            let channel = match server.lookup_channel(&channel_ident) {
+                Some(c) => c,
+                None => {
+                    return error_resp!(Command::ERR_NOSUCHCHANNEL(Responses::NoSuchChannel {
+                        nick: nick,
+                        channel: name,
+                    }))
+                }
+            };
+            if !channel.has_user(&ident) {
+                return error_resp!(Command::ERR_NOTONCHANNEL(Responses::NotOnChannel {
+                    nick: nick,
+                    channel: name,
+                }));
+            }
+            if channel.has_user(&UserIdentifier::from_nick(&target)) {
+                return error_resp!(Command::ERR_USERONCHANNEL(Responses::UserOnChannel {
+                    nick: nick,
+                    target: target,
+                    channel: name,
+                }));
+            }
+            if channel.has_mode(&ChannelMode::InviteOnly) &&
+                !channel.has_status(&ident, MemberStatus::Operator)
+            {
+                return error_resp!(Command::ERR_CHANOPRIVSNEEDED(Responses::ChanOPrivsNeeded {
+                    nick: nick,
+                    channel: name,
+                }));
+            }
+        }
+        let invite = Event::Message(vec![
+            IRCMessage {
+                prefix: Some(ident.as_prefix()),
+                command: Command::INVITE(Requests::Invite {
+                    nickname: target.clone(),
+                    channel: name.clone(),
+                }),
+            },
+        ]);
+        let invited = match server.notify_user(&UserIdentifier::from_nick(&target), invite) {
+            Ok(u) => u,
+            Err(_) => {
+                return error_resp!(Command::ERR_NOSUCHNICK(Responses::NoSuchNick {
+                    nick: nick,
+                    target: target,
+                }))
+            }
+        };
+        server.lookup_channel_mut(&channel_ident).unwrap().invite(&invited);
+        error_resp!(Command::RPL_INVITING(Responses::Inviting {
+            nick: nick,
+            target: target,
+            channel: name,
+        }))
+    }
+
+    // Shows or sets the topic of the channel named name.
+    fn topic(&mut self, name: String, topic: Option<String>) -> Vec<IRCMessage> {
+        let (ident, account) = {
+            let user = self.get_user();
+            (user.identifier().clone(), user.account().cloned())
+        };
+        let nick = ident.nick().clone();
+        let mut server = self.server.lock().unwrap();
+        let registered = server.registry().get(&name).map(|r| {
+            (r.topic_lock, r.access(&ident, account.as_ref()))
+        });
+        let channel = match server.lookup_channel_mut(&ChannelIdentifier::from_name(&name)) {
+            Some(c) => c,
+            None => {
+                return error_resp!(Command::ERR_NOSUCHCHANNEL(Responses::NoSuchChannel {
+                    nick: nick,
+                    channel: name,
+                }))
+            }
+        };
+        let topic = match topic {
+            Some(t) => t,
+            None => {
+                return match channel.topic().clone() {
+                    Some(topic) => {
+                        error_resp!(Command::RPL_TOPIC(Responses::Topic {
+                            nick: nick,
+                            channel: name,
+                            topic: topic,
+                        }))
+                    }
+                    None => {
+                        error_resp!(Command::RPL_NOTOPIC(Responses::NoTopic {
+                            nick: nick,
+                            channel: name,
+                        }))
+                    }
+                }
+            }
+        };
+        if !channel.has_user(&ident) {
+            return error_resp!(Command::ERR_NOTONCHANNEL(Responses::NotOnChannel {
+                nick: nick,
+                channel: name,
+            }));
+        }
+        // A ChanServ topic lock overrides +t.
+        let allowed = match registered {
+            Some((true, access)) => access >= Some(AccessLevel::Op),
+            _ => {
+                !channel.has_mode(&ChannelMode::ProtectedTopic) ||
+                    channel.has_status(&ident, MemberStatus::Operator)
+            }
+        };
+        if !allowed {
+            return error_resp!(Command::ERR_CHANOPRIVSNEEDED(Responses::ChanOPrivsNeeded {
+                nick: nick,
+                channel: name,
+            }));
+        }
+        channel.set_topic(ident.as_prefix(), topic);
+        let topic = channel.topic().clone();
+        if registered.is_some() {
+            let _ = server.registry().set_topic(&name, topic);
+        }
+        Vec::new()
+    }
+
+    // Shows or changes the modes of the channel named name.
+    fn channel_mode(
+        &mut self,
+        name: String,
+        modes: Option<String>,
+        args: Option<String>,
+    ) -> Vec<IRCMessage> {
+        let ident = self.get_user().identifier().clone();
+        let nick = ident.nick().clone();
+        let mut server = self.server.lock().unwrap();
+        let locked = server.registry().get(&name).map(|r| r.locked_modes());
+        // Setting +f needs op in the forward target too, so note the named channels the user
+        // is an operator in before borrowing this one.
+        let op_in: Vec<String> = args.as_ref().map_or(Vec::new(), |a| {
+            a.split_whitespace()
+                .map(|a| a.to_string())
+                .filter(|a| a.starts_with('#'))
+                .filter(|a| {
+                    server
+                        .lookup_channel(&ChannelIdentifier::from_name(a))
+                        .map_or(false, |c| c.has_status(&ident, MemberStatus::Operator))
+                })
+                .collect()
+        });
+        let channel = match server.lookup_channel_mut(&ChannelIdentifier::from_name(&name)) {
+            Some(c) => c,
+            None => {
+                return error_resp!(Command::ERR_NOSUCHCHANNEL(Responses::NoSuchChannel {
+                    nick: nick,
+                    channel: name,
+                }))
+            }
+        };
+        let modes = match modes {
+            Some(m) => m,
+            None => {
+                let (modes, args) = channel.mode_string(channel.has_user(&ident));
+                let mut modes = vec![modes];
+                modes.extend(args);
+                return error_resp!(Command::RPL_CHANNELMODEIS(Responses::ChannelModeIs {
+                    nick: nick,
+                    channel: name,
+                    modes: modes.join(" "),
+                }));
+            }
+        };
+        // A list mode without a mask lists it, which needs no privileges.
+        let list = match modes.trim_matches('+') {
+            "b" if args.is_none() => Some(ListMode::Ban),
+            "q" if args.is_none() => Some(ListMode::Quiet),
+            _ => None,
+        };
+        if let Some(list) = list {
+            let mut responses: Vec<IRCMessage> = channel
+                .list(list)
+                .iter()
+                .map(|m| IRCMessage {
+                    prefix: None,
+                    command: match list {
+                        ListMode::Ban => Command::RPL_BANLIST(Responses::BanList {
+                            nick: nick.clone(),
+                            channel: name.clone(),
+                            mask: m.clone(),
+                        }),
+                        ListMode::Quiet => Command::RPL_QUIETLIST(Responses::QuietList {
+                            nick: nick.clone(),
+                            channel: name.clone(),
+                            mask: m.clone(),
+                        }),
+                    },
+                })
+                .collect();
+            responses.push(IRCMessage {
+                prefix: None,
+                command: match list {
+                    ListMode::Ban => Command::RPL_ENDOFBANLIST(Responses::EndOfBanList {
+                        nick: nick,
+                        channel: name,
+                    }),
+                    ListMode::Quiet => Command::RPL_ENDOFQUIETLIST(Responses::EndOfQuietList {
+                        nick: nick,
+                        channel: name,
+                    }),
+                },
+            });
+            return responses;
+        }
+        let args: Vec<String> = args.map_or(Vec::new(), |a| {
+            a.split_whitespace().map(|a| a.to_string()).collect()
+        });
+        let mut changes = match channel::parse_modes(&modes, &args) {
+            Ok(c) => c,
+            Err(c) => {
+                return error_resp!(Command::ERR_UNKNOWNMODE(Responses::UnknownMode {
+                    nick: nick,
+                    mode: c,
+                }))
+            }
+        };
+        if !channel.has_status(&ident, MemberStatus::Operator) {
+            return error_resp!(Command::ERR_CHANOPRIVSNEEDED(Responses::ChanOPrivsNeeded {
+                nick: nick,
+                channel: name,
+            }));
+        }
+        // Changes against the ChanServ mode lock are dropped.
+        if let Some((on, off)) = locked {
+            changes.retain(|c| match *c {
+                ModeChange::Flag(SetMode::Add, ref m) => !off.contains(m),
+                ModeChange::Flag(SetMode::Remove, ref m) => !on.contains(m),
+                _ => true,
+            });
+        }
+        let mut result = Vec::new();
+        // Masks the lists have room for.
+        let mut bans = MAX_LIST_LENGTH.saturating_sub(channel.list(ListMode::Ban).len());
+        let mut quiets = MAX_LIST_LENGTH.saturating_sub(channel.list(ListMode::Quiet).len());
+        changes.retain(|c| match *c {
+            ModeChange::List(SetMode::Add, list, ref mask)
+                if !channel.list(list).iter().any(|m| m.eq_ignore_ascii_case(mask)) =>
+            {
+                let room = match list {
+                    ListMode::Ban => &mut bans,
+                    ListMode::Quiet => &mut quiets,
+                };
+                if *room == 0 {
+                    result.push(IRCMessage {
+                        prefix: None,
+                        command: Command::ERR_BANLISTFULL(Responses::BanListFull {
+                            nick: nick.clone(),
+                            channel: name.clone(),
+                            mask: mask.clone(),
+                        }),
+                    });
+                    return false;
+                }
+                *room -= 1;
+                true
+            }
+            ModeChange::Status(_, _, ref target) => {
+                let member = channel.has_user(&UserIdentifier::from_nick(target));
+                if !member {
+                    result.push(IRCMessage {
+                        prefix: None,
+                        command: Command::ERR_USERNOTINCHANNEL(Responses::UserNotInChannel {
+                            nick: nick.clone(),
+                            target: target.clone(),
+                            channel: name.clone(),
+                        }),
+                    });
+                }
+                member
+            }
+            ModeChange::Forward(SetMode::Add, ref target) => {
+                let op = op_in.contains(target);
+                if !op {
+                    result.push(IRCMessage {
+                        prefix: None,
+                        command: Command::ERR_CHANOPRIVSNEEDED(Responses::ChanOPrivsNeeded {
+                            nick: nick.clone(),
+                            channel: target.clone(),
+                        }),
+                    });
+                }
+                op
+            }
+            _ => true,
+        });
+        channel.apply_modes(ident.as_prefix(), changes);
+        result
+    }
+
+    // Logs the user in to account, showing vhost if the account has one.
+    fn log_in(&mut self, account: &String, vhost: Option<String>) -> Vec<IRCMessage> {
+        let user = self.get_user_mut();
+        user.set_account(Some(account.clone()));
+        let vhost_changed = vhost.map_or(false, |v| user.set_vhost(v));
+        let ident = user.identifier().clone();
+        {
+            let mut server = self.server.lock().unwrap();
+            server.set_account(&ident, Some(account.clone()));
+            if vhost_changed {
+                server.update_user(&ident);
+            }
+        }
+        info!("{} logged in as {}.", ident.as_prefix(), account);
+
+        let mut result = vec![
+            self.nickserv_notice(format!("You are now logged in as {}.", account)),
+        ];
+        if vhost_changed {
+            result.extend(Connection::host_hidden(&ident));
+        }
+        result.extend(self.check_nick_owner());
+        result
+    }
+
+    // Handles capability negotiation. sasl is the only capability offered.
+    fn cap(&mut self, subcommand: String, capabilities: Option<String>) -> Vec<IRCMessage> {
+        let reply = |connection: &Self, subcommand: &str, capabilities: String| {
+            vec![
+                IRCMessage {
+                    prefix: None,
+                    command: Command::CAP(Requests::Cap {
+                        target: Some(connection.nick_or_star()),
+                        subcommand: subcommand.to_string(),
+                        capabilities: Some(capabilities),
+                    }),
+                },
+            ]
+        };
+        match subcommand.as_ref() {
+            "LS" => {
+                self.negotiate(true);
+                // Capabilities have values from CAP LS 302 on.
+                let version = capabilities.and_then(|v| v.parse::<u32>().ok()).unwrap_or(0);
+                if version >= 302 {
+                    reply(self, "LS", format!("sasl={}", sasl::MECHANISMS))
+                } else {
+                    reply(self, "LS", "sasl".to_string())
+                }
+            }
+            "LIST" => {
+                let mut enabled: Vec<String> = self.capabilities.iter().cloned().collect();
+                enabled.sort();
+                reply(self, "LIST", enabled.join(" "))
+            }
+            "REQ" => {
+                self.negotiate(true);
+                let requested = capabilities.unwrap_or_default();
+                // All or nothing.
+                if !requested.split_whitespace().all(
+                    |c| c.trim_start_matches('-') == "sasl",
+                )
+                {
+                    return reply(self, "NAK", requested);
+                }
+                for c in requested.split_whitespace() {
+                    if c.starts_with('-') {
+                        self.capabilities.remove(&c[1..]);
+                    } else {
+                        self.capabilities.insert(c.to_string());
+                    }
+                }
+                reply(self, "ACK", requested)
+            }
+            "END" => {
+                let mut result = Vec::new();
+                if self.sasl.take().is_some() {
+                    result.extend(error_resp!(Command::ERR_SASLABORTED(
+                        Responses::SaslAborted { nick: self.nick_or_star() },
+                    )));
+                }
+                if !self.registered() {
+                    self.negotiate(false);
+                    result.extend(self.try_register());
+                }
+                result
+            }
+            _ => {
+                error_resp!(Command::ERR_INVALIDCAPCMD(Responses::InvalidCapCmd {
+                    nick: self.nick_or_star(),
+                    subcommand: subcommand,
+                }))
+            }
+        }
+    }
+
+    // Holds registration until CAP END, or releases it.
+    fn negotiate(&mut self, negotiating: bool) {
+        if let ConnectionType::Registering(ref mut r) = self.conn_type {
+            r.negotiating = negotiating;
+        }
+    }
+
+    // Handles a line of SASL authentication: the mechanism, part of the client's response, or
+    // "*" to abort.
+    fn authenticate(&mut self, data: String) -> Vec<IRCMessage> {
+        let nick = self.nick_or_star();
+        if !self.capabilities.contains("sasl") {
+            return error_resp!(Command::ERR_SASLFAIL(Responses::SaslFail { nick: nick }));
+        }
+        let logged_in = match self.conn_type {
+            ConnectionType::Registering(ref r) => r.account.is_some(),
+            ConnectionType::Client(ref u) => u.account().is_some(),
+            ConnectionType::Server => false,
+        };
+        if logged_in {
+            return error_resp!(Command::ERR_SASLALREADY(Responses::SaslAlready { nick: nick }));
+        }
+        if data == "*" {
+            self.sasl = None;
+            let mut result =
+                error_resp!(Command::ERR_SASLABORTED(Responses::SaslAborted { nick: nick }));
+            if !self.registered() {
+                result.extend(self.try_register());
+            }
+            return result;
+        }
+
+        let reply = match self.sasl {
+            Some(ref mut session) => session.receive(&*self.server, &data),
+            None => {
+                match data.parse::<Mechanism>() {
+                    Ok(m) => {
+                        self.sasl = Some(SaslSession::new(m));
+                        // The client goes first.
+                        SaslReply::Challenge(Vec::new())
+                    }
+                    Err(_) => {
+                        let mut result = error_resp!(Command::RPL_SASLMECHS(Responses::SaslMechs {
+                            nick: nick.clone(),
+                            mechanisms: sasl::MECHANISMS.to_string(),
+                        }));
+                        result.extend(error_resp!(
+                            Command::ERR_SASLFAIL(Responses::SaslFail { nick: nick })
+                        ));
+                        return result;
+                    }
+                }
+            }
+        };
+        let mut result = match reply {
+            SaslReply::Challenge(challenge) => {
+                return sasl::encode(&challenge)
+                    .into_iter()
+                    .map(|data| {
+                        IRCMessage {
+                            prefix: None,
+                            command: Command::AUTHENTICATE(Requests::Authenticate { data: data }),
+                        }
+                    })
+                    .collect();
+            }
+            SaslReply::More => return Vec::new(),
+            SaslReply::Success(account) => self.sasl_logged_in(account),
+            SaslReply::Failure => {
+                warn!("{} ({}) failed SASL authentication.", nick, self.socket.remote.ip());
+                error_resp!(Command::ERR_SASLFAIL(Responses::SaslFail { nick: nick }))
+            }
+            SaslReply::TooLong => {
+                error_resp!(Command::ERR_SASLTOOLONG(Responses::SaslTooLong { nick: nick }))
+            }
+        };
+        self.sasl = None;
+        if !self.registered() {
+            result.extend(self.try_register());
+        }
+        result
+    }
+
+    // Logs in to account after SASL authentication. Before registration, the user is logged in
+    // once registered.
+    fn sasl_logged_in(&mut self, account: String) -> Vec<IRCMessage> {
+        let nick = self.nick_or_star();
+        let mut logged_in = Vec::new();
+        let prefix = match self.conn_type {
+            ConnectionType::Registering(ref mut r) => {
+                r.account = Some(account.clone());
+                format!(
+                    "{}!{}@{}",
+                    nick,
+                    r.username.as_ref().map_or("*".to_string(), |u| r.ident.username(u)),
+                    r.hostname
+                )
+            }
+            _ => {
+                let vhost = self.server.lock().unwrap().accounts().get(&account).and_then(
+                    |a| a.vhost.clone(),
+                );
+                logged_in = self.log_in(&account, vhost);
+                self.get_user().identifier().as_prefix()
+            }
+        };
+        info!("{} logged in as {} with SASL.", prefix, account);
+        let mut result = vec![
+            IRCMessage {
+                prefix: None,
+                command: Command::RPL_LOGGEDIN(Responses::LoggedIn {
+                    nick: nick.clone(),
+                    prefix: prefix,
+                    account: account,
+                }),
+            },
+            IRCMessage {
+                prefix: None,
+                command: Command::RPL_SASLSUCCESS(Responses::SaslSuccess { nick: nick }),
+            },
+        ];
+        result.extend(logged_in);
+        result
+    }
+
+    // Disconnects whoever uses nick, if it belongs to the account the user is logged in to or
+    // password is right. With regain, the user then takes nick over.
+    fn ghost(&mut self, nick: String, password: Option<String>, regain: bool) -> Vec<IRCMessage> {
+        let (own_nick, account) = {
+            let user = self.get_user();
+            (user.nick().clone(), user.account().cloned())
+        };
+        if nick.eq_ignore_ascii_case(&own_nick) {
+            return vec![self.nickserv_notice("You can't ghost yourself.".to_string())];
+        }
+        let owner = self.server.lock().unwrap().accounts().owner(&nick).cloned();
+        let owner = match owner {
+            Some(o) => o,
+            None => return vec![self.nickserv_notice(format!("{} is not registered.", nick))],
+        };
+        let logged_in = account.map_or(false, |a| a.eq_ignore_ascii_case(&owner.name));
+        let password_ok = password.map_or(false, |p| password::verify(&p, &owner.password));
+        if !logged_in && !password_ok {
+            return self.password_failed(&nick);
+        }
+
+        let mut result = Vec::new();
+        if regain && !logged_in {
+            result.extend(self.log_in(&owner.name, owner.vhost.clone()));
+        }
+        let reason = format!("Killed ({} (GHOST command used by {}))", nickserv::NICK, own_nick);
+        let regainer = if regain { Some(self.tx.clone()) } else { None };
+        let ghosted = self.server.lock().unwrap().notify_user(
+            &UserIdentifier::from_nick(&nick),
+            Event::Ghost(reason, regainer),
+        );
+        match ghosted {
+            Ok(ghost) => {
+                result.push(self.nickserv_notice(format!("{} has been ghosted.", ghost.nick())));
+            }
+            Err(_) if regain => result.extend(self.change_nick(nick)),
+            Err(_) => result.push(self.nickserv_notice(format!("{} is not online.", nick))),
+        }
+        result
+    }
+
+    // Warns the user if its nick belongs to an account it isn't logged in to, and starts the
+    // timer after which it is renamed.
+    fn check_nick_owner(&mut self) -> Vec<IRCMessage> {
+        let (nick, account) = {
+            let user = self.get_user();
+            (user.nick().clone(), user.account().cloned())
+        };
+        let owner = self.server.lock().unwrap().accounts().owner(&nick).map(
+            |a| a.name.clone(),
+        );
+        let owner = match owner {
+            Some(ref o) if account.map_or(true, |a| !a.eq_ignore_ascii_case(o)) => o.clone(),
+            _ => {
+                self.nick_enforcement = None;
+                return Vec::new();
+            }
+        };
+        self.nick_enforcement = Some(Instant::now());
+        let seconds = self.shared_state.settings().configuration.nick_enforce_seconds;
+        let mut result = vec![
+            self.nickserv_notice(format!(
+                "{} is registered. Please choose a different nick, or identify via /msg {} \
+                 IDENTIFY <password>.",
+                owner,
+                nickserv::NICK
+            )),
+        ];
+        if seconds > 0 {
+            result.push(self.nickserv_notice(format!(
+                "If you do not, your nick will be changed in {} seconds.",
+                seconds
+            )));
+        }
+        result
+    }
+
+    // Renames a user that kept a nick it doesn't own for too long.
+    fn enforce_nick(&mut self) -> Vec<IRCMessage> {
+        self.nick_enforcement = None;
+        if !self.registered() || self.closing() {
+            return Vec::new();
+        }
+        let nick = self.get_user().nick().clone();
+        let mut result = vec![
+            self.nickserv_notice(format!("You did not identify for {} in time.", nick)),
+        ];
+        // Retried a few times, in case the guest nick is taken.
+        for _ in 0..5 {
+            let guest = format!("Guest{:05}", rand::random::<u32>() % 100000);
+            let renamed = self.change_nick(guest);
+            let done = self.get_user().nick() != &nick;
+            result.extend(renamed);
+            if done {
+                break;
+            }
+        }
+        result
+    }
+
+    // Tells ident its new displayed host.
+    fn host_hidden(ident: &UserIdentifier) -> Vec<IRCMessage> {
+        vec![
+            IRCMessage {
+                prefix: None,
+                command: Command::RPL_HOSTHIDDEN(Responses::HostHidden {
+                    nick: ident.nick().clone(),
+                    host: ident.displayed_hostname().clone(),
+                }),
+            },
+        ]
+    }
+
+    fn join(
+        &mut self,
+        _user: UserIdentifier,
+        channels: Vec<(String, Option<String>)>,
     ) -> Vec<IRCMessage> {
         let joined = {
             let user = self.get_user();
@@ -556,10 +3424,30 @@ impl Connection {
             .into_iter()
             .zip(channels.into_iter())
             .flat_map(|(res, (channel_name, _))| {
-                if res.is_ok() {
-                    user.join(&ChannelIdentifier::from_name(&channel_name));
-                }
-                Connection::produce_join_messages(user.identifier(), &channel_name, res)
+                let (channel_name, res, mut result) = match res {
+                    Ok((joined, topic, names)) => {
+                        user.join(&ChannelIdentifier::from_name(&joined));
+                        let mut result = Vec::new();
+                        if joined != channel_name {
+                            result.push(IRCMessage {
+                                prefix: None,
+                                command: Command::ERR_LINKCHANNEL(Responses::LinkChannel {
+                                    nick: user.nick().clone(),
+                                    channel: channel_name,
+                                    forward: joined.clone(),
+                                }),
+                            });
+                        }
+                        (joined, Ok((topic, names)), result)
+                    }
+                    Err(e) => (channel_name, Err(e), Vec::new()),
+                };
+                result.extend(Connection::produce_join_messages(
+                    user.identifier(),
+                    &channel_name,
+                    res,
+                ));
+                result
             })
             .collect()
     }
@@ -622,7 +3510,7 @@ impl Connection {
     fn produce_join_messages(
         user: &UserIdentifier,
         channel_name: &String,
-        res: Result<(Option<String>, Vec<UserIdentifier>), ChannelError>,
+        res: Result<(Option<String>, Vec<(String, UserIdentifier)>), ChannelError>,
     ) -> Vec<IRCMessage> {
         match res {
             Ok((topic, users)) => {
@@ -651,7 +3539,7 @@ impl Connection {
                         channel: channel_name.clone(),
                         members: users
                             .into_iter()
-                            .map(|m| ("".to_string(), m.into_nick()))
+                            .map(|(prefix, m)| (prefix, m.into_nick()))
                             .collect(),
                     }),
                 });
@@ -678,6 +3566,30 @@ impl Connection {
                             channel: channel_name.clone(),
                         }))
                     }
+                    ChannelError::Full => {
+                        error_resp!(Command::ERR_CHANNELISFULL(Responses::ChannelIsFull {
+                            nick: user.nick().clone(),
+                            channel: channel_name.clone(),
+                        }))
+                    }
+                    ChannelError::InviteOnly => {
+                        error_resp!(Command::ERR_INVITEONLYCHAN(Responses::InviteOnlyChan {
+                            nick: user.nick().clone(),
+                            channel: channel_name.clone(),
+                        }))
+                    }
+                    ChannelError::Throttled => {
+                        error_resp!(Command::ERR_THROTTLE(Responses::Throttle {
+                            nick: user.nick().clone(),
+                            channel: channel_name.clone(),
+                        }))
+                    }
+                    ChannelError::TooManyChannels => {
+                        error_resp!(Command::ERR_TOOMANYCHANNELS(Responses::TooManyChannels {
+                            nick: user.nick().clone(),
+                            channel: channel_name.clone(),
+                        }))
+                    }
                     ChannelError::AlreadyMember => {
                         warn!(
                             "{:?} trying to join a channel I'm already a member of.",
@@ -692,9 +3604,705 @@ impl Connection {
 
     fn disconnect(&mut self) {
         debug!("{:#?} disconnecting.", self.socket);
+        let message = self.quit_message.take().unwrap_or(if self.tx.exceeded() {
+            "SendQ exceeded".to_string()
+        } else {
+            "Connection closed".to_string()
+        });
+        let regainer = self.regainer.take();
+        let mut server = self.server.lock().unwrap();
+        server.remove_link(&self.socket);
         if self.registered() {
-            let user = self.get_user();
-            self.server.lock().unwrap().remove_user(user.identifier());
+            let ident = self.get_user().identifier();
+            server.remove_user(ident, &message);
+            if let Some(regainer) = regainer {
+                regainer.send(Event::Regain(ident.nick().clone()));
+            }
+            server.server_notice(
+                &SnoMask::Exit,
+                &format!(
+                    "Client exiting: {} ({}@{}) [{}] [{}]",
+                    ident.nick(),
+                    ident.username(),
+                    ident.hostname(),
+                    message,
+                    self.socket.remote.ip()
+                ),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use base64;
+    use chrono;
+    use futures::{future, Async, Future, Stream};
+    use futures_cpupool::CpuPool;
+    use serde_yaml;
+    use std::{self, fs};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use super::super::super::configuration::{ChannelConfiguration, Configuration, ConnectionClass,
+                                             Operator, OperatorPrivilege};
+    use super::super::super::password;
+    use super::super::accounts::Credentials;
+    use super::super::channel::{Identifier as ChannelIdentifier, ListMode, ModeChange,
+                                MAX_LIST_LENGTH};
+    use super::super::channel_registry::AccessLevel;
+    use super::super::nickserv;
+    use super::super::sendq;
+    use super::super::server::{Server, ShutdownKind};
+    use super::super::shared_state::{SharedState, Settings};
+    use super::super::statistics::LinkStatistics;
+    use super::super::user::SetMode;
+    use super::{Connection, Event, Snapshot, SocketPair};
+
+    // Keeps state in memory, exempts everyone from flood protection, and has an operator admin
+    // with password secret.
+    fn configuration() -> Configuration {
+        Configuration {
+            dns_resolver: None,
+            flood_exempt: vec!["*!*@*".to_string()],
+            bans_file: None,
+            accounts_file: None,
+            registered_channels_file: None,
+            operators: vec![
+                Operator {
+                    name: "admin".to_string(),
+                    password: password::hash("secret"),
+                    hosts: Vec::new(),
+                    privileges: vec![
+                        OperatorPrivilege::Kill,
+                        OperatorPrivilege::Ban,
+                        OperatorPrivilege::Die,
+                    ],
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn shared_state(configuration: Configuration, path: Option<String>) -> Arc<SharedState> {
+        Arc::new(SharedState::new(
+            chrono::offset::Utc::now(),
+            "test".to_string(),
+            &CpuPool::new(1),
+            Settings::new(configuration).unwrap(),
+            path,
+        ))
+    }
+
+    fn server(shared_state: &Arc<SharedState>) -> Arc<Mutex<Server>> {
+        Arc::new(Mutex::new(Server::new(Arc::clone(shared_state)).unwrap()))
+    }
+
+    // A client connecting from 127.0.0.1:port, and the queue of events sent to it.
+    fn connect(
+        shared_state: &Arc<SharedState>,
+        server: &Arc<Mutex<Server>>,
+        port: u16,
+    ) -> (Connection, sendq::Receiver) {
+        let socket = SocketPair {
+            local: "127.0.0.1:6667".parse().unwrap(),
+            remote: format!("127.0.0.1:{}", port).parse().unwrap(),
+        };
+        let class = shared_state.settings().configuration.classes[0].clone();
+        let link = Arc::new(LinkStatistics::new(format!("*[{}]", socket.remote.ip())));
+        let (tx, rx, _) = sendq::channel(Arc::clone(&link), class.max_sendq);
+        let connection = Connection::new(
+            socket,
+            -1,
+            class,
+            Arc::clone(shared_state),
+            Arc::clone(server),
+            tx,
+            link,
+        );
+        (connection, rx)
+    }
+
+    // Sends line from the client. Returns the replies.
+    fn send(connection: &mut Connection, line: &str) -> Vec<String> {
+        connection
+            .receive(line.parse().unwrap())
+            .iter()
+            .map(|m| m.to_string())
+            .collect()
+    }
+
+    fn register(connection: &mut Connection, nick: &str) -> Vec<String> {
+        send(connection, &format!("NICK {}", nick));
+        send(connection, &format!("USER {} 0 * :{}", nick, nick))
+    }
+
+    // Events sent to the connection so far.
+    fn events(rx: &mut sendq::Receiver) -> Vec<Event> {
+        future::lazy(|| -> Result<Vec<Event>, ()> {
+            let mut events = Vec::new();
+            while let Ok(Async::Ready(Some(e))) = rx.poll() {
+                events.push(e);
+            }
+            Ok(events)
+        }).wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_nick() {
+        let shared_state = shared_state(configuration(), None);
+        let server = server(&shared_state);
+        let (mut alice, _alice_rx) = connect(&shared_state, &server, 1000);
+        let (mut bob, _bob_rx) = connect(&shared_state, &server, 1001);
+        assert_eq!(
+            send(&mut alice, "NICK 1alice"),
+            vec!["432 * 1alice :Erroneous nickname"]
+        );
+        register(&mut alice, "alice");
+        register(&mut bob, "bob");
+
+        assert_eq!(
+            send(&mut bob, "NICK alice"),
+            vec!["433 alice :Nickname is already in use"]
+        );
+        for nick in vec!["-bob", "bob!", "b@b", &"b".repeat(31)] {
+            assert_eq!(
+                send(&mut bob, &format!("NICK {}", nick)),
+                vec![format!("432 bob {} :Erroneous nickname", nick)]
+            );
+        }
+        assert_eq!(send(&mut bob, "NICK [bob]_-1"), vec![":bob!bob@127.0.0.1 NICK [bob]_-1"]);
+    }
+
+    #[test]
+    fn test_rehash() {
+        let path = std::env::temp_dir().join(format!("irc_server.rehash.{}", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let write = |configuration: &Configuration| {
+            fs::write(&path, serde_yaml::to_string(configuration).unwrap()).unwrap();
+        };
+        let mut configuration = configuration();
+        write(&configuration);
+        let shared_state = shared_state(Configuration::load(&path).unwrap(), Some(path.clone()));
+        let server = server(&shared_state);
+        let (mut alice, _rx) = connect(&shared_state, &server, 1000);
+        register(&mut alice, "alice");
+        assert!(send(&mut alice, "REHASH")[0].starts_with("481 alice "));
+        send(&mut alice, "OPER admin secret");
+
+        configuration.network_name = "Rehashed".to_string();
+        write(&configuration);
+        assert_eq!(
+            send(&mut alice, "REHASH"),
+            vec![format!("382 alice {} :Rehashing", path)]
+        );
+        assert_eq!(shared_state.settings().configuration.network_name, "Rehashed");
+
+        // Invalid configurations are not applied.
+        configuration.network_name = "Invalid".to_string();
+        configuration.classes.clear();
+        write(&configuration);
+        let replies = send(&mut alice, "REHASH");
+        assert_eq!(
+            replies[1],
+            "NOTICE alice :Rehash failed: at least one connection class is required"
+        );
+        assert_eq!(shared_state.settings().configuration.network_name, "Rehashed");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_configure_channels() {
+        let path = std::env::temp_dir().join(format!("irc_server.channels.{}", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let write = |configuration: &Configuration| {
+            fs::write(&path, serde_yaml::to_string(configuration).unwrap()).unwrap();
+        };
+        let mut configuration = Configuration {
+            channels: vec![
+                ChannelConfiguration {
+                    name: "#general".to_string(),
+                    topic: Some("Welcome".to_string()),
+                    modes: "+nt".to_string(),
+                    key: Some("sesame".to_string()),
+                    limit: Some(10),
+                    permanent: true,
+                },
+            ],
+            ..configuration()
+        };
+        write(&configuration);
+        let shared_state = shared_state(Configuration::load(&path).unwrap(), Some(path.clone()));
+        let server = server(&shared_state);
+        let general = ChannelIdentifier::from_name(&"#general".to_string());
+        let modes = || {
+            let mut server = server.lock().unwrap();
+            let channel = server.lookup_channel(&general).unwrap();
+            channel.mode_string(true)
+        };
+        assert_eq!(modes(), ("+ntlk".to_string(), vec!["10".to_string(), "sesame".to_string()]));
+        let (mut alice, mut alice_rx) = connect(&shared_state, &server, 1000);
+        register(&mut alice, "alice");
+        send(&mut alice, "OPER admin secret");
+        send(&mut alice, "JOIN #general sesame");
+        events(&mut alice_rx);
+
+        // Settings the configuration drops are unset.
+        configuration.channels[0].key = None;
+        configuration.channels[0].limit = None;
+        write(&configuration);
+        send(&mut alice, "REHASH");
+        assert_eq!(modes(), ("+nt".to_string(), vec![]));
+        let messages: Vec<String> = events(&mut alice_rx)
+            .into_iter()
+            .filter_map(|e| match e {
+                Event::Message(m) => Some(m[0].to_string()),
+                _ => None,
+            })
+            .filter(|m| !m.starts_with("NOTICE"))
+            .collect();
+        assert_eq!(messages, vec![":test MODE #general -lk *"]);
+
+        // Limits set by operators are theirs, unless the configuration had set one.
+        server.lock().unwrap().lookup_channel_mut(&general).unwrap().apply_modes(
+            "op".to_string(),
+            vec![ModeChange::Limit(SetMode::Add, 5)],
+        );
+        send(&mut alice, "REHASH");
+        assert_eq!(modes(), ("+ntl".to_string(), vec!["5".to_string()]));
+
+        configuration.channels.clear();
+        write(&configuration);
+        send(&mut alice, "REHASH");
+        assert!(!server.lock().unwrap().lookup_channel(&general).unwrap().is_permanent());
+        send(&mut alice, "PART #general");
+        assert!(server.lock().unwrap().lookup_channel(&general).is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let shared_state = shared_state(configuration(), None);
+        let server = server(&shared_state);
+        let signal = server.lock().unwrap().shutdown_signal();
+        let (mut alice, _rx) = connect(&shared_state, &server, 1000);
+        register(&mut alice, "alice");
+        assert!(send(&mut alice, "DIE")[0].starts_with("481 alice "));
+        send(&mut alice, "OPER admin secret");
+
+        assert_eq!(send(&mut alice, "RESTART :Upgrading"), Vec::<String>::new());
+        assert_eq!(
+            send(&mut alice, "DIE"),
+            vec!["NOTICE alice :Server is already shutting down"]
+        );
+        let shutdown = signal.wait().unwrap();
+        assert_eq!(shutdown.kind, ShutdownKind::Restart);
+        assert_eq!(shutdown.reason, "Upgrading");
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let shared_state = shared_state(configuration(), None);
+        let server = server(&shared_state);
+        let (mut alice, _rx) = connect(&shared_state, &server, 1000);
+        send(&mut alice, "PASS hunter2");
+        send(&mut alice, "NICK alice");
+        // The password is sent in the clear, so stays out of the hot restart state.
+        send(&mut alice, "CAP REQ :sasl");
+        let snapshot = serde_yaml::to_string(&alice.snapshot()).unwrap();
+        assert!(snapshot.contains("alice"));
+        assert!(!snapshot.contains("hunter2"));
+        let snapshot: Snapshot = serde_yaml::from_str(&snapshot).unwrap();
+        assert_eq!(snapshot.capabilities, vec!["sasl"]);
+    }
+
+    #[test]
+    fn test_assign_class() {
+        let shared_state = shared_state(
+            Configuration {
+                classes: vec![
+                    ConnectionClass {
+                        name: "bots".to_string(),
+                        hosts: vec!["bot@*".to_string()],
+                        max_clients: 1,
+                        ..Default::default()
+                    },
+                    ConnectionClass {
+                        name: "users".to_string(),
+                        tls: Some(false),
+                        ..Default::default()
+                    },
+                ],
+                ..configuration()
+            },
+            None,
+        );
+        let server = server(&shared_state);
+        let (mut alice, _) = connect(&shared_state, &server, 1000);
+        register(&mut alice, "alice");
+        assert!(alice.registered());
+        assert_eq!(alice.class.name, "users");
+
+        let (mut bot, _) = connect(&shared_state, &server, 1001);
+        send(&mut bot, "NICK bot1");
+        send(&mut bot, "USER bot 0 * :Bot");
+        assert!(bot.registered());
+        assert_eq!(bot.class.name, "bots");
+
+        // The class user@host masks admit the client, but the class is full.
+        let (mut full, _) = connect(&shared_state, &server, 1002);
+        send(&mut full, "NICK bot2");
+        let replies = send(&mut full, "USER bot 0 * :Bot");
+        assert_eq!(
+            replies,
+            vec![
+                "465 bot2 :You are banned from this server- Server is full, try again later",
+                "ERROR :Closing Link (Server is full, try again later)",
+            ]
+        );
+        assert!(!full.registered());
+        assert!(full.closing());
+    }
+
+    #[test]
+    fn test_password() {
+        let shared_state = shared_state(
+            Configuration {
+                server_password: Some(password::hash("server")),
+                classes: vec![
+                    ConnectionClass {
+                        name: "staff".to_string(),
+                        hosts: vec!["staff@*".to_string()],
+                        password: Some(password::hash("staff")),
+                        ..Default::default()
+                    },
+                    ConnectionClass::default(),
+                ],
+                ..configuration()
+            },
+            None,
+        );
+        let server = server(&shared_state);
+        let login = |port, username: &str, pass: Option<&str>| {
+            let (mut c, _) = connect(&shared_state, &server, port);
+            if let Some(p) = pass {
+                send(&mut c, &format!("PASS {}", p));
+            }
+            send(&mut c, &format!("NICK {}{}", username, port));
+            let replies = send(&mut c, &format!("USER {} 0 * :Name", username));
+            (c.registered(), replies)
+        };
+
+        assert!(login(1000, "alice", Some("server")).0);
+        // The class password replaces the server password, rather than adding to it.
+        assert!(login(1001, "staff", Some("staff")).0);
+        assert!(!login(1002, "staff", Some("server")).0);
+        let (registered, replies) = login(1003, "alice", Some("staff"));
+        assert!(!registered);
+        assert_eq!(
+            replies,
+            vec![
+                "464 alice1003 :Password incorrect",
+                "ERROR :Closing Link (Bad password)",
+            ]
+        );
+        assert!(!login(1004, "alice", None).0);
+    }
+
+    #[test]
+    fn test_keepalive() {
+        let shared_state = shared_state(configuration(), None);
+        let server = server(&shared_state);
+        let class = shared_state.settings().configuration.classes[0].clone();
+        let (frequency, timeout) = (
+            Duration::from_secs(class.ping_frequency_seconds),
+            Duration::from_secs(class.ping_timeout_seconds),
+        );
+        let (mut alice, mut alice_rx) = connect(&shared_state, &server, 1000);
+        let (mut bob, mut bob_rx) = connect(&shared_state, &server, 1001);
+        register(&mut alice, "alice");
+        let now = Instant::now();
+
+        alice.tick(now + frequency);
+        match events(&mut alice_rx).as_slice() {
+            &[Event::Message(ref m)] => assert_eq!(m[0].to_string(), "PING test"),
+            e => panic!("{:?}", e),
+        }
+        // Timing out disconnects once, however often the timer fires.
+        for i in 0..3 {
+            alice.tick(now + frequency + timeout + Duration::from_secs(i));
+        }
+        match events(&mut alice_rx).as_slice() {
+            &[Event::Disconnect(ref reason)] => assert!(reason.starts_with("Ping timeout: ")),
+            e => panic!("{:?}", e),
+        }
+
+        let deadline = Duration::from_secs(class.registration_timeout_seconds);
+        bob.tick(now + deadline);
+        bob.tick(now + deadline + Duration::from_secs(1));
+        match events(&mut bob_rx).as_slice() {
+            &[Event::Disconnect(ref reason)] => assert_eq!(reason, "Registration timed out"),
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_nickserv() {
+        let shared_state = shared_state(configuration(), None);
+        let server = server(&shared_state);
+        let (mut alice, _) = connect(&shared_state, &server, 1000);
+        register(&mut alice, "alice");
+        let (mut bob, _) = connect(&shared_state, &server, 1001);
+        register(&mut bob, "bob");
+        let nickserv = |c: &mut Connection, text: &str| {
+            send(c, &format!("PRIVMSG NickServ :{}", text))
+        };
+
+        assert_eq!(
+            nickserv(&mut alice, "REGISTER hunter2"),
+            vec![
+                ":NickServ!NickServ@test NOTICE alice :alice is now registered.",
+                ":NickServ!NickServ@test NOTICE alice :You are now logged in as alice.",
+            ]
+        );
+        assert_eq!(
+            nickserv(&mut bob, "IDENTIFY alice wrong"),
+            vec![":NickServ!NickServ@test NOTICE bob :Invalid password for alice."]
+        );
+        assert_eq!(
+            nickserv(&mut bob, "IDENTIFY carol hunter2"),
+            vec![":NickServ!NickServ@test NOTICE bob :carol is not registered."]
+        );
+        assert_eq!(
+            nickserv(&mut bob, "IDENTIFY alice hunter2"),
+            vec![":NickServ!NickServ@test NOTICE bob :You are now logged in as alice."]
+        );
+
+        nickserv(&mut alice, "SET PASSWORD swordfish");
+        assert_eq!(
+            nickserv(&mut alice, "DROP hunter2"),
+            vec![":NickServ!NickServ@test NOTICE alice :Invalid password for alice."]
+        );
+
+        // Not while it owns channels, which whoever registers alice next would take over.
+        send(&mut alice, "JOIN #rust");
+        send(&mut alice, "PRIVMSG ChanServ :REGISTER #rust");
+        assert_eq!(
+            nickserv(&mut alice, "DROP swordfish"),
+            vec![
+                ":NickServ!NickServ@test NOTICE alice :alice still owns #rust. Drop them with \
+                 ChanServ first.",
+            ]
+        );
+        send(&mut alice, "PRIVMSG ChanServ :DROP #rust");
+        {
+            let mut server = server.lock().unwrap();
+            server.registry().register("#bots", "bob", None).unwrap();
+            server.registry().set_access("#bots", "alice", Some(AccessLevel::Op)).unwrap();
+        }
+        assert_eq!(
+            nickserv(&mut alice, "DROP swordfish"),
+            vec![":NickServ!NickServ@test NOTICE alice :alice has been dropped."]
+        );
+        let mut server = server.lock().unwrap();
+        assert!(server.accounts().get("alice").is_none());
+        assert!(server.registry().get("#rust").is_none());
+        assert!(server.registry().get("#bots").unwrap().access.is_empty());
+    }
+
+    #[test]
+    fn test_password_failures() {
+        let shared_state = shared_state(configuration(), None);
+        let server = server(&shared_state);
+        let (mut alice, mut alice_rx) = connect(&shared_state, &server, 1000);
+        register(&mut alice, "alice");
+        send(&mut alice, "PRIVMSG NickServ :REGISTER hunter2");
+        send(&mut alice, "OPER admin secret");
+        events(&mut alice_rx);
+        let (mut bob, _) = connect(&shared_state, &server, 1001);
+        register(&mut bob, "bob");
+
+        for _ in 1..nickserv::MAX_PASSWORD_FAILURES {
+            assert_eq!(
+                send(&mut bob, "PRIVMSG NickServ :GHOST alice wrong"),
+                vec![":NickServ!NickServ@test NOTICE bob :Invalid password for alice."]
+            );
+        }
+        assert_eq!(
+            send(&mut bob, "PRIVMSG NickServ :IDENTIFY alice wrong"),
+            vec![
+                ":NickServ!NickServ@test NOTICE bob :Invalid password for alice.",
+                "ERROR :Closing Link (Too many wrong passwords)",
+            ]
+        );
+        assert!(bob.closing());
+        let notices: Vec<String> = events(&mut alice_rx)
+            .into_iter()
+            .filter_map(|e| match e {
+                Event::Message(m) => Some(m[0].to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(notices.len(), nickserv::MAX_PASSWORD_FAILURES);
+        assert_eq!(
+            notices[0],
+            "NOTICE alice :*** Notice -- Wrong NickServ password for alice by bob!bob@127.0.0.1"
+        );
+    }
+
+    #[test]
+    fn test_sasl() {
+        let shared_state = shared_state(configuration(), None);
+        let server = server(&shared_state);
+        let (mut alice, _) = connect(&shared_state, &server, 1000);
+        assert_eq!(send(&mut alice, "CAP LS 302"), vec!["CAP * LS :sasl=PLAIN,SCRAM-SHA-256"]);
+        send(&mut alice, "CAP REQ :sasl");
+        // Not without TLS client certificates.
+        assert_eq!(
+            send(&mut alice, "AUTHENTICATE EXTERNAL"),
+            vec![
+                "908 * PLAIN,SCRAM-SHA-256 :are available SASL mechanisms",
+                "904 * :SASL authentication failed",
+            ]
+        );
+
+        server.lock().unwrap().accounts().register(
+            "alice",
+            Credentials::new("hunter2"),
+            None,
+        ).unwrap();
+        assert_eq!(send(&mut alice, "AUTHENTICATE PLAIN"), vec!["AUTHENTICATE +"]);
+        let wrong = format!("AUTHENTICATE {}", base64::encode("\0alice\0wrong"));
+        assert_eq!(send(&mut alice, &wrong), vec!["904 * :SASL authentication failed"]);
+        assert_eq!(send(&mut alice, "AUTHENTICATE PLAIN"), vec!["AUTHENTICATE +"]);
+        let right = format!("AUTHENTICATE {}", base64::encode("\0alice\0hunter2"));
+        assert_eq!(
+            send(&mut alice, &right),
+            vec![
+                "900 * *!*@127.0.0.1 alice :You are now logged in as alice",
+                "903 * :SASL authentication successful",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let shared_state = shared_state(configuration(), None);
+        let server = server(&shared_state);
+        let (mut alice, _rx) = connect(&shared_state, &server, 1000);
+        assert!(register(&mut alice, "alice")[0].starts_with("001 alice "));
+
+        let uptime = send(&mut alice, "STATS u");
+        assert!(uptime[0].starts_with("242 alice :Server Up 0 days"));
+        assert_eq!(uptime[1], "219 alice u :End of STATS report");
+        let usage = send(&mut alice, "STATS m");
+        assert!(usage.contains(&"212 alice NICK 1".to_string()));
+        assert!(usage.contains(&"212 alice STATS 2".to_string()));
+
+        // The rest is for operators only.
+        assert!(send(&mut alice, "STATS o")[0].starts_with("481 alice "));
+        assert!(send(&mut alice, "OPER admin wrong")[0].starts_with("464 alice "));
+        assert!(send(&mut alice, "OPER admin secret")[0].starts_with("381 alice "));
+        assert_eq!(
+            send(&mut alice, "STATS o"),
+            vec!["243 alice O *@* * admin", "219 alice o :End of STATS report"]
+        );
+        assert_eq!(
+            send(&mut alice, "STATS i"),
+            vec!["215 alice I *@* * *@* 0 default", "219 alice i :End of STATS report"]
+        );
+        assert!(send(&mut alice, "STATS y")[0].starts_with("218 alice Y default 120 "));
+        let links = send(&mut alice, "STATS l");
+        assert_eq!(links.len(), 2);
+        assert!(links[0].starts_with("211 alice alice[127.0.0.1] "));
+    }
+
+    #[test]
+    fn test_chanlimit() {
+        let configuration = Configuration {
+            max_channels: 2,
+            ..configuration()
+        };
+        let shared_state = shared_state(configuration, None);
+        let server = server(&shared_state);
+        let (mut alice, _rx) = connect(&shared_state, &server, 1000);
+        let welcome = register(&mut alice, "alice");
+        assert!(welcome.iter().any(|r| r.starts_with("005 ") && r.contains(" CHANLIMIT=#:2 ")));
+        send(&mut alice, "JOIN #a");
+        send(&mut alice, "JOIN #b");
+        assert_eq!(
+            send(&mut alice, "JOIN #c"),
+            vec!["405 alice #c :You have joined too many channels"]
+        );
+        send(&mut alice, "PART #b");
+        assert_eq!(send(&mut alice, "JOIN #c")[0], ":alice!alice@127.0.0.1 JOIN #c");
+    }
+
+    #[test]
+    fn test_join_modes() {
+        let shared_state = shared_state(configuration(), None);
+        let server = server(&shared_state);
+        let (mut alice, _alice_rx) = connect(&shared_state, &server, 1000);
+        let (mut bob, _bob_rx) = connect(&shared_state, &server, 1001);
+        register(&mut alice, "alice");
+        register(&mut bob, "bob");
+        send(&mut alice, "JOIN #a");
+        send(&mut alice, "JOIN #b");
+
+        // +l turns joiners away once the channel is full.
+        send(&mut alice, "MODE #a +l 1");
+        assert_eq!(send(&mut bob, "JOIN #a"), vec!["471 bob #a :Cannot join channel (+l)"]);
+
+        // +f sends them to the forward target instead, which the setter must be op in.
+        assert_eq!(
+            send(&mut alice, "MODE #a +f #c"),
+            vec!["482 alice #c :You're not channel operator"]
+        );
+        send(&mut alice, "MODE #a +f #b");
+        assert_eq!(
+            send(&mut bob, "JOIN #a")[..2],
+            [
+                "470 bob #a #b :Forwarding to another channel",
+                ":bob!bob@127.0.0.1 JOIN #b",
+            ]
+        );
+        send(&mut bob, "PART #b");
+
+        // +i needs an INVITE, which lasts for one join.
+        send(&mut alice, "MODE #b +i");
+        assert_eq!(send(&mut bob, "JOIN #b"), vec!["473 bob #b :Cannot join channel (+i)"]);
+        assert_eq!(send(&mut alice, "INVITE bob #b"), vec!["341 alice bob #b"]);
+        assert_eq!(send(&mut bob, "JOIN #b")[0], ":bob!bob@127.0.0.1 JOIN #b");
+        send(&mut bob, "PART #b");
+        assert_eq!(send(&mut bob, "JOIN #b"), vec!["473 bob #b :Cannot join channel (+i)"]);
+
+        // +j allows only so many joins in its window.
+        send(&mut alice, "MODE #b -i+j 1:60");
+        assert_eq!(send(&mut bob, "JOIN #b")[0], ":bob!bob@127.0.0.1 JOIN #b");
+        send(&mut bob, "PART #b");
+        assert!(send(&mut bob, "JOIN #b")[0].starts_with("480 bob #b "));
+    }
+
+    #[test]
+    fn test_maxlist() {
+        let shared_state = shared_state(configuration(), None);
+        let server = server(&shared_state);
+        let (mut alice, _rx) = connect(&shared_state, &server, 1000);
+        let welcome = register(&mut alice, "alice");
+        let maxlist = format!(" MAXLIST=bq:{} ", MAX_LIST_LENGTH);
+        assert!(welcome.iter().any(|r| r.starts_with("005 ") && r.contains(&maxlist)));
+        send(&mut alice, "JOIN #a");
+        for i in 1..MAX_LIST_LENGTH {
+            send(&mut alice, &format!("MODE #a +b spam{}", i));
         }
+        assert_eq!(
+            send(&mut alice, "MODE #a +bbq spam0 spam101 spam102"),
+            vec!["478 alice #a spam101!*@* :Channel list is full"]
+        );
+        let mut server = server.lock().unwrap();
+        let channel = server.lookup_channel(&ChannelIdentifier::from_name(&"#a".to_string()));
+        let channel = channel.unwrap();
+        assert_eq!(channel.list(ListMode::Ban).len(), MAX_LIST_LENGTH);
+        assert_eq!(channel.list(ListMode::Quiet), &vec!["spam102!*@*".to_string()]);
     }
 }
@@ -0,0 +1,303 @@
+// Hostname lookups for connecting clients.
+//
+// A client's hostname is the name its IP reverse resolves to, as long as that name resolves back
+// to the IP. Otherwise the IP itself is used.
+use futures_cpupool::CpuPool;
+use libc;
+use std::{self, fmt, io};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::io::{BufRead, BufReader};
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Longest hostname accepted, as in most IRC daemons.
+const MAX_HOSTNAME_LENGTH: usize = 63;
+
+pub trait Resolver: fmt::Debug + Send + Sync {
+    // Name of ip, if it has one.
+    fn reverse(&self, ip: &IpAddr) -> io::Result<Option<String>>;
+    // Addresses of hostname.
+    fn forward(&self, hostname: &str) -> io::Result<Vec<IpAddr>>;
+}
+
+// Resolves through the system's resolver, as configured by nsswitch.conf.
+#[derive(Debug)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn reverse(&self, ip: &IpAddr) -> io::Result<Option<String>> {
+        let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+        let result = match *ip {
+            IpAddr::V4(ref v4) => {
+                let mut sin: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_addr.s_addr = u32::from(*v4).to_be();
+                getnameinfo(&sin, &mut host)
+            }
+            IpAddr::V6(ref v6) => {
+                let mut sin6: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_addr.s6_addr = v6.octets();
+                getnameinfo(&sin6, &mut host)
+            }
+        };
+        match result {
+            0 => {
+                let name = unsafe { CStr::from_ptr(host.as_ptr()) };
+                Ok(Some(name.to_string_lossy().into_owned()))
+            }
+            libc::EAI_NONAME => Ok(None),
+            e => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("getnameinfo failed: {}", e),
+            )),
+        }
+    }
+
+    fn forward(&self, hostname: &str) -> io::Result<Vec<IpAddr>> {
+        Ok((hostname, 0).to_socket_addrs()?.map(|a| a.ip()).collect())
+    }
+}
+
+// Looks up the name of address, a sockaddr_in or sockaddr_in6, into host.
+fn getnameinfo<T>(address: &T, host: &mut [libc::c_char]) -> libc::c_int {
+    unsafe {
+        libc::getnameinfo(
+            address as *const T as *const libc::sockaddr,
+            std::mem::size_of::<T>() as libc::socklen_t,
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    }
+}
+
+// Resolves from a file in /etc/hosts format, for tests and networks without DNS. The first name
+// listed for an address is its reverse name.
+#[derive(Debug)]
+pub struct HostsFileResolver {
+    names: HashMap<IpAddr, String>,
+    addresses: HashMap<String, Vec<IpAddr>>,
+}
+
+impl HostsFileResolver {
+    pub fn load(path: &str) -> io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let mut resolver = HostsFileResolver {
+            names: HashMap::new(),
+            addresses: HashMap::new(),
+        };
+        for line in BufReader::new(file).lines() {
+            resolver.add(&line?);
+        }
+        Ok(resolver)
+    }
+
+    fn add(&mut self, line: &str) {
+        let line = match line.find('#') {
+            Some(idx) => &line[..idx],
+            None => line,
+        };
+        let mut fields = line.split_whitespace();
+        let ip = match fields.next().and_then(|ip| ip.parse::<IpAddr>().ok()) {
+            Some(ip) => ip,
+            None => return,
+        };
+        for name in fields {
+            let name = name.to_lowercase();
+            self.names.entry(ip.clone()).or_insert(name.clone());
+            self.addresses.entry(name).or_insert_with(Vec::new).push(ip.clone());
+        }
+    }
+}
+
+impl Resolver for HostsFileResolver {
+    fn reverse(&self, ip: &IpAddr) -> io::Result<Option<String>> {
+        Ok(self.names.get(ip).cloned())
+    }
+
+    fn forward(&self, hostname: &str) -> io::Result<Vec<IpAddr>> {
+        Ok(
+            self.addresses
+                .get(&hostname.to_lowercase())
+                .cloned()
+                .unwrap_or_default(),
+        )
+    }
+}
+
+// Looks up the hostname of ip, confirmed by resolving it back to ip.
+pub fn resolve(resolver: &dyn Resolver, ip: &IpAddr) -> Option<String> {
+    let name = match resolver.reverse(ip) {
+        Ok(Some(name)) => name.trim_end_matches('.').to_lowercase(),
+        Ok(None) => return None,
+        Err(e) => {
+            debug!("Reverse lookup of {} failed: {}.", ip, e);
+            return None;
+        }
+    };
+    if !valid_hostname(&name) {
+        debug!("{} reverse resolves to invalid hostname {:?}.", ip, name);
+        return None;
+    }
+    match resolver.forward(&name) {
+        Ok(ref addresses) if addresses.iter().any(|a| same_address(a, ip)) => Some(name),
+        Ok(_) => {
+            debug!("{} reverse resolves to {}, which does not resolve back.", ip, name);
+            None
+        }
+        Err(e) => {
+            debug!("Forward lookup of {} failed: {}.", name, e);
+            None
+        }
+    }
+}
+
+// Whether name can stand in for an address in nick!user@host.
+pub fn valid_hostname(name: &str) -> bool {
+    name.len() > 0 && name.len() <= MAX_HOSTNAME_LENGTH &&
+        !name.starts_with('.') && !name.starts_with('-') &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+}
+
+// IPv4-mapped IPv6 addresses are the same as their IPv4 equivalent.
+fn same_address(a: &IpAddr, b: &IpAddr) -> bool {
+    let canonical = |ip: &IpAddr| match ip {
+        &IpAddr::V6(ref v6) => v6.to_ipv4_mapped().map_or(ip.clone(), IpAddr::V4),
+        _ => ip.clone(),
+    };
+    canonical(a) == canonical(b)
+}
+
+// Runs lookups off the connection threads, and remembers their results.
+#[derive(Debug)]
+pub struct Dns {
+    pool: CpuPool,
+    // IP -> (hostname, when it was looked up).
+    cache: Arc<Mutex<HashMap<IpAddr, (Option<String>, Instant)>>>,
+}
+
+impl Dns {
+    pub fn new(pool: &CpuPool) -> Self {
+        Dns {
+            pool: pool.clone(),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Looks up the hostname of ip with resolver, and calls done with it. Results younger than
+    // cache_time are reused, in which case done is called right away.
+    pub fn lookup<F>(&self, resolver: Arc<dyn Resolver>, ip: IpAddr, cache_time: Duration, done: F)
+    where
+        F: FnOnce(Option<String>) + Send + 'static,
+    {
+        let cached = match self.cache.lock().unwrap().get(&ip) {
+            Some(&(ref hostname, at)) if at.elapsed() < cache_time => Some(hostname.clone()),
+            _ => None,
+        };
+        if let Some(hostname) = cached {
+            debug!("Using cached hostname {:?} for {}.", hostname, ip);
+            return done(hostname);
+        }
+
+        let cache = Arc::clone(&self.cache);
+        self.pool
+            .spawn_fn(move || {
+                let hostname = resolve(&*resolver, &ip);
+                {
+                    let mut cache = cache.lock().unwrap();
+                    let now = Instant::now();
+                    cache.retain(|_, &mut (_, at)| now.duration_since(at) < cache_time);
+                    cache.insert(ip, (hostname.clone(), now));
+                }
+                done(hostname);
+                Ok::<(), ()>(())
+            })
+            .forget();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::io;
+    use std::net::IpAddr;
+    use super::{resolve, HostsFileResolver, Resolver};
+
+    #[derive(Debug)]
+    struct StubResolver {
+        reverse: HashMap<IpAddr, String>,
+        forward: HashMap<String, Vec<IpAddr>>,
+    }
+
+    impl Resolver for StubResolver {
+        fn reverse(&self, ip: &IpAddr) -> io::Result<Option<String>> {
+            Ok(self.reverse.get(ip).cloned())
+        }
+
+        fn forward(&self, hostname: &str) -> io::Result<Vec<IpAddr>> {
+            match self.forward.get(hostname) {
+                Some(a) => Ok(a.clone()),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, "NXDOMAIN")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let stub = StubResolver {
+            reverse: vec![
+                (ip("10.0.0.1"), "Host.Example.com.".to_string()),
+                (ip("10.0.0.2"), "spoofed.example.com".to_string()),
+                (ip("10.0.0.3"), "bad host".to_string()),
+                (ip("10.0.0.4"), "gone.example.com".to_string()),
+            ].into_iter()
+                .collect(),
+            forward: vec![
+                ("host.example.com".to_string(), vec![ip("10.0.0.9"), ip("10.0.0.1")]),
+                ("spoofed.example.com".to_string(), vec![ip("10.0.0.9")]),
+            ].into_iter()
+                .collect(),
+        };
+        assert_eq!(resolve(&stub, &ip("10.0.0.1")), Some("host.example.com".to_string()));
+        assert_eq!(
+            resolve(&stub, &ip("::ffff:10.0.0.1")),
+            None,
+            "reverse lookups are by exact address"
+        );
+        assert_eq!(resolve(&stub, &ip("10.0.0.2")), None);
+        assert_eq!(resolve(&stub, &ip("10.0.0.3")), None);
+        assert_eq!(resolve(&stub, &ip("10.0.0.4")), None);
+        assert_eq!(resolve(&stub, &ip("10.0.0.5")), None);
+    }
+
+    #[test]
+    fn test_hosts_file() {
+        let mut hosts = HostsFileResolver {
+            names: HashMap::new(),
+            addresses: HashMap::new(),
+        };
+        hosts.add("127.0.0.1 localhost.localdomain localhost # loopback");
+        hosts.add("# 10.0.0.1 commented.example.com");
+        hosts.add("10.0.0.2 Second.Example.com localhost.localdomain");
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(
+            resolve(&hosts, &ip("127.0.0.1")),
+            Some("localhost.localdomain".to_string())
+        );
+        assert_eq!(resolve(&hosts, &ip("10.0.0.1")), None);
+        assert_eq!(
+            resolve(&hosts, &ip("10.0.0.2")),
+            Some("second.example.com".to_string())
+        );
+        assert_eq!(
+            hosts.forward("localhost.localdomain").unwrap(),
+            vec![ip("127.0.0.1"), ip("10.0.0.2")]
+        );
+    }
+}
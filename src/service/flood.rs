@@ -0,0 +1,69 @@
+use std::time::Instant;
+
+// Limits how fast a connection's commands are processed. Holds up to capacity tokens, refilled at
+// rate tokens per second. Each command takes its cost in tokens.
+#[derive(Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    rate: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    // Starts out full.
+    pub fn new(capacity: u64, rate: u64, now: Instant) -> Self {
+        Self {
+            tokens: capacity as f64,
+            capacity: capacity as f64,
+            rate: rate as f64,
+            updated: now,
+        }
+    }
+
+    // Takes cost tokens if there are enough.
+    pub fn take(&mut self, cost: u64, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < cost as f64 {
+            return false;
+        }
+        self.tokens -= cost as f64;
+        true
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now <= self.updated {
+            return;
+        }
+        let elapsed = now.duration_since(self.updated);
+        let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use super::TokenBucket;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(5, 2, start);
+        assert!(bucket.take(3, start));
+        assert!(bucket.take(2, start));
+        assert!(!bucket.take(1, start));
+        // Free commands always go through.
+        assert!(bucket.take(0, start));
+
+        let later = start + Duration::from_millis(500);
+        assert!(bucket.take(1, later));
+        assert!(!bucket.take(1, later));
+
+        // Never refills past capacity.
+        let much_later = later + Duration::from_secs(60);
+        assert!(bucket.take(5, much_later));
+        assert!(!bucket.take(1, much_later));
+    }
+}
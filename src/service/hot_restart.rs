@@ -0,0 +1,184 @@
+// Hot restart hands the listener and every client socket over to a freshly exec'd binary.
+//
+// The old process serializes its state to a file in a directory only its user can access, clears
+// close-on-exec on the sockets it hands over, and execs itself with STATE_ENV pointing at the
+// file, and FDS_ENV listing the sockets. The new process deletes the file as soon as it has read
+// it, since it holds account password hashes. It adopts the sockets instead of binding, and
+// resumes each connection where it left off. Connections that arrive in the meantime wait in the
+// listen backlog. Partially read lines and messages still queued for a connection at the time of
+// the handoff are lost. If the new binary can't read the state, it closes the client sockets and
+// keeps only the listener.
+//
+// Known limitation: a password sent with PASS is kept out of the state, as it is in the clear. It
+// can't be checked ahead of the handoff either, since the class it is checked against depends on
+// the rest of the registration. So a client that sent PASS but had not finished registering is
+// refused with ERR_PASSWDMISMATCH if its class or the server needs a password.
+use libc;
+use serde_yaml;
+use std;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use super::connection::Snapshot as ConnectionSnapshot;
+use super::server::Snapshot as ServerSnapshot;
+
+// Path of the state file, set only for the exec'd process.
+pub static STATE_ENV: &'static str = "IRC_SERVER_HOT_RESTART_STATE";
+// Comma separated sockets handed over, the listener first. Set only for the exec'd process, and
+// kept out of the state file so they can be dealt with when it can't be read.
+pub static FDS_ENV: &'static str = "IRC_SERVER_HOT_RESTART_FDS";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct State {
+    // Server creation time, as a timestamp. Survives restarts.
+    pub created: i64,
+    pub listener: RawFd,
+    pub connections: Vec<ConnectionSnapshot>,
+    pub server: ServerSnapshot,
+}
+
+// What a hot restarted process inherits.
+pub struct Handoff {
+    pub listener: RawFd,
+    // None if the state could not be read, in which case only the listener is left open.
+    pub state: Option<State>,
+}
+
+// Replaces the running binary with the one on disk, handing over state. Only returns on failure.
+pub fn exec(state: &State) -> io::Error {
+    let path = match state_dir() {
+        Ok(dir) => dir.join(format!("{}.state", std::process::id())),
+        Err(e) => return e,
+    };
+    let written = serde_yaml::to_string(state)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        .and_then(|yaml| {
+            // Stale files from a failed restart of a process with the same pid are removed
+            // rather than written through.
+            if let Err(e) = fs::remove_file(&path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e);
+                }
+            }
+            let mut f = open_options().write(true).create_new(true).mode(0o600).open(&path)?;
+            f.write_all(yaml.as_bytes())
+        });
+    if let Err(e) = written {
+        let _ = fs::remove_file(&path);
+        return e;
+    }
+
+    let fds: Vec<RawFd> = std::iter::once(state.listener)
+        .chain(state.connections.iter().map(|c| c.fd()))
+        .collect();
+    for &fd in &fds {
+        if let Err(e) = inherit(fd) {
+            let _ = fs::remove_file(&path);
+            return e;
+        }
+    }
+
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => return e,
+    };
+    info!("Hot restarting {:?} with {} connections.", exe, state.connections.len());
+    let e = std::process::Command::new(exe)
+        .args(std::env::args_os().skip(1))
+        .env(STATE_ENV, &path)
+        .env(
+            FDS_ENV,
+            fds.iter().map(|fd| fd.to_string()).collect::<Vec<_>>().join(","),
+        )
+        .exec();
+    let _ = fs::remove_file(&path);
+    e
+}
+
+// Takes what the previous process handed over, if this process was hot restarted.
+pub fn take() -> Option<Handoff> {
+    let path = match std::env::var_os(STATE_ENV) {
+        Some(p) => p,
+        None => return None,
+    };
+    let fds: Vec<RawFd> = std::env::var(FDS_ENV)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|fd| fd.parse().ok())
+        .collect();
+    std::env::remove_var(STATE_ENV);
+    std::env::remove_var(FDS_ENV);
+    let state: Result<State, String> = open_options()
+        .read(true)
+        .open(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| serde_yaml::from_reader(f).map_err(|e| e.to_string()));
+    let _ = fs::remove_file(&path);
+    match state {
+        Ok(s) => {
+            Some(Handoff {
+                listener: s.listener,
+                state: Some(s),
+            })
+        }
+        Err(e) => {
+            error!("Failed to read hot restart state {:?}: {}.", path, e);
+            // Connections can't be resumed without their state, so are closed rather than leaked.
+            // The listener is kept, as it still holds the listen address.
+            let mut fds = fds.into_iter();
+            let listener = fds.next();
+            for fd in fds {
+                unsafe {
+                    libc::close(fd);
+                }
+            }
+            listener.map(|l| {
+                Handoff {
+                    listener: l,
+                    state: None,
+                }
+            })
+        }
+    }
+}
+
+// The directory state files are written to, created if need be. It is private to the user the
+// server runs as, and refused if anyone else could have planted or read files in it.
+fn state_dir() -> io::Result<PathBuf> {
+    let uid = unsafe { libc::getuid() };
+    let dir = std::env::temp_dir().join(format!("irc_server.{}", uid));
+    match fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        r => r?,
+    }
+    // Not following symlinks, so the directory itself is checked.
+    let metadata = fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.permissions().mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{:?} is not a private directory", dir),
+        ));
+    }
+    Ok(dir)
+}
+
+// Options refusing to open state files through symlinks.
+fn open_options() -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    options.custom_flags(libc::O_NOFOLLOW);
+    options
+}
+
+// Keeps fd open across exec.
+fn inherit(fd: RawFd) -> io::Result<()> {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
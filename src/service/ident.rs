@@ -0,0 +1,177 @@
+// RFC 1413 ident queries, asking the client's host who owns its connection.
+//
+// Queries run on a reactor thread of their own, so that identds which never answer hold up
+// neither the DNS lookups on the lookup pool nor the connections.
+use futures::{Future, IntoFuture};
+use futures::future::Either;
+use futures::sync::oneshot;
+use std::{io, thread};
+use std::io::{BufReader, Read};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Core, Handle, Remote, Timeout};
+use tokio_io;
+
+// Longest username taken from an ident reply.
+const MAX_USERNAME_LENGTH: usize = 10;
+// Longest reply read, per RFC 1413.
+const MAX_REPLY_LENGTH: u64 = 1000;
+
+#[derive(Debug)]
+pub struct Ident {
+    remote: Remote,
+    // Stops the reactor thread when dropped.
+    _stop: oneshot::Sender<()>,
+}
+
+impl Ident {
+    pub fn new() -> Self {
+        let (remote_tx, remote_rx) = ::std::sync::mpsc::channel();
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        thread::Builder::new()
+            .name("ident".to_string())
+            .spawn(move || {
+                let mut core = Core::new().expect("Failed to create the ident reactor");
+                remote_tx.send(core.remote()).unwrap();
+                let _ = core.run(stop_rx);
+            })
+            .expect("Failed to start the ident thread");
+        Self {
+            remote: remote_rx.recv().expect("Ident thread failed"),
+            _stop: stop_tx,
+        }
+    }
+
+    // Asks the identd on port of client's host who owns the connection from client to server,
+    // and calls done with the username if it answered within timeout.
+    pub fn lookup<F>(
+        &self,
+        client: SocketAddr,
+        server: SocketAddr,
+        port: u16,
+        timeout: Duration,
+        done: F,
+    ) where
+        F: FnOnce(Option<String>) + Send + 'static,
+    {
+        self.remote.spawn(move |handle| {
+            query(handle, client, server, port, timeout).then(move |result| {
+                done(result.unwrap_or_else(|e| {
+                    debug!("Ident query for {} failed: {}.", client, e);
+                    None
+                }));
+                Ok(())
+            })
+        });
+    }
+}
+
+fn query(
+    handle: &Handle,
+    client: SocketAddr,
+    server: SocketAddr,
+    port: u16,
+    timeout: Duration,
+) -> Box<dyn Future<Item = Option<String>, Error = io::Error>> {
+    // In one write, as some identds only read once.
+    let request = format!("{} , {}\r\n", client.port(), server.port());
+    let reply = TcpStream::connect(&SocketAddr::new(client.ip(), port), handle)
+        .and_then(move |stream| tokio_io::io::write_all(stream, request.into_bytes()))
+        .and_then(|(stream, _)| {
+            let reader = BufReader::new(stream.take(MAX_REPLY_LENGTH));
+            tokio_io::io::read_until(reader, b'\n', Vec::new())
+        })
+        .map(move |(_, reply)| {
+            parse(&String::from_utf8_lossy(&reply), client.port(), server.port())
+        });
+    let timeout = match Timeout::new(timeout, handle) {
+        Ok(t) => t,
+        Err(e) => return Box::new(Err(e).into_future()),
+    };
+    Box::new(reply.select2(timeout).then(|result| match result {
+        Ok(Either::A((username, _))) => Ok(username),
+        Ok(Either::B(_)) => Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")),
+        Err(Either::A((e, _))) |
+        Err(Either::B((e, _))) => Err(e),
+    }))
+}
+
+// Extracts the username from a reply such as "6193, 23 : USERID : UNIX : stjohns".
+fn parse(reply: &str, client_port: u16, server_port: u16) -> Option<String> {
+    let fields: Vec<&str> = reply.splitn(4, ':').map(|f| f.trim()).collect();
+    if fields.len() != 4 || !fields[1].eq_ignore_ascii_case("USERID") {
+        return None;
+    }
+    let ports: Vec<_> = fields[0].split(',').map(|p| p.trim().parse::<u16>()).collect();
+    if ports.len() != 2 || ports[0] != Ok(client_port) || ports[1] != Ok(server_port) {
+        return None;
+    }
+    let username: String = fields[3]
+        .chars()
+        .take_while(|c| !c.is_whitespace())
+        .filter(|&c| c.is_ascii_alphanumeric() || "-_.[]{}\\`^|".contains(c))
+        .take(MAX_USERNAME_LENGTH)
+        .collect();
+    if username.is_empty() {
+        None
+    } else {
+        Some(username)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use super::{parse, Ident};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("6193, 23 : USERID : UNIX : stjohns\r\n", 6193, 23),
+            Some("stjohns".to_string())
+        );
+        assert_eq!(
+            parse("6193,23:USERID:OTHER,UTF-8:lazau", 6193, 23),
+            Some("lazau".to_string())
+        );
+        assert_eq!(parse("6193, 23 : USERID : UNIX : stjohns", 6194, 23), None);
+        assert_eq!(parse("6193, 23 : ERROR : NO-USER", 6193, 23), None);
+        assert_eq!(parse("6193, 23 : USERID : UNIX : @!*", 6193, 23), None);
+        assert_eq!(
+            parse("6193, 23 : USERID : UNIX : a:very@longusername x", 6193, 23),
+            Some("averylongu".to_string())
+        );
+    }
+
+    // Against a fake identd, answering for whichever ports it is asked about.
+    #[test]
+    fn test_lookup() {
+        let identd = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = identd.local_addr().unwrap().port();
+        let fake = thread::spawn(move || {
+            let (stream, _) = identd.accept().unwrap();
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            write!(&stream, "{} : USERID : UNIX : fake\r\n", request.trim()).unwrap();
+            // Never answers the second query.
+            identd.accept().unwrap()
+        });
+
+        let ident = Ident::new();
+        let client: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        let server: SocketAddr = "127.0.0.1:6667".parse().unwrap();
+        let lookup = |timeout| {
+            let (tx, rx) = mpsc::channel();
+            ident.lookup(client, server, port, timeout, move |u| tx.send(u).unwrap());
+            rx.recv().unwrap()
+        };
+        assert_eq!(lookup(Duration::from_secs(5)), Some("fake".to_string()));
+        assert_eq!(lookup(Duration::from_millis(100)), None);
+        fake.join().unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+use std::{fmt, str};
+use std::net::IpAddr;
+
+// IRC style wildcard masks.
+// '*' matches any (possibly empty) run of characters, '?' matches exactly one character.
+// Matching is case insensitive.
+pub fn matches(mask: &str, text: &str) -> bool {
+    let mask: Vec<char> = mask.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut m, mut t) = (0, 0);
+    // Position of the last '*' seen in mask, and the text position it was matched against.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if m < mask.len() && (mask[m] == '?' || mask[m] == text[t]) {
+            m += 1;
+            t += 1;
+        } else if m < mask.len() && mask[m] == '*' {
+            backtrack = Some((m, t));
+            m += 1;
+        } else if let Some((star, star_t)) = backtrack {
+            // Let the last '*' swallow one more character and retry.
+            m = star + 1;
+            t = star_t + 1;
+            backtrack = Some((star, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    mask[m..].iter().all(|&c| c == '*')
+}
+
+// An IP address range in CIDR notation. A bare address is a single host range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (&self.address, &canonical(ip)) {
+            (&IpAddr::V4(ref net), &IpAddr::V4(ref ip)) => {
+                let mask = u32::max_value()
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(*net) & mask == u32::from(*ip) & mask
+            }
+            (&IpAddr::V6(ref net), &IpAddr::V6(ref ip)) => {
+                let mask = u128::max_value()
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(*net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// IPv4-mapped IPv6 addresses are treated as their IPv4 equivalent.
+fn canonical(ip: &IpAddr) -> IpAddr {
+    match ip {
+        &IpAddr::V6(ref v6) => {
+            match v6.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => ip.clone(),
+            }
+        }
+        _ => ip.clone(),
+    }
+}
+
+impl str::FromStr for Cidr {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match s.find('/') {
+            Some(idx) => (&s[..idx], Some(&s[idx + 1..])),
+            None => (s, None),
+        };
+        let address = canonical(&address.parse::<IpAddr>().map_err(|_| ())?);
+        let max_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(l) => l.parse::<u8>().map_err(|_| ())?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(());
+        }
+        Ok(Cidr {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{matches, Cidr};
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "lazau@127.0.0.1"));
+        assert!(matches("lazau@*", "lazau@127.0.0.1"));
+        assert!(matches("LAZAU@*.0.0.1", "lazau@127.0.0.1"));
+        assert!(matches("*@127.0.0.?", "lazau@127.0.0.1"));
+        assert!(matches("*a*u@*", "lazau@host"));
+        assert!(!matches("*@127.0.0.?", "lazau@127.0.0.10"));
+        assert!(!matches("lazau", "lazau@127.0.0.1"));
+        assert!(!matches("", "a"));
+    }
+
+    #[test]
+    fn test_cidr() {
+        let net = "10.1.0.0/16".parse::<Cidr>().unwrap();
+        assert!(net.contains(&"10.1.200.3".parse().unwrap()));
+        assert!(net.contains(&"::ffff:10.1.0.1".parse().unwrap()));
+        assert!(!net.contains(&"10.2.0.1".parse().unwrap()));
+        assert!(!net.contains(&"::1".parse().unwrap()));
+
+        let host = "2001:db8::1".parse::<Cidr>().unwrap();
+        assert!(host.contains(&"2001:db8::1".parse().unwrap()));
+        assert!(!host.contains(&"2001:db8::2".parse().unwrap()));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(
+            &"1.2.3.4".parse().unwrap(),
+        ));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("host.example.com".parse::<Cidr>().is_err());
+    }
+}
@@ -62,6 +62,7 @@ pub enum Command {
     // 5 Optionals.
     AWAY(requests::Away),
     REHASH(requests::Rehash),
+    DIE(requests::Die),
     RESTART(requests::Restart),
     SUMMON(requests::Summon),
     USERS(requests::Users),
@@ -69,13 +70,28 @@ pub enum Command {
     USERHOST(requests::Userhost),
     ISON(requests::Ison),
 
+    // Server bans (ircd-ratbox style extensions).
+    KLINE(requests::Kline),
+    UNKLINE(requests::Unkline),
+    GLINE(requests::Gline),
+    UNGLINE(requests::Ungline),
+    DLINE(requests::Dline),
+    UNDLINE(requests::Undline),
+
+    // Host changes (UnrealIRCd style extensions).
+    CHGHOST(requests::Chghost),
+
+    // Capability negotiation and SASL (IRCv3).
+    CAP(requests::Cap),
+    AUTHENTICATE(requests::Authenticate),
+
     // RFC 1459 6
     // 6.1 Error replies.
-    ERR_NOSUCHNICK(responses::NOSUCHNICK),
+    ERR_NOSUCHNICK(responses::NoSuchNick),
     ERR_NOSUCHSERVER(responses::NOSUCHSERVER),
     ERR_NOSUCHCHANNEL(responses::NoSuchChannel),
-    ERR_CANNOTSENDTOCHAN(responses::CANNOTSENDTOCHAN),
-    ERR_TOOMANYCHANNELS(responses::TOOMANYCHANNELS),
+    ERR_CANNOTSENDTOCHAN(responses::CannotSendToChan),
+    ERR_TOOMANYCHANNELS(responses::TooManyChannels),
     ERR_WASNOSUCHNICK(responses::WASNOSUCHNICK),
     ERR_TOOMANYTARGETS(responses::TOOMANYTARGETS),
     ERR_NOORIGIN(responses::NOORIGIN),
@@ -84,16 +100,16 @@ pub enum Command {
     ERR_NOTOPLEVEL(responses::NOTOPLEVEL),
     ERR_WILDTOPLEVEL(responses::WILDTOPLEVEL),
     ERR_UNKNOWNCOMMAND(responses::UNKNOWNCOMMAND),
-    ERR_NOMOTD(responses::NOMOTD),
+    ERR_NOMOTD(responses::NoMotd),
     ERR_NOADMININFO(responses::NOADMININFO),
     ERR_FILEERROR(responses::FILEERROR),
     ERR_NONICKNAMEGIVEN(responses::NONICKNAMEGIVEN),
-    ERR_ERRONEUSNICKNAME(responses::ERRONEUSNICKNAME),
+    ERR_ERRONEUSNICKNAME(responses::ErroneousNickname),
     ERR_NICKNAMEINUSE(responses::NICKNAMEINUSE),
     ERR_NICKCOLLISION(responses::NICKCOLLISION),
-    ERR_USERNOTINCHANNEL(responses::USERNOTINCHANNEL),
+    ERR_USERNOTINCHANNEL(responses::UserNotInChannel),
     ERR_NOTONCHANNEL(responses::NotOnChannel),
-    ERR_USERONCHANNEL(responses::USERONCHANNEL),
+    ERR_USERONCHANNEL(responses::UserOnChannel),
     ERR_NOLOGIN(responses::NOLOGIN),
     ERR_SUMMONDISABLED(responses::SUMMONDISABLED),
     ERR_USERSDISABLED(responses::USERSDISABLED),
@@ -101,18 +117,18 @@ pub enum Command {
     ERR_NEEDMOREPARAMS(responses::NeedMoreParams),
     ERR_ALREADYREGISTRED(responses::AlreadyRegistered),
     ERR_NOPERMFORHOST(responses::NOPERMFORHOST),
-    ERR_PASSWDMISMATCH(responses::PASSWDMISMATCH),
-    ERR_YOUREBANNEDCREEP(responses::YOUREBANNEDCREEP),
+    ERR_PASSWDMISMATCH(responses::PasswdMismatch),
+    ERR_YOUREBANNEDCREEP(responses::YoureBannedCreep),
     ERR_KEYSET(responses::KEYSET),
-    ERR_CHANNELISFULL(responses::CHANNELISFULL),
-    ERR_UNKNOWNMODE(responses::UNKNOWNMODE),
-    ERR_INVITEONLYCHAN(responses::INVITEONLYCHAN),
+    ERR_CHANNELISFULL(responses::ChannelIsFull),
+    ERR_UNKNOWNMODE(responses::UnknownMode),
+    ERR_INVITEONLYCHAN(responses::InviteOnlyChan),
     ERR_BANNEDFROMCHAN(responses::BannedFromChan),
     ERR_BADCHANNELKEY(responses::BadChannelKey),
-    ERR_NOPRIVILEGES(responses::NOPRIVILEGES),
-    ERR_CHANOPRIVSNEEDED(responses::CHANOPRIVSNEEDED),
-    ERR_CANTKILLSERVER(responses::CANTKILLSERVER),
-    ERR_NOOPERHOST(responses::NOOPERHOST),
+    ERR_NOPRIVILEGES(responses::NoPrivileges),
+    ERR_CHANOPRIVSNEEDED(responses::ChanOPrivsNeeded),
+    ERR_CANTKILLSERVER(responses::CantKillServer),
+    ERR_NOOPERHOST(responses::NoOperHost),
     ERR_UMODEUNKNOWNFLAG(responses::UModeUnknownFlag),
     ERR_USERSDONTMATCH(responses::UsersDontMatch),
     // 6.2 Command responses.
@@ -122,38 +138,38 @@ pub enum Command {
     RPL_AWAY(responses::AWAY),
     RPL_UNAWAY(responses::UNAWAY),
     RPL_NOWAWAY(responses::NOWAWAY),
-    RPL_WHOISUSER(responses::WHOISUSER),
-    RPL_WHOISSERVER(responses::WHOISSERVER),
-    RPL_WHOISOPERATOR(responses::WHOISOPERATOR),
+    RPL_WHOISUSER(responses::WhoisUser),
+    RPL_WHOISSERVER(responses::WhoisServer),
+    RPL_WHOISOPERATOR(responses::WhoisOperator),
     RPL_WHOISIDLE(responses::WHOISIDLE),
-    RPL_ENDOFWHOIS(responses::ENDOFWHOIS),
-    RPL_WHOISCHANNELS(responses::WHOISCHANNELS),
+    RPL_ENDOFWHOIS(responses::EndOfWhois),
+    RPL_WHOISCHANNELS(responses::WhoisChannels),
     RPL_WHOWASUSER(responses::WHOWASUSER),
     RPL_ENDOFWHOWAS(responses::ENDOFWHOWAS),
     RPL_LISTSTART(responses::LISTSTART),
     RPL_LIST(responses::LIST),
     RPL_LISTEND(responses::LISTEND),
-    RPL_CHANNELMODEIS(responses::CHANNELMODEIS),
-    RPL_NOTOPIC(responses::NOTOPIC),
+    RPL_CHANNELMODEIS(responses::ChannelModeIs),
+    RPL_NOTOPIC(responses::NoTopic),
     RPL_TOPIC(responses::Topic),
-    RPL_INVITING(responses::INVITING),
+    RPL_INVITING(responses::Inviting),
     RPL_SUMMONING(responses::SUMMONING),
     RPL_VERSION(responses::VERSION),
-    RPL_WHOREPLY(responses::WHOREPLY),
-    RPL_ENDOFWHO(responses::ENDOFWHO),
+    RPL_WHOREPLY(responses::WhoReply),
+    RPL_ENDOFWHO(responses::EndOfWho),
     RPL_NAMREPLY(responses::NamReply),
     RPL_ENDOFNAMES(responses::EndOfNames),
     RPL_LINKS(responses::LINKS),
     RPL_ENDOFLINKS(responses::ENDOFLINKS),
-    RPL_BANLIST(responses::BANLIST),
-    RPL_ENDOFBANLIST(responses::ENDOFBANLIST),
+    RPL_BANLIST(responses::BanList),
+    RPL_ENDOFBANLIST(responses::EndOfBanList),
     RPL_INFO(responses::INFO),
     RPL_ENDOFINFO(responses::ENDOFINFO),
-    RPL_MOTDSTART(responses::MOTDSTART),
-    RPL_MOTD(responses::MOTD),
-    RPL_ENDOFMOTD(responses::ENDOFMOTD),
-    RPL_YOUREOPER(responses::YOUREOPER),
-    RPL_REHASHING(responses::REHASHING),
+    RPL_MOTDSTART(responses::MotdStart),
+    RPL_MOTD(responses::Motd),
+    RPL_ENDOFMOTD(responses::EndOfMotd),
+    RPL_YOUREOPER(responses::YoureOper),
+    RPL_REHASHING(responses::Rehashing),
     RPL_TIME(responses::TIME),
     RPL_USERSSTART(responses::USERSSTART),
     RPL_USERS(responses::USERS),
@@ -168,17 +184,17 @@ pub enum Command {
     RPL_TRACESERVER(responses::TRACESERVER),
     RPL_TRACENEWTYPE(responses::TRACENEWTYPE),
     RPL_TRACELOG(responses::TRACELOG),
-    RPL_STATSLINKINFO(responses::STATSLINKINFO),
-    RPL_STATSCOMMANDS(responses::STATSCOMMANDS),
+    RPL_STATSLINKINFO(responses::StatsLinkInfo),
+    RPL_STATSCOMMANDS(responses::StatsCommands),
     RPL_STATSCLINE(responses::STATSCLINE),
     RPL_STATSNLINE(responses::STATSNLINE),
-    RPL_STATSILINE(responses::STATSILINE),
-    RPL_STATSKLINE(responses::STATSKLINE),
-    RPL_STATSYLINE(responses::STATSYLINE),
-    RPL_ENDOFSTATS(responses::ENDOFSTATS),
+    RPL_STATSILINE(responses::StatsILine),
+    RPL_STATSKLINE(responses::StatsKLine),
+    RPL_STATSYLINE(responses::StatsYLine),
+    RPL_ENDOFSTATS(responses::EndOfStats),
     RPL_STATSLLINE(responses::STATSLLINE),
-    RPL_STATSUPTIME(responses::STATSUPTIME),
-    RPL_STATSOLINE(responses::STATSOLINE),
+    RPL_STATSUPTIME(responses::StatsUptime),
+    RPL_STATSOLINE(responses::StatsOLine),
     RPL_STATSHLINE(responses::STATSHLINE),
     RPL_UMODEIS(responses::UMODEIS),
     RPL_LUSERCLIENT(responses::LUSERCLIENT),
@@ -212,8 +228,25 @@ pub enum Command {
     RPL_YOURHOST(responses::YourHost),
     RPL_CREATED(responses::Created),
     RPL_MYINFO(responses::MyInfo),
-    RPL_ISUPPORT(responses::ISUPPORT),
+    RPL_ISUPPORT(responses::ISupport),
     RPL_BOUNCE(responses::BOUNCE),
+    // Common extensions.
+    RPL_WHOISACCOUNT(responses::WhoisAccount),
+    RPL_WHOISHOST(responses::WhoisHost),
+    RPL_HOSTHIDDEN(responses::HostHidden),
+    ERR_INVALIDCAPCMD(responses::InvalidCapCmd),
+    RPL_LOGGEDIN(responses::LoggedIn),
+    RPL_SASLSUCCESS(responses::SaslSuccess),
+    ERR_SASLFAIL(responses::SaslFail),
+    ERR_SASLTOOLONG(responses::SaslTooLong),
+    ERR_SASLABORTED(responses::SaslAborted),
+    ERR_SASLALREADY(responses::SaslAlready),
+    RPL_SASLMECHS(responses::SaslMechs),
+    ERR_LINKCHANNEL(responses::LinkChannel),
+    ERR_THROTTLE(responses::Throttle),
+    RPL_QUIETLIST(responses::QuietList),
+    RPL_ENDOFQUIETLIST(responses::EndOfQuietList),
+    ERR_BANLISTFULL(responses::BanListFull),
 }
 
 impl fmt::Display for Command {
@@ -258,12 +291,22 @@ impl fmt::Display for Command {
             &Command::ERROR(ref c) => write!(f, "{}", c),
             &Command::AWAY(ref c) => write!(f, "{}", c),
             &Command::REHASH(ref c) => write!(f, "{}", c),
+            &Command::DIE(ref c) => write!(f, "{}", c),
             &Command::RESTART(ref c) => write!(f, "{}", c),
             &Command::SUMMON(ref c) => write!(f, "{}", c),
             &Command::USERS(ref c) => write!(f, "{}", c),
             &Command::WALLOPS(ref c) => write!(f, "{}", c),
             &Command::USERHOST(ref c) => write!(f, "{}", c),
             &Command::ISON(ref c) => write!(f, "{}", c),
+            &Command::KLINE(ref c) => write!(f, "{}", c),
+            &Command::UNKLINE(ref c) => write!(f, "{}", c),
+            &Command::GLINE(ref c) => write!(f, "{}", c),
+            &Command::UNGLINE(ref c) => write!(f, "{}", c),
+            &Command::DLINE(ref c) => write!(f, "{}", c),
+            &Command::UNDLINE(ref c) => write!(f, "{}", c),
+            &Command::CHGHOST(ref c) => write!(f, "{}", c),
+            &Command::CAP(ref c) => write!(f, "{}", c),
+            &Command::AUTHENTICATE(ref c) => write!(f, "{}", c),
             &Command::ERR_NOSUCHNICK(ref c) => write!(f, "{}", c),
             &Command::ERR_NOSUCHSERVER(ref c) => write!(f, "{}", c),
             &Command::ERR_NOSUCHCHANNEL(ref c) => write!(f, "{}", c),
@@ -404,6 +447,241 @@ impl fmt::Display for Command {
             &Command::RPL_MYINFO(ref c) => write!(f, "{}", c),
             &Command::RPL_ISUPPORT(ref c) => write!(f, "{}", c),
             &Command::RPL_BOUNCE(ref c) => write!(f, "{}", c),
+            &Command::RPL_WHOISACCOUNT(ref c) => write!(f, "{}", c),
+            &Command::RPL_WHOISHOST(ref c) => write!(f, "{}", c),
+            &Command::RPL_HOSTHIDDEN(ref c) => write!(f, "{}", c),
+            &Command::ERR_INVALIDCAPCMD(ref c) => write!(f, "{}", c),
+            &Command::RPL_LOGGEDIN(ref c) => write!(f, "{}", c),
+            &Command::RPL_SASLSUCCESS(ref c) => write!(f, "{}", c),
+            &Command::ERR_SASLFAIL(ref c) => write!(f, "{}", c),
+            &Command::ERR_SASLTOOLONG(ref c) => write!(f, "{}", c),
+            &Command::ERR_SASLABORTED(ref c) => write!(f, "{}", c),
+            &Command::ERR_SASLALREADY(ref c) => write!(f, "{}", c),
+            &Command::RPL_SASLMECHS(ref c) => write!(f, "{}", c),
+            &Command::ERR_LINKCHANNEL(ref c) => write!(f, "{}", c),
+            &Command::ERR_THROTTLE(ref c) => write!(f, "{}", c),
+            &Command::RPL_QUIETLIST(ref c) => write!(f, "{}", c),
+            &Command::RPL_ENDOFQUIETLIST(ref c) => write!(f, "{}", c),
+            &Command::ERR_BANLISTFULL(ref c) => write!(f, "{}", c),
+        }
+    }
+}
+
+impl Command {
+    // Command name, as used for usage statistics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            &Command::NICK(_) => "NICK",
+            &Command::PASS(_) => "PASS",
+            &Command::USER(_) => "USER",
+            &Command::SERVER(_) => "SERVER",
+            &Command::OPER(_) => "OPER",
+            &Command::SERVICE(_) => "SERVICE",
+            &Command::QUIT(_) => "QUIT",
+            &Command::SQUIT(_) => "SQUIT",
+            &Command::JOIN(_) => "JOIN",
+            &Command::PART(_) => "PART",
+            &Command::MODE(_) => "MODE",
+            &Command::TOPIC(_) => "TOPIC",
+            &Command::NAMES(_) => "NAMES",
+            &Command::LIST(_) => "LIST",
+            &Command::INVITE(_) => "INVITE",
+            &Command::KICK(_) => "KICK",
+            &Command::MOTD(_) => "MOTD",
+            &Command::LUSERS(_) => "LUSERS",
+            &Command::VERSION(_) => "VERSION",
+            &Command::STATS(_) => "STATS",
+            &Command::LINKS(_) => "LINKS",
+            &Command::TIME(_) => "TIME",
+            &Command::CONNECT(_) => "CONNECT",
+            &Command::TRACE(_) => "TRACE",
+            &Command::ADMIN(_) => "ADMIN",
+            &Command::INFO(_) => "INFO",
+            &Command::PRIVMSG(_) => "PRIVMSG",
+            &Command::NOTICE(_) => "NOTICE",
+            &Command::SERVLIST(_) => "SERVLIST",
+            &Command::SQUERY(_) => "SQUERY",
+            &Command::WHO(_) => "WHO",
+            &Command::WHOIS(_) => "WHOIS",
+            &Command::WHOWAS(_) => "WHOWAS",
+            &Command::KILL(_) => "KILL",
+            &Command::PING(_) => "PING",
+            &Command::PONG(_) => "PONG",
+            &Command::ERROR(_) => "ERROR",
+            &Command::AWAY(_) => "AWAY",
+            &Command::REHASH(_) => "REHASH",
+            &Command::DIE(_) => "DIE",
+            &Command::RESTART(_) => "RESTART",
+            &Command::SUMMON(_) => "SUMMON",
+            &Command::USERS(_) => "USERS",
+            &Command::WALLOPS(_) => "WALLOPS",
+            &Command::USERHOST(_) => "USERHOST",
+            &Command::ISON(_) => "ISON",
+            &Command::KLINE(_) => "KLINE",
+            &Command::UNKLINE(_) => "UNKLINE",
+            &Command::GLINE(_) => "GLINE",
+            &Command::UNGLINE(_) => "UNGLINE",
+            &Command::DLINE(_) => "DLINE",
+            &Command::UNDLINE(_) => "UNDLINE",
+            &Command::CHGHOST(_) => "CHGHOST",
+            &Command::CAP(_) => "CAP",
+            &Command::AUTHENTICATE(_) => "AUTHENTICATE",
+            &Command::ERR_NOSUCHNICK(_) => "ERR_NOSUCHNICK",
+            &Command::ERR_NOSUCHSERVER(_) => "ERR_NOSUCHSERVER",
+            &Command::ERR_NOSUCHCHANNEL(_) => "ERR_NOSUCHCHANNEL",
+            &Command::ERR_CANNOTSENDTOCHAN(_) => "ERR_CANNOTSENDTOCHAN",
+            &Command::ERR_TOOMANYCHANNELS(_) => "ERR_TOOMANYCHANNELS",
+            &Command::ERR_WASNOSUCHNICK(_) => "ERR_WASNOSUCHNICK",
+            &Command::ERR_TOOMANYTARGETS(_) => "ERR_TOOMANYTARGETS",
+            &Command::ERR_NOORIGIN(_) => "ERR_NOORIGIN",
+            &Command::ERR_NORECIPIENT(_) => "ERR_NORECIPIENT",
+            &Command::ERR_NOTEXTTOSEND(_) => "ERR_NOTEXTTOSEND",
+            &Command::ERR_NOTOPLEVEL(_) => "ERR_NOTOPLEVEL",
+            &Command::ERR_WILDTOPLEVEL(_) => "ERR_WILDTOPLEVEL",
+            &Command::ERR_UNKNOWNCOMMAND(_) => "ERR_UNKNOWNCOMMAND",
+            &Command::ERR_NOMOTD(_) => "ERR_NOMOTD",
+            &Command::ERR_NOADMININFO(_) => "ERR_NOADMININFO",
+            &Command::ERR_FILEERROR(_) => "ERR_FILEERROR",
+            &Command::ERR_NONICKNAMEGIVEN(_) => "ERR_NONICKNAMEGIVEN",
+            &Command::ERR_ERRONEUSNICKNAME(_) => "ERR_ERRONEUSNICKNAME",
+            &Command::ERR_NICKNAMEINUSE(_) => "ERR_NICKNAMEINUSE",
+            &Command::ERR_NICKCOLLISION(_) => "ERR_NICKCOLLISION",
+            &Command::ERR_USERNOTINCHANNEL(_) => "ERR_USERNOTINCHANNEL",
+            &Command::ERR_NOTONCHANNEL(_) => "ERR_NOTONCHANNEL",
+            &Command::ERR_USERONCHANNEL(_) => "ERR_USERONCHANNEL",
+            &Command::ERR_NOLOGIN(_) => "ERR_NOLOGIN",
+            &Command::ERR_SUMMONDISABLED(_) => "ERR_SUMMONDISABLED",
+            &Command::ERR_USERSDISABLED(_) => "ERR_USERSDISABLED",
+            &Command::ERR_NOTREGISTERED(_) => "ERR_NOTREGISTERED",
+            &Command::ERR_NEEDMOREPARAMS(_) => "ERR_NEEDMOREPARAMS",
+            &Command::ERR_ALREADYREGISTRED(_) => "ERR_ALREADYREGISTRED",
+            &Command::ERR_NOPERMFORHOST(_) => "ERR_NOPERMFORHOST",
+            &Command::ERR_PASSWDMISMATCH(_) => "ERR_PASSWDMISMATCH",
+            &Command::ERR_YOUREBANNEDCREEP(_) => "ERR_YOUREBANNEDCREEP",
+            &Command::ERR_KEYSET(_) => "ERR_KEYSET",
+            &Command::ERR_CHANNELISFULL(_) => "ERR_CHANNELISFULL",
+            &Command::ERR_UNKNOWNMODE(_) => "ERR_UNKNOWNMODE",
+            &Command::ERR_INVITEONLYCHAN(_) => "ERR_INVITEONLYCHAN",
+            &Command::ERR_BANNEDFROMCHAN(_) => "ERR_BANNEDFROMCHAN",
+            &Command::ERR_BADCHANNELKEY(_) => "ERR_BADCHANNELKEY",
+            &Command::ERR_NOPRIVILEGES(_) => "ERR_NOPRIVILEGES",
+            &Command::ERR_CHANOPRIVSNEEDED(_) => "ERR_CHANOPRIVSNEEDED",
+            &Command::ERR_CANTKILLSERVER(_) => "ERR_CANTKILLSERVER",
+            &Command::ERR_NOOPERHOST(_) => "ERR_NOOPERHOST",
+            &Command::ERR_UMODEUNKNOWNFLAG(_) => "ERR_UMODEUNKNOWNFLAG",
+            &Command::ERR_USERSDONTMATCH(_) => "ERR_USERSDONTMATCH",
+            &Command::RPL_NONE(_) => "RPL_NONE",
+            &Command::RPL_USERHOST(_) => "RPL_USERHOST",
+            &Command::RPL_ISON(_) => "RPL_ISON",
+            &Command::RPL_AWAY(_) => "RPL_AWAY",
+            &Command::RPL_UNAWAY(_) => "RPL_UNAWAY",
+            &Command::RPL_NOWAWAY(_) => "RPL_NOWAWAY",
+            &Command::RPL_WHOISUSER(_) => "RPL_WHOISUSER",
+            &Command::RPL_WHOISSERVER(_) => "RPL_WHOISSERVER",
+            &Command::RPL_WHOISOPERATOR(_) => "RPL_WHOISOPERATOR",
+            &Command::RPL_WHOISIDLE(_) => "RPL_WHOISIDLE",
+            &Command::RPL_ENDOFWHOIS(_) => "RPL_ENDOFWHOIS",
+            &Command::RPL_WHOISCHANNELS(_) => "RPL_WHOISCHANNELS",
+            &Command::RPL_WHOWASUSER(_) => "RPL_WHOWASUSER",
+            &Command::RPL_ENDOFWHOWAS(_) => "RPL_ENDOFWHOWAS",
+            &Command::RPL_LISTSTART(_) => "RPL_LISTSTART",
+            &Command::RPL_LIST(_) => "RPL_LIST",
+            &Command::RPL_LISTEND(_) => "RPL_LISTEND",
+            &Command::RPL_CHANNELMODEIS(_) => "RPL_CHANNELMODEIS",
+            &Command::RPL_NOTOPIC(_) => "RPL_NOTOPIC",
+            &Command::RPL_TOPIC(_) => "RPL_TOPIC",
+            &Command::RPL_INVITING(_) => "RPL_INVITING",
+            &Command::RPL_SUMMONING(_) => "RPL_SUMMONING",
+            &Command::RPL_VERSION(_) => "RPL_VERSION",
+            &Command::RPL_WHOREPLY(_) => "RPL_WHOREPLY",
+            &Command::RPL_ENDOFWHO(_) => "RPL_ENDOFWHO",
+            &Command::RPL_NAMREPLY(_) => "RPL_NAMREPLY",
+            &Command::RPL_ENDOFNAMES(_) => "RPL_ENDOFNAMES",
+            &Command::RPL_LINKS(_) => "RPL_LINKS",
+            &Command::RPL_ENDOFLINKS(_) => "RPL_ENDOFLINKS",
+            &Command::RPL_BANLIST(_) => "RPL_BANLIST",
+            &Command::RPL_ENDOFBANLIST(_) => "RPL_ENDOFBANLIST",
+            &Command::RPL_INFO(_) => "RPL_INFO",
+            &Command::RPL_ENDOFINFO(_) => "RPL_ENDOFINFO",
+            &Command::RPL_MOTDSTART(_) => "RPL_MOTDSTART",
+            &Command::RPL_MOTD(_) => "RPL_MOTD",
+            &Command::RPL_ENDOFMOTD(_) => "RPL_ENDOFMOTD",
+            &Command::RPL_YOUREOPER(_) => "RPL_YOUREOPER",
+            &Command::RPL_REHASHING(_) => "RPL_REHASHING",
+            &Command::RPL_TIME(_) => "RPL_TIME",
+            &Command::RPL_USERSSTART(_) => "RPL_USERSSTART",
+            &Command::RPL_USERS(_) => "RPL_USERS",
+            &Command::RPL_ENDOFUSERS(_) => "RPL_ENDOFUSERS",
+            &Command::RPL_NOUSERS(_) => "RPL_NOUSERS",
+            &Command::RPL_TRACELINK(_) => "RPL_TRACELINK",
+            &Command::RPL_TRACECONNECTING(_) => "RPL_TRACECONNECTING",
+            &Command::RPL_TRACEHANDSHAKE(_) => "RPL_TRACEHANDSHAKE",
+            &Command::RPL_TRACEUNKNOWN(_) => "RPL_TRACEUNKNOWN",
+            &Command::RPL_TRACEOPERATOR(_) => "RPL_TRACEOPERATOR",
+            &Command::RPL_TRACEUSER(_) => "RPL_TRACEUSER",
+            &Command::RPL_TRACESERVER(_) => "RPL_TRACESERVER",
+            &Command::RPL_TRACENEWTYPE(_) => "RPL_TRACENEWTYPE",
+            &Command::RPL_TRACELOG(_) => "RPL_TRACELOG",
+            &Command::RPL_STATSLINKINFO(_) => "RPL_STATSLINKINFO",
+            &Command::RPL_STATSCOMMANDS(_) => "RPL_STATSCOMMANDS",
+            &Command::RPL_STATSCLINE(_) => "RPL_STATSCLINE",
+            &Command::RPL_STATSNLINE(_) => "RPL_STATSNLINE",
+            &Command::RPL_STATSILINE(_) => "RPL_STATSILINE",
+            &Command::RPL_STATSKLINE(_) => "RPL_STATSKLINE",
+            &Command::RPL_STATSYLINE(_) => "RPL_STATSYLINE",
+            &Command::RPL_ENDOFSTATS(_) => "RPL_ENDOFSTATS",
+            &Command::RPL_STATSLLINE(_) => "RPL_STATSLLINE",
+            &Command::RPL_STATSUPTIME(_) => "RPL_STATSUPTIME",
+            &Command::RPL_STATSOLINE(_) => "RPL_STATSOLINE",
+            &Command::RPL_STATSHLINE(_) => "RPL_STATSHLINE",
+            &Command::RPL_UMODEIS(_) => "RPL_UMODEIS",
+            &Command::RPL_LUSERCLIENT(_) => "RPL_LUSERCLIENT",
+            &Command::RPL_LUSEROP(_) => "RPL_LUSEROP",
+            &Command::RPL_LUSERUNKNOWN(_) => "RPL_LUSERUNKNOWN",
+            &Command::RPL_LUSERCHANNELS(_) => "RPL_LUSERCHANNELS",
+            &Command::RPL_LUSERME(_) => "RPL_LUSERME",
+            &Command::RPL_ADMINME(_) => "RPL_ADMINME",
+            &Command::RPL_ADMINLOC1(_) => "RPL_ADMINLOC1",
+            &Command::RPL_ADMINLOC2(_) => "RPL_ADMINLOC2",
+            &Command::RPL_ADMINEMAIL(_) => "RPL_ADMINEMAIL",
+            &Command::RPL_TRACECLASS(_) => "RPL_TRACECLASS",
+            &Command::RPL_STATSQLINE(_) => "RPL_STATSQLINE",
+            &Command::RPL_SERVICEINFO(_) => "RPL_SERVICEINFO",
+            &Command::RPL_ENDOFSERVICES(_) => "RPL_ENDOFSERVICES",
+            &Command::RPL_SERVICE(_) => "RPL_SERVICE",
+            &Command::RPL_SERVLIST(_) => "RPL_SERVLIST",
+            &Command::RPL_SERVLISTEND(_) => "RPL_SERVLISTEND",
+            &Command::RPL_WHOISCHANOP(_) => "RPL_WHOISCHANOP",
+            &Command::RPL_KILLDONE(_) => "RPL_KILLDONE",
+            &Command::RPL_CLOSING(_) => "RPL_CLOSING",
+            &Command::RPL_CLOSEEND(_) => "RPL_CLOSEEND",
+            &Command::RPL_INFOSTART(_) => "RPL_INFOSTART",
+            &Command::RPL_MYPORTIS(_) => "RPL_MYPORTIS",
+            &Command::ERR_YOUWILLBEBANNED(_) => "ERR_YOUWILLBEBANNED",
+            &Command::ERR_BADCHANMASK(_) => "ERR_BADCHANMASK",
+            &Command::ERR_NOSERVICEHOST(_) => "ERR_NOSERVICEHOST",
+            &Command::RPL_WELCOME(_) => "RPL_WELCOME",
+            &Command::RPL_YOURHOST(_) => "RPL_YOURHOST",
+            &Command::RPL_CREATED(_) => "RPL_CREATED",
+            &Command::RPL_MYINFO(_) => "RPL_MYINFO",
+            &Command::RPL_ISUPPORT(_) => "RPL_ISUPPORT",
+            &Command::RPL_BOUNCE(_) => "RPL_BOUNCE",
+            &Command::RPL_WHOISACCOUNT(_) => "RPL_WHOISACCOUNT",
+            &Command::RPL_WHOISHOST(_) => "RPL_WHOISHOST",
+            &Command::RPL_HOSTHIDDEN(_) => "RPL_HOSTHIDDEN",
+            &Command::ERR_INVALIDCAPCMD(_) => "ERR_INVALIDCAPCMD",
+            &Command::RPL_LOGGEDIN(_) => "RPL_LOGGEDIN",
+            &Command::RPL_SASLSUCCESS(_) => "RPL_SASLSUCCESS",
+            &Command::ERR_SASLFAIL(_) => "ERR_SASLFAIL",
+            &Command::ERR_SASLTOOLONG(_) => "ERR_SASLTOOLONG",
+            &Command::ERR_SASLABORTED(_) => "ERR_SASLABORTED",
+            &Command::ERR_SASLALREADY(_) => "ERR_SASLALREADY",
+            &Command::RPL_SASLMECHS(_) => "RPL_SASLMECHS",
+            &Command::ERR_LINKCHANNEL(_) => "ERR_LINKCHANNEL",
+            &Command::ERR_THROTTLE(_) => "ERR_THROTTLE",
+            &Command::RPL_QUIETLIST(_) => "RPL_QUIETLIST",
+            &Command::RPL_ENDOFQUIETLIST(_) => "RPL_ENDOFQUIETLIST",
+            &Command::ERR_BANLISTFULL(_) => "ERR_BANLISTFULL",
         }
     }
 }
@@ -442,6 +720,29 @@ fn extract_params<'a>(
     Ok(params)
 }
 
+// Extracts "[duration] mask [:reason]" as used by KLINE, GLINE and DLINE.
+fn extract_ban_params(
+    rem: &str,
+    err: &'static str,
+) -> Result<(Option<u64>, String, Option<String>), ParseError> {
+    let p = try!(extract_params(rem, 1, err));
+    let (duration, idx) = match p[0].parse::<u64>() {
+        // A zero duration is permanent.
+        Ok(0) => (None, 1),
+        Ok(d) => (Some(d), 1),
+        Err(_) => (None, 0),
+    };
+    if p.len() <= idx {
+        return Err(ParseError::NeedMoreParams { command: err.to_string() });
+    }
+    let reason = if p.len() > idx + 1 {
+        Some(p[idx + 1..].join(" "))
+    } else {
+        None
+    };
+    Ok((duration, p[idx].to_string(), reason))
+}
+
 // Macro to generate a value for a required field.
 macro_rules! rf {
     ($p:expr, $idx:expr, $type:ty) => { $p[$idx].parse::<$type>()? };
@@ -848,7 +1149,15 @@ impl str::FromStr for Command {
 
             "REHASH" => Ok(Command::REHASH(requests::Rehash {})),
 
-            "RESTART" => Ok(Command::RESTART(requests::Restart {})),
+            "DIE" => {
+                let p = try!(extract_params(r, 0, "DIE"));
+                Ok(Command::DIE(requests::Die { reason: of!(p, 0, String) }))
+            }
+
+            "RESTART" => {
+                let p = try!(extract_params(r, 0, "RESTART"));
+                Ok(Command::RESTART(requests::Restart { reason: of!(p, 0, String) }))
+            }
 
             "SUMMON" => {
                 let p = try!(extract_params(r, 1, "SUMMON"));
@@ -887,8 +1196,74 @@ impl str::FromStr for Command {
                 }))
             }
 
+            "KLINE" => {
+                let (duration, mask, reason) = try!(extract_ban_params(r, "KLINE"));
+                Ok(Command::KLINE(requests::Kline {
+                    duration,
+                    mask,
+                    reason,
+                }))
+            }
+
+            "UNKLINE" => {
+                let p = try!(extract_params(r, 1, "UNKLINE"));
+                Ok(Command::UNKLINE(requests::Unkline { mask: rf!(p, 0, String) }))
+            }
+
+            "GLINE" => {
+                let (duration, mask, reason) = try!(extract_ban_params(r, "GLINE"));
+                Ok(Command::GLINE(requests::Gline {
+                    duration,
+                    mask,
+                    reason,
+                }))
+            }
+
+            "UNGLINE" => {
+                let p = try!(extract_params(r, 1, "UNGLINE"));
+                Ok(Command::UNGLINE(requests::Ungline { mask: rf!(p, 0, String) }))
+            }
+
+            "DLINE" => {
+                let (duration, mask, reason) = try!(extract_ban_params(r, "DLINE"));
+                Ok(Command::DLINE(requests::Dline {
+                    duration,
+                    mask,
+                    reason,
+                }))
+            }
+
+            "UNDLINE" => {
+                let p = try!(extract_params(r, 1, "UNDLINE"));
+                Ok(Command::UNDLINE(requests::Undline { mask: rf!(p, 0, String) }))
+            }
+
+            "CHGHOST" => {
+                let p = extract_params(r, 2, "CHGHOST")?;
+                Ok(Command::CHGHOST(requests::Chghost {
+                    nickname: rf!(p, 0, String),
+                    host: rf!(p, 1, String),
+                }))
+            }
+
+            "CAP" => {
+                let p = extract_params(r, 1, "CAP")?;
+                Ok(Command::CAP(requests::Cap {
+                    target: None,
+                    subcommand: p[0].to_uppercase(),
+                    capabilities: of!(p, 1, String),
+                }))
+            }
+
+            "AUTHENTICATE" => {
+                let p = extract_params(r, 1, "AUTHENTICATE")?;
+                Ok(Command::AUTHENTICATE(
+                    requests::Authenticate { data: rf!(p, 0, String) },
+                ))
+            }
+
             // TODO(lazau): Parse parameters.
-            "401" => Ok(Command::ERR_NOSUCHNICK(responses::NOSUCHNICK::default())),
+            "401" => Ok(Command::ERR_NOSUCHNICK(responses::NoSuchNick::default())),
             "402" => Ok(Command::ERR_NOSUCHSERVER(
                 responses::NOSUCHSERVER::default(),
             )),
@@ -896,10 +1271,10 @@ impl str::FromStr for Command {
                 responses::NoSuchChannel::default(),
             )),
             "404" => Ok(Command::ERR_CANNOTSENDTOCHAN(
-                responses::CANNOTSENDTOCHAN::default(),
+                responses::CannotSendToChan::default(),
             )),
             "405" => Ok(Command::ERR_TOOMANYCHANNELS(
-                responses::TOOMANYCHANNELS::default(),
+                responses::TooManyChannels::default(),
             )),
             "406" => Ok(Command::ERR_WASNOSUCHNICK(
                 responses::WASNOSUCHNICK::default(),
@@ -919,14 +1294,14 @@ impl str::FromStr for Command {
             "421" => Ok(Command::ERR_UNKNOWNCOMMAND(
                 responses::UNKNOWNCOMMAND::default(),
             )),
-            "422" => Ok(Command::ERR_NOMOTD(responses::NOMOTD::default())),
+            "422" => Ok(Command::ERR_NOMOTD(responses::NoMotd::default())),
             "423" => Ok(Command::ERR_NOADMININFO(responses::NOADMININFO::default())),
             "424" => Ok(Command::ERR_FILEERROR(responses::FILEERROR::default())),
             "431" => Ok(Command::ERR_NONICKNAMEGIVEN(
                 responses::NONICKNAMEGIVEN::default(),
             )),
             "432" => Ok(Command::ERR_ERRONEUSNICKNAME(
-                responses::ERRONEUSNICKNAME::default(),
+                responses::ErroneousNickname::default(),
             )),
             "433" => Ok(Command::ERR_NICKNAMEINUSE(
                 responses::NICKNAMEINUSE::default(),
@@ -935,13 +1310,13 @@ impl str::FromStr for Command {
                 responses::NICKCOLLISION::default(),
             )),
             "441" => Ok(Command::ERR_USERNOTINCHANNEL(
-                responses::USERNOTINCHANNEL::default(),
+                responses::UserNotInChannel::default(),
             )),
             "442" => Ok(Command::ERR_NOTONCHANNEL(
                 responses::NotOnChannel::default(),
             )),
             "443" => Ok(Command::ERR_USERONCHANNEL(
-                responses::USERONCHANNEL::default(),
+                responses::UserOnChannel::default(),
             )),
             "444" => Ok(Command::ERR_NOLOGIN(responses::NOLOGIN::default())),
             "445" => Ok(Command::ERR_SUMMONDISABLED(
@@ -963,18 +1338,18 @@ impl str::FromStr for Command {
                 responses::NOPERMFORHOST::default(),
             )),
             "464" => Ok(Command::ERR_PASSWDMISMATCH(
-                responses::PASSWDMISMATCH::default(),
+                responses::PasswdMismatch::default(),
             )),
             "465" => Ok(Command::ERR_YOUREBANNEDCREEP(
-                responses::YOUREBANNEDCREEP::default(),
+                responses::YoureBannedCreep::default(),
             )),
             "467" => Ok(Command::ERR_KEYSET(responses::KEYSET::default())),
             "471" => Ok(Command::ERR_CHANNELISFULL(
-                responses::CHANNELISFULL::default(),
+                responses::ChannelIsFull::default(),
             )),
-            "472" => Ok(Command::ERR_UNKNOWNMODE(responses::UNKNOWNMODE::default())),
+            "472" => Ok(Command::ERR_UNKNOWNMODE(responses::UnknownMode::default())),
             "473" => Ok(Command::ERR_INVITEONLYCHAN(
-                responses::INVITEONLYCHAN::default(),
+                responses::InviteOnlyChan::default(),
             )),
             "474" => Ok(Command::ERR_BANNEDFROMCHAN(
                 responses::BannedFromChan::default(),
@@ -983,15 +1358,15 @@ impl str::FromStr for Command {
                 responses::BadChannelKey::default(),
             )),
             "481" => Ok(Command::ERR_NOPRIVILEGES(
-                responses::NOPRIVILEGES::default(),
+                responses::NoPrivileges::default(),
             )),
             "482" => Ok(Command::ERR_CHANOPRIVSNEEDED(
-                responses::CHANOPRIVSNEEDED::default(),
+                responses::ChanOPrivsNeeded::default(),
             )),
             "483" => Ok(Command::ERR_CANTKILLSERVER(
-                responses::CANTKILLSERVER::default(),
+                responses::CantKillServer::default(),
             )),
-            "491" => Ok(Command::ERR_NOOPERHOST(responses::NOOPERHOST::default())),
+            "491" => Ok(Command::ERR_NOOPERHOST(responses::NoOperHost::default())),
             "501" => Ok(Command::ERR_UMODEUNKNOWNFLAG(
                 responses::UModeUnknownFlag::default(),
             )),
@@ -1004,15 +1379,15 @@ impl str::FromStr for Command {
             "301" => Ok(Command::RPL_AWAY(responses::AWAY::default())),
             "305" => Ok(Command::RPL_UNAWAY(responses::UNAWAY::default())),
             "306" => Ok(Command::RPL_NOWAWAY(responses::NOWAWAY::default())),
-            "311" => Ok(Command::RPL_WHOISUSER(responses::WHOISUSER::default())),
-            "312" => Ok(Command::RPL_WHOISSERVER(responses::WHOISSERVER::default())),
+            "311" => Ok(Command::RPL_WHOISUSER(responses::WhoisUser::default())),
+            "312" => Ok(Command::RPL_WHOISSERVER(responses::WhoisServer::default())),
             "313" => Ok(Command::RPL_WHOISOPERATOR(
-                responses::WHOISOPERATOR::default(),
+                responses::WhoisOperator::default(),
             )),
             "317" => Ok(Command::RPL_WHOISIDLE(responses::WHOISIDLE::default())),
-            "318" => Ok(Command::RPL_ENDOFWHOIS(responses::ENDOFWHOIS::default())),
+            "318" => Ok(Command::RPL_ENDOFWHOIS(responses::EndOfWhois::default())),
             "319" => Ok(Command::RPL_WHOISCHANNELS(
-                responses::WHOISCHANNELS::default(),
+                responses::WhoisChannels::default(),
             )),
             "314" => Ok(Command::RPL_WHOWASUSER(responses::WHOWASUSER::default())),
             "369" => Ok(Command::RPL_ENDOFWHOWAS(responses::ENDOFWHOWAS::default())),
@@ -1020,30 +1395,30 @@ impl str::FromStr for Command {
             "322" => Ok(Command::RPL_LIST(responses::LIST::default())),
             "323" => Ok(Command::RPL_LISTEND(responses::LISTEND::default())),
             "324" => Ok(Command::RPL_CHANNELMODEIS(
-                responses::CHANNELMODEIS::default(),
+                responses::ChannelModeIs::default(),
             )),
-            "331" => Ok(Command::RPL_NOTOPIC(responses::NOTOPIC::default())),
+            "331" => Ok(Command::RPL_NOTOPIC(responses::NoTopic::default())),
             "332" => Ok(Command::RPL_TOPIC(responses::Topic::default())),
-            "341" => Ok(Command::RPL_INVITING(responses::INVITING::default())),
+            "341" => Ok(Command::RPL_INVITING(responses::Inviting::default())),
             "342" => Ok(Command::RPL_SUMMONING(responses::SUMMONING::default())),
             "351" => Ok(Command::RPL_VERSION(responses::VERSION::default())),
-            "352" => Ok(Command::RPL_WHOREPLY(responses::WHOREPLY::default())),
-            "315" => Ok(Command::RPL_ENDOFWHO(responses::ENDOFWHO::default())),
+            "352" => Ok(Command::RPL_WHOREPLY(responses::WhoReply::default())),
+            "315" => Ok(Command::RPL_ENDOFWHO(responses::EndOfWho::default())),
             "353" => Ok(Command::RPL_NAMREPLY(responses::NamReply::default())),
             "366" => Ok(Command::RPL_ENDOFNAMES(responses::EndOfNames::default())),
             "364" => Ok(Command::RPL_LINKS(responses::LINKS::default())),
             "365" => Ok(Command::RPL_ENDOFLINKS(responses::ENDOFLINKS::default())),
-            "367" => Ok(Command::RPL_BANLIST(responses::BANLIST::default())),
+            "367" => Ok(Command::RPL_BANLIST(responses::BanList::default())),
             "368" => Ok(Command::RPL_ENDOFBANLIST(
-                responses::ENDOFBANLIST::default(),
+                responses::EndOfBanList::default(),
             )),
             "371" => Ok(Command::RPL_INFO(responses::INFO::default())),
             "374" => Ok(Command::RPL_ENDOFINFO(responses::ENDOFINFO::default())),
-            "375" => Ok(Command::RPL_MOTDSTART(responses::MOTDSTART::default())),
-            "372" => Ok(Command::RPL_MOTD(responses::MOTD::default())),
-            "376" => Ok(Command::RPL_ENDOFMOTD(responses::ENDOFMOTD::default())),
-            "381" => Ok(Command::RPL_YOUREOPER(responses::YOUREOPER::default())),
-            "382" => Ok(Command::RPL_REHASHING(responses::REHASHING::default())),
+            "375" => Ok(Command::RPL_MOTDSTART(responses::MotdStart::default())),
+            "372" => Ok(Command::RPL_MOTD(responses::Motd::default())),
+            "376" => Ok(Command::RPL_ENDOFMOTD(responses::EndOfMotd::default())),
+            "381" => Ok(Command::RPL_YOUREOPER(responses::YoureOper::default())),
+            "382" => Ok(Command::RPL_REHASHING(responses::Rehashing::default())),
             "391" => Ok(Command::RPL_TIME(responses::TIME::default())),
             "392" => Ok(Command::RPL_USERSSTART(responses::USERSSTART::default())),
             "393" => Ok(Command::RPL_USERS(responses::USERS::default())),
@@ -1069,20 +1444,20 @@ impl str::FromStr for Command {
             )),
             "261" => Ok(Command::RPL_TRACELOG(responses::TRACELOG::default())),
             "211" => Ok(Command::RPL_STATSLINKINFO(
-                responses::STATSLINKINFO::default(),
+                responses::StatsLinkInfo::default(),
             )),
             "212" => Ok(Command::RPL_STATSCOMMANDS(
-                responses::STATSCOMMANDS::default(),
+                responses::StatsCommands::default(),
             )),
             "213" => Ok(Command::RPL_STATSCLINE(responses::STATSCLINE::default())),
             "214" => Ok(Command::RPL_STATSNLINE(responses::STATSNLINE::default())),
-            "215" => Ok(Command::RPL_STATSILINE(responses::STATSILINE::default())),
-            "216" => Ok(Command::RPL_STATSKLINE(responses::STATSKLINE::default())),
-            "218" => Ok(Command::RPL_STATSYLINE(responses::STATSYLINE::default())),
-            "219" => Ok(Command::RPL_ENDOFSTATS(responses::ENDOFSTATS::default())),
+            "215" => Ok(Command::RPL_STATSILINE(responses::StatsILine::default())),
+            "216" => Ok(Command::RPL_STATSKLINE(responses::StatsKLine::default())),
+            "218" => Ok(Command::RPL_STATSYLINE(responses::StatsYLine::default())),
+            "219" => Ok(Command::RPL_ENDOFSTATS(responses::EndOfStats::default())),
             "241" => Ok(Command::RPL_STATSLLINE(responses::STATSLLINE::default())),
-            "242" => Ok(Command::RPL_STATSUPTIME(responses::STATSUPTIME::default())),
-            "243" => Ok(Command::RPL_STATSOLINE(responses::STATSOLINE::default())),
+            "242" => Ok(Command::RPL_STATSUPTIME(responses::StatsUptime::default())),
+            "243" => Ok(Command::RPL_STATSOLINE(responses::StatsOLine::default())),
             "244" => Ok(Command::RPL_STATSHLINE(responses::STATSHLINE::default())),
             "221" => Ok(Command::RPL_UMODEIS(responses::UMODEIS::default())),
             "251" => Ok(Command::RPL_LUSERCLIENT(responses::LUSERCLIENT::default())),
@@ -1124,8 +1499,28 @@ impl str::FromStr for Command {
             "002" => Ok(Command::RPL_YOURHOST(responses::YourHost::default())),
             "003" => Ok(Command::RPL_CREATED(responses::Created::default())),
             "004" => Ok(Command::RPL_MYINFO(responses::MyInfo::default())),
-            "005" => Ok(Command::RPL_ISUPPORT(responses::ISUPPORT::default())),
+            "005" => Ok(Command::RPL_ISUPPORT(responses::ISupport::default())),
             "010" => Ok(Command::RPL_BOUNCE(responses::BOUNCE::default())),
+            "330" => Ok(Command::RPL_WHOISACCOUNT(
+                responses::WhoisAccount::default(),
+            )),
+            "378" => Ok(Command::RPL_WHOISHOST(responses::WhoisHost::default())),
+            "396" => Ok(Command::RPL_HOSTHIDDEN(responses::HostHidden::default())),
+            "410" => Ok(Command::ERR_INVALIDCAPCMD(
+                responses::InvalidCapCmd::default(),
+            )),
+            "900" => Ok(Command::RPL_LOGGEDIN(responses::LoggedIn::default())),
+            "903" => Ok(Command::RPL_SASLSUCCESS(responses::SaslSuccess::default())),
+            "904" => Ok(Command::ERR_SASLFAIL(responses::SaslFail::default())),
+            "905" => Ok(Command::ERR_SASLTOOLONG(responses::SaslTooLong::default())),
+            "906" => Ok(Command::ERR_SASLABORTED(responses::SaslAborted::default())),
+            "907" => Ok(Command::ERR_SASLALREADY(responses::SaslAlready::default())),
+            "908" => Ok(Command::RPL_SASLMECHS(responses::SaslMechs::default())),
+            "470" => Ok(Command::ERR_LINKCHANNEL(responses::LinkChannel::default())),
+            "480" => Ok(Command::ERR_THROTTLE(responses::Throttle::default())),
+            "728" => Ok(Command::RPL_QUIETLIST(responses::QuietList::default())),
+            "729" => Ok(Command::RPL_ENDOFQUIETLIST(responses::EndOfQuietList::default())),
+            "478" => Ok(Command::ERR_BANLISTFULL(responses::BanListFull::default())),
             _ => Err(ParseError::UnrecognizedCommand),
         }
     }
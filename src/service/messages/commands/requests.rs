@@ -253,7 +253,14 @@ pub struct Away {
 pub struct Rehash {}
 
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct Restart {}
+pub struct Die {
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Restart {
+    pub reason: Option<String>,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Summon {
@@ -282,6 +289,64 @@ pub struct Ison {
     pub nicknames: Vec<String>,
 }
 
+// Duration is in minutes. None is permanent.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Kline {
+    pub duration: Option<u64>,
+    pub mask: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Unkline {
+    pub mask: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Gline {
+    pub duration: Option<u64>,
+    pub mask: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Ungline {
+    pub mask: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Dline {
+    pub duration: Option<u64>,
+    pub mask: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Undline {
+    pub mask: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Chghost {
+    pub nickname: String,
+    pub host: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Cap {
+    // The client the server replies to, "*" before registration.
+    pub target: Option<String>,
+    pub subcommand: String,
+    // Space separated.
+    pub capabilities: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Authenticate {
+    // A mechanism, base64 of a chunk of a message, "+" if empty, or "*" to abort.
+    pub data: String,
+}
+
 impl str::FromStr for StatsQuery {
     type Err = super::super::ParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -300,10 +365,26 @@ impl str::FromStr for StatsQuery {
     }
 }
 
+impl fmt::Display for StatsQuery {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        match self {
+            &StatsQuery::C => write!(f, "c"),
+            &StatsQuery::H => write!(f, "h"),
+            &StatsQuery::I => write!(f, "i"),
+            &StatsQuery::K => write!(f, "k"),
+            &StatsQuery::L => write!(f, "l"),
+            &StatsQuery::M => write!(f, "m"),
+            &StatsQuery::O => write!(f, "o"),
+            &StatsQuery::U => write!(f, "u"),
+            &StatsQuery::Y => write!(f, "y"),
+            &StatsQuery::UNKNOWN(ref u) => write!(f, "{}", u),
+        }
+    }
+}
+
 impl fmt::Display for Nick {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "NICK");
-        unimplemented!()
+        write!(f, "NICK {}", self.nickname)
     }
 }
 
@@ -344,8 +425,11 @@ impl fmt::Display for Service {
 
 impl fmt::Display for Quit {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "QUIT");
-        unimplemented!()
+        write!(f, "QUIT")?;
+        if let Some(ref m) = self.message {
+            write!(f, " :{}", m)?;
+        }
+        Ok(())
     }
 }
 
@@ -401,7 +485,7 @@ impl fmt::Display for Mode {
             write!(f, " {}", m)?;
         }
         if let Some(ref a) = self.mode_args {
-            write!(f, " :{}", a)?;
+            write!(f, " {}", a)?;
         }
         Ok(())
     }
@@ -409,8 +493,11 @@ impl fmt::Display for Mode {
 
 impl fmt::Display for Topic {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "TOPIC");
-        unimplemented!()
+        write!(f, "TOPIC {}", self.channel)?;
+        if let Some(ref t) = self.topic {
+            write!(f, " :{}", t)?;
+        }
+        Ok(())
     }
 }
 
@@ -430,8 +517,7 @@ impl fmt::Display for List {
 
 impl fmt::Display for Invite {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "INVITE");
-        unimplemented!()
+        write!(f, "INVITE {} {}", self.nickname, self.channel)
     }
 }
 
@@ -444,8 +530,10 @@ impl fmt::Display for Kick {
 
 impl fmt::Display for Motd {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "MOTD");
-        unimplemented!()
+        match self.target {
+            Some(ref t) => write!(f, "MOTD {}", t),
+            None => write!(f, "MOTD"),
+        }
     }
 }
 
@@ -514,15 +602,13 @@ impl fmt::Display for Info {
 
 impl fmt::Display for Privmsg {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        assert!(self.targets.len() == 1);
-        write!(f, "PRIVMSG {} {}", self.targets[0], self.message)
+        write!(f, "PRIVMSG {} :{}", self.targets.join(","), self.message)
     }
 }
 
 impl fmt::Display for Notice {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "NOTICE");
-        unimplemented!()
+        write!(f, "NOTICE {} :{}", self.targets.join(","), self.message)
     }
 }
 
@@ -563,15 +649,17 @@ impl fmt::Display for Whowas {
 
 impl fmt::Display for Kill {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "KILL");
-        unimplemented!()
+        write!(f, "KILL {} :{}", self.nickname, self.comment)
     }
 }
 
 impl fmt::Display for Ping {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "PING");
-        unimplemented!()
+        write!(f, "PING {}", self.originator)?;
+        if self.target.is_some() {
+            write!(f, " {}", self.target.as_ref().unwrap())?;
+        }
+        Ok(())
     }
 }
 
@@ -587,8 +675,7 @@ impl fmt::Display for Pong {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "ERROR");
-        unimplemented!()
+        write!(f, "ERROR :{}", self.message)
     }
 }
 
@@ -601,15 +688,25 @@ impl fmt::Display for Away {
 
 impl fmt::Display for Rehash {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "REHASH");
-        unimplemented!()
+        write!(f, "REHASH")
+    }
+}
+
+impl fmt::Display for Die {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        match self.reason {
+            Some(ref r) => write!(f, "DIE :{}", r),
+            None => write!(f, "DIE"),
+        }
     }
 }
 
 impl fmt::Display for Restart {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "RESTART");
-        unimplemented!()
+        match self.reason {
+            Some(ref r) => write!(f, "RESTART :{}", r),
+            None => write!(f, "RESTART"),
+        }
     }
 }
 
@@ -629,8 +726,7 @@ impl fmt::Display for Users {
 
 impl fmt::Display for Wallops {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "WALLOPS");
-        unimplemented!()
+        write!(f, "WALLOPS :{}", self.message)
     }
 }
 
@@ -647,3 +743,89 @@ impl fmt::Display for Ison {
         unimplemented!()
     }
 }
+
+impl fmt::Display for Kline {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "KLINE")?;
+        if let Some(d) = self.duration {
+            write!(f, " {}", d)?;
+        }
+        write!(f, " {}", self.mask)?;
+        if let Some(ref r) = self.reason {
+            write!(f, " :{}", r)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Unkline {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "UNKLINE {}", self.mask)
+    }
+}
+
+impl fmt::Display for Gline {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "GLINE")?;
+        if let Some(d) = self.duration {
+            write!(f, " {}", d)?;
+        }
+        write!(f, " {}", self.mask)?;
+        if let Some(ref r) = self.reason {
+            write!(f, " :{}", r)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Ungline {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "UNGLINE {}", self.mask)
+    }
+}
+
+impl fmt::Display for Dline {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "DLINE")?;
+        if let Some(d) = self.duration {
+            write!(f, " {}", d)?;
+        }
+        write!(f, " {}", self.mask)?;
+        if let Some(ref r) = self.reason {
+            write!(f, " :{}", r)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Undline {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "UNDLINE {}", self.mask)
+    }
+}
+
+impl fmt::Display for Chghost {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "CHGHOST {} {}", self.nickname, self.host)
+    }
+}
+
+impl fmt::Display for Cap {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "CAP")?;
+        if let Some(ref t) = self.target {
+            write!(f, " {}", t)?;
+        }
+        write!(f, " {}", self.subcommand)?;
+        if let Some(ref c) = self.capabilities {
+            write!(f, " :{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Authenticate {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "AUTHENTICATE {}", self.data)
+    }
+}
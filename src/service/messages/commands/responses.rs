@@ -1,7 +1,10 @@
 use std::fmt::{self, Formatter, Error as FmtError};
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct NOSUCHNICK {}
+pub struct NoSuchNick {
+    pub nick: String,
+    pub target: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct NOSUCHSERVER {}
@@ -13,10 +16,17 @@ pub struct NoSuchChannel {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct CANNOTSENDTOCHAN {}
+pub struct CannotSendToChan {
+    pub nick: String,
+    pub channel: String,
+    pub reason: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct TOOMANYCHANNELS {}
+pub struct TooManyChannels {
+    pub nick: String,
+    pub channel: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct WASNOSUCHNICK {}
@@ -43,7 +53,9 @@ pub struct WILDTOPLEVEL {}
 pub struct UNKNOWNCOMMAND {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct NOMOTD {}
+pub struct NoMotd {
+    pub nick: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct NOADMININFO {}
@@ -55,7 +67,10 @@ pub struct FILEERROR {}
 pub struct NONICKNAMEGIVEN {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct ERRONEUSNICKNAME {}
+pub struct ErroneousNickname {
+    pub nick: String,
+    pub target: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct NICKNAMEINUSE {
@@ -66,7 +81,11 @@ pub struct NICKNAMEINUSE {
 pub struct NICKCOLLISION {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct USERNOTINCHANNEL {}
+pub struct UserNotInChannel {
+    pub nick: String,
+    pub target: String,
+    pub channel: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct NotOnChannel {
@@ -75,7 +94,11 @@ pub struct NotOnChannel {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct USERONCHANNEL {}
+pub struct UserOnChannel {
+    pub nick: String,
+    pub target: String,
+    pub channel: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct NOLOGIN {}
@@ -103,22 +126,36 @@ pub struct AlreadyRegistered {
 pub struct NOPERMFORHOST {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct PASSWDMISMATCH {}
+pub struct PasswdMismatch {
+    pub nick: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct YOUREBANNEDCREEP {}
+pub struct YoureBannedCreep {
+    pub nick: String,
+    pub reason: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct KEYSET {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct CHANNELISFULL {}
+pub struct ChannelIsFull {
+    pub nick: String,
+    pub channel: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct UNKNOWNMODE {}
+pub struct UnknownMode {
+    pub nick: String,
+    pub mode: char,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct INVITEONLYCHAN {}
+pub struct InviteOnlyChan {
+    pub nick: String,
+    pub channel: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct BannedFromChan {
@@ -133,16 +170,25 @@ pub struct BadChannelKey {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct NOPRIVILEGES {}
+pub struct NoPrivileges {
+    pub nick: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct CHANOPRIVSNEEDED {}
+pub struct ChanOPrivsNeeded {
+    pub nick: String,
+    pub channel: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct CANTKILLSERVER {}
+pub struct CantKillServer {
+    pub nick: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct NOOPERHOST {}
+pub struct NoOperHost {
+    pub nick: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct UModeUnknownFlag {
@@ -173,22 +219,43 @@ pub struct UNAWAY {}
 pub struct NOWAWAY {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct WHOISUSER {}
+pub struct WhoisUser {
+    pub nick: String,
+    pub target: String,
+    pub username: String,
+    pub host: String,
+    pub realname: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct WHOISSERVER {}
+pub struct WhoisServer {
+    pub nick: String,
+    pub target: String,
+    pub server: String,
+    pub info: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct WHOISOPERATOR {}
+pub struct WhoisOperator {
+    pub nick: String,
+    pub target: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct WHOISIDLE {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct ENDOFWHOIS {}
+pub struct EndOfWhois {
+    pub nick: String,
+    pub target: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct WHOISCHANNELS {}
+pub struct WhoisChannels {
+    pub nick: String,
+    pub target: String,
+    pub channels: Vec<String>,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct WHOWASUSER {}
@@ -206,10 +273,17 @@ pub struct LIST {}
 pub struct LISTEND {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct CHANNELMODEIS {}
+pub struct ChannelModeIs {
+    pub nick: String,
+    pub channel: String,
+    pub modes: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct NOTOPIC {}
+pub struct NoTopic {
+    pub nick: String,
+    pub channel: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct Topic {
@@ -219,7 +293,11 @@ pub struct Topic {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct INVITING {}
+pub struct Inviting {
+    pub nick: String,
+    pub target: String,
+    pub channel: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct SUMMONING {}
@@ -228,10 +306,22 @@ pub struct SUMMONING {}
 pub struct VERSION {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct WHOREPLY {}
+pub struct WhoReply {
+    pub nick: String,
+    pub channel: String,
+    pub username: String,
+    pub host: String,
+    pub server: String,
+    pub target: String,
+    pub flags: String,
+    pub realname: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct ENDOFWHO {}
+pub struct EndOfWho {
+    pub nick: String,
+    pub mask: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct NamReply {
@@ -255,10 +345,17 @@ pub struct LINKS {}
 pub struct ENDOFLINKS {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct BANLIST {}
+pub struct BanList {
+    pub nick: String,
+    pub channel: String,
+    pub mask: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct ENDOFBANLIST {}
+pub struct EndOfBanList {
+    pub nick: String,
+    pub channel: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct INFO {}
@@ -267,19 +364,32 @@ pub struct INFO {}
 pub struct ENDOFINFO {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct MOTDSTART {}
+pub struct MotdStart {
+    pub nick: String,
+    pub server: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct MOTD {}
+pub struct Motd {
+    pub nick: String,
+    pub line: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct ENDOFMOTD {}
+pub struct EndOfMotd {
+    pub nick: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct YOUREOPER {}
+pub struct YoureOper {
+    pub nick: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct REHASHING {}
+pub struct Rehashing {
+    pub nick: String,
+    pub config_file: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct TIME {}
@@ -324,10 +434,23 @@ pub struct TRACENEWTYPE {}
 pub struct TRACELOG {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct STATSLINKINFO {}
+pub struct StatsLinkInfo {
+    pub nick: String,
+    pub link: String,
+    pub sendq: usize,
+    pub sent_messages: usize,
+    pub sent_kbytes: usize,
+    pub received_messages: usize,
+    pub received_kbytes: usize,
+    pub time_open: i64,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct STATSCOMMANDS {}
+pub struct StatsCommands {
+    pub nick: String,
+    pub command: String,
+    pub count: usize,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct STATSCLINE {}
@@ -336,25 +459,51 @@ pub struct STATSCLINE {}
 pub struct STATSNLINE {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct STATSILINE {}
+pub struct StatsILine {
+    pub nick: String,
+    pub mask: String,
+    pub class: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct STATSKLINE {}
+pub struct StatsKLine {
+    pub nick: String,
+    pub kind: char,
+    pub host: String,
+    pub username: String,
+    pub reason: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct STATSYLINE {}
+pub struct StatsYLine {
+    pub nick: String,
+    pub class: String,
+    pub ping_frequency: u64,
+    pub connect_frequency: u64,
+    pub max_sendq: usize,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct ENDOFSTATS {}
+pub struct EndOfStats {
+    pub nick: String,
+    pub query: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct STATSLLINE {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct STATSUPTIME {}
+pub struct StatsUptime {
+    pub nick: String,
+    pub uptime: i64,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct STATSOLINE {}
+pub struct StatsOLine {
+    pub nick: String,
+    pub hostmask: String,
+    pub name: String,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct STATSHLINE {}
@@ -459,14 +608,117 @@ pub struct Created {
 pub struct MyInfo {}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
-pub struct ISUPPORT {}
+pub struct ISupport {
+    pub nick: String,
+    pub tokens: Vec<String>,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct BOUNCE {}
 
-impl fmt::Display for NOSUCHNICK {
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct WhoisAccount {
+    pub nick: String,
+    pub target: String,
+    pub account: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct WhoisHost {
+    pub nick: String,
+    pub target: String,
+    pub host: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct HostHidden {
+    pub nick: String,
+    pub host: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct InvalidCapCmd {
+    pub nick: String,
+    pub subcommand: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct LoggedIn {
+    pub nick: String,
+    // nick!user@host.
+    pub prefix: String,
+    pub account: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SaslSuccess {
+    pub nick: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SaslFail {
+    pub nick: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SaslTooLong {
+    pub nick: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SaslAborted {
+    pub nick: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SaslAlready {
+    pub nick: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SaslMechs {
+    pub nick: String,
+    // Comma separated.
+    pub mechanisms: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct LinkChannel {
+    pub nick: String,
+    pub channel: String,
+    // Where the user is sent instead.
+    pub forward: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Throttle {
+    pub nick: String,
+    pub channel: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct QuietList {
+    pub nick: String,
+    pub channel: String,
+    pub mask: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct EndOfQuietList {
+    pub nick: String,
+    pub channel: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct BanListFull {
+    pub nick: String,
+    pub channel: String,
+    pub mask: String,
+}
+
+impl fmt::Display for NoSuchNick {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "401")
+        write!(f, "401 {} {} :No such nick/channel", self.nick, self.target)
     }
 }
 
@@ -482,15 +734,20 @@ impl fmt::Display for NoSuchChannel {
     }
 }
 
-impl fmt::Display for CANNOTSENDTOCHAN {
+impl fmt::Display for CannotSendToChan {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "404")
+        write!(f, "404 {} {} :{}", self.nick, self.channel, self.reason)
     }
 }
 
-impl fmt::Display for TOOMANYCHANNELS {
+impl fmt::Display for TooManyChannels {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "405")
+        write!(
+            f,
+            "405 {} {} :You have joined too many channels",
+            self.nick,
+            self.channel
+        )
     }
 }
 
@@ -542,9 +799,9 @@ impl fmt::Display for UNKNOWNCOMMAND {
     }
 }
 
-impl fmt::Display for NOMOTD {
+impl fmt::Display for NoMotd {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "422")
+        write!(f, "422 {} :MOTD File is missing", self.nick)
     }
 }
 
@@ -566,9 +823,9 @@ impl fmt::Display for NONICKNAMEGIVEN {
     }
 }
 
-impl fmt::Display for ERRONEUSNICKNAME {
+impl fmt::Display for ErroneousNickname {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "432")
+        write!(f, "432 {} {} :Erroneous nickname", self.nick, self.target)
     }
 }
 
@@ -584,9 +841,9 @@ impl fmt::Display for NICKCOLLISION {
     }
 }
 
-impl fmt::Display for USERNOTINCHANNEL {
+impl fmt::Display for UserNotInChannel {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "441")
+        write!(f, "441 {} {} {} :They aren't on that channel", self.nick, self.target, self.channel)
     }
 }
 
@@ -601,9 +858,15 @@ impl fmt::Display for NotOnChannel {
     }
 }
 
-impl fmt::Display for USERONCHANNEL {
+impl fmt::Display for UserOnChannel {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "443")
+        write!(
+            f,
+            "443 {} {} {} :is already on channel",
+            self.nick,
+            self.target,
+            self.channel
+        )
     }
 }
 
@@ -649,15 +912,20 @@ impl fmt::Display for NOPERMFORHOST {
     }
 }
 
-impl fmt::Display for PASSWDMISMATCH {
+impl fmt::Display for PasswdMismatch {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "464")
+        write!(f, "464 {} :Password incorrect", self.nick)
     }
 }
 
-impl fmt::Display for YOUREBANNEDCREEP {
+impl fmt::Display for YoureBannedCreep {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "465")
+        write!(
+            f,
+            "465 {} :You are banned from this server- {}",
+            self.nick,
+            self.reason
+        )
     }
 }
 
@@ -667,21 +935,31 @@ impl fmt::Display for KEYSET {
     }
 }
 
-impl fmt::Display for CHANNELISFULL {
+impl fmt::Display for ChannelIsFull {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "471")
+        write!(
+            f,
+            "471 {} {} :Cannot join channel (+l)",
+            self.nick,
+            self.channel
+        )
     }
 }
 
-impl fmt::Display for UNKNOWNMODE {
+impl fmt::Display for UnknownMode {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "472")
+        write!(f, "472 {} {} :is unknown mode char to me", self.nick, self.mode)
     }
 }
 
-impl fmt::Display for INVITEONLYCHAN {
+impl fmt::Display for InviteOnlyChan {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "473")
+        write!(
+            f,
+            "473 {} {} :Cannot join channel (+i)",
+            self.nick,
+            self.channel
+        )
     }
 }
 
@@ -689,7 +967,7 @@ impl fmt::Display for BannedFromChan {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
         write!(
             f,
-            "474 {} {} :Cannot join channel (+k)",
+            "474 {} {} :Cannot join channel (+b)",
             self.nick,
             self.channel
         )
@@ -707,27 +985,31 @@ impl fmt::Display for BadChannelKey {
     }
 }
 
-impl fmt::Display for NOPRIVILEGES {
+impl fmt::Display for NoPrivileges {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "481")
+        write!(
+            f,
+            "481 {} :Permission Denied- You're not an IRC operator",
+            self.nick
+        )
     }
 }
 
-impl fmt::Display for CHANOPRIVSNEEDED {
+impl fmt::Display for ChanOPrivsNeeded {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "482")
+        write!(f, "482 {} {} :You're not channel operator", self.nick, self.channel)
     }
 }
 
-impl fmt::Display for CANTKILLSERVER {
+impl fmt::Display for CantKillServer {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "483")
+        write!(f, "483 {} :You cant kill a server!", self.nick)
     }
 }
 
-impl fmt::Display for NOOPERHOST {
+impl fmt::Display for NoOperHost {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "491")
+        write!(f, "491 {} :No O-lines for your host", self.nick)
     }
 }
 
@@ -779,21 +1061,29 @@ impl fmt::Display for NOWAWAY {
     }
 }
 
-impl fmt::Display for WHOISUSER {
+impl fmt::Display for WhoisUser {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "311")
+        write!(
+            f,
+            "311 {} {} {} {} * :{}",
+            self.nick,
+            self.target,
+            self.username,
+            self.host,
+            self.realname
+        )
     }
 }
 
-impl fmt::Display for WHOISSERVER {
+impl fmt::Display for WhoisServer {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "312")
+        write!(f, "312 {} {} {} :{}", self.nick, self.target, self.server, self.info)
     }
 }
 
-impl fmt::Display for WHOISOPERATOR {
+impl fmt::Display for WhoisOperator {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "313")
+        write!(f, "313 {} {} :is an IRC operator", self.nick, self.target)
     }
 }
 
@@ -803,15 +1093,15 @@ impl fmt::Display for WHOISIDLE {
     }
 }
 
-impl fmt::Display for ENDOFWHOIS {
+impl fmt::Display for EndOfWhois {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "318")
+        write!(f, "318 {} {} :End of WHOIS list", self.nick, self.target)
     }
 }
 
-impl fmt::Display for WHOISCHANNELS {
+impl fmt::Display for WhoisChannels {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "319")
+        write!(f, "319 {} {} :{}", self.nick, self.target, self.channels.join(" "))
     }
 }
 
@@ -845,15 +1135,15 @@ impl fmt::Display for LISTEND {
     }
 }
 
-impl fmt::Display for CHANNELMODEIS {
+impl fmt::Display for ChannelModeIs {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "324")
+        write!(f, "324 {} {} {}", self.nick, self.channel, self.modes)
     }
 }
 
-impl fmt::Display for NOTOPIC {
+impl fmt::Display for NoTopic {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "331")
+        write!(f, "331 {} {} :No topic is set", self.nick, self.channel)
     }
 }
 
@@ -863,9 +1153,9 @@ impl fmt::Display for Topic {
     }
 }
 
-impl fmt::Display for INVITING {
+impl fmt::Display for Inviting {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "341")
+        write!(f, "341 {} {} {}", self.nick, self.target, self.channel)
     }
 }
 
@@ -881,15 +1171,26 @@ impl fmt::Display for VERSION {
     }
 }
 
-impl fmt::Display for WHOREPLY {
+impl fmt::Display for WhoReply {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "352")
+        write!(
+            f,
+            "352 {} {} {} {} {} {} {} :0 {}",
+            self.nick,
+            self.channel,
+            self.username,
+            self.host,
+            self.server,
+            self.target,
+            self.flags,
+            self.realname
+        )
     }
 }
 
-impl fmt::Display for ENDOFWHO {
+impl fmt::Display for EndOfWho {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "315")
+        write!(f, "315 {} {} :End of WHO list", self.nick, self.mask)
     }
 }
 
@@ -926,15 +1227,20 @@ impl fmt::Display for ENDOFLINKS {
     }
 }
 
-impl fmt::Display for BANLIST {
+impl fmt::Display for BanList {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "367")
+        write!(f, "367 {} {} {}", self.nick, self.channel, self.mask)
     }
 }
 
-impl fmt::Display for ENDOFBANLIST {
+impl fmt::Display for EndOfBanList {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "368")
+        write!(
+            f,
+            "368 {} {} :End of channel ban list",
+            self.nick,
+            self.channel
+        )
     }
 }
 
@@ -950,33 +1256,33 @@ impl fmt::Display for ENDOFINFO {
     }
 }
 
-impl fmt::Display for MOTDSTART {
+impl fmt::Display for MotdStart {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "375")
+        write!(f, "375 {} :- {} Message of the day - ", self.nick, self.server)
     }
 }
 
-impl fmt::Display for MOTD {
+impl fmt::Display for Motd {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "372")
+        write!(f, "372 {} :- {}", self.nick, self.line)
     }
 }
 
-impl fmt::Display for ENDOFMOTD {
+impl fmt::Display for EndOfMotd {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "376")
+        write!(f, "376 {} :End of MOTD command", self.nick)
     }
 }
 
-impl fmt::Display for YOUREOPER {
+impl fmt::Display for YoureOper {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "381")
+        write!(f, "381 {} :You are now an IRC operator", self.nick)
     }
 }
 
-impl fmt::Display for REHASHING {
+impl fmt::Display for Rehashing {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "382")
+        write!(f, "382 {} {} :Rehashing", self.nick, self.config_file)
     }
 }
 
@@ -1064,15 +1370,26 @@ impl fmt::Display for TRACELOG {
     }
 }
 
-impl fmt::Display for STATSLINKINFO {
+impl fmt::Display for StatsLinkInfo {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "211")
+        write!(
+            f,
+            "211 {} {} {} {} {} {} {} {}",
+            self.nick,
+            self.link,
+            self.sendq,
+            self.sent_messages,
+            self.sent_kbytes,
+            self.received_messages,
+            self.received_kbytes,
+            self.time_open
+        )
     }
 }
 
-impl fmt::Display for STATSCOMMANDS {
+impl fmt::Display for StatsCommands {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "212")
+        write!(f, "212 {} {} {}", self.nick, self.command, self.count)
     }
 }
 
@@ -1088,27 +1405,43 @@ impl fmt::Display for STATSNLINE {
     }
 }
 
-impl fmt::Display for STATSILINE {
+impl fmt::Display for StatsILine {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "215")
+        write!(f, "215 {} I {} * {} 0 {}", self.nick, self.mask, self.mask, self.class)
     }
 }
 
-impl fmt::Display for STATSKLINE {
+impl fmt::Display for StatsKLine {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "216")
+        write!(
+            f,
+            "216 {} {} {} * {} :{}",
+            self.nick,
+            self.kind,
+            self.host,
+            self.username,
+            self.reason
+        )
     }
 }
 
-impl fmt::Display for STATSYLINE {
+impl fmt::Display for StatsYLine {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "218")
+        write!(
+            f,
+            "218 {} Y {} {} {} {}",
+            self.nick,
+            self.class,
+            self.ping_frequency,
+            self.connect_frequency,
+            self.max_sendq
+        )
     }
 }
 
-impl fmt::Display for ENDOFSTATS {
+impl fmt::Display for EndOfStats {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "219")
+        write!(f, "219 {} {} :End of STATS report", self.nick, self.query)
     }
 }
 
@@ -1118,15 +1451,23 @@ impl fmt::Display for STATSLLINE {
     }
 }
 
-impl fmt::Display for STATSUPTIME {
+impl fmt::Display for StatsUptime {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "242")
+        write!(
+            f,
+            "242 {} :Server Up {} days {}:{:02}:{:02}",
+            self.nick,
+            self.uptime / 86400,
+            self.uptime % 86400 / 3600,
+            self.uptime % 3600 / 60,
+            self.uptime % 60
+        )
     }
 }
 
-impl fmt::Display for STATSOLINE {
+impl fmt::Display for StatsOLine {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "243")
+        write!(f, "243 {} O {} * {}", self.nick, self.hostmask, self.name)
     }
 }
 
@@ -1316,9 +1657,13 @@ impl fmt::Display for MyInfo {
     }
 }
 
-impl fmt::Display for ISUPPORT {
+impl fmt::Display for ISupport {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
-        write!(f, "005")
+        write!(f, "005 {}", self.nick)?;
+        for token in self.tokens.iter() {
+            write!(f, " {}", token)?;
+        }
+        write!(f, " :are supported by this server")
     }
 }
 
@@ -1327,3 +1672,128 @@ impl fmt::Display for BOUNCE {
         write!(f, "010")
     }
 }
+
+impl fmt::Display for WhoisAccount {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "330 {} {} {} :is logged in as", self.nick, self.target, self.account)
+    }
+}
+
+impl fmt::Display for WhoisHost {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "378 {} {} :is connecting from *@{}", self.nick, self.target, self.host)
+    }
+}
+
+impl fmt::Display for HostHidden {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "396 {} {} :is now your displayed host", self.nick, self.host)
+    }
+}
+
+impl fmt::Display for InvalidCapCmd {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "410 {} {} :Invalid CAP command", self.nick, self.subcommand)
+    }
+}
+
+impl fmt::Display for LoggedIn {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(
+            f,
+            "900 {} {} {} :You are now logged in as {}",
+            self.nick,
+            self.prefix,
+            self.account,
+            self.account
+        )
+    }
+}
+
+impl fmt::Display for SaslSuccess {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "903 {} :SASL authentication successful", self.nick)
+    }
+}
+
+impl fmt::Display for SaslFail {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "904 {} :SASL authentication failed", self.nick)
+    }
+}
+
+impl fmt::Display for SaslTooLong {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "905 {} :SASL message too long", self.nick)
+    }
+}
+
+impl fmt::Display for SaslAborted {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "906 {} :SASL authentication aborted", self.nick)
+    }
+}
+
+impl fmt::Display for SaslAlready {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "907 {} :You have already authenticated using SASL", self.nick)
+    }
+}
+
+impl fmt::Display for SaslMechs {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "908 {} {} :are available SASL mechanisms", self.nick, self.mechanisms)
+    }
+}
+
+impl fmt::Display for LinkChannel {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(
+            f,
+            "470 {} {} {} :Forwarding to another channel",
+            self.nick,
+            self.channel,
+            self.forward
+        )
+    }
+}
+
+impl fmt::Display for Throttle {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(
+            f,
+            "480 {} {} :Cannot join channel (+j) - throttle exceeded, try again later",
+            self.nick,
+            self.channel
+        )
+    }
+}
+
+impl fmt::Display for QuietList {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(f, "728 {} {} q {}", self.nick, self.channel, self.mask)
+    }
+}
+
+impl fmt::Display for EndOfQuietList {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(
+            f,
+            "729 {} {} q :End of channel quiet list",
+            self.nick,
+            self.channel
+        )
+    }
+}
+
+impl fmt::Display for BanListFull {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FmtError> {
+        write!(
+            f,
+            "478 {} {} {} :Channel list is full",
+            self.nick,
+            self.channel,
+            self.mask
+        )
+    }
+}
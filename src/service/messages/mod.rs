@@ -4,6 +4,10 @@ pub use self::commands::Command;
 
 use std::{self, fmt, str};
 use std::fmt::{Formatter, Error as FmtError};
+use self::commands::{requests, responses};
+
+// Longest line allowed on the wire, excluding CRLF.
+pub const MAX_LINE_LENGTH: usize = 510;
 
 fn next_token<'a>(s: &'a str) -> (&'a str, &'a str) {
     match s.find(' ') {
@@ -57,6 +61,121 @@ impl fmt::Display for Message {
     }
 }
 
+impl Message {
+    // Serializes into lines of at most max bytes. Messages ending in free-form text or a list are
+    // split into several; anything else is truncated.
+    pub fn to_lines(&self, max: usize) -> Vec<String> {
+        let line = self.to_string();
+        if line.len() <= max {
+            return vec![line];
+        }
+
+        let with = |command| {
+            Message {
+                prefix: self.prefix.clone(),
+                command: command,
+            }
+        };
+        let parts = match self.command {
+            Command::PRIVMSG(ref p) => {
+                split_text(&p.message, (max + p.message.len()).checked_sub(line.len()))
+                    .into_iter()
+                    .map(|message| {
+                        with(Command::PRIVMSG(requests::Privmsg {
+                            targets: p.targets.clone(),
+                            message: message,
+                        }))
+                    })
+                    .collect()
+            }
+            Command::NOTICE(ref n) => {
+                split_text(&n.message, (max + n.message.len()).checked_sub(line.len()))
+                    .into_iter()
+                    .map(|message| {
+                        with(Command::NOTICE(requests::Notice {
+                            targets: n.targets.clone(),
+                            message: message,
+                        }))
+                    })
+                    .collect()
+            }
+            Command::RPL_NAMREPLY(ref r) => {
+                pack(&r.members, max, |members| {
+                    with(Command::RPL_NAMREPLY(responses::NamReply {
+                        members: members,
+                        ..r.clone()
+                    }))
+                })
+            }
+            Command::RPL_ISUPPORT(ref r) => {
+                pack(&r.tokens, max, |tokens| {
+                    with(Command::RPL_ISUPPORT(responses::ISupport {
+                        tokens: tokens,
+                        ..r.clone()
+                    }))
+                })
+            }
+            _ => Vec::new(),
+        };
+        if parts.is_empty() {
+            warn!("Truncating {} byte line: {}.", line.len(), line);
+            return vec![truncate(&line, max).to_string()];
+        }
+        // A single list item can still be too long.
+        parts
+            .iter()
+            .map(|m| truncate(&m.to_string(), max).to_string())
+            .collect()
+    }
+}
+
+// Longest prefix of s that is at most max bytes and ends on a character boundary.
+pub fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+// Splits text into chunks of at most max bytes, on character boundaries. Empty if max leaves no
+// room for any text.
+fn split_text(text: &str, max: Option<usize>) -> Vec<String> {
+    let max = match max {
+        Some(m) if m >= 4 => m,
+        _ => return Vec::new(),
+    };
+    let mut result = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let chunk = truncate(rest, max);
+        result.push(chunk.to_string());
+        rest = &rest[chunk.len()..];
+    }
+    result
+}
+
+// Greedily packs items into as few messages as fit in max bytes each.
+fn pack<T: Clone, F: Fn(Vec<T>) -> Message>(items: &Vec<T>, max: usize, make: F) -> Vec<Message> {
+    let mut result = Vec::new();
+    let mut current: Vec<T> = Vec::new();
+    for item in items.iter() {
+        current.push(item.clone());
+        if current.len() > 1 && make(current.clone()).to_string().len() > max {
+            let item = current.pop().unwrap();
+            result.push(make(current));
+            current = vec![item];
+        }
+    }
+    if !current.is_empty() {
+        result.push(make(current));
+    }
+    result
+}
+
 impl str::FromStr for Message {
     type Err = ParseError;
 
@@ -158,7 +277,7 @@ mod test {
                 command: Command::ERR_NEEDMOREPARAMS(Responses::NeedMoreParams::default()),
             },
             "461"
-        )
+        );
     }
 
     #[test]
@@ -213,4 +332,65 @@ mod test {
         );
 
     }
+
+    #[test]
+    fn test_to_lines() {
+        let privmsg = Message {
+            prefix: Some("lazau!lazau@127.0.0.1".to_string()),
+            command: Command::PRIVMSG(Requests::Privmsg {
+                targets: vec!["#test".to_string()],
+                message: "é".repeat(300),
+            }),
+        };
+        let lines = privmsg.to_lines(super::MAX_LINE_LENGTH);
+        assert_eq!(lines.len(), 2);
+        let mut text = String::new();
+        for line in lines.iter() {
+            assert!(line.len() <= super::MAX_LINE_LENGTH);
+            assert!(line.starts_with(":lazau!lazau@127.0.0.1 PRIVMSG #test :"));
+            text.push_str(&line[":lazau!lazau@127.0.0.1 PRIVMSG #test :".len()..]);
+        }
+        assert_eq!(text, "é".repeat(300));
+
+        let names = Message {
+            prefix: Some("test".to_string()),
+            command: Command::RPL_NAMREPLY(Responses::NamReply {
+                nick: "lazau".to_string(),
+                symbol: "=".to_string(),
+                channel: "#test".to_string(),
+                members: (0..100)
+                    .map(|i| ("".to_string(), format!("user{}", i)))
+                    .collect(),
+            }),
+        };
+        let lines = names.to_lines(100);
+        assert!(lines.len() > 1);
+        let mut members = Vec::new();
+        for line in lines.iter() {
+            assert!(line.len() <= 100);
+            let (head, tail) = line.split_at(line.find(" :").unwrap());
+            assert_eq!(head, ":test 353 lazau = #test");
+            members.extend(tail[2..].split(' ').map(|m| m.to_string()));
+        }
+        assert_eq!(members, (0..100).map(|i| format!("user{}", i)).collect::<Vec<_>>());
+
+        let welcome = Message {
+            prefix: None,
+            command: Command::RPL_WELCOME(Responses::Welcome {
+                nick: "lazau".to_string(),
+                message: "x".repeat(600),
+            }),
+        };
+        assert_eq!(welcome.to_lines(super::MAX_LINE_LENGTH), vec![
+            format!("001 lazau :{}", "x".repeat(499)),
+        ]);
+    }
+
+    #[test]
+    fn test_display_targets() {
+        let notice = ":lazau NOTICE #test,bob :hi".parse::<Message>().unwrap();
+        assert_eq!(notice.to_string(), ":lazau NOTICE #test,bob :hi");
+        let privmsg = ":lazau PRIVMSG #test,bob :hi".parse::<Message>().unwrap();
+        assert_eq!(privmsg.to_string(), ":lazau PRIVMSG #test,bob :hi");
+    }
 }
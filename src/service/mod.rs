@@ -1,5 +1,21 @@
+mod accounts;
+mod bans;
+mod channel_registry;
+mod chanserv;
+mod cloak;
 mod codec;
+mod dns;
+mod flood;
+mod hot_restart;
+mod ident;
+pub mod mask;
 mod messages;
+mod nickserv;
+mod persist;
+mod sasl;
+mod sendq;
+mod statistics;
+mod throttle;
 pub mod server;
 pub mod shared_state;
 pub mod connection;
@@ -12,25 +28,103 @@ use futures::stream::Stream;
 use futures_cpupool::CpuPool;
 use hostname;
 use hyper::server::Http;
+use signal_hook;
+use std;
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use super::configuration;
 use super::debug;
+use self::connection::{Connection, SocketPair};
+use self::server::{Server, Shutdown, ShutdownKind, SnoMask};
+use self::shared_state::SharedState;
+use futures::future::Either;
 use tokio_core::net::TcpListener;
-use tokio_core::reactor::Core;
+use tokio_core::reactor::{Core, Handle, Interval, Timeout};
+use tokio_io;
 
-pub fn start(configuration: Arc<configuration::Configuration>) {
+// How often connection timers are checked.
+const KEEPALIVE_INTERVAL_MILLIS: u64 = 1000;
+// How long connections get to flush the restart notice before a hot restart.
+const HOT_RESTART_GRACE_MILLIS: u64 = 200;
+
+type Connections = Arc<Mutex<HashMap<SocketPair, Arc<Mutex<Connection>>>>>;
+
+// configuration_path is re-read on REHASH and SIGHUP.
+// Returns once the server has shut down, with how it was asked to.
+pub fn start(
+    configuration: configuration::Configuration,
+    configuration_path: Option<String>,
+) -> Result<Shutdown, configuration::ConfigurationError> {
     trace!("Using configuration: {:#?}.", configuration);
 
+    // Set if this process was exec'd by a hot restart.
+    let handoff = hot_restart::take();
+    let created = match handoff.as_ref().and_then(|h| h.state.as_ref()) {
+        Some(state) => {
+            chrono::DateTime::from_utc(
+                chrono::NaiveDateTime::from_timestamp(state.created, 0),
+                chrono::Utc,
+            )
+        }
+        None => chrono::offset::Utc::now(),
+    };
+
     let thread_pool = CpuPool::new_num_cpus();
     let shared_state = Arc::new(shared_state::SharedState::new(
-        chrono::offset::Utc::now(),
+        created,
         hostname::get_hostname().unwrap(),
         &thread_pool,
-        configuration,
+        shared_state::Settings::new(configuration)?,
+        configuration_path,
     ));
+    let settings = shared_state.settings();
+
+    let srv = Arc::new(Mutex::new(server::Server::new(Arc::clone(&shared_state))?));
 
-    let srv = Arc::new(Mutex::new(server::Server::new(Arc::clone(&shared_state))));
+    let shutdown_signal = srv.lock().unwrap().shutdown_signal();
+
+    let signals = signal_hook::iterator::Signals::new(
+        &[signal_hook::SIGHUP, signal_hook::SIGTERM, signal_hook::SIGUSR2],
+    ).unwrap();
+    let srv_signals = Arc::clone(&srv);
+    let shared_state_signals = Arc::clone(&shared_state);
+    std::thread::spawn(move || for signal in signals.forever() {
+        match signal {
+            signal_hook::SIGHUP => {
+                info!("Received SIGHUP.");
+                let _ = srv_signals.lock().unwrap().rehash(&"SIGHUP".to_string());
+            }
+            signal_hook::SIGTERM => {
+                info!("Received SIGTERM.");
+                let reason = shared_state_signals
+                    .settings()
+                    .configuration
+                    .shutdown_message
+                    .clone();
+                srv_signals.lock().unwrap().shutdown(
+                    &"SIGTERM".to_string(),
+                    Shutdown {
+                        kind: ShutdownKind::Die,
+                        reason: reason,
+                    },
+                );
+            }
+            signal_hook::SIGUSR2 => {
+                info!("Received SIGUSR2.");
+                srv_signals.lock().unwrap().shutdown(
+                    &"SIGUSR2".to_string(),
+                    Shutdown {
+                        kind: ShutdownKind::HotRestart,
+                        reason: "Upgrading".to_string(),
+                    },
+                );
+            }
+            _ => unreachable!(),
+        }
+    });
     let connections = Arc::new(Mutex::new(HashMap::new()));
     let debug_service = Arc::new(debug::DebugService::new(
         Arc::clone(&shared_state),
@@ -44,7 +138,7 @@ pub fn start(configuration: Arc<configuration::Configuration>) {
     //     HTTP Server Enabled  - hyper::server::Http owned Core,
     //     HTTP Server Disabled - This module owned Core
     // >
-    let core = match shared_state.configuration.debug_http_listen_address {
+    let core = match settings.configuration.debug_http_listen_address {
         Some(ref addr) => {
             debug!("Starting debug HTTP server at {:?}.", addr);
             Ok(
@@ -61,23 +155,75 @@ pub fn start(configuration: Arc<configuration::Configuration>) {
     };
 
     // TODO(lazau): Add secure listener.
-    let insecure_lis = TcpListener::bind(
-        shared_state
-            .configuration
-            .insecure_listen_address
-            .as_ref()
-            .unwrap(),
-        &handle,
-    ).unwrap();
+    let listen_address = settings.configuration.insecure_listen_address.as_ref().unwrap();
+    let insecure_lis = match handoff {
+        Some(h) => {
+            let (listener, resumed) =
+                adopt(h, listen_address, &handle, &shared_state, &srv, &connections)
+                    .map_err(configuration::ConfigurationError::Io)?;
+            for fut in resumed {
+                handle.spawn(thread_pool.spawn(fut));
+            }
+            listener
+        }
+        None => {
+            TcpListener::bind(listen_address, &handle).map_err(
+                configuration::ConfigurationError::Io,
+            )?
+        }
+    };
+    let listener_fd = insecure_lis.as_raw_fd();
     debug!(
         "Starting IRC server at {:?}.",
         insecure_lis.local_addr().unwrap()
     );
 
+    let timer_connections = Arc::clone(&connections);
+    handle.spawn(
+        Interval::new(Duration::from_millis(KEEPALIVE_INTERVAL_MILLIS), &handle)
+            .unwrap()
+            .for_each(move |_| {
+                let now = Instant::now();
+                for c in timer_connections.lock().unwrap().values() {
+                    c.lock().unwrap().tick(now);
+                }
+                Ok(())
+            })
+            .map_err(|e| error!("Connection timer failed: {:?}.", e)),
+    );
+
+    let handover_srv = Arc::clone(&srv);
+    let drain_handle = handle.clone();
+    let drain_connections = Arc::clone(&connections);
+    let drain_shared_state = Arc::clone(&shared_state);
     let lis_handle = handle.clone();
     let lis = insecure_lis
         .incoming()
-        .for_each(move |(stream, _)| {
+        .for_each(move |(stream, addr)| {
+            // Refuse D-lined and throttled hosts before spending a Connection on them.
+            let refusal = {
+                let mut server = srv.lock().unwrap();
+                let dline = server.bans().find_ip(&addr.ip()).map(|b| b.describe());
+                if let Some(ref reason) = dline {
+                    server.record_refusal("D-line");
+                    server.server_notice(
+                        &SnoMask::Ban,
+                        &format!("Refused connection from {}: {}", addr.ip(), reason),
+                    );
+                }
+                dline.or_else(|| server.throttle(&addr.ip(), Instant::now()))
+            };
+            if let Some(reason) = refusal {
+                debug!("Refusing connection from {}: {}.", addr, reason);
+                lis_handle.spawn(
+                    tokio_io::io::write_all(
+                        stream,
+                        format!("ERROR :Closing Link ({})\r\n", reason).into_bytes(),
+                    ).then(|_| Ok(())),
+                );
+                return Ok(());
+            }
+
             let shared_state = Arc::clone(&shared_state);
             let srv = Arc::clone(&srv);
             let connections = Arc::clone(&connections);
@@ -91,15 +237,195 @@ pub fn start(configuration: Arc<configuration::Configuration>) {
             ));
             Ok(())
         })
-        .map_err(|_| ());
+        .map_err(|e| error!("IRC listener failed: {:?}.", e));
 
-    match core {
+    let serve = lis.select2(shutdown_signal.map_err(|_| ()))
+        .then(|r| -> Result<_, ()> {
+            match r {
+                Ok(Either::B((shutdown, lis))) => Ok((shutdown, Some(lis))),
+                // The listener failed, or nothing is left to signal a shutdown. Either way the
+                // server drains its connections as if told to DIE.
+                _ => {
+                    Ok((
+                        Shutdown {
+                            kind: ShutdownKind::Die,
+                            reason: "Listener failed".to_string(),
+                        },
+                        None,
+                    ))
+                }
+            }
+        })
+        .and_then(move |(shutdown, lis)| -> Box<Future<Item = Shutdown, Error = ()>> {
+            let lis = match lis {
+                Some(lis) if shutdown.kind == ShutdownKind::HotRestart => {
+                    return hand_over(
+                        shutdown,
+                        lis,
+                        listener_fd,
+                        handover_srv,
+                        drain_shared_state,
+                        drain_connections,
+                        &drain_handle,
+                    );
+                }
+                lis => lis,
+            };
+            // Dropping the listener stops accepting.
+            drop(lis);
+            let timeout = Duration::from_secs(
+                drain_shared_state
+                    .settings()
+                    .configuration
+                    .shutdown_timeout_seconds,
+            );
+            Box::new(
+                drain(&shutdown.reason, drain_connections, timeout, &drain_handle)
+                    .map(move |_| shutdown),
+            )
+        });
+
+    let shutdown = match core {
         Ok(dbg_srv) => {
-            handle.spawn(lis);
-            dbg_srv.run().unwrap();
-        }
-        Err(mut core) => {
-            core.run(lis).unwrap();
+            let shutdown = Arc::new(Mutex::new(None));
+            let shutdown_serve = Arc::clone(&shutdown);
+            dbg_srv
+                .run_until(serve.map(move |s| {
+                    *shutdown_serve.lock().unwrap() = Some(s);
+                }))
+                .unwrap();
+            let s = shutdown.lock().unwrap().take();
+            s
         }
+        Err(mut core) => core.run(serve).ok(),
     };
+    // serve never fails, so shutdown is always set.
+    Ok(shutdown.unwrap())
+}
+
+// Adopts the listener and connections handed over by a hot restart. Returns the listener, and the
+// futures driving each resumed connection.
+fn adopt(
+    handoff: hot_restart::Handoff,
+    address: &SocketAddr,
+    handle: &Handle,
+    shared_state: &Arc<SharedState>,
+    server: &Arc<Mutex<Server>>,
+    connections: &Connections,
+) -> std::io::Result<
+    (
+        TcpListener,
+        Vec<Box<Future<Item = (), Error = ()> + std::marker::Send>>,
+    ),
+> {
+    let listener = TcpListener::from_listener(
+        unsafe { std::net::TcpListener::from_raw_fd(handoff.listener) },
+        address,
+        handle,
+    )?;
+    // The listen address may have changed in the configuration since the previous process bound it.
+    let listener = if listener.local_addr().ok().as_ref() == Some(address) {
+        listener
+    } else {
+        info!("Listen address changed to {:?} across hot restart.", address);
+        drop(listener);
+        TcpListener::bind(address, handle)?
+    };
+
+    let state = match handoff.state {
+        Some(s) => s,
+        None => return Ok((listener, Vec::new())),
+    };
+    let mut resumed = Vec::new();
+    for c in state.connections {
+        let socket = c.socket().clone();
+        match Connection::resume(
+            c,
+            handle,
+            Arc::clone(shared_state),
+            Arc::clone(server),
+            Arc::clone(connections),
+        ) {
+            Ok(fut) => resumed.push(fut),
+            Err(e) => error!("Failed to resume connection {:?}: {:?}.", socket, e),
+        }
+    }
+    // Channels refer to users, so are restored once every connection is back.
+    server.lock().unwrap().restore(state.server);
+    info!("Resumed {} connections.", resumed.len());
+    Ok((listener, resumed))
+}
+
+// Tells every connection about the restart, then execs into the binary on disk, handing over the
+// listener and connections. Falls back to a regular restart if that fails.
+fn hand_over<L: 'static>(
+    shutdown: Shutdown,
+    listener: L,
+    listener_fd: RawFd,
+    server: Arc<Mutex<Server>>,
+    shared_state: Arc<SharedState>,
+    connections: Connections,
+    handle: &Handle,
+) -> Box<Future<Item = Shutdown, Error = ()>> {
+    for c in connections.lock().unwrap().values() {
+        c.lock().unwrap().notify(connection::Event::Restarting(shutdown.reason.clone()));
+    }
+
+    let handle = handle.clone();
+    let grace = Timeout::new(Duration::from_millis(HOT_RESTART_GRACE_MILLIS), &handle).unwrap();
+    Box::new(grace.then(move |_| {
+        let e = {
+            // Hold every lock, so no connection changes state while it is serialized.
+            let connections = connections.lock().unwrap();
+            let guards: Vec<_> = connections.values().map(|c| c.lock().unwrap()).collect();
+            let server = server.lock().unwrap();
+            hot_restart::exec(&hot_restart::State {
+                created: shared_state.created.timestamp(),
+                listener: listener_fd,
+                connections: guards
+                    .iter()
+                    .filter(|c| !c.closing())
+                    .map(|c| c.snapshot())
+                    .collect(),
+                server: server.snapshot(),
+            })
+        };
+        error!("Hot restart failed, falling back to restart: {}.", e);
+        drop(listener);
+        let timeout = Duration::from_secs(
+            shared_state.settings().configuration.shutdown_timeout_seconds,
+        );
+        drain(&shutdown.reason, connections, timeout, &handle).map(move |_| {
+            Shutdown {
+                kind: ShutdownKind::Restart,
+                reason: shutdown.reason,
+            }
+        })
+    }))
+}
+
+// Asks every connection to close with reason, then waits up to timeout for their pending messages
+// to be written.
+fn drain(
+    reason: &String,
+    connections: Connections,
+    timeout: Duration,
+    handle: &Handle,
+) -> Box<Future<Item = (), Error = ()>> {
+    info!("Draining {} connections.", connections.lock().unwrap().len());
+    for c in connections.lock().unwrap().values() {
+        c.lock().unwrap().notify(connection::Event::Shutdown(reason.clone()));
+    }
+
+    let drained = Interval::new(Duration::from_millis(100), handle)
+        .unwrap()
+        .take_while(move |_| Ok(!connections.lock().unwrap().is_empty()))
+        .for_each(|_| Ok(()));
+    let deadline = Timeout::new(timeout, handle).unwrap();
+    Box::new(drained.select2(deadline).then(|r| {
+        if let Ok(Either::B(_)) = r {
+            warn!("Timed out waiting for connections to drain.");
+        }
+        Ok(())
+    }))
 }
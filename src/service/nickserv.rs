@@ -0,0 +1,172 @@
+// The NickServ pseudo-client, which users PRIVMSG to manage their accounts.
+pub static NICK: &'static str = "NickServ";
+
+pub const MIN_PASSWORD_LENGTH: usize = 5;
+// Wrong passwords a connection may send before it is disconnected, to slow down guessing.
+pub const MAX_PASSWORD_FAILURES: usize = 3;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Request {
+    // Registers the current nick as an account.
+    Register {
+        password: String,
+        email: Option<String>,
+    },
+    // Logs in to account, or to the account owning the current nick.
+    Identify {
+        account: Option<String>,
+        password: String,
+    },
+    // Adds the current nick to the account logged in to.
+    Group,
+    // Disconnects whoever is using nick. Regain also takes nick over once they are gone. The
+    // password is needed unless logged in to the account owning nick.
+    Ghost {
+        nick: String,
+        password: Option<String>,
+    },
+    Regain {
+        nick: String,
+        password: Option<String>,
+    },
+    // Deletes the account logged in to.
+    Drop { password: String },
+    SetPassword(String),
+    SetEmail(Option<String>),
+    // Binds a vhost to account, or unbinds it. Operators only.
+    Vhost {
+        account: String,
+        host: Option<String>,
+    },
+    Help,
+}
+
+static HELP: &'static [&'static str] = &[
+    "REGISTER <password> [email]",
+    "IDENTIFY [account] <password>",
+    "GROUP",
+    "GHOST <nick> [password]",
+    "REGAIN <nick> [password]",
+    "DROP <password>",
+    "SET PASSWORD <password>",
+    "SET EMAIL [email]",
+    "VHOST <account> [host]",
+];
+
+pub fn help() -> Vec<String> {
+    let mut lines = vec![format!("{} commands:", NICK)];
+    lines.extend(HELP.iter().map(|h| format!("  {}", h)));
+    lines
+}
+
+pub fn valid_email(email: &str) -> bool {
+    match email.find('@') {
+        Some(idx) => idx > 0 && idx + 1 < email.len() && !email[idx + 1..].contains('@'),
+        None => false,
+    }
+}
+
+// Parses text sent to NickServ. Errors are the usage to show.
+pub fn parse(text: &str) -> Result<Request, String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let command = match words.first() {
+        Some(c) => c.to_uppercase(),
+        None => return Ok(Request::Help),
+    };
+    let args = &words[1..];
+    let arg = |i: usize| args.get(i).map(|a| a.to_string());
+    let usage = |command: &str| {
+        let help = HELP.iter().find(|h| h.starts_with(command)).unwrap();
+        Err(format!("Syntax: {}", help))
+    };
+    match (command.as_ref(), args.len()) {
+        ("REGISTER", 1) | ("REGISTER", 2) => Ok(Request::Register {
+            password: args[0].to_string(),
+            email: arg(1),
+        }),
+        ("REGISTER", _) => usage("REGISTER"),
+        ("IDENTIFY", 1) => Ok(Request::Identify {
+            account: None,
+            password: args[0].to_string(),
+        }),
+        ("IDENTIFY", 2) => Ok(Request::Identify {
+            account: arg(0),
+            password: args[1].to_string(),
+        }),
+        ("IDENTIFY", _) => usage("IDENTIFY"),
+        ("GROUP", 0) => Ok(Request::Group),
+        ("GROUP", _) => usage("GROUP"),
+        ("GHOST", 1) | ("GHOST", 2) => Ok(Request::Ghost {
+            nick: args[0].to_string(),
+            password: arg(1),
+        }),
+        ("GHOST", _) => usage("GHOST"),
+        ("REGAIN", 1) | ("REGAIN", 2) => Ok(Request::Regain {
+            nick: args[0].to_string(),
+            password: arg(1),
+        }),
+        ("REGAIN", _) => usage("REGAIN"),
+        ("DROP", 1) => Ok(Request::Drop { password: args[0].to_string() }),
+        ("DROP", _) => usage("DROP"),
+        ("SET", _) => {
+            match (args.first().map(|a| a.to_uppercase()), args.len()) {
+                (Some(ref s), 2) if s == "PASSWORD" => Ok(Request::SetPassword(args[1].to_string())),
+                (Some(ref s), _) if s == "PASSWORD" => usage("SET PASSWORD"),
+                (Some(ref s), 1) | (Some(ref s), 2) if s == "EMAIL" => Ok(Request::SetEmail(arg(1))),
+                _ => usage("SET EMAIL"),
+            }
+        }
+        ("VHOST", 1) | ("VHOST", 2) => Ok(Request::Vhost {
+            account: args[0].to_string(),
+            host: arg(1),
+        }),
+        ("VHOST", _) => usage("VHOST"),
+        ("HELP", _) => Ok(Request::Help),
+        _ => Err(format!("Unknown command {}. Try HELP.", command)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, Request};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("register hunter2 a@example.com"),
+            Ok(Request::Register {
+                password: "hunter2".to_string(),
+                email: Some("a@example.com".to_string()),
+            })
+        );
+        assert_eq!(
+            parse("IDENTIFY hunter2"),
+            Ok(Request::Identify {
+                account: None,
+                password: "hunter2".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("identify alice hunter2"),
+            Ok(Request::Identify {
+                account: Some("alice".to_string()),
+                password: "hunter2".to_string(),
+            })
+        );
+        assert_eq!(parse("set email"), Ok(Request::SetEmail(None)));
+        assert_eq!(
+            parse("SET password new"),
+            Ok(Request::SetPassword("new".to_string()))
+        );
+        assert_eq!(parse(""), Ok(Request::Help));
+        assert_eq!(
+            parse("REGISTER"),
+            Err("Syntax: REGISTER <password> [email]".to_string())
+        );
+        assert_eq!(
+            parse("SET PASSWORD"),
+            Err("Syntax: SET PASSWORD <password>".to_string())
+        );
+        assert!(parse("FROB").is_err());
+    }
+}
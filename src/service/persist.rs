@@ -0,0 +1,69 @@
+// YAML files the server keeps state in across restarts: bans, accounts and channel registrations.
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_yaml;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+
+// Reads the file at path. A missing file is None. A file that can't be read or parsed is an
+// error, rather than an empty store the next save would overwrite it with.
+pub fn load<T: DeserializeOwned>(path: &str) -> Result<Option<T>, String> {
+    let file = match fs::File::open(path) {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("failed to open {}: {}", path, e)),
+    };
+    serde_yaml::from_reader(file).map(Some).map_err(|e| {
+        format!("failed to parse {}: {}", path, e)
+    })
+}
+
+// Replaces the file at path with value. The contents are written to a temporary file readable by
+// the server's user only, synced, then renamed over path, so a crash leaves either the old or the
+// new contents behind.
+pub fn save<T: Serialize>(path: &str, value: &T) -> Result<(), String> {
+    let tmp = format!("{}.tmp", path);
+    // Left behind by a crash. create_new refuses to follow it if it is a symlink.
+    match fs::remove_file(&tmp) {
+        Err(ref e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(format!("failed to remove {}: {}", tmp, e))
+        }
+        _ => {}
+    }
+    let yaml = serde_yaml::to_string(value).map_err(|e| e.to_string())?;
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp)
+        .and_then(|mut f| {
+            f.write_all(yaml.as_bytes())?;
+            f.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| format!("failed to write {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{self, fs};
+    use std::os::unix::fs::PermissionsExt;
+    use super::{load, save};
+
+    #[test]
+    fn test_persist() {
+        let path = std::env::temp_dir().join(format!("irc_server.persist.{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(load::<Vec<String>>(path), Ok(None));
+
+        save(path, &vec!["a".to_string()]).unwrap();
+        assert_eq!(load(path), Ok(Some(vec!["a".to_string()])));
+        assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // Corrupt files are errors, not empty.
+        fs::write(path, "{ not yaml").unwrap();
+        assert!(load::<Vec<String>>(path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
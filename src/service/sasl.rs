@@ -0,0 +1,472 @@
+// SASL authentication, with AUTHENTICATE, during or after registration.
+//
+// The client picks a mechanism, then client responses and server challenges are exchanged as
+// base64 in AUTHENTICATE lines of at most 400 bytes, until the server decides.
+use base64;
+use hmac::{Hmac, Mac};
+use pbkdf2;
+use rand;
+use sha2::{Digest, Sha256};
+use std::{fmt, str};
+use super::super::password;
+
+// EXTERNAL waits for links to support TLS client certificates.
+pub static MECHANISMS: &'static str = "PLAIN,SCRAM-SHA-256";
+
+// Longest AUTHENTICATE line, and longest response taken, in base64.
+const CHUNK_LENGTH: usize = 400;
+const MAX_RESPONSE_LENGTH: usize = 4096;
+const NONCE_LENGTH: usize = 18;
+const SCRAM_ITERATIONS: u32 = 10000;
+const SCRAM_SALT_LENGTH: usize = 16;
+
+// Where SASL looks accounts up.
+pub trait Backend {
+    // The name of account, and the hash of its password, see password::hash. Checking the
+    // password is left to the caller, as hashing is slow and lookups may hold a lock.
+    fn password(&self, account: &str) -> Option<(String, String)>;
+    fn scram_credentials(&self, account: &str) -> Option<ScramCredentials>;
+}
+
+// What SCRAM-SHA-256 keeps of a password (RFC 5802): enough to check a client's proof, and to
+// prove to the client that the server knows the password, but not to log in as the client.
+// Hex encoded, like password hashes.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ScramKeys {
+    pub iterations: u32,
+    pub salt: String,
+    pub stored_key: String,
+    pub server_key: String,
+}
+
+impl ScramKeys {
+    // Derives the keys of password with a fresh salt. Slow, like password::hash.
+    pub fn new(password: &str) -> Self {
+        let salt: Vec<u8> = (0..SCRAM_SALT_LENGTH).map(|_| rand::random::<u8>()).collect();
+        Self::derive(password, &salt, SCRAM_ITERATIONS)
+    }
+
+    fn derive(password: &str, salt: &[u8], iterations: u32) -> Self {
+        let mut salted_password = vec![0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut salted_password);
+        let client_key = hmac(&salted_password, b"Client Key");
+        ScramKeys {
+            iterations: iterations,
+            salt: password::to_hex(salt),
+            stored_key: password::to_hex(&Sha256::digest(&client_key)),
+            server_key: password::to_hex(&hmac(&salted_password, b"Server Key")),
+        }
+    }
+
+    // The keys of account, decoded for an exchange. None if malformed.
+    pub fn credentials(&self, account: &str) -> Option<ScramCredentials> {
+        Some(ScramCredentials {
+            account: account.to_string(),
+            iterations: self.iterations,
+            salt: password::from_hex(&self.salt)?,
+            stored_key: password::from_hex(&self.stored_key)?,
+            server_key: password::from_hex(&self.server_key)?,
+        })
+    }
+}
+
+// What SCRAM-SHA-256 needs to know of an account's password, see ScramKeys.
+#[derive(Debug, Clone)]
+pub struct ScramCredentials {
+    pub account: String,
+    pub iterations: u32,
+    pub salt: Vec<u8>,
+    pub stored_key: Vec<u8>,
+    pub server_key: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mechanism {
+    Plain,
+    ScramSha256,
+}
+
+impl str::FromStr for Mechanism {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_ref() {
+            "PLAIN" => Ok(Mechanism::Plain),
+            "SCRAM-SHA-256" => Ok(Mechanism::ScramSha256),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reply {
+    // Send the challenge to the client, see encode.
+    Challenge(Vec<u8>),
+    // The client's response continues on another line.
+    More,
+    // Logged in to the account.
+    Success(String),
+    Failure,
+    TooLong,
+}
+
+enum State {
+    Start,
+    // SCRAM-SHA-256, after the server-first-message.
+    ScramFinal {
+        account: String,
+        gs2_header: String,
+        client_first_bare: String,
+        server_first: String,
+        nonce: String,
+        stored_key: Vec<u8>,
+        server_key: Vec<u8>,
+    },
+    // SCRAM-SHA-256, after the server-final-message, waiting for the client to accept it.
+    ScramDone(String),
+}
+
+// An authentication in progress.
+pub struct Session {
+    mechanism: Mechanism,
+    state: State,
+    // Base64 of the client's response so far.
+    response: String,
+}
+
+impl fmt::Debug for Session {
+    // Leaves out the keys.
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Session({:?})", self.mechanism)
+    }
+}
+
+impl Session {
+    pub fn new(mechanism: Mechanism) -> Self {
+        Session {
+            mechanism: mechanism,
+            state: State::Start,
+            response: String::new(),
+        }
+    }
+
+    // Takes a line of the client's response, "+" if empty, and answers once it is complete.
+    pub fn receive(&mut self, backend: &dyn Backend, data: &str) -> Reply {
+        if data != "+" {
+            self.response.push_str(data);
+        }
+        if self.response.len() > MAX_RESPONSE_LENGTH {
+            return Reply::TooLong;
+        }
+        if data.len() == CHUNK_LENGTH {
+            return Reply::More;
+        }
+        let response = match base64::decode(&self.response) {
+            Ok(r) => r,
+            Err(_) => return Reply::Failure,
+        };
+        self.response.clear();
+        match str::from_utf8(&response) {
+            Ok(r) => self.step(backend, r),
+            Err(_) => Reply::Failure,
+        }
+    }
+
+    fn step(&mut self, backend: &dyn Backend, response: &str) -> Reply {
+        match (&self.mechanism, &self.state) {
+            (&Mechanism::Plain, &State::Start) => {
+                let fields: Vec<&str> = response.split('\0').collect();
+                if fields.len() != 3 {
+                    return Reply::Failure;
+                }
+                match backend.password(fields[1]) {
+                    Some((ref a, ref hash)) if authorized(fields[0], a) &&
+                                               password::verify(fields[2], hash) => {
+                        Reply::Success(a.clone())
+                    }
+                    _ => Reply::Failure,
+                }
+            }
+            (&Mechanism::ScramSha256, &State::Start) => self.scram_first(backend, response),
+            (&Mechanism::ScramSha256, &State::ScramFinal { .. }) => self.scram_final(response),
+            (&Mechanism::ScramSha256, &State::ScramDone(ref account)) if response.is_empty() => {
+                Reply::Success(account.clone())
+            }
+            _ => Reply::Failure,
+        }
+    }
+
+    // Answers "n,,n=user,r=nonce" with "r=nonce,s=salt,i=iterations".
+    fn scram_first(&mut self, backend: &dyn Backend, response: &str) -> Reply {
+        let fields: Vec<&str> = response.splitn(3, ',').collect();
+        // Channel binding is not supported, as links are not TLS.
+        if fields.len() != 3 || (fields[0] != "n" && fields[0] != "y") {
+            return Reply::Failure;
+        }
+        let authzid = match fields[1] {
+            "" => "",
+            a if a.starts_with("a=") => &a[2..],
+            _ => return Reply::Failure,
+        };
+        let client_first_bare = fields[2];
+        let attributes = attributes(client_first_bare);
+        let (username, client_nonce) = match (attributes.get(0), attributes.get(1)) {
+            (Some(&('n', u)), Some(&('r', r))) if !r.is_empty() => (unescape(u), r),
+            _ => return Reply::Failure,
+        };
+        let credentials = match backend.scram_credentials(&username) {
+            Some(c) => c,
+            None => return Reply::Failure,
+        };
+        if !authorized(&unescape(authzid), &credentials.account) {
+            return Reply::Failure;
+        }
+
+        let server_nonce: Vec<u8> = (0..NONCE_LENGTH).map(|_| rand::random::<u8>()).collect();
+        let nonce = format!("{}{}", client_nonce, base64::encode(&server_nonce));
+        let server_first = format!(
+            "r={},s={},i={}",
+            nonce,
+            base64::encode(&credentials.salt),
+            credentials.iterations
+        );
+        self.state = State::ScramFinal {
+            account: credentials.account,
+            gs2_header: format!("{},{},", fields[0], fields[1]),
+            client_first_bare: client_first_bare.to_string(),
+            server_first: server_first.clone(),
+            nonce: nonce,
+            stored_key: credentials.stored_key,
+            server_key: credentials.server_key,
+        };
+        Reply::Challenge(server_first.into_bytes())
+    }
+
+    // Checks the proof in "c=binding,r=nonce,p=proof", and answers with "v=signature".
+    fn scram_final(&mut self, response: &str) -> Reply {
+        let (account, signature) = match self.state {
+            State::ScramFinal {
+                ref account,
+                ref gs2_header,
+                ref client_first_bare,
+                ref server_first,
+                ref nonce,
+                ref stored_key,
+                ref server_key,
+            } => {
+                let idx = match response.rfind(",p=") {
+                    Some(idx) => idx,
+                    None => return Reply::Failure,
+                };
+                let without_proof = &response[..idx];
+                let proof = match base64::decode(&response[idx + 3..]) {
+                    Ok(p) => p,
+                    Err(_) => return Reply::Failure,
+                };
+                let attributes = attributes(without_proof);
+                let binding = base64::encode(gs2_header.as_bytes());
+                match (attributes.get(0), attributes.get(1)) {
+                    (Some(&('c', c)), Some(&('r', r))) if c == binding && r == nonce => {}
+                    _ => return Reply::Failure,
+                }
+
+                let auth_message =
+                    format!("{},{},{}", client_first_bare, server_first, without_proof);
+                let client_signature = hmac(stored_key, auth_message.as_bytes());
+                if proof.len() != client_signature.len() {
+                    return Reply::Failure;
+                }
+                let claimed_key: Vec<u8> = proof
+                    .iter()
+                    .zip(client_signature.iter())
+                    .map(|(p, s)| p ^ s)
+                    .collect();
+                // Constant time, like password::verify.
+                let difference = Sha256::digest(&claimed_key)
+                    .iter()
+                    .zip(stored_key.iter())
+                    .fold(0, |d, (a, b)| d | (a ^ b));
+                if difference != 0 {
+                    return Reply::Failure;
+                }
+                (account.clone(), hmac(server_key, auth_message.as_bytes()))
+            }
+            _ => return Reply::Failure,
+        };
+        self.state = State::ScramDone(account);
+        Reply::Challenge(format!("v={}", base64::encode(&signature)).into_bytes())
+    }
+}
+
+// Splits a challenge into AUTHENTICATE lines.
+pub fn encode(challenge: &[u8]) -> Vec<String> {
+    let encoded = base64::encode(challenge);
+    let mut lines: Vec<String> = encoded
+        .as_bytes()
+        .chunks(CHUNK_LENGTH)
+        .map(|c| String::from_utf8_lossy(c).into_owned())
+        .collect();
+    // An empty or full last line is followed by "+", so the client knows it is over.
+    if encoded.len() % CHUNK_LENGTH == 0 {
+        lines.push("+".to_string());
+    }
+    lines
+}
+
+// Whether authenticating as account allows acting as authzid, if given. Only as itself.
+fn authorized(authzid: &str, account: &str) -> bool {
+    authzid.is_empty() || authzid.eq_ignore_ascii_case(account)
+}
+
+// Splits "n=user,r=nonce" into its attributes.
+fn attributes(message: &str) -> Vec<(char, &str)> {
+    message
+        .split(',')
+        .filter_map(|a| {
+            let mut chars = a.chars();
+            match (chars.next(), chars.next()) {
+                (Some(name), Some('=')) => Some((name, &a[2..])),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// SCRAM escapes ',' and '=' in names.
+fn unescape(name: &str) -> String {
+    name.replace("=2C", ",").replace("=3D", "=")
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use base64;
+    use pbkdf2;
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+    use super::super::super::password;
+    use super::{encode, hmac, Backend, Mechanism, Reply, ScramCredentials, ScramKeys, Session};
+
+    // Stands in for a directory server, keeping passwords in the clear.
+    struct StubDirectory {
+        passwords: HashMap<String, String>,
+    }
+
+    impl Backend for StubDirectory {
+        fn password(&self, account: &str) -> Option<(String, String)> {
+            self.passwords.get(account).map(
+                |p| (account.to_string(), password::hash(p)),
+            )
+        }
+
+        fn scram_credentials(&self, account: &str) -> Option<ScramCredentials> {
+            self.passwords.get(account).and_then(|p| {
+                ScramKeys::derive(p, b"salt", 4096).credentials(account)
+            })
+        }
+    }
+
+    fn salted(password: &str, salt: &[u8], iterations: u32) -> Vec<u8> {
+        let mut result = vec![0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut result);
+        result
+    }
+
+    fn directory() -> StubDirectory {
+        StubDirectory {
+            passwords: vec![("alice".to_string(), "hunter2".to_string())]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    fn send(session: &mut Session, backend: &dyn Backend, message: &str) -> Reply {
+        let encoded = base64::encode(message.as_bytes());
+        session.receive(backend, if encoded.is_empty() { "+" } else { &encoded })
+    }
+
+    #[test]
+    fn test_plain() {
+        let backend = directory();
+        let mut session = Session::new(Mechanism::Plain);
+        assert_eq!(
+            send(&mut session, &backend, "\0alice\0hunter2"),
+            Reply::Success("alice".to_string())
+        );
+        let mut session = Session::new(Mechanism::Plain);
+        assert_eq!(send(&mut session, &backend, "alice\0alice\0wrong"), Reply::Failure);
+        let mut session = Session::new(Mechanism::Plain);
+        assert_eq!(send(&mut session, &backend, "bob\0alice\0hunter2"), Reply::Failure);
+
+        // Split over several lines.
+        let mut session = Session::new(Mechanism::Plain);
+        let long = base64::encode(&vec![b'a'; 500]);
+        assert_eq!(session.receive(&backend, &long[..400]), Reply::More);
+        assert_eq!(session.receive(&backend, &long[400..]), Reply::Failure);
+        let mut session = Session::new(Mechanism::Plain);
+        let too_long = "a".repeat(400);
+        for _ in 0..10 {
+            assert_eq!(session.receive(&backend, &too_long), Reply::More);
+        }
+        assert_eq!(session.receive(&backend, &too_long), Reply::TooLong);
+    }
+
+    // Plays the client side of RFC 7677.
+    fn scram(password: &str) -> Reply {
+        let backend = directory();
+        let mut session = Session::new(Mechanism::ScramSha256);
+        let client_first_bare = "n=alice,r=rOprNGfwEbeRWgbNEkqO";
+        let server_first = match send(&mut session, &backend, &format!("n,,{}", client_first_bare)) {
+            Reply::Challenge(c) => String::from_utf8(c).unwrap(),
+            r => panic!("{:?}", r),
+        };
+        assert!(server_first.starts_with("r=rOprNGfwEbeRWgbNEkqO"));
+        let nonce = &server_first[2..server_first.find(',').unwrap()];
+
+        let without_proof = format!("c=biws,r={}", nonce);
+        let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+        let salted_password = salted(password, b"salt", 4096);
+        let client_key = hmac(&salted_password, b"Client Key");
+        let client_signature = hmac(&Sha256::digest(&client_key), auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(client_signature.iter())
+            .map(|(k, s)| k ^ s)
+            .collect();
+        let reply = send(
+            &mut session,
+            &backend,
+            &format!("{},p={}", without_proof, base64::encode(&proof)),
+        );
+        let server_final = match reply {
+            Reply::Challenge(c) => String::from_utf8(c).unwrap(),
+            r => return r,
+        };
+        let server_signature = hmac(
+            &hmac(&salted_password, b"Server Key"),
+            auth_message.as_bytes(),
+        );
+        assert_eq!(server_final, format!("v={}", base64::encode(&server_signature)));
+        send(&mut session, &backend, "")
+    }
+
+    #[test]
+    fn test_scram_sha_256() {
+        assert_eq!(scram("hunter2"), Reply::Success("alice".to_string()));
+        assert_eq!(scram("hunter3"), Reply::Failure);
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(b""), vec!["+"]);
+        assert_eq!(encode(b"abc"), vec!["YWJj"]);
+        let lines = encode(&vec![0; 300]);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), 400);
+        assert_eq!(lines[1], "+");
+    }
+}
@@ -0,0 +1,87 @@
+use futures::{Async, Poll, Stream};
+use futures::sync::{mpsc, oneshot};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::connection::Event;
+use super::statistics::LinkStatistics;
+
+// Creates a connection's event queue, holding up to limit bytes of messages. The oneshot fires
+// once the limit is exceeded.
+pub fn channel(
+    link: Arc<LinkStatistics>,
+    limit: usize,
+) -> (SendQueue, Receiver, oneshot::Receiver<()>) {
+    let (tx, rx) = mpsc::unbounded();
+    let (exceeded_tx, exceeded_rx) = oneshot::channel();
+    (
+        SendQueue {
+            tx: tx,
+            link: Arc::clone(&link),
+            limit: Arc::new(AtomicUsize::new(limit)),
+            exceeded: Arc::new(Mutex::new(Some(exceeded_tx))),
+        },
+        Receiver { rx: rx, link: link },
+        exceeded_rx,
+    )
+}
+
+// Sending half of a connection's event queue. Never blocks: once more than limit bytes are waiting
+// to be written, further messages are dropped and the connection is expected to close.
+#[derive(Debug, Clone)]
+pub struct SendQueue {
+    tx: mpsc::UnboundedSender<(usize, Event)>,
+    link: Arc<LinkStatistics>,
+    limit: Arc<AtomicUsize>,
+    // Taken once the limit is exceeded.
+    exceeded: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+}
+
+impl SendQueue {
+    pub fn send(&self, event: Event) {
+        let size = event.size();
+        if self.link.queued(size) > self.limit.load(Ordering::Relaxed) {
+            self.link.dequeued(size);
+            if let Some(exceeded) = self.exceeded.lock().unwrap().take() {
+                debug!("SendQ exceeded for {}.", self.link.name());
+                let _ = exceeded.send(());
+            }
+            return;
+        }
+        if let Err(e) = self.tx.unbounded_send((size, event)) {
+            debug!("Send error: {:?}.", e);
+            self.link.dequeued(size);
+        }
+    }
+
+    // Applies to every clone of this queue.
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn exceeded(&self) -> bool {
+        self.exceeded.lock().unwrap().is_none()
+    }
+}
+
+// Receiving half of a connection's event queue.
+#[derive(Debug)]
+pub struct Receiver {
+    rx: mpsc::UnboundedReceiver<(usize, Event)>,
+    link: Arc<LinkStatistics>,
+}
+
+impl Stream for Receiver {
+    type Item = Event;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Event>, ()> {
+        match self.rx.poll()? {
+            Async::Ready(Some((size, event))) => {
+                self.link.dequeued(size);
+                Ok(Async::Ready(Some(event)))
+            }
+            Async::Ready(None) => Ok(Async::Ready(None)),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
@@ -1,10 +1,25 @@
+use futures::sync::oneshot;
 use std;
-use std::collections::HashMap;
-use std::sync::Arc;
-use super::channel::{Channel, Identifier as ChannelIdentifier, ChannelError};
-use super::connection::ConnectionTX;
-use super::user::Identifier as UserIdentifier;
-use super::shared_state::SharedState;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use super::super::configuration::{ChannelConfiguration, ConfigurationError, ConnectionClass};
+use super::accounts::{Account, Accounts};
+use super::bans::{Ban, Bans};
+use super::channel::{Channel, Identifier as ChannelIdentifier, ChannelError, MemberStatus,
+                     ModeChange, Snapshot as ChannelSnapshot};
+use super::channel_registry::{self, AccessLevel, ChannelRegistry, RegisteredChannel};
+use super::chanserv;
+use super::connection::{ConnectionTX, Event, SocketPair};
+use super::messages::Message as IRCMessage;
+use super::messages::commands::{Command, requests as Requests};
+use super::nickserv;
+use super::sasl::{Backend, ScramCredentials};
+use super::user::{Identifier as UserIdentifier, SetMode, User};
+use super::shared_state::{SharedState, Settings};
+use super::statistics::LinkStatistics;
+use super::throttle::{self, Throttle};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum ServerError {
@@ -12,9 +27,120 @@ pub enum ServerError {
     UnknownUser,
     NoSuchChannel,
     NotOnChannel,
+    // The connection class has no room for another client.
+    ClassFull,
+    // The connection class has no room for another client from the same IP.
+    TooManyFromHost,
+    // The channel refused the message, for the reason given.
+    CannotSend(&'static str),
     Other,
 }
 
+// Server notice mask (snomask) categories operators can subscribe to, with MODE nick +s <mask>.
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
+pub enum SnoMask {
+    // c: Client connects.
+    Connect,
+    // C: Client exits.
+    Exit,
+    // k: KILLs.
+    Kill,
+    // K: K/G/D-line hits.
+    Ban,
+    // f: Flood events.
+    Flood,
+    // r: Connections refused by throttling, clone limits or their connection class.
+    Reject,
+    // n: Nick changes.
+    Nick,
+    // x: OPER attempts and wrong NickServ passwords.
+    Oper,
+    // g: General server events, e.g. REHASH.
+    General,
+}
+
+// Snomask given to new operators.
+pub static DEFAULT_SNOMASK: &'static str = "+kKfxg";
+
+impl SnoMask {
+    fn from_char(c: char) -> Option<SnoMask> {
+        match c {
+            'c' => Some(SnoMask::Connect),
+            'C' => Some(SnoMask::Exit),
+            'k' => Some(SnoMask::Kill),
+            'K' => Some(SnoMask::Ban),
+            'f' => Some(SnoMask::Flood),
+            'r' => Some(SnoMask::Reject),
+            'n' => Some(SnoMask::Nick),
+            'x' => Some(SnoMask::Oper),
+            'g' => Some(SnoMask::General),
+            _ => None,
+        }
+    }
+
+    // Applies a +/- snomask change string (e.g. "+cC-k") to current.
+    // Unknown letters are ignored. A leading sign is optional and defaults to +.
+    pub fn apply(current: &HashSet<SnoMask>, changes: &str) -> HashSet<SnoMask> {
+        let mut result = current.clone();
+        let mut add = true;
+        for c in changes.chars() {
+            match c {
+                '+' => add = true,
+                '-' => add = false,
+                _ => {
+                    if let Some(m) = SnoMask::from_char(c) {
+                        if add {
+                            result.insert(m);
+                        } else {
+                            result.remove(&m);
+                        }
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    // Formats masks as a "+cCk" string, in canonical letter order.
+    pub fn format(masks: &HashSet<SnoMask>) -> String {
+        let letters: String = "cCkKfrnxg"
+            .chars()
+            .filter(|&c| masks.contains(&SnoMask::from_char(c).unwrap()))
+            .collect();
+        format!("+{}", letters)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownKind {
+    // Exit the process.
+    Die,
+    // Re-exec the binary.
+    Restart,
+    // Re-exec the binary, handing over client connections.
+    HotRestart,
+}
+
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    pub kind: ShutdownKind,
+    // Sent to every client.
+    pub reason: String,
+}
+
+// Server, preserved across hot restarts. Users and links are restored with their Connection.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    channels: Vec<ChannelSnapshot>,
+    operators: Vec<(UserIdentifier, Vec<SnoMask>)>,
+    bans: Vec<Ban>,
+    command_usage: Vec<(String, usize)>,
+    #[serde(default)]
+    accounts: Option<Vec<Account>>,
+    #[serde(default)]
+    registered_channels: Option<Vec<RegisteredChannel>>,
+}
+
 #[derive(Debug)]
 pub struct Server {
     // Unlike Channel/Connection,
@@ -22,35 +148,592 @@ pub struct Server {
     users: HashMap<UserIdentifier, ConnectionTX>,
     // All known channels.
     channels: HashMap<ChannelIdentifier, Channel>,
+    // Operators -> subscribed server notices.
+    operators: HashMap<UserIdentifier, HashSet<SnoMask>>,
+    // K/G/D-lines.
+    bans: Bans,
+    // NickServ accounts.
+    accounts: Accounts,
+    // Users logged in -> their account name.
+    logins: HashMap<UserIdentifier, String>,
+    // ChanServ channel registrations.
+    registry: ChannelRegistry,
+    // Every open connection, registered or not.
+    links: HashMap<SocketPair, Arc<LinkStatistics>>,
+    // Connection class name of every registered client.
+    classes: HashMap<SocketPair, String>,
+    // Recent connections per IP.
+    throttle: Throttle,
+    // Reason -> number of connections refused before registering.
+    refusals: HashMap<&'static str, usize>,
+    // Command name -> number of times it was received.
+    command_usage: HashMap<String, usize>,
+    // Settings at startup, which listeners were bound with.
+    startup_settings: Arc<Settings>,
+    // Taken once the server starts shutting down.
+    shutdown_tx: Option<oneshot::Sender<Shutdown>>,
+    shutdown_rx: Option<oneshot::Receiver<Shutdown>>,
     shared_state: Arc<SharedState>,
 }
 
+// Nicks taken by services.
+fn reserved(nick: &str) -> bool {
+    nick.eq_ignore_ascii_case(nickserv::NICK) || nick.eq_ignore_ascii_case(chanserv::NICK)
+}
+
 impl Server {
-    pub fn new(shared_state: Arc<SharedState>) -> Self {
-        // TODO(lazau): Load preconfigured channels.
-        Self {
+    // Fails if a persisted store can't be loaded.
+    pub fn new(shared_state: Arc<SharedState>) -> Result<Self, ConfigurationError> {
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let registered_channels_file = shared_state
+            .settings()
+            .configuration
+            .registered_channels_file
+            .clone();
+        let mut server = Self {
             users: HashMap::new(),
             channels: HashMap::new(),
+            operators: HashMap::new(),
+            bans: Bans::load(shared_state.settings().configuration.bans_file.clone())
+                .map_err(ConfigurationError::Store)?,
+            accounts: Accounts::load(shared_state.settings().configuration.accounts_file.clone())
+                .map_err(ConfigurationError::Store)?,
+            logins: HashMap::new(),
+            registry: ChannelRegistry::load(registered_channels_file)
+                .map_err(ConfigurationError::Store)?,
+            links: HashMap::new(),
+            classes: HashMap::new(),
+            throttle: Throttle::new(),
+            refusals: HashMap::new(),
+            command_usage: HashMap::new(),
+            startup_settings: shared_state.settings(),
+            shutdown_tx: Some(shutdown_tx),
+            shutdown_rx: Some(shutdown_rx),
             shared_state: shared_state,
+        };
+        server.create_registered_channels();
+        server.configure_channels(&[]);
+        Ok(server)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            channels: self.channels.values().map(|c| c.snapshot()).collect(),
+            operators: self.operators
+                .iter()
+                .map(|(u, s)| (u.clone(), s.iter().cloned().collect()))
+                .collect(),
+            bans: self.bans.snapshot(),
+            command_usage: self.command_usage
+                .iter()
+                .map(|(c, n)| (c.clone(), *n))
+                .collect(),
+            accounts: Some(self.accounts.snapshot()),
+            registered_channels: Some(self.registry.snapshot()),
         }
     }
 
+    // Restores channels and operators. Users must have been re-added by their Connection first.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        for channel in snapshot.channels {
+            let channel = {
+                let users = &self.users;
+                Channel::restore(channel, |u| users.get(u).cloned())
+            };
+            self.channels.insert(channel.identifier().clone(), channel);
+        }
+        for (user, snomasks) in snapshot.operators {
+            if self.users.contains_key(&user) {
+                self.operators.insert(user, snomasks.into_iter().collect());
+            }
+        }
+        self.bans.restore(snapshot.bans);
+        self.command_usage = snapshot.command_usage.into_iter().collect();
+        if let Some(accounts) = snapshot.accounts {
+            self.accounts.restore(accounts);
+        }
+        if let Some(registered_channels) = snapshot.registered_channels {
+            self.registry.restore(registered_channels);
+        }
+        self.create_registered_channels();
+        self.configure_channels(&[]);
+        self.collect_channels();
+    }
+
+    // Creates the channels registered with ChanServ that don't exist, so their topic and mode lock
+    // are in place before anyone joins.
+    fn create_registered_channels(&mut self) {
+        let names: Vec<String> = self.registry.iter().map(|c| c.name.clone()).collect();
+        for name in names {
+            let ident = ChannelIdentifier::from_name(&name);
+            if !self.channels.contains_key(&ident) {
+                self.create_channel(&ident);
+            }
+        }
+    }
+
+    // Creates the channels in the configuration that don't exist, and brings every one of them back
+    // in line with its settings, telling members what changed. Channels no longer configured stop
+    // being permanent, and lose the limit and key previous (the configuration before a REHASH) set
+    // but the configuration no longer does.
+    fn configure_channels(&mut self, previous: &[ChannelConfiguration]) {
+        let settings = self.shared_state.settings();
+        let source = self.shared_state.hostname.clone();
+        for channel in self.channels.values_mut() {
+            channel.set_permanent(false);
+        }
+        for old in previous.iter() {
+            let config = settings.configuration.channels.iter().find(|c| c.name == old.name);
+            let mut changes = Vec::new();
+            if old.limit.is_some() && config.map_or(true, |c| c.limit.is_none()) {
+                changes.push(ModeChange::Limit(SetMode::Remove, 0));
+            }
+            if old.key.is_some() && config.map_or(true, |c| c.key.is_none()) {
+                changes.push(ModeChange::Key(SetMode::Remove, "*".to_string()));
+            }
+            if let Some(channel) = self.channels.get_mut(&ChannelIdentifier::from_name(&old.name)) {
+                channel.apply_modes(source.clone(), changes);
+            }
+        }
+        for config in settings.configuration.channels.iter() {
+            let ident = ChannelIdentifier::from_name(&config.name);
+            if !self.channels.contains_key(&ident) {
+                self.create_channel(&ident);
+            }
+            let channel = self.channels.get_mut(&ident).unwrap();
+            channel.set_permanent(config.permanent);
+            if let Some(ref topic) = config.topic {
+                if channel.topic().as_ref() != Some(topic) {
+                    channel.set_topic(source.clone(), topic.clone());
+                }
+            }
+            let (on, off) = channel_registry::parse_mode_lock(&config.modes).unwrap_or_default();
+            let mut changes: Vec<ModeChange> = on.into_iter()
+                .map(|m| ModeChange::Flag(SetMode::Add, m))
+                .chain(off.into_iter().map(|m| ModeChange::Flag(SetMode::Remove, m)))
+                .collect();
+            if let Some(ref key) = config.key {
+                changes.push(ModeChange::Key(SetMode::Add, key.clone()));
+            }
+            if let Some(limit) = config.limit {
+                changes.push(ModeChange::Limit(SetMode::Add, limit));
+            }
+            channel.apply_modes(source.clone(), changes);
+        }
+        self.collect_channels();
+    }
+
+    // Destroys channel if its last member left, unless it is permanent or registered.
+    pub fn collect_channel(&mut self, ident: &ChannelIdentifier) {
+        let collect = match self.channels.get(ident) {
+            Some(c) => {
+                c.users().next().is_none() && !c.is_permanent() &&
+                    self.registry.get(ident.name()).is_none()
+            }
+            None => false,
+        };
+        if collect {
+            debug!("Destroying empty channel {}.", ident.name());
+            self.channels.remove(ident);
+        }
+    }
+
+    // Destroys every channel collect_channel would, e.g. once channels stop being permanent.
+    pub fn collect_channels(&mut self) {
+        let idents: Vec<ChannelIdentifier> = self.channels.keys().cloned().collect();
+        for ident in idents.iter() {
+            self.collect_channel(ident);
+        }
+    }
+
+    // Creates channel, with the topic and locked modes ChanServ keeps if it is registered.
+    fn create_channel(&mut self, ident: &ChannelIdentifier) {
+        let mut channel = Channel::new(ident.clone());
+        if let Some(registered) = self.registry.get(ident.name()) {
+            channel.restore_topic(registered.topic.clone());
+            for mode in registered.locked_modes().0 {
+                channel.set_mode(&SetMode::Add, mode);
+            }
+        }
+        self.channels.insert(ident.clone(), channel);
+    }
+
     pub fn add_user(&mut self, user: &UserIdentifier, tx: ConnectionTX) -> Result<(), ServerError> {
         debug!("Inserting {:?} into {:?}.", user, self.users);
-        if self.users.contains_key(user) {
+        if self.users.contains_key(user) || reserved(user.nick()) {
             return Err(ServerError::NickInUse);
         }
         self.users.insert(user.clone(), tx);
         Ok(())
     }
 
-    pub fn remove_user(&mut self, user: &UserIdentifier) {
+    // Removes user from the server and all its channels, broadcasting QUIT with message to
+    // everyone that shared a channel with user.
+    pub fn remove_user(&mut self, user: &UserIdentifier, message: &String) {
         if self.users.remove(user).is_none() {
             warn!("Removing unknown user: {:?}.", user);
+            return;
+        }
+        self.operators.remove(user);
+        self.logins.remove(user);
+
+        let mut recipients = HashMap::new();
+        let mut left = Vec::new();
+        for channel in self.channels.values_mut() {
+            if channel.remove_user(user) {
+                for (u, tx) in channel.members() {
+                    recipients.insert(u.clone(), tx.clone());
+                }
+                left.push(channel.identifier().clone());
+            }
+        }
+        for ident in left.iter() {
+            self.collect_channel(ident);
         }
+
+        let quit = Event::Message(vec![
+            IRCMessage {
+                prefix: Some(user.as_prefix()),
+                command: Command::QUIT(Requests::Quit { message: Some(message.clone()) }),
+            },
+        ]);
+        for tx in recipients.values() {
+            self.send_event(tx, quit.clone());
+        }
+    }
+
+    // Asks the connection owning user to close its link with reason.
+    // Returns the full identifier of the disconnected user.
+    pub fn disconnect_user(
+        &self,
+        user: &UserIdentifier,
+        reason: &String,
+    ) -> Result<UserIdentifier, ServerError> {
+        self.notify_user(user, Event::Disconnect(reason.clone()))
+    }
+
+    // Sends event to the connection owning user.
+    // Returns the full identifier of user.
+    pub fn notify_user(
+        &self,
+        user: &UserIdentifier,
+        event: Event,
+    ) -> Result<UserIdentifier, ServerError> {
+        match self.users.iter().find(|&(u, _)| u == user) {
+            Some((ident, tx)) => {
+                self.send_event(tx, event);
+                Ok(ident.clone())
+            }
+            None => Err(ServerError::UnknownUser),
+        }
+    }
+
+    // Silently replaces the stored identifier of user, after its displayed host changed.
+    pub fn update_user(&mut self, user: &UserIdentifier) {
+        match self.users.remove(user) {
+            Some(tx) => {
+                self.users.insert(user.clone(), tx);
+            }
+            None => {
+                warn!("Updating unknown user: {:?}.", user);
+                return;
+            }
+        }
+        if let Some(snomasks) = self.operators.remove(user) {
+            self.operators.insert(user.clone(), snomasks);
+        }
+        if let Some(account) = self.logins.remove(user) {
+            self.logins.insert(user.clone(), account);
+        }
+        for channel in self.channels.values_mut() {
+            channel.rename_user(user, user);
+        }
+    }
+
+    // The full identifier of user, if connected.
+    pub fn find_user(&self, user: &UserIdentifier) -> Option<&UserIdentifier> {
+        self.users.get_key_value(user).map(|(u, _)| u)
+    }
+
+    pub fn is_operator(&self, user: &UserIdentifier) -> bool {
+        self.operators.contains_key(user)
+    }
+
+    pub fn bans(&mut self) -> &mut Bans {
+        &mut self.bans
+    }
+
+    // NickServ accounts.
+    pub fn accounts(&mut self) -> &mut Accounts {
+        &mut self.accounts
+    }
+
+    pub fn registry(&mut self) -> &mut ChannelRegistry {
+        &mut self.registry
+    }
+
+    // Records user as logged in to account, or logged out if None.
+    pub fn set_account(&mut self, user: &UserIdentifier, account: Option<String>) {
+        match account {
+            Some(a) => self.logins.insert(user.clone(), a),
+            None => self.logins.remove(user),
+        };
+    }
+
+    // The account user is logged in to.
+    pub fn account(&self, user: &UserIdentifier) -> Option<&String> {
+        self.logins.get(user)
+    }
+
+    // Users logged in to account.
+    pub fn logged_in(&self, account: &str) -> Vec<UserIdentifier> {
+        self.logins
+            .iter()
+            .filter(|&(_, a)| a.eq_ignore_ascii_case(account))
+            .map(|(u, _)| u.clone())
+            .collect()
     }
 
-    // Replaces old_nick with new_nick for user.
+    // Adds ban, and asks every connected user to check whether it applies to them.
+    pub fn add_ban(&mut self, ban: Ban) {
+        info!("Adding ban: {:?}.", ban);
+        self.bans.add(ban);
+        for tx in self.users.values() {
+            self.send_event(tx, Event::CheckBans);
+        }
+    }
+
+    pub fn add_link(&mut self, socket: &SocketPair, statistics: Arc<LinkStatistics>) {
+        self.links.insert(socket.clone(), statistics);
+    }
+
+    pub fn remove_link(&mut self, socket: &SocketPair) {
+        self.links.remove(socket);
+        self.classes.remove(socket);
+    }
+
+    // Decides whether to accept a new connection from ip, before any state is allocated for it.
+    // Returns the reason it is refused.
+    pub fn throttle(&mut self, ip: &IpAddr, now: Instant) -> Option<String> {
+        let settings = self.shared_state.settings();
+        let configuration = &settings.configuration;
+        if configuration.throttle_exempt(ip) {
+            return None;
+        }
+
+        let window = Duration::from_secs(configuration.throttle_window_seconds);
+        let registering = self.links
+            .keys()
+            .filter(|s| !self.classes.contains_key(s))
+            .count();
+        let key = throttle::clone_key(ip);
+        let clones = self.links
+            .keys()
+            .filter(|s| throttle::clone_key(&s.remote.ip()) == key)
+            .count();
+        let (kind, reason) = if configuration.throttle_connections > 0 &&
+            !self.throttle.record(ip, configuration.throttle_connections, window, now)
+        {
+            ("throttle", "Reconnecting too fast, throttled")
+        } else if configuration.max_registering > 0 &&
+                   registering >= configuration.max_registering
+        {
+            ("registering", "Too many connections registering, try again later")
+        } else if configuration.max_clones > 0 && clones >= configuration.max_clones {
+            ("clones", "Too many connections from your host")
+        } else {
+            return None;
+        };
+
+        self.record_refusal(kind);
+        self.server_notice(
+            &SnoMask::Reject,
+            &format!("Refused connection from {}: {}", ip, reason),
+        );
+        Some(reason.to_string())
+    }
+
+    pub fn record_refusal(&mut self, kind: &'static str) {
+        *self.refusals.entry(kind).or_insert(0) += 1;
+    }
+
+    pub fn refusals<'a>(
+        &'a self,
+    ) -> std::collections::hash_map::Iter<'a, &'static str, usize> {
+        self.refusals.iter()
+    }
+
+    // Checks whether class has room for another client connecting from socket.
+    pub fn check_class(
+        &self,
+        socket: &SocketPair,
+        class: &ConnectionClass,
+    ) -> Result<(), ServerError> {
+        let members: Vec<_> = self.classes
+            .iter()
+            .filter(|&(s, c)| s != socket && c == &class.name)
+            .map(|(s, _)| s)
+            .collect();
+        if class.max_clients > 0 && members.len() >= class.max_clients {
+            return Err(ServerError::ClassFull);
+        }
+        let from_ip = members
+            .iter()
+            .filter(|s| s.remote.ip() == socket.remote.ip())
+            .count();
+        if class.max_clients_per_ip > 0 && from_ip >= class.max_clients_per_ip {
+            return Err(ServerError::TooManyFromHost);
+        }
+        Ok(())
+    }
+
+    pub fn remove_class(&mut self, socket: &SocketPair) {
+        self.classes.remove(socket);
+    }
+
+    pub fn set_class(&mut self, socket: &SocketPair, class: &ConnectionClass) {
+        self.classes.insert(socket.clone(), class.name.clone());
+    }
+
+    pub fn links<'a>(
+        &'a self,
+    ) -> std::collections::hash_map::Iter<'a, SocketPair, Arc<LinkStatistics>> {
+        self.links.iter()
+    }
+
+    pub fn record_command(&mut self, command: &'static str) {
+        *self.command_usage.entry(command.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn command_usage<'a>(
+        &'a self,
+    ) -> std::collections::hash_map::Iter<'a, String, usize> {
+        self.command_usage.iter()
+    }
+
+    // Reloads the configuration file on behalf of source (an operator, or SIGHUP).
+    // Returns the changed settings that need a restart to take effect.
+    pub fn rehash(&mut self, source: &String) -> Result<Vec<&'static str>, ConfigurationError> {
+        info!("{} is rehashing the configuration.", source);
+        let previous = self.shared_state.settings();
+        if let Err(e) = self.shared_state.reload() {
+            error!("Rehash failed: {}.", e);
+            self.server_notice(
+                &SnoMask::General,
+                &format!("Rehash by {} failed: {}", source, e),
+            );
+            return Err(e);
+        }
+        self.server_notice(
+            &SnoMask::General,
+            &format!("{} is rehashing server config file", source),
+        );
+
+        self.configure_channels(&previous.configuration.channels);
+
+        let settings = self.shared_state.settings();
+        if settings.configuration.bans_file.is_some() {
+            match Bans::load(settings.configuration.bans_file.clone()) {
+                Ok(bans) => {
+                    self.bans = bans;
+                    for tx in self.users.values() {
+                        self.send_event(tx, Event::CheckBans);
+                    }
+                }
+                Err(e) => {
+                    error!("Keeping the current bans: {}.", e);
+                    self.server_notice(
+                        &SnoMask::General,
+                        &format!("Keeping the current bans, {}", e),
+                    );
+                }
+            }
+        }
+
+        let restart_required = self.startup_settings.configuration.restart_required(
+            &settings.configuration,
+        );
+        for setting in restart_required.iter() {
+            warn!("Changed {} requires a restart.", setting);
+            self.server_notice(
+                &SnoMask::General,
+                &format!("Changed {} requires a restart to take effect", setting),
+            );
+        }
+        Ok(restart_required)
+    }
+
+    // Resolves once shutdown is called. Can only be taken once.
+    pub fn shutdown_signal(&mut self) -> oneshot::Receiver<Shutdown> {
+        self.shutdown_rx.take().expect("shutdown signal already taken")
+    }
+
+    // Starts shutting down, on behalf of source. Returns false if already shutting down.
+    pub fn shutdown(&mut self, source: &String, shutdown: Shutdown) -> bool {
+        let tx = match self.shutdown_tx.take() {
+            Some(tx) => tx,
+            None => return false,
+        };
+        info!("{} requested {:?}.", source, shutdown);
+        self.server_notice(
+            &SnoMask::General,
+            &format!("{} is shutting down the server: {}", source, shutdown.reason),
+        );
+        tx.send(shutdown).is_ok()
+    }
+
+    pub fn add_operator(&mut self, user: &UserIdentifier, snomasks: HashSet<SnoMask>) {
+        self.operators.insert(user.clone(), snomasks);
+    }
+
+    pub fn remove_operator(&mut self, user: &UserIdentifier) {
+        self.operators.remove(user);
+    }
+
+    pub fn snomasks(&self, user: &UserIdentifier) -> Option<&HashSet<SnoMask>> {
+        self.operators.get(user)
+    }
+
+    // Sends WALLOPS from source to every user; connections deliver it to +w users only.
+    pub fn wallops(&self, source: &UserIdentifier, message: &String) {
+        let wallops = IRCMessage {
+            prefix: Some(source.as_prefix()),
+            command: Command::WALLOPS(Requests::Wallops { message: message.clone() }),
+        };
+        for tx in self.users.values() {
+            self.send_event(tx, Event::Wallops(wallops.clone()));
+        }
+    }
+
+    // Sends a server notice to every operator subscribed to mask.
+    pub fn server_notice(&self, mask: &SnoMask, message: &String) {
+        debug!("Server notice ({:?}): {}.", mask, message);
+        for (oper, snomasks) in self.operators.iter() {
+            if !snomasks.contains(mask) {
+                continue;
+            }
+            if let Some(tx) = self.users.get(oper) {
+                self.send_event(
+                    tx,
+                    Event::Message(vec![
+                        IRCMessage {
+                            prefix: None,
+                            command: Command::NOTICE(Requests::Notice {
+                                targets: vec![oper.nick().clone()],
+                                message: format!("*** Notice -- {}", message),
+                            }),
+                        },
+                    ]),
+                );
+            }
+        }
+    }
+
+    fn send_event(&self, tx: &ConnectionTX, event: Event) {
+        tx.send(event);
+    }
+
+    // Replaces old_nick with new_nick for user, notifying everyone that shares a channel with user.
     pub fn replace_nick(
         &mut self,
         old: &UserIdentifier,
@@ -61,11 +744,35 @@ impl Server {
             old,
             new,
         );
-        if self.users.contains_key(new) {
+        if self.users.contains_key(new) || reserved(new.nick()) {
             return Err(ServerError::NickInUse);
         }
         let removed = self.users.remove(old).unwrap();
         self.users.insert(new.clone(), removed);
+        if let Some(snomasks) = self.operators.remove(old) {
+            self.operators.insert(new.clone(), snomasks);
+        }
+        if let Some(account) = self.logins.remove(old) {
+            self.logins.insert(new.clone(), account);
+        }
+
+        let mut recipients = HashMap::new();
+        for channel in self.channels.values_mut() {
+            if channel.rename_user(old, new) {
+                for (u, tx) in channel.members().filter(|&(u, _)| u != new) {
+                    recipients.insert(u.clone(), tx.clone());
+                }
+            }
+        }
+        let nick = Event::Message(vec![
+            IRCMessage {
+                prefix: Some(old.as_prefix()),
+                command: Command::NICK(Requests::Nick { nickname: new.nick().clone() }),
+            },
+        ]);
+        for tx in recipients.values() {
+            self.send_event(tx, nick.clone());
+        }
         Ok(())
     }
 
@@ -74,6 +781,10 @@ impl Server {
         self.channels.get(&channel)
     }
 
+    pub fn lookup_channel_mut(&mut self, channel: &ChannelIdentifier) -> Option<&mut Channel> {
+        self.channels.get_mut(&channel)
+    }
+
     pub fn channels<'a>(
         &'a self,
     ) -> std::collections::hash_map::Iter<'a, ChannelIdentifier, Channel> {
@@ -90,35 +801,120 @@ impl Server {
         self.users.keys()
     }
 
+    // Joins user to each of channels, with its key. Users refused by +i or +l are sent to the
+    // channel's forward instead, if it exists. Results are the channel joined, its topic and
+    // members.
     pub fn join(
         &mut self,
         user: &UserIdentifier,
         channels: &Vec<(String, Option<String>)>,
-    ) -> Vec<Result<(Option<String>, Vec<UserIdentifier>), ChannelError>> {
+    ) -> Vec<Result<(String, Option<String>, Vec<(String, UserIdentifier)>), ChannelError>> {
         let mut result = Vec::with_capacity(channels.len());
         let tx = self.lookup_user(user).unwrap().clone();
+        let account = self.logins.get(user).cloned();
+        let max_channels = self.shared_state.settings().configuration.max_channels;
+        let mut joined = self.channels.values().filter(|c| c.has_user(user)).count();
         for &(ref channel_name, ref key) in channels.iter() {
-            let ident = ChannelIdentifier::from_name(channel_name);
-            if !self.channels.contains_key(&ident) {
-                self.channels.insert(
-                    ident.clone(),
-                    Channel::new(ident.clone(), Arc::clone(&self.shared_state)),
-                );
+            if max_channels > 0 && joined >= max_channels {
+                result.push(Err(ChannelError::TooManyChannels));
+                continue;
             }
-            let channel = self.channels.get_mut(&ident).unwrap();
-            match channel.join(user, &tx, key) {
-                Ok(_) => {
-                    result.push(Ok((
-                        channel.topic().clone(),
-                        channel.users().cloned().collect(),
-                    )))
+            let mut res = self.join_channel(user, &tx, account.as_ref(), channel_name, key);
+            match res {
+                Err(ChannelError::Full) |
+                Err(ChannelError::InviteOnly) => {
+                    let forward = self.channels
+                        .get(&ChannelIdentifier::from_name(channel_name))
+                        .and_then(|c| c.forward().cloned())
+                        .filter(|f| {
+                            f != channel_name &&
+                                self.channels.contains_key(&ChannelIdentifier::from_name(f))
+                        });
+                    if let Some(forward) = forward {
+                        let forwarded =
+                            self.join_channel(user, &tx, account.as_ref(), &forward, &None);
+                        if forwarded.is_ok() {
+                            res = forwarded;
+                        }
+                    }
                 }
-                Err(e) => result.push(Err(e)),
-            };
+                _ => {}
+            }
+            if res.is_ok() {
+                joined += 1;
+            }
+            result.push(res);
         }
         result
     }
 
+    fn join_channel(
+        &mut self,
+        user: &UserIdentifier,
+        tx: &ConnectionTX,
+        account: Option<&String>,
+        channel_name: &String,
+        key: &Option<String>,
+    ) -> Result<(String, Option<String>, Vec<(String, UserIdentifier)>), ChannelError> {
+        let chanserv = chanserv::prefix(&self.shared_state.hostname);
+        let ident = ChannelIdentifier::from_name(channel_name);
+        if !self.channels.contains_key(&ident) {
+            self.create_channel(&ident);
+        }
+        let registered = self.registry.get(channel_name).cloned();
+        let access = registered.as_ref().and_then(|r| r.access(user, account));
+        if access == Some(AccessLevel::Akick) {
+            self.collect_channel(&ident);
+            return Err(ChannelError::Banned);
+        }
+        let joined = {
+            let channel = self.channels.get_mut(&ident).unwrap();
+            // Whoever creates an unregistered channel runs it. Permanent channels are never
+            // created by joining.
+            let status = if registered.is_none() && !channel.is_permanent() &&
+                channel.users().next().is_none()
+            {
+                Some(MemberStatus::Operator)
+            } else {
+                None
+            };
+            channel.join(user, tx, key, status).map(|_| {
+                (channel_name.clone(), channel.topic().clone(), channel.names())
+            })
+        };
+        if joined.is_err() {
+            self.collect_channel(&ident);
+            return joined;
+        }
+
+        // ChanServ gives registered channel members their status, and greets them.
+        let channel = self.channels.get_mut(&ident).unwrap();
+        let status = match access {
+            Some(AccessLevel::Founder) |
+            Some(AccessLevel::Op) => Some(MemberStatus::Operator),
+            Some(AccessLevel::Voice) => Some(MemberStatus::Voice),
+            _ => None,
+        };
+        if let Some(status) = status {
+            channel.apply_modes(
+                chanserv.clone(),
+                vec![ModeChange::Status(SetMode::Add, status, user.nick().clone())],
+            );
+        }
+        if let Some(message) = registered.and_then(|r| r.entry_message) {
+            tx.send(Event::Message(vec![
+                IRCMessage {
+                    prefix: Some(chanserv),
+                    command: Command::NOTICE(Requests::Notice {
+                        targets: vec![user.nick().clone()],
+                        message: format!("[{}] {}", channel_name, message),
+                    }),
+                },
+            ]));
+        }
+        joined
+    }
+
     pub fn part(
         &mut self,
         user: &UserIdentifier,
@@ -140,17 +936,393 @@ impl Server {
             }
 
             channel.part(user, message);
+            self.collect_channel(&ident);
             result.push(Ok(()));
         }
         result
     }
 
-    pub fn send(&mut self, user: &UserIdentifier, targets: &Vec<String>, message: &String) {
-        for t in targets {
-            self.channels
-                .get_mut(&ChannelIdentifier::from_name(t))
-                .unwrap()
-                .privmsg(user, message);
+    // Cross-checks the users and channels the server knows against users, the users of every
+    // registered connection. Returns a description of each disagreement.
+    pub fn check_consistency<'a, I>(&self, users: I) -> Vec<String>
+    where
+        I: IntoIterator<Item = &'a User>,
+    {
+        let mut result = Vec::new();
+        let users: HashMap<&UserIdentifier, &User> =
+            users.into_iter().map(|u| (u.identifier(), u)).collect();
+        for ident in self.users.keys() {
+            if !users.contains_key(ident) {
+                result.push(format!("{} has no connection.", ident.nick()));
+            }
         }
+        for ident in self.operators.keys().chain(self.logins.keys()) {
+            if !self.users.contains_key(ident) {
+                result.push(format!("{} has operator or login state but is unknown.", ident.nick()));
+            }
+        }
+        for (ident, user) in users.iter() {
+            if !self.users.contains_key(ident) {
+                result.push(format!("{} is connected but unknown.", ident.nick()));
+            }
+            for c in user.channels() {
+                match self.channels.get(c) {
+                    Some(channel) if channel.has_user(ident) => {}
+                    Some(_) => {
+                        result.push(format!("{} thinks it is in {}.", ident.nick(), c.name()))
+                    }
+                    None => {
+                        result.push(format!("{} is in nonexistent {}.", ident.nick(), c.name()))
+                    }
+                }
+            }
+        }
+        for (ident, channel) in self.channels.iter() {
+            if ident != channel.identifier() {
+                result.push(format!("{} is filed under {}.", channel.name(), ident.name()));
+            }
+            for member in channel.users() {
+                if !self.users.contains_key(member) {
+                    result.push(format!("{} in {} is unknown.", member.nick(), ident.name()));
+                }
+                if users.get(member).map_or(false, |u| !u.channels().any(|c| c == ident)) {
+                    result.push(format!(
+                        "{} in {} doesn't know it.",
+                        member.nick(),
+                        ident.name()
+                    ));
+                }
+            }
+            for member in channel.stray_members() {
+                result.push(format!(
+                    "{} has status in {} but isn't in it.",
+                    member.nick(),
+                    ident.name()
+                ));
+            }
+            if channel.users().next().is_none() && !channel.is_permanent() &&
+                self.registry.get(ident.name()).is_none()
+            {
+                result.push(format!("{} is empty but wasn't destroyed.", ident.name()));
+            }
+        }
+        result
+    }
+
+    // Sends text from user to target, a channel or a nick, as a PRIVMSG or a NOTICE.
+    pub fn send(
+        &self,
+        user: &UserIdentifier,
+        target: &String,
+        text: &String,
+        notice: bool,
+    ) -> Result<(), ServerError> {
+        let command = if notice {
+            Command::NOTICE(Requests::Notice {
+                targets: vec![target.clone()],
+                message: text.clone(),
+            })
+        } else {
+            Command::PRIVMSG(Requests::Privmsg {
+                targets: vec![target.clone()],
+                message: text.clone(),
+            })
+        };
+        let message = IRCMessage {
+            prefix: Some(user.as_prefix()),
+            command: command,
+        };
+        if target.starts_with('#') {
+            let channel = self.channels.get(&ChannelIdentifier::from_name(target)).ok_or(
+                ServerError::NoSuchChannel,
+            )?;
+            channel.can_send(user, self.logins.get(user)).map_err(
+                ServerError::CannotSend,
+            )?;
+            channel.send(user, message);
+            Ok(())
+        } else {
+            self.notify_user(&UserIdentifier::from_nick(target), Event::Message(vec![message]))
+                .map(|_| ())
+        }
+    }
+}
+
+// SASL looks accounts up through the server lock, holding it for each lookup only, so that
+// passwords are checked without it.
+impl Backend for Mutex<Server> {
+    fn password(&self, account: &str) -> Option<(String, String)> {
+        self.lock().unwrap().accounts().password(account)
+    }
+
+    fn scram_credentials(&self, account: &str) -> Option<ScramCredentials> {
+        self.lock().unwrap().accounts().scram_credentials(account)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono;
+    use futures_cpupool::CpuPool;
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+    use super::super::super::configuration::{ChannelConfiguration, Configuration, ConnectionClass};
+    use serde_yaml;
+    use super::super::channel::{parse_modes, Identifier as ChannelIdentifier, ListMode};
+    use super::super::connection::SocketPair;
+    use super::super::sendq;
+    use super::super::shared_state::{SharedState, Settings};
+    use super::super::statistics::LinkStatistics;
+    use super::super::user::{Identifier as UserIdentifier, User};
+    use super::{Server, ServerError, SnoMask};
+
+    fn server() -> Arc<Mutex<Server>> {
+        let configuration = Configuration {
+            dns_resolver: None,
+            bans_file: None,
+            accounts_file: None,
+            registered_channels_file: None,
+            channels: vec![
+                ChannelConfiguration {
+                    name: "#general".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let shared_state = SharedState::new(
+            chrono::offset::Utc::now(),
+            "test".to_string(),
+            &CpuPool::new(1),
+            Settings::new(configuration).unwrap(),
+            None,
+        );
+        Arc::new(Mutex::new(Server::new(Arc::new(shared_state)).unwrap()))
+    }
+
+    // Registers nick with server, as its connection would.
+    fn connect(server: &Arc<Mutex<Server>>, nick: &str) -> User {
+        let ident = UserIdentifier::new(
+            nick.to_string(),
+            nick.to_string(),
+            "real".to_string(),
+            "localhost".to_string(),
+        );
+        let link = Arc::new(LinkStatistics::new(nick.to_string()));
+        let (tx, _, _) = sendq::channel(link, 1024);
+        server.lock().unwrap().add_user(&ident, tx.clone()).unwrap();
+        User::new(&ident, None, Arc::clone(server), tx)
+    }
+
+    fn join(server: &Arc<Mutex<Server>>, user: &mut User, channel: &str) {
+        let res = server.lock().unwrap().join(
+            user.identifier(),
+            &vec![(channel.to_string(), None)],
+        );
+        assert!(res[0].is_ok());
+        user.join(&ChannelIdentifier::from_name(&channel.to_string()));
+    }
+
+    fn channels(server: &Arc<Mutex<Server>>) -> Vec<String> {
+        let mut channels: Vec<String> = server
+            .lock()
+            .unwrap()
+            .channels()
+            .map(|(c, _)| c.name().clone())
+            .collect();
+        channels.sort();
+        channels
+    }
+
+    #[test]
+    fn test_channel_collection() {
+        let server = server();
+        let mut alice = connect(&server, "alice");
+        let mut bob = connect(&server, "bob");
+        join(&server, &mut alice, "#rust");
+        join(&server, &mut bob, "#rust");
+        join(&server, &mut alice, "#general");
+        assert_eq!(channels(&server), vec!["#general", "#rust"]);
+        assert!(server.lock().unwrap().check_consistency(vec![&alice, &bob]).is_empty());
+
+        let rust = ChannelIdentifier::from_name(&"#rust".to_string());
+        server.lock().unwrap().part(
+            alice.identifier(),
+            &vec!["#rust".to_string()],
+            &None,
+        );
+        alice.part(&rust);
+        assert_eq!(channels(&server), vec!["#general", "#rust"]);
+        server.lock().unwrap().remove_user(bob.identifier(), &"bye".to_string());
+        assert_eq!(channels(&server), vec!["#general"]);
+        assert!(server.lock().unwrap().check_consistency(vec![&alice]).is_empty());
+
+        // Permanent channels outlive their members.
+        server.lock().unwrap().remove_user(alice.identifier(), &"bye".to_string());
+        assert_eq!(channels(&server), vec!["#general"]);
+        assert!(server.lock().unwrap().check_consistency(vec![]).is_empty());
+
+        let mut carol = connect(&server, "carol");
+        join(&server, &mut carol, "#rust");
+        carol.join(&ChannelIdentifier::from_name(&"#gone".to_string()));
+        let mut inconsistencies = server.lock().unwrap().check_consistency(vec![&alice, &carol]);
+        inconsistencies.sort();
+        assert_eq!(
+            inconsistencies,
+            vec![
+                "alice is connected but unknown.".to_string(),
+                "alice thinks it is in #general.".to_string(),
+                "carol is in nonexistent #gone.".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_send() {
+        let server = server();
+        let mut alice = connect(&server, "alice");
+        let mut bob = connect(&server, "bob");
+        let carol = connect(&server, "carol");
+        join(&server, &mut alice, "#rust");
+        join(&server, &mut bob, "#rust");
+        let send = |user: &User, target: &str| {
+            server.lock().unwrap().send(
+                user.identifier(),
+                &target.to_string(),
+                &"hi".to_string(),
+                false,
+            )
+        };
+        let set = |modes: &str, args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            let changes = parse_modes(modes, &args).unwrap();
+            server
+                .lock()
+                .unwrap()
+                .lookup_channel_mut(&ChannelIdentifier::from_name(&"#rust".to_string()))
+                .unwrap()
+                .apply_modes("test".to_string(), changes);
+        };
+        assert_eq!(send(&carol, "#rust"), Ok(()));
+        assert_eq!(send(&carol, "bob"), Ok(()));
+        assert_eq!(send(&carol, "dave"), Err(ServerError::UnknownUser));
+        assert_eq!(send(&carol, "#nowhere"), Err(ServerError::NoSuchChannel));
+
+        set("+n", &[]);
+        assert_eq!(
+            send(&carol, "#rust"),
+            Err(ServerError::CannotSend("Cannot send to channel (+n) - no external messages"))
+        );
+        set("-n+q", &["bob"]);
+        assert_eq!(
+            send(&bob, "#rust"),
+            Err(ServerError::CannotSend("Cannot send to channel (+q) - you are quieted"))
+        );
+        // Not even voice exempts from quiets.
+        set("+v", &["bob"]);
+        assert_eq!(
+            send(&bob, "#rust"),
+            Err(ServerError::CannotSend("Cannot send to channel (+q) - you are quieted"))
+        );
+        set("-v-q+b", &["bob", "bob", "*!*@localhost"]);
+        assert_eq!(
+            send(&bob, "#rust"),
+            Err(ServerError::CannotSend("Cannot send to channel (+b) - you are banned"))
+        );
+        // Nor does op, which the first joiner got, from bans.
+        assert_eq!(
+            send(&alice, "#rust"),
+            Err(ServerError::CannotSend("Cannot send to channel (+b) - you are banned"))
+        );
+        set("-b+r", &["*!*@localhost"]);
+        assert_eq!(
+            send(&bob, "#rust"),
+            Err(ServerError::CannotSend(
+                "Cannot send to channel (+r) - you need to identify to an account",
+            ))
+        );
+        server.lock().unwrap().set_account(bob.identifier(), Some("bob".to_string()));
+        assert_eq!(send(&bob, "#rust"), Ok(()));
+        set("+m", &[]);
+        assert_eq!(
+            send(&bob, "#rust"),
+            Err(ServerError::CannotSend("Cannot send to channel (+m) - you need voice"))
+        );
+    }
+
+    #[test]
+    fn test_check_class() {
+        let server = server();
+        let mut server = server.lock().unwrap();
+        let socket = |remote: &str| {
+            SocketPair {
+                local: "127.0.0.1:6667".parse().unwrap(),
+                remote: remote.parse().unwrap(),
+            }
+        };
+        let class = ConnectionClass {
+            max_clients: 3,
+            max_clients_per_ip: 2,
+            ..Default::default()
+        };
+        let other = ConnectionClass {
+            name: "other".to_string(),
+            ..Default::default()
+        };
+
+        server.set_class(&socket("10.0.0.1:1"), &class);
+        server.set_class(&socket("10.0.0.9:1"), &other);
+        assert_eq!(server.check_class(&socket("10.0.0.1:2"), &class), Ok(()));
+        server.set_class(&socket("10.0.0.1:2"), &class);
+        assert_eq!(
+            server.check_class(&socket("10.0.0.1:3"), &class),
+            Err(ServerError::TooManyFromHost)
+        );
+        // A client already in the class is not counted against itself.
+        assert_eq!(server.check_class(&socket("10.0.0.1:2"), &class), Ok(()));
+        server.set_class(&socket("10.0.0.2:1"), &class);
+        assert_eq!(
+            server.check_class(&socket("10.0.0.3:1"), &class),
+            Err(ServerError::ClassFull)
+        );
+        server.remove_class(&socket("10.0.0.2:1"));
+        assert_eq!(server.check_class(&socket("10.0.0.3:1"), &class), Ok(()));
+    }
+
+    #[test]
+    fn test_snomask() {
+        let masks = SnoMask::apply(&HashSet::new(), "+cCkz");
+        assert_eq!(SnoMask::format(&masks), "+cCk");
+        let masks = SnoMask::apply(&masks, "-c+x");
+        assert_eq!(SnoMask::format(&masks), "+Ckx");
+        assert_eq!(SnoMask::format(&SnoMask::apply(&masks, "Kf")), "+CkKfx");
+        assert_eq!(SnoMask::format(&SnoMask::apply(&masks, "r")), "+Ckrx");
+        assert_eq!(SnoMask::format(&HashSet::new()), "+");
+    }
+
+    #[test]
+    fn test_restore_banned() {
+        let original = server();
+        let bob = connect(&original, "bob");
+        let general = ChannelIdentifier::from_name(&"#general".to_string());
+        let snapshot = serde_yaml::to_value(original.lock().unwrap().snapshot()).unwrap();
+        let bans = |server: &Arc<Mutex<Server>>| {
+            let mut server = server.lock().unwrap();
+            server.lookup_channel(&general).unwrap().list(ListMode::Ban).clone()
+        };
+
+        // Snapshots from before ban masks list banned users instead.
+        let mut old = snapshot.clone();
+        let channel = &mut old["channels"][0];
+        let banned = serde_yaml::to_value(vec![bob.identifier()]).unwrap();
+        channel.as_mapping_mut().unwrap().remove(&"bans".into());
+        channel.as_mapping_mut().unwrap().insert("banned".into(), banned);
+        let restored = server();
+        restored.lock().unwrap().restore(serde_yaml::from_value(old).unwrap());
+        assert_eq!(bans(&restored), vec!["bob!*@*"]);
+
+        let restored = server();
+        restored.lock().unwrap().restore(serde_yaml::from_value(snapshot).unwrap());
+        assert!(bans(&restored).is_empty());
     }
 }
@@ -2,17 +2,40 @@ use chrono;
 use futures_cpupool::CpuPool;
 use handlebars;
 use std;
+use std::io::{BufRead, BufReader};
+use std::sync::{Arc, RwLock};
 use super::super::{configuration, templates};
+use super::super::configuration::{Configuration, ConfigurationError, DnsResolver};
+use super::dns::{Dns, HostsFileResolver, Resolver, SystemResolver};
+use super::ident::Ident;
+
+// Threads blocking on DNS lookups.
+const LOOKUP_THREADS: usize = 4;
 
 // State that is initialize on server start, but not preconfigured.
-// Shared, lock-free, across the binary.
+// Shared across the binary. Only settings may change after start, and are swapped on REHASH.
 #[derive(Debug)]
 pub struct SharedState {
     pub created: chrono::DateTime<chrono::Utc>,
     pub hostname: String,
-    pub template_engine: TE,
-    pub configuration: std::sync::Arc<configuration::Configuration>,
+    // Where the configuration was loaded from, if not the defaults.
+    pub configuration_path: Option<String>,
+    settings: RwLock<Arc<Settings>>,
     pub thread_pool: CpuPool,
+    // Runs DNS lookups, which block.
+    pub lookup_pool: CpuPool,
+    pub dns: Dns,
+    pub ident: Ident,
+}
+
+// Everything derived from the configuration file.
+#[derive(Debug)]
+pub struct Settings {
+    pub configuration: Configuration,
+    pub template_engine: TE,
+    pub motd: Option<Vec<String>>,
+    // None if hostnames are not looked up.
+    pub resolver: Option<Arc<dyn Resolver>>,
 }
 
 // Workaround since Handlebars doensn't derive Debug.
@@ -24,17 +47,20 @@ impl std::fmt::Debug for TE {
     }
 }
 
-impl SharedState {
-    pub fn new(
-        time: chrono::DateTime<chrono::Utc>,
-        hostname: String,
-        thread_pool: &CpuPool,
-        configuration: std::sync::Arc<configuration::Configuration>,
-    ) -> Self {
+impl Settings {
+    // Compiles templates and reads the MOTD for configuration.
+    pub fn new(configuration: Configuration) -> Result<Self, ConfigurationError> {
         let mut template_engine = handlebars::Handlebars::new();
         macro_rules! register_template {
             ($name:ident, $template:ident) => {
-                template_engine.register_template_string(templates::$name, templates::$template).unwrap();
+                let template = configuration
+                    .templates
+                    .get(templates::$name)
+                    .map(|t| t.as_str())
+                    .unwrap_or(templates::$template);
+                template_engine.register_template_string(templates::$name, template).map_err(|e| {
+                    ConfigurationError::Invalid(format!("template {}: {}", templates::$name, e))
+                })?;
             }
         }
         // Register all known templates.
@@ -42,6 +68,12 @@ impl SharedState {
         register_template!(RPL_WELCOME_TEMPLATE_NAME, RPL_WELCOME_TEMPLATE);
         register_template!(RPL_YOURHOST_TEMPLATE_NAME, RPL_YOURHOST_TEMPLATE);
         register_template!(RPL_CREATED_TEMPLATE_NAME, RPL_CREATED_TEMPLATE);
+        if let Some(name) = configuration.templates.keys().find(|n| {
+            template_engine.get_template(n).is_none()
+        })
+        {
+            return Err(ConfigurationError::Invalid(format!("unknown template {}", name)));
+        }
 
         /* For rapid template iteration (only bin restart required).
         template_engine.register_template_file(
@@ -50,12 +82,67 @@ impl SharedState {
             ).unwrap();
        */
 
+        let motd = match configuration.motd_file {
+            Some(ref path) => {
+                let file = std::fs::File::open(path).map_err(ConfigurationError::Io)?;
+                Some(BufReader::new(file).lines().collect::<Result<_, _>>().map_err(
+                    ConfigurationError::Io,
+                )?)
+            }
+            None => None,
+        };
+
+        let resolver: Option<Arc<dyn Resolver>> = match configuration.dns_resolver {
+            Some(DnsResolver::System) => Some(Arc::new(SystemResolver)),
+            Some(DnsResolver::HostsFile(ref path)) => {
+                Some(Arc::new(
+                    HostsFileResolver::load(path).map_err(ConfigurationError::Io)?,
+                ))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            configuration: configuration,
+            template_engine: TE(template_engine),
+            motd: motd,
+            resolver: resolver,
+        })
+    }
+}
+
+impl SharedState {
+    pub fn new(
+        time: chrono::DateTime<chrono::Utc>,
+        hostname: String,
+        thread_pool: &CpuPool,
+        settings: Settings,
+        configuration_path: Option<String>,
+    ) -> Self {
+        let lookup_pool = CpuPool::new(LOOKUP_THREADS);
         Self {
             created: time,
             hostname: hostname,
-            template_engine: TE(template_engine),
+            configuration_path: configuration_path,
+            settings: RwLock::new(Arc::new(settings)),
             thread_pool: thread_pool.clone(),
-            configuration: configuration,
+            dns: Dns::new(&lookup_pool),
+            lookup_pool: lookup_pool,
+            ident: Ident::new(),
         }
     }
+
+    pub fn settings(&self) -> Arc<Settings> {
+        Arc::clone(&self.settings.read().unwrap())
+    }
+
+    // Re-reads the configuration file, and swaps in the new settings if valid.
+    pub fn reload(&self) -> Result<(), ConfigurationError> {
+        let settings = match self.configuration_path {
+            Some(ref path) => Settings::new(Configuration::load(path)?)?,
+            None => Settings::new(configuration::Configuration::default())?,
+        };
+        *self.settings.write().unwrap() = Arc::new(settings);
+        Ok(())
+    }
 }
@@ -0,0 +1,118 @@
+use chrono;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Traffic counters for a single connection.
+// Updated by the connection pipeline, and read by STATS without locking the Connection.
+#[derive(Debug)]
+pub struct LinkStatistics {
+    pub opened: chrono::DateTime<chrono::Utc>,
+    // Name reported by STATS l, nick[ip] once registered.
+    name: Mutex<String>,
+    sent_messages: AtomicUsize,
+    sent_bytes: AtomicUsize,
+    received_messages: AtomicUsize,
+    received_bytes: AtomicUsize,
+    // Bytes of messages waiting to be written.
+    sendq: AtomicUsize,
+}
+
+// LinkStatistics, preserved across hot restarts.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    opened: i64,
+    name: String,
+    sent_messages: usize,
+    sent_bytes: usize,
+    received_messages: usize,
+    received_bytes: usize,
+}
+
+impl LinkStatistics {
+    pub fn new(name: String) -> Self {
+        LinkStatistics {
+            opened: chrono::offset::Utc::now(),
+            name: Mutex::new(name),
+            sent_messages: AtomicUsize::new(0),
+            sent_bytes: AtomicUsize::new(0),
+            received_messages: AtomicUsize::new(0),
+            received_bytes: AtomicUsize::new(0),
+            sendq: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            opened: self.opened.timestamp(),
+            name: self.name(),
+            sent_messages: self.sent_messages(),
+            sent_bytes: self.sent_bytes(),
+            received_messages: self.received_messages(),
+            received_bytes: self.received_bytes(),
+        }
+    }
+
+    pub fn restore(snapshot: Snapshot) -> Self {
+        LinkStatistics {
+            opened: chrono::DateTime::from_utc(
+                chrono::NaiveDateTime::from_timestamp(snapshot.opened, 0),
+                chrono::Utc,
+            ),
+            name: Mutex::new(snapshot.name),
+            sent_messages: AtomicUsize::new(snapshot.sent_messages),
+            sent_bytes: AtomicUsize::new(snapshot.sent_bytes),
+            received_messages: AtomicUsize::new(snapshot.received_messages),
+            received_bytes: AtomicUsize::new(snapshot.received_bytes),
+            sendq: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.name.lock().unwrap().clone()
+    }
+
+    pub fn set_name(&self, name: String) {
+        *self.name.lock().unwrap() = name;
+    }
+
+    // Records a message of bytes (including CRLF) written to the socket.
+    pub fn sent(&self, bytes: usize) {
+        self.sent_messages.fetch_add(1, Ordering::Relaxed);
+        self.sent_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    // Records a message of bytes (including CRLF) read from the socket.
+    pub fn received(&self, bytes: usize) {
+        self.received_messages.fetch_add(1, Ordering::Relaxed);
+        self.received_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    // Records bytes queued to be written. Returns the send queue depth.
+    pub fn queued(&self, bytes: usize) -> usize {
+        self.sendq.fetch_add(bytes, Ordering::Relaxed) + bytes
+    }
+
+    pub fn dequeued(&self, bytes: usize) {
+        self.sendq.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    pub fn sendq(&self) -> usize {
+        self.sendq.load(Ordering::Relaxed)
+    }
+
+    pub fn sent_messages(&self) -> usize {
+        self.sent_messages.load(Ordering::Relaxed)
+    }
+
+    pub fn sent_bytes(&self) -> usize {
+        self.sent_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn received_messages(&self) -> usize {
+        self.received_messages.load(Ordering::Relaxed)
+    }
+
+    pub fn received_bytes(&self) -> usize {
+        self.received_bytes.load(Ordering::Relaxed)
+    }
+}
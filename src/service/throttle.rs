@@ -0,0 +1,99 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+// Remembers when each IP last connected, to limit how fast a host may reconnect.
+#[derive(Debug)]
+pub struct Throttle {
+    recent: HashMap<IpAddr, VecDeque<Instant>>,
+    // When IPs that stopped connecting were last forgotten.
+    swept: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn new() -> Self {
+        Self {
+            recent: HashMap::new(),
+            swept: None,
+        }
+    }
+
+    // Records a connection from ip. Returns false, without recording it, if ip already connected
+    // limit times within window.
+    pub fn record(&mut self, ip: &IpAddr, limit: usize, window: Duration, now: Instant) -> bool {
+        if self.swept.map_or(true, |s| now.duration_since(s) >= window) {
+            self.recent.retain(|_, times| {
+                expire(times, window, now);
+                !times.is_empty()
+            });
+            self.swept = Some(now);
+        }
+
+        let times = self.recent.entry(ip.clone()).or_insert_with(VecDeque::new);
+        expire(times, window, now);
+        if times.len() >= limit {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+}
+
+fn expire(times: &mut VecDeque<Instant>, window: Duration, now: Instant) {
+    while times.front().map_or(false, |&t| now.duration_since(t) >= window) {
+        times.pop_front();
+    }
+}
+
+// Connections that count as clones of each other share a key: the IPv4 address, or the /64 of an
+// IPv6 address.
+pub fn clone_key(ip: &IpAddr) -> IpAddr {
+    match ip {
+        &IpAddr::V6(ref v6) => {
+            match v6.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => {
+                    let mut segments = v6.segments();
+                    for s in segments[4..].iter_mut() {
+                        *s = 0;
+                    }
+                    IpAddr::V6(segments.into())
+                }
+            }
+        }
+        _ => ip.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+    use super::{clone_key, Throttle};
+
+    #[test]
+    fn test_throttle() {
+        let start = Instant::now();
+        let window = Duration::from_secs(10);
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut throttle = Throttle::new();
+        assert!(throttle.record(&a, 2, window, start));
+        assert!(throttle.record(&a, 2, window, start + Duration::from_secs(1)));
+        assert!(!throttle.record(&a, 2, window, start + Duration::from_secs(2)));
+        assert!(throttle.record(&b, 2, window, start + Duration::from_secs(2)));
+
+        // The first connection falls out of the window.
+        assert!(throttle.record(&a, 2, window, start + Duration::from_secs(10)));
+        assert!(!throttle.record(&a, 2, window, start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_clone_key() {
+        let key = |s: &str| clone_key(&s.parse().unwrap()).to_string();
+        assert_eq!(key("10.0.0.1"), "10.0.0.1");
+        assert_eq!(key("::ffff:10.0.0.1"), "10.0.0.1");
+        assert_eq!(key("2001:db8:1:2:3:4:5:6"), "2001:db8:1:2::");
+        assert_eq!(key("2001:db8:1:2::"), key("2001:db8:1:2:ffff::1"));
+    }
+}
@@ -8,7 +8,25 @@ use super::messages::Message as IRCMessage;
 use super::messages::commands::{Command, requests as Requests, responses as Responses};
 use super::connection::ConnectionTX;
 use super::channel::{Identifier as ChannelIdentifier, ChannelError, Channel};
+use super::mask;
 use super::server::Server;
+use super::super::configuration::OperatorPrivilege;
+
+// Longest nick accepted, advertised as NICKLEN.
+pub const MAX_NICK_LENGTH: usize = 30;
+
+// Whether nick follows the RFC 2812 grammar: a letter or special character, followed by letters,
+// digits, special characters and dashes.
+pub fn valid_nick(nick: &str) -> bool {
+    let special = |c: char| "[]\\`_^{|}".contains(c);
+    let mut chars = nick.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || special(c) => {}
+        _ => return false,
+    }
+    nick.len() <= MAX_NICK_LENGTH &&
+        chars.all(|c| c.is_ascii_alphanumeric() || special(c) || c == '-')
+}
 
 #[derive(Debug)]
 pub enum Message {
@@ -33,12 +51,15 @@ pub enum Message {
     //ServerRegistrationResult(Option<ServerError>),
 }
 
-#[derive(Debug, Default, Clone, Serialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Identifier {
     nickname: String,
     username: String,
     realname: String,
     hostname: String,
+    // Shown instead of hostname to other clients: a cloak or vhost.
+    #[serde(default)]
+    displayed_hostname: Option<String>,
 }
 
 impl Identifier {
@@ -48,11 +69,41 @@ impl Identifier {
             username,
             realname,
             hostname,
+            displayed_hostname: None,
+        }
+    }
+    // Identifier suitable only for lookups, since equality is based on nickname.
+    pub fn from_nick(nickname: &String) -> Self {
+        Self {
+            nickname: nickname.clone(),
+            ..Default::default()
         }
     }
     pub fn nick(&self) -> &String {
         &self.nickname
     }
+    pub fn username(&self) -> &String {
+        &self.username
+    }
+    pub fn realname(&self) -> &String {
+        &self.realname
+    }
+    // The real hostname, for bans and operators with the RealHost privilege.
+    pub fn hostname(&self) -> &String {
+        &self.hostname
+    }
+    // The hostname everyone else sees.
+    pub fn displayed_hostname(&self) -> &String {
+        self.displayed_hostname.as_ref().unwrap_or(&self.hostname)
+    }
+    pub fn set_nick(&mut self, nickname: String) {
+        self.nickname = nickname;
+    }
+    // Whether mask matches nick!user@host with either the real or the displayed host.
+    pub fn matches(&self, mask: &str) -> bool {
+        let real = format!("{}!{}@{}", self.nickname, self.username, self.hostname);
+        mask::matches(mask, &real) || mask::matches(mask, &self.as_prefix())
+    }
     pub fn into_nick(self) -> String {
         self.nickname
     }
@@ -62,6 +113,13 @@ impl Identifier {
 pub struct User {
     ident: Identifier,
     modes: HashSet<UserMode>,
+    privileges: HashSet<OperatorPrivilege>,
+    // Shown with +x, if cloaking is enabled.
+    cloak: Option<String>,
+    // Shown regardless of +x, once set by an operator.
+    vhost: Option<String>,
+    // NickServ account logged in to.
+    account: Option<String>,
     #[serde(skip)]
     channels: HashSet<ChannelIdentifier>,
     #[serde(skip)]
@@ -70,7 +128,22 @@ pub struct User {
     tx: ConnectionTX,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize)]
+// User, preserved across hot restarts.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    ident: Identifier,
+    modes: Vec<UserMode>,
+    privileges: Vec<OperatorPrivilege>,
+    channels: Vec<ChannelIdentifier>,
+    #[serde(default)]
+    cloak: Option<String>,
+    #[serde(default)]
+    vhost: Option<String>,
+    #[serde(default)]
+    account: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
 pub enum UserMode {
     Away,
     Invisible,
@@ -79,6 +152,7 @@ pub enum UserMode {
     Operator,
     LocalOperator,
     ServerNotices,
+    Cloaked,
 }
 
 impl fmt::Display for Identifier {
@@ -104,7 +178,12 @@ impl std::hash::Hash for Identifier {
 
 impl Identifier {
     pub fn as_prefix(&self) -> String {
-        format!("{}!{}@{}", self.nickname, self.username, self.hostname)
+        format!(
+            "{}!{}@{}",
+            self.nickname,
+            self.username,
+            self.displayed_hostname()
+        )
     }
 }
 
@@ -116,26 +195,53 @@ impl std::cmp::PartialEq for User {
 
 impl std::cmp::Eq for User {}
 
-macro_rules! send_log_err {
-    ($tx:expr, $message:expr) => {
-        match $tx.try_send($message) {
-            Err(e) => debug!("Send error: {:?}.", e),
-            _ =>{},
-        };
-    }
-}
-
 impl User {
-    pub fn new(ident: &Identifier, server: Arc<Mutex<Server>>, tx: ConnectionTX) -> Self {
+    // cloak is the host shown once the user sets +x, None if cloaking is disabled.
+    pub fn new(
+        ident: &Identifier,
+        cloak: Option<String>,
+        server: Arc<Mutex<Server>>,
+        tx: ConnectionTX,
+    ) -> Self {
         Self {
             ident: ident.clone(),
             modes: HashSet::new(),
+            privileges: HashSet::new(),
+            cloak: cloak,
+            vhost: None,
+            account: None,
             channels: HashSet::new(),
             server: server,
             tx: tx,
         }
     }
 
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            ident: self.ident.clone(),
+            modes: self.modes.iter().cloned().collect(),
+            privileges: self.privileges.iter().cloned().collect(),
+            channels: self.channels.iter().cloned().collect(),
+            cloak: self.cloak.clone(),
+            vhost: self.vhost.clone(),
+            account: self.account.clone(),
+        }
+    }
+
+    pub fn restore(snapshot: Snapshot, server: Arc<Mutex<Server>>, tx: ConnectionTX) -> Self {
+        Self {
+            ident: snapshot.ident,
+            modes: snapshot.modes.into_iter().collect(),
+            privileges: snapshot.privileges.into_iter().collect(),
+            cloak: snapshot.cloak,
+            vhost: snapshot.vhost,
+            account: snapshot.account,
+            channels: snapshot.channels.into_iter().collect(),
+            server: server,
+            tx: tx,
+        }
+    }
+
     pub fn identifier(&self) -> &Identifier {
         &self.ident
     }
@@ -156,9 +262,85 @@ impl User {
         self.channels.iter()
     }
 
+    pub fn is_operator(&self) -> bool {
+        self.modes.contains(&UserMode::Operator)
+    }
+
+    pub fn has_privilege(&self, privilege: &OperatorPrivilege) -> bool {
+        self.is_operator() && self.privileges.contains(privilege)
+    }
+
+    pub fn has_mode(&self, mode: &UserMode) -> bool {
+        self.modes.contains(mode)
+    }
+
+    pub fn set_nick(&mut self, nickname: String) {
+        self.ident.set_nick(nickname);
+    }
+
+    pub fn account(&self) -> Option<&String> {
+        self.account.as_ref()
+    }
+
+    pub fn set_account(&mut self, account: Option<String>) {
+        self.account = account;
+    }
+
+    // Replaces the displayed host with vhost. Returns whether the displayed host changed.
+    pub fn set_vhost(&mut self, vhost: String) -> bool {
+        self.vhost = Some(vhost);
+        self.update_displayed_hostname()
+    }
+
+    // A vhost takes precedence over the cloak. Returns whether the displayed host changed.
+    fn update_displayed_hostname(&mut self) -> bool {
+        let displayed = match self.vhost {
+            Some(ref vhost) => Some(vhost.clone()),
+            None if self.has_mode(&UserMode::Cloaked) => self.cloak.clone(),
+            None => None,
+        };
+        if displayed == self.ident.displayed_hostname {
+            return false;
+        }
+        self.ident.displayed_hostname = displayed;
+        true
+    }
+
+    // Grants operator status, subscribed to server notices. Returns the resulting MODE change.
+    pub fn oper(&mut self, privileges: &Vec<OperatorPrivilege>) -> Vec<IRCMessage> {
+        self.privileges = privileges.iter().cloned().collect();
+        self.modes.insert(UserMode::Operator);
+        self.modes.insert(UserMode::ServerNotices);
+        vec![
+            IRCMessage {
+                prefix: None,
+                command: Command::MODE(Requests::Mode {
+                    target: self.nick().clone(),
+                    mode_string: Some(
+                        format!("+{}{}", UserMode::Operator, UserMode::ServerNotices),
+                    ),
+                    mode_args: None,
+                }),
+            },
+        ]
+    }
+
     pub fn set_mode(&mut self, set: &SetMode, mode: &Vec<UserMode>) -> Vec<IRCMessage> {
         let mut modified = Vec::new();
         for m in mode.iter() {
+            // Operator status can only be gained through OPER.
+            if let &SetMode::Add = set {
+                if m == &UserMode::Operator || m == &UserMode::LocalOperator {
+                    continue;
+                }
+                // Server notices are only delivered to operators.
+                if m == &UserMode::ServerNotices && !self.is_operator() {
+                    continue;
+                }
+                if m == &UserMode::Cloaked && self.cloak.is_none() {
+                    continue;
+                }
+            }
             if match set {
                 &SetMode::Add => self.modes.insert(m.clone()),
                 &SetMode::Remove => self.modes.remove(&m),
@@ -167,6 +349,13 @@ impl User {
                 modified.push(m.clone());
             }
         }
+        if !self.is_operator() {
+            self.privileges.clear();
+            if self.modes.remove(&UserMode::ServerNotices) {
+                modified.push(UserMode::ServerNotices);
+            }
+        }
+        self.update_displayed_hostname();
         trace!(
             "{} mode is {}.",
             self.nick(),
@@ -202,7 +391,7 @@ impl User {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SetMode {
     Add,
     Remove,
@@ -223,6 +412,7 @@ impl str::FromStr for UserMode {
             "o" => Ok(UserMode::Operator),
             "O" => Ok(UserMode::LocalOperator),
             "s" => Ok(UserMode::ServerNotices),
+            "x" => Ok(UserMode::Cloaked),
             _ => Err(()),
         }
     }
@@ -241,6 +431,7 @@ impl fmt::Display for UserMode {
                 &UserMode::Operator => "o",
                 &UserMode::LocalOperator => "O",
                 &UserMode::ServerNotices => "s",
+                &UserMode::Cloaked => "x",
             }
         )
     }
@@ -77,17 +77,48 @@ pub static DEBUG_HTML_TEMPLATE: &'static str = "
   <tr>
     <th>Socket</th>
     <th>Nick</th>
+    <th>SendQ</th>
   </tr>
   {{#each connections}}
   <tr>
     <td>{{@key}}</td>
     <td>{{#if this.0}}<a href=\"#{{this.1.1}}\">{{this.1.0}}</a>
     {{else}}<b style=\"color:red\">Not registered.</b>{{/if}}</td>
+    <td>{{this.2}}</td>
   </tr>
   {{/each}}
 </table>
 </div>
 
+<div id=\"Refusals\">
+<h2>Refused Connections</h2>
+<table>
+  <tr>
+    <th>Reason</th>
+    <th>Count</th>
+  </tr>
+  {{#each refusals}}
+  <tr>
+    <td>{{this.0}}</td>
+    <td>{{this.1}}</td>
+  </tr>
+  {{/each}}
+</table>
+</div>
+
+<div id=\"Inconsistencies\">
+<h2>Inconsistencies</h2>
+{{#if inconsistencies}}
+<ul>
+  {{#each inconsistencies}}
+  <li><b style=\"color:red\">{{this}}</b></li>
+  {{/each}}
+</ul>
+{{else}}
+None.
+{{/if}}
+</div>
+
 </body>
 </html>";
 